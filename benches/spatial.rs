@@ -0,0 +1,62 @@
+// benches/spatial.rs
+// Hit-testing on generated diagrams: filling the grid, and point lookups through it versus a scan of every shape.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use ggez::glam::Vec2;
+use rust_visual_mouse_app::spatial::SpatialHash;
+use rust_visual_mouse_app::stress::{self, STRESS_SPACING};
+use rust_visual_mouse_app::Diagram;
+
+const SHAPE_COUNTS: [usize; 3] = [1_000, 10_000, 50_000];
+const CELL_SIZE: f32 = 150.0; // As the editor's grids
+const DEFAULT_SHAPE_SIZE: Vec2 = Vec2::new(100.0, 60.0);
+const QUERY_COUNT: usize = 1_000;
+const LINEAR_SCAN_MAX_SHAPES: usize = 10_000; // Beyond this the baseline only takes time
+
+fn shape_grid(diagram: &Diagram) -> SpatialHash {
+    let mut grid = SpatialHash::new(CELL_SIZE);
+    for (index, shape) in diagram.shapes.iter().enumerate() {
+        grid.insert(index, shape.rect(DEFAULT_SHAPE_SIZE));
+    }
+    grid
+}
+
+// Points spread over the diagram, about half of them on a shape
+fn query_points(shape_count: usize) -> Vec<Vec2> {
+    let columns = (shape_count as f32).sqrt().ceil();
+    (0..QUERY_COUNT).map(|i| {
+        let t = i as f32 / QUERY_COUNT as f32;
+        Vec2::new((t * 7.3).fract() * columns, (t * 3.1).fract() * columns) * STRESS_SPACING
+    }).collect()
+}
+
+fn bench_spatial(c: &mut Criterion) {
+    let mut group = c.benchmark_group("spatial");
+    for shape_count in SHAPE_COUNTS {
+        let diagram = stress::stress_diagram(shape_count);
+        group.bench_with_input(BenchmarkId::new("build", shape_count), &diagram, |b, diagram| b.iter(|| shape_grid(diagram)));
+
+        let grid = shape_grid(&diagram);
+        let points = query_points(shape_count);
+        group.bench_with_input(BenchmarkId::new("query_point", shape_count), &points, |b, points| {
+            b.iter(|| {
+                points.iter().filter_map(|&point| {
+                    grid.query_point(point).iter().rev().copied()
+                        .find(|&index| diagram.shapes[index].kind.contains(diagram.shapes[index].rect(DEFAULT_SHAPE_SIZE), point))
+                }).map(black_box).count()
+            })
+        });
+        if shape_count > LINEAR_SCAN_MAX_SHAPES { continue; }
+        group.bench_with_input(BenchmarkId::new("linear_scan", shape_count), &points, |b, points| {
+            b.iter(|| {
+                points.iter().filter_map(|&point| {
+                    diagram.shapes.iter().rposition(|shape| shape.kind.contains(shape.rect(DEFAULT_SHAPE_SIZE), point))
+                }).map(black_box).count()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_spatial);
+criterion_main!(benches);
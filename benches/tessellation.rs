@@ -0,0 +1,64 @@
+// benches/tessellation.rs
+// Connector tessellation on generated diagrams: every line stroked from scratch, as after a zoom or a
+// theme change, against a frame where the stroke cache has all of them.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use ggez::glam::Vec2;
+use ggez::graphics::{Color, Vertex};
+use lyon_tessellation::VertexBuffers;
+use rust_visual_mouse_app::tessellation::{self, ConnectorPath, ConnectorStroke, StrokeCache};
+use rust_visual_mouse_app::{geometry, stress, LineDash, CONNECTOR_LINE_WIDTH};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+const SHAPE_COUNTS: [usize; 2] = [1_000, 10_000];
+const DEFAULT_SHAPE_SIZE: Vec2 = Vec2::new(100.0, 60.0);
+
+// Each connection's curve between its ports, as the editor draws unrouted connectors
+fn connector_paths(shape_count: usize) -> Vec<ConnectorPath> {
+    let diagram = stress::stress_diagram(shape_count);
+    diagram.connections.iter().filter_map(|connection| {
+        let (start, start_side) = diagram.port_anchor(connection.from_shape_index, connection.from_port, DEFAULT_SHAPE_SIZE)?;
+        let (end, end_side) = diagram.port_anchor(connection.to_shape_index, connection.to_port, DEFAULT_SHAPE_SIZE)?;
+        Some(ConnectorPath::Curve(geometry::connector_curve(start, start_side, end, end_side)))
+    }).collect()
+}
+
+// Stands in for the editor's connector key: a hash of the geometry the line is built from
+fn path_key(path: &ConnectorPath) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for point in path.points() {
+        (point.x.to_bits(), point.y.to_bits()).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+fn stroke(dash: LineDash) -> ConnectorStroke {
+    ConnectorStroke { color: Color::WHITE, width: CONNECTOR_LINE_WIDTH, dash }
+}
+
+fn bench_tessellation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tessellation");
+    group.sample_size(20);
+    for shape_count in SHAPE_COUNTS {
+        let paths = connector_paths(shape_count);
+        for (name, dash) in [("stroke_solid", LineDash::Solid), ("stroke_dashed", LineDash::Dashed)] {
+            group.bench_with_input(BenchmarkId::new(name, shape_count), &paths, |b, paths| {
+                b.iter(|| paths.iter().filter_map(|path| tessellation::stroke_geometry(path, stroke(dash))).map(black_box).count())
+            });
+        }
+
+        let keys: Vec<u64> = paths.iter().map(path_key).collect();
+        let mut cache: StrokeCache<VertexBuffers<Vertex, u32>> = StrokeCache::default();
+        for (index, (path, &key)) in paths.iter().zip(&keys).enumerate() {
+            cache.insert(index, key, stroke(LineDash::Solid), tessellation::stroke_geometry(path, stroke(LineDash::Solid)));
+        }
+        group.bench_with_input(BenchmarkId::new("cache_hits", shape_count), &keys, |b, keys| {
+            b.iter(|| keys.iter().enumerate().filter_map(|(index, &key)| cache.get(index, key, stroke(LineDash::Solid))).map(black_box).count())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_tessellation);
+criterion_main!(benches);
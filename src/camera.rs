@@ -0,0 +1,81 @@
+// camera.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::Rect;
+
+pub const MIN_ZOOM: f32 = 0.1;
+pub const MAX_ZOOM: f32 = 8.0;
+pub const ZOOM_STEP: f32 = 1.1; // Zoom multiplier per scroll wheel notch
+
+// --- Viewport into the (unbounded) world ---
+// `offset` is the world position shown at the top-left corner of the window,
+// `zoom` is how many logical screen pixels one world unit covers.
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub offset: Vec2,
+    pub zoom: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera { offset: Vec2::ZERO, zoom: 1.0 }
+    }
+}
+
+impl Camera {
+    pub fn screen_to_world(&self, screen_pos: Vec2) -> Vec2 {
+        screen_pos / self.zoom + self.offset
+    }
+
+    pub fn world_to_screen(&self, world_pos: Vec2) -> Vec2 {
+        (world_pos - self.offset) * self.zoom
+    }
+
+    // World-space rect covering a logical screen area of the given size, for `set_screen_coordinates`
+    pub fn visible_rect(&self, logical_width: f32, logical_height: f32) -> Rect {
+        Rect::new(self.offset.x, self.offset.y, logical_width / self.zoom, logical_height / self.zoom)
+    }
+
+    // Moves the view by a screen-space delta (e.g. a mouse drag)
+    pub fn pan_by_screen_delta(&mut self, screen_delta: Vec2) {
+        self.offset -= screen_delta / self.zoom;
+    }
+
+    // Zooms and pans so the world box `min`..`max` fills `viewport` (logical screen area) less `margin` on each side
+    pub fn fit_to(&mut self, min: Vec2, max: Vec2, viewport: Rect, margin: f32) {
+        let world_size = (max - min).max(Vec2::ONE);
+        let available = Vec2::new(viewport.w - margin * 2.0, viewport.h - margin * 2.0).max(Vec2::ONE);
+        self.zoom = (available / world_size).min_element().clamp(MIN_ZOOM, MAX_ZOOM);
+        let world_center = (min + max) / 2.0;
+        let screen_center = Vec2::from(viewport.center());
+        self.offset = world_center - screen_center / self.zoom;
+    }
+
+    // Pans so the world point sits in the middle of a logical screen area of the given size
+    pub fn center_on(&mut self, world_pos: Vec2, logical_size: Vec2) {
+        self.offset = world_pos - logical_size / (2.0 * self.zoom);
+    }
+
+    // Centers the view on the world box `min`..`max` unless it is already fully on screen
+    pub fn reveal(&mut self, min: Vec2, max: Vec2, logical_size: Vec2) {
+        let visible = self.visible_rect(logical_size.x, logical_size.y);
+        if visible.contains(min) && visible.contains(max) { return; }
+        self.center_on((min + max) / 2.0, logical_size);
+    }
+
+    // Partway (`t` from 0 to 1) from this view to `target` on a screen of `logical_size`: the view's
+    // center moves in a straight line while zoom changes geometrically, so neither seems to lurch
+    pub fn interpolate(&self, target: &Camera, t: f32, logical_size: Vec2) -> Camera {
+        let center = |camera: &Camera| camera.offset + logical_size / (2.0 * camera.zoom);
+        let zoom = self.zoom * (target.zoom / self.zoom).powf(t);
+        let center = center(self).lerp(center(target), t);
+        Camera { offset: center - logical_size / (2.0 * zoom), zoom }
+    }
+
+    // Multiplies zoom by `factor`, keeping the world point under `screen_anchor` fixed
+    pub fn zoom_at(&mut self, screen_anchor: Vec2, factor: f32) {
+        let world_anchor = self.screen_to_world(screen_anchor);
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.offset = world_anchor - screen_anchor / self.zoom;
+    }
+}
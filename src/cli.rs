@@ -0,0 +1,123 @@
+// cli.rs
+
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Usage: rust_visual_mouse_app [diagram.json|.dot|.csv] [--config config.toml]
+                             [--export svg|png|dot|csv|adjacency|rust [--out file] [--png-scale 1|2|4]]
+                             [--record session.json | --replay session.json [--headless]]
+                             [--stress shape_count] [--readonly]
+                             [--host [address:]port | --join address:port]";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Svg,
+    Png,
+    Dot,
+    Csv, // Connections as from_label,to_label,edge_label rows
+    Adjacency, // Shapes as a JSON adjacency list
+    Rust, // The diagram as a state machine: State and Event enums and a transition match
+}
+
+impl ExportFormat {
+    fn parse(name: &str) -> Option<ExportFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "svg" => Some(ExportFormat::Svg),
+            "png" => Some(ExportFormat::Png),
+            "dot" => Some(ExportFormat::Dot),
+            "csv" => Some(ExportFormat::Csv),
+            "adjacency" => Some(ExportFormat::Adjacency),
+            "rust" => Some(ExportFormat::Rust),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Svg => "svg",
+            ExportFormat::Png => "png",
+            ExportFormat::Dot => "dot",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Adjacency => "adjacency.json",
+            ExportFormat::Rust => "rs",
+        }
+    }
+}
+
+// --- Parsed command line ---
+#[derive(Debug, Default)]
+pub struct CliArgs {
+    pub diagram_path: Option<PathBuf>,
+    pub config_path: Option<PathBuf>, // Read over the per-user and working-directory config.toml
+    pub export: Option<ExportFormat>, // Export and exit instead of opening the editor
+    pub out_path: Option<PathBuf>, // Defaults to the diagram path with the format's extension
+    pub png_scale: Option<u32>,
+    pub record_path: Option<PathBuf>, // Input events are written here as the editor runs
+    pub replay_path: Option<PathBuf>, // Recorded input fed back instead of live input
+    pub headless: bool, // Replay in a hidden window and exit when done
+    pub stress: Option<usize>, // Open a generated diagram of this many shapes and log frame and hit-test timings
+    pub read_only: bool, // Start in read-only mode: pan, zoom and select, nothing else
+    pub host: Option<String>, // Share the diagram live with editors that join at this address; 127.0.0.1 unless one is given
+    pub join: Option<String>, // Edit the diagram shared by the editor hosting at this address
+}
+
+impl CliArgs {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<CliArgs, String> {
+        let mut cli = CliArgs::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| args.next().ok_or_else(|| format!("{} needs a value", flag));
+            match arg.as_str() {
+                "--config" => cli.config_path = Some(PathBuf::from(value("--config")?)),
+                "--export" => {
+                    let format = value("--export")?;
+                    cli.export = Some(ExportFormat::parse(&format).ok_or_else(|| format!("Unknown export format '{}'", format))?);
+                }
+                "--out" => cli.out_path = Some(PathBuf::from(value("--out")?)),
+                "--png-scale" => {
+                    let scale = value("--png-scale")?;
+                    cli.png_scale = Some(scale.parse().ok().filter(|s| matches!(s, 1 | 2 | 4)).ok_or_else(|| format!("Invalid PNG scale '{}'", scale))?);
+                }
+                "--record" => cli.record_path = Some(PathBuf::from(value("--record")?)),
+                "--replay" => cli.replay_path = Some(PathBuf::from(value("--replay")?)),
+                "--headless" => cli.headless = true,
+                "--readonly" => cli.read_only = true,
+                "--host" => {
+                    let host = value("--host")?;
+                    let (address, port) = host.rsplit_once(':').unwrap_or(("127.0.0.1", &host));
+                    let port: u16 = port.parse().map_err(|_| format!("Invalid port '{}'", port))?;
+                    cli.host = Some(format!("{}:{}", address, port));
+                }
+                "--join" => cli.join = Some(value("--join")?),
+                "--stress" => {
+                    let count = value("--stress")?;
+                    cli.stress = Some(count.parse().ok().filter(|&n| n > 0).ok_or_else(|| format!("Invalid shape count '{}'", count))?);
+                }
+                flag if flag.starts_with("--") => return Err(format!("Unknown option '{}'", flag)),
+                _ if cli.diagram_path.is_some() => return Err(format!("Unexpected argument '{}'", arg)),
+                _ => cli.diagram_path = Some(PathBuf::from(arg)),
+            }
+        }
+        if cli.export.is_none() && (cli.out_path.is_some() || cli.png_scale.is_some()) {
+            return Err(String::from("--out and --png-scale need --export"));
+        }
+        if cli.record_path.is_some() && cli.replay_path.is_some() {
+            return Err(String::from("--record and --replay can't be combined"));
+        }
+        if cli.export.is_some() && (cli.record_path.is_some() || cli.replay_path.is_some()) {
+            return Err(String::from("--record and --replay don't apply to --export"));
+        }
+        if cli.stress.is_some() && (cli.export.is_some() || cli.diagram_path.is_some()) {
+            return Err(String::from("--stress opens its own diagram; it can't be combined with a file or --export"));
+        }
+        if cli.host.is_some() && cli.join.is_some() {
+            return Err(String::from("--host and --join can't be combined"));
+        }
+        if (cli.host.is_some() || cli.join.is_some()) && (cli.export.is_some() || cli.headless) {
+            return Err(String::from("--host and --join need the editor window; they don't apply to --export or --headless"));
+        }
+        if cli.headless && cli.replay_path.is_none() {
+            return Err(String::from("--headless needs --replay"));
+        }
+        Ok(cli)
+    }
+}
@@ -0,0 +1,48 @@
+// clipboard.rs
+
+use ggez::glam::Vec2;
+
+use crate::logging;
+
+// Offset applied to pasted/duplicated shapes so they don't sit exactly on top of the originals
+pub const PASTE_OFFSET: Vec2 = Vec2::new(20.0, 20.0);
+
+// --- OS clipboard, for plain text ---
+// Opened on first use and kept open, since on X11 copied text is only available while it is.
+#[derive(Default)]
+pub struct SystemClipboard {
+    clipboard: Option<arboard::Clipboard>,
+}
+
+impl SystemClipboard {
+    fn open(&mut self) -> Option<&mut arboard::Clipboard> {
+        if self.clipboard.is_none() {
+            match arboard::Clipboard::new() {
+                Ok(clipboard) => self.clipboard = Some(clipboard),
+                Err(e) => {
+                    log::warn!(target: logging::IO, "Could not open the system clipboard: {}", e);
+                    return None;
+                }
+            }
+        }
+        self.clipboard.as_mut()
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        if let Some(clipboard) = self.open() {
+            if let Err(e) = clipboard.set_text(text.to_string()) {
+                log::warn!(target: logging::IO, "Could not copy to the system clipboard: {}", e);
+            }
+        }
+    }
+
+    pub fn text(&mut self) -> Option<String> {
+        match self.open()?.get_text() {
+            Ok(text) => Some(text),
+            Err(e) => {
+                log::warn!(target: logging::IO, "Could not paste from the system clipboard: {}", e);
+                None
+            }
+        }
+    }
+}
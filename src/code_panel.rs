@@ -0,0 +1,159 @@
+// code_panel.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::{self, Canvas, Color, DrawMode, Drawable, Mesh, Rect, Text};
+use ggez::input::keyboard::{KeyCode, KeyMods};
+use ggez::{Context, GameResult};
+
+use crate::clipboard::SystemClipboard;
+use crate::dsl::DslError;
+use crate::text_edit::TextEdit;
+use crate::ui::UiStyle;
+
+const CODE_PANEL_WIDTH: f32 = 320.0;
+const CODE_FONT_SIZE: f32 = 14.0;
+const CODE_LINE_HEIGHT: f32 = 18.0;
+const CODE_PADDING: f32 = 8.0;
+const CODE_CARET_WIDTH: f32 = 1.5;
+const ERROR_COLOR: Color = Color { r: 1.0, g: 0.45, b: 0.4, a: 1.0 };
+
+// --- Diagram-as-code: the diagram as dsl text beside the canvas, editable in place ---
+// While focused, keys go to the text, which is applied to the diagram on Enter and when the panel
+// loses focus; while not, the text follows the diagram.
+#[derive(Default)]
+pub struct CodePanel {
+    pub visible: bool,
+    pub focused: bool,
+    pub edit: TextEdit,
+    pub error: Option<DslError>, // Why the text doesn't parse; the diagram keeps its last good state meanwhile
+}
+
+impl CodePanel {
+    // Docked against `right_edge` (the screen's, or the inspector's left side) at full height, or None while hidden
+    pub fn panel_rect(&self, right_edge: f32, screen_height: f32) -> Option<Rect> {
+        self.visible.then(|| Rect::new(right_edge - CODE_PANEL_WIDTH, 0.0, CODE_PANEL_WIDTH, screen_height))
+    }
+
+    // Replaces the text with the diagram's, unless it is being typed into
+    pub fn follow_diagram(&mut self, text: String) {
+        if self.focused || self.edit.text == text { return; }
+        self.edit = TextEdit::new(text);
+        self.error = None;
+    }
+
+    // Flags the line that doesn't parse, without touching the diagram
+    pub fn check_text(&mut self) {
+        self.error = crate::dsl::parse(&self.edit.text).err();
+    }
+
+    fn visible_rows(panel: Rect) -> usize {
+        (((panel.h - CODE_PADDING * 2.0 - CODE_LINE_HEIGHT) / CODE_LINE_HEIGHT).floor() as usize).max(1) // Last row holds the error
+    }
+
+    // First line shown: the view scrolls just far enough to keep the caret's line in it
+    fn first_visible_line(&self, panel: Rect) -> usize {
+        self.edit.line_and_column().0.saturating_sub(Self::visible_rows(panel) - 1)
+    }
+
+    fn text_width(ctx: &Context, text: &str) -> f32 {
+        let mut text_obj = Text::new(text);
+        text_obj.set_scale(CODE_FONT_SIZE);
+        text_obj.dimensions(ctx).map_or(0.0, |dimensions| dimensions.w)
+    }
+
+    // Places the caret at the char nearest a click
+    pub fn click(&mut self, ctx: &Context, panel: Rect, pos: Vec2, extend_selection: bool) {
+        let line = self.first_visible_line(panel) + ((pos.y - panel.y - CODE_PADDING).max(0.0) / CODE_LINE_HEIGHT) as usize;
+        let text = self.edit.text.split('\n').nth(line).unwrap_or("");
+        let x = pos.x - panel.x - CODE_PADDING;
+        let column = (0..=text.chars().count())
+            .min_by(|&a, &b| {
+                let width = |column: usize| (Self::text_width(ctx, &text.chars().take(column).collect::<String>()) - x).abs();
+                width(a).total_cmp(&width(b))
+            })
+            .unwrap_or(0);
+        self.edit.move_to(self.edit.index_at(line, column), extend_selection);
+        self.focused = true;
+    }
+
+    // Editing keys while focused; returns whether the text changed
+    pub fn key(&mut self, keycode: KeyCode, mods: KeyMods, clipboard: &mut SystemClipboard) -> bool {
+        let (shift, ctrl) = (mods.contains(KeyMods::SHIFT), mods.contains(KeyMods::CTRL));
+        let before = self.edit.text.clone();
+        let edit = &mut self.edit;
+        match keycode {
+            KeyCode::Escape => self.focused = false,
+            KeyCode::Return | KeyCode::NumpadEnter => edit.insert_char('\n'),
+            KeyCode::Tab => edit.insert_str("    "),
+            KeyCode::Back if ctrl => edit.delete_word_backward(),
+            KeyCode::Back => edit.backspace(),
+            KeyCode::Delete if ctrl => edit.delete_word_forward(),
+            KeyCode::Delete => edit.delete_forward(),
+            KeyCode::Left if ctrl => edit.move_word_left(shift),
+            KeyCode::Left => edit.move_left(shift),
+            KeyCode::Right if ctrl => edit.move_word_right(shift),
+            KeyCode::Right => edit.move_right(shift),
+            KeyCode::Up => edit.move_line_up(shift),
+            KeyCode::Down => edit.move_line_down(shift),
+            KeyCode::Home => edit.home(shift),
+            KeyCode::End => edit.end(shift),
+            KeyCode::A if ctrl => edit.select_all(),
+            KeyCode::C | KeyCode::X if ctrl => {
+                if let Some(selected) = edit.selected_text() {
+                    clipboard.set_text(selected);
+                    if keycode == KeyCode::X { edit.backspace(); }
+                }
+            }
+            KeyCode::V if ctrl => {
+                if let Some(pasted) = clipboard.text() { edit.insert_str(&pasted); }
+            }
+            _ => {}
+        }
+        self.edit.text != before
+    }
+
+    pub fn draw(&self, ctx: &mut Context, canvas: &mut Canvas, panel: Rect, style: &UiStyle) -> GameResult {
+        let background = Mesh::new_rectangle(ctx, DrawMode::fill(), panel, style.panel_color)?;
+        canvas.draw(&background, graphics::DrawParam::default());
+        if self.focused {
+            let border = Mesh::new_rectangle(ctx, DrawMode::stroke(1.0), panel, style.focus_color)?;
+            canvas.draw(&border, graphics::DrawParam::default());
+        }
+        let first = self.first_visible_line(panel);
+        let (caret_line, caret_column) = self.edit.line_and_column();
+        let selection = self.edit.selection();
+        let mut line_start = 0; // Char index of each line's first char
+        for (number, line) in self.edit.text.split('\n').enumerate() {
+            let length = line.chars().count();
+            let row = number as isize - first as isize;
+            if row >= Self::visible_rows(panel) as isize { break; }
+            if row >= 0 {
+                let origin = Vec2::new(panel.x + CODE_PADDING, panel.y + CODE_PADDING + row as f32 * CODE_LINE_HEIGHT);
+                let prefix_width = |column: usize| Self::text_width(ctx, &line.chars().take(column).collect::<String>());
+                if let Some((start, end)) = selection.filter(|&(start, end)| start <= line_start + length && end > line_start) {
+                    let (from, to) = (start.saturating_sub(line_start), (end - line_start).min(length));
+                    let highlight = Rect::new(origin.x + prefix_width(from), origin.y, (prefix_width(to) - prefix_width(from)).max(4.0), CODE_LINE_HEIGHT);
+                    let mesh = Mesh::new_rectangle(ctx, DrawMode::fill(), highlight, Color::from_rgba(255, 255, 255, 60))?;
+                    canvas.draw(&mesh, graphics::DrawParam::default());
+                }
+                let color = if self.error.as_ref().is_some_and(|e| e.line == number + 1) { ERROR_COLOR } else { style.text_color };
+                let mut text = Text::new(line);
+                text.set_scale(CODE_FONT_SIZE);
+                canvas.draw(&text, graphics::DrawParam::default().dest(origin).color(color));
+                if self.focused && number == caret_line {
+                    let x = origin.x + prefix_width(caret_column);
+                    let caret = Mesh::new_line(ctx, &[Vec2::new(x, origin.y), Vec2::new(x, origin.y + CODE_LINE_HEIGHT)], CODE_CARET_WIDTH, style.focus_color)?;
+                    canvas.draw(&caret, graphics::DrawParam::default());
+                }
+            }
+            line_start += length + 1;
+        }
+        if let Some(error) = &self.error {
+            let mut text = Text::new(format!("Line {}: {}", error.line, error.message));
+            text.set_scale(CODE_FONT_SIZE);
+            let dest = Vec2::new(panel.x + CODE_PADDING, panel.bottom() - CODE_PADDING - CODE_LINE_HEIGHT);
+            canvas.draw(&text, graphics::DrawParam::default().dest(dest).color(ERROR_COLOR));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,523 @@
+// collab.rs
+// Live collaboration: editors sharing one diagram over TCP. One editor hosts (`--host [address:]port`)
+// and the others join it (`--join address:port`); the host relays every message to the other peers.
+// Messages are JSON, one per line. Nothing checks who connects, so a host listens on 127.0.0.1 unless
+// given an address to share on.
+//
+// While shared, every shape and connection has a stable id. The shared diagram is a set of elements
+// (each shape, each connection, and the board: groups, lanes, layers, presentation steps, annotations
+// and the drawing order), each holding its last value stamped with a Lamport version (a counter, ties
+// broken by peer id). An edit sends only the elements it changed, with removed ones as tombstones,
+// and every editor keeps the highest version of each element, so all of them settle on the same
+// diagram. Edits to different elements merge; edits made at the same moment to the same element keep
+// the one with the higher version. Cursors and selections travel alongside and are drawn in each
+// peer's color.
+
+use ggez::glam::Vec2;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::diagram::{Annotation, Group, Lane, Layer, PresentationStep};
+use crate::{logging, Connection, Diagram, ShapeData};
+
+const HOST_PEER: u32 = 0; // Joined peers are numbered from 1 in the order they connect
+const EDIT_SEND_INTERVAL: Duration = Duration::from_millis(100); // Edits go out at most this often, so a drag streams
+const PRESENCE_SEND_INTERVAL: Duration = Duration::from_millis(50);
+const ID_PEER_SHIFT: u32 = 40; // Ids a peer hands out carry its peer id (plus one, so none is 0) above this bit
+
+// Lamport version of an element: (counter, peer that made the edit)
+pub type Version = (u64, u32);
+
+// --- What an edit is about ---
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Element {
+    Shape(u64),
+    Connection(u64),
+    Board, // Everything besides the shapes and connections, shared as one
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub element: Element,
+    pub version: Version,
+    pub value: Option<Value>, // None once removed
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    Welcome { peer: u32 }, // From the host to a peer that just connected: its id
+    Edits { edits: Vec<Edit> },
+    Snapshot { edits: Vec<Edit> }, // Every element, removed ones included: where a newcomer starts from
+    Presence { peer: u32, name: String, cursor: Option<[f32; 2]>, selection: Vec<u64> }, // Cursor in world coordinates, shapes by id
+    Left { peer: u32 },
+}
+
+// A connection as shared: its ends by shape id, since indices differ from editor to editor
+#[derive(Serialize, Deserialize)]
+struct SharedConnection {
+    from: u64,
+    to: u64,
+    connection: Connection,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SharedStep {
+    Shapes(Vec<u64>),
+    Group(usize),
+}
+
+#[derive(Serialize, Deserialize)]
+struct SharedBoard {
+    order: Vec<u64>, // Shape ids, bottom to top
+    groups: Vec<Group>,
+    lanes: Vec<Lane>,
+    layers: Vec<Layer>,
+    steps: Vec<SharedStep>,
+    annotations: Vec<Annotation>,
+}
+
+// What the network threads hand the editor
+pub enum NetEvent {
+    Line(String), // A message from a peer
+    Joined(u32), // Hosting: a peer connected
+    Left(u32), // Hosting: a peer's connection closed
+    Disconnected, // Joined: the connection to the host closed
+}
+
+// What polling the connection asks of the editor
+pub enum Update {
+    Joined(String), // Name of a peer heard from for the first time
+    Left(String), // Name of a peer that left
+    Presence, // A cursor or selection moved
+    Disconnected,
+}
+
+// --- How the others' edits changed the diagram ---
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RemoteChange {
+    Nothing,
+    Contents, // Only values changed; every index still means what it did
+    Structure, // Shapes or connections came or went, or the shapes were restacked
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RemotePeer {
+    pub name: String,
+    pub cursor: Option<Vec2>, // None while they show another diagram
+    pub selection: Vec<u64>, // Shape ids
+}
+
+pub struct Collaboration {
+    pub peer: Option<u32>, // Ours; a joined editor learns it from the host's welcome
+    pub name: String,
+    pub document: PathBuf, // The diagram being shared; others open in the meantime aren't
+    pub peers: BTreeMap<u32, RemotePeer>,
+    shared: BTreeMap<Element, (Version, Option<Value>)>, // The shared diagram as last seen or sent
+    clock: u64, // Highest version counter seen
+    next_id: u64,
+    inbox: Vec<Edit>, // Come in, not yet merged
+    synced: bool, // A joined editor shares nothing until the host's snapshot has arrived
+    detached: bool, // The shared diagram wasn't showing while edits came in; it is rebuilt when it is back
+    snapshot_due: bool, // Hosting: a peer connected and needs the whole diagram
+    shared_at: Option<Instant>,
+    presence: Option<(Option<Vec2>, Vec<u64>)>, // Last sent
+    presence_at: Option<Instant>,
+    outgoing: Sender<String>,
+    incoming: Receiver<NetEvent>,
+}
+
+impl Collaboration {
+    // Talks to the peers through a pair of channels: lines to send, and what came in
+    pub fn over(peer: Option<u32>, name: String, document: PathBuf, outgoing: Sender<String>, incoming: Receiver<NetEvent>) -> Collaboration {
+        Collaboration {
+            peer,
+            name,
+            document,
+            peers: BTreeMap::new(),
+            shared: BTreeMap::new(),
+            clock: 0,
+            next_id: 0,
+            inbox: Vec::new(),
+            synced: peer == Some(HOST_PEER),
+            detached: false,
+            snapshot_due: false,
+            shared_at: None,
+            presence: None,
+            presence_at: None,
+            outgoing,
+            incoming,
+        }
+    }
+
+    // Listens for peers on `address` (e.g. 127.0.0.1:7000); this editor's diagram is the one they all start from
+    pub fn host(address: &str, name: String, document: PathBuf) -> Result<Collaboration, String> {
+        let listener = TcpListener::bind(address).map_err(|e| format!("Could not listen on {}: {}", address, e))?;
+        let (router, incoming) = spawn_router(true);
+        let accepting = router.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if accepting.send(RouterEvent::Connected(stream)).is_err() { break; }
+            }
+        });
+        Ok(Collaboration::over(Some(HOST_PEER), name, document, forward_to(router), incoming))
+    }
+
+    // Connects to a host; its diagram replaces this editor's once it arrives
+    pub fn join(address: &str, name: String, document: PathBuf) -> Result<Collaboration, String> {
+        let stream = TcpStream::connect(address).map_err(|e| format!("Could not connect to {}: {}", address, e))?;
+        let (router, incoming) = spawn_router(false);
+        router.send(RouterEvent::Connected(stream)).map_err(|e| e.to_string())?;
+        Ok(Collaboration::over(None, name, document, forward_to(router), incoming))
+    }
+
+    // Handles what came in since the last call; edits wait for sync_diagram
+    pub fn poll(&mut self) -> Vec<Update> {
+        let mut updates = Vec::new();
+        while let Ok(event) = self.incoming.try_recv() {
+            match event {
+                NetEvent::Line(line) => match serde_json::from_str(&line) {
+                    Ok(message) => updates.extend(self.receive(message)),
+                    Err(e) => log::warn!(target: logging::IO, "Ignoring a malformed collaboration message: {}", e),
+                },
+                NetEvent::Joined(peer) => {
+                    log::info!(target: logging::IO, "Peer {} connected.", peer);
+                    // The newcomer hears the whole diagram, and where everyone is
+                    self.snapshot_due = true;
+                    self.presence = None;
+                }
+                NetEvent::Left(peer) => updates.extend(self.peers.remove(&peer).map(|peer| Update::Left(peer.name))),
+                NetEvent::Disconnected => {
+                    self.peers.clear();
+                    updates.push(Update::Disconnected);
+                }
+            }
+        }
+        updates
+    }
+
+    fn receive(&mut self, message: Message) -> Option<Update> {
+        match message {
+            Message::Welcome { peer } => {
+                self.peer = Some(peer);
+                None
+            }
+            Message::Edits { edits } => {
+                self.inbox.extend(edits);
+                None
+            }
+            Message::Snapshot { edits } => {
+                self.inbox.extend(edits);
+                if !self.synced {
+                    // Our own diagram gives way to the shared one
+                    self.synced = true;
+                    self.detached = true;
+                }
+                None
+            }
+            Message::Presence { peer, name, cursor, selection } => {
+                if Some(peer) == self.peer { return None; }
+                let update = if self.peers.contains_key(&peer) { Update::Presence } else {
+                    self.presence = None; // A newcomer hasn't heard where we are yet
+                    Update::Joined(name.clone())
+                };
+                self.peers.insert(peer, RemotePeer { name, cursor: cursor.map(Vec2::from), selection });
+                Some(update)
+            }
+            Message::Left { peer } => self.peers.remove(&peer).map(|peer| Update::Left(peer.name)),
+        }
+    }
+
+    // Whether enough time has passed since the last send to look for edits to share
+    fn edits_due(&self, now: Instant) -> bool {
+        self.shared_at.is_none_or(|at| now.duration_since(at) >= EDIT_SEND_INTERVAL)
+    }
+
+    // Trades edits with the others: `diagram` is the shared document while it is showing, None
+    // otherwise. Edits made here go out before the ones that came in are applied over them, so a
+    // local edit not yet sent is merged rather than lost.
+    pub fn sync_diagram(&mut self, diagram: Option<&mut Diagram>, now: Instant) -> RemoteChange {
+        let mut change = RemoteChange::Nothing;
+        match diagram {
+            _ if !self.synced || self.peer.is_none() => return change,
+            // Edits to a diagram that isn't showing wait in the shared state until it is back
+            None => {
+                for edit in std::mem::take(&mut self.inbox) { self.merge(edit); }
+                self.detached = true;
+            }
+            Some(diagram) if self.detached => {
+                for edit in std::mem::take(&mut self.inbox) { self.merge(edit); }
+                *diagram = self.shared_diagram();
+                self.detached = false;
+                change = RemoteChange::Structure;
+            }
+            Some(diagram) => {
+                if !self.inbox.is_empty() || self.snapshot_due || self.edits_due(now) { self.share_edits(diagram, now); }
+                let mut inbox = std::mem::take(&mut self.inbox);
+                inbox.sort_by_key(|edit| edit.element); // Shapes before the connections between them
+                for edit in inbox {
+                    let element = edit.element;
+                    if !self.merge(edit) { continue; }
+                    let value = self.shared.get(&element).and_then(|(_, value)| value.as_ref());
+                    change = change.max(apply_element(diagram, element, value));
+                }
+            }
+        }
+        if self.snapshot_due {
+            let edits = self.shared.iter().map(|(&element, (version, value))| Edit { element, version: *version, value: value.clone() }).collect();
+            self.send(&Message::Snapshot { edits });
+            self.snapshot_due = false;
+        }
+        change
+    }
+
+    // Keeps an edit if it is newer than what we have of its element; returns whether it was
+    fn merge(&mut self, edit: Edit) -> bool {
+        self.clock = self.clock.max(edit.version.0);
+        if self.shared.get(&edit.element).is_some_and(|(version, _)| *version >= edit.version) { return false; }
+        self.shared.insert(edit.element, (edit.version, edit.value));
+        true
+    }
+
+    // Sends, under one new version, every element of the diagram that differs from the shared one,
+    // and removals for the ones it no longer has
+    fn share_edits(&mut self, diagram: &mut Diagram, now: Instant) {
+        let Some(peer) = self.peer else { return; };
+        self.shared_at = Some(now);
+        self.identify(diagram);
+        let current = elements(diagram);
+        let changed = current.iter()
+            .filter(|&(element, value)| self.shared.get(element).and_then(|(_, shared)| shared.as_ref()) != Some(value))
+            .map(|(&element, value)| (element, Some(value.clone())));
+        let removed = self.shared.iter()
+            .filter(|&(element, (_, shared))| shared.is_some() && !current.contains_key(element))
+            .map(|(&element, _)| (element, None));
+        let edits: Vec<(Element, Option<Value>)> = changed.chain(removed).collect();
+        if edits.is_empty() { return; }
+        self.clock += 1;
+        let version = (self.clock, peer);
+        let edits: Vec<Edit> = edits.into_iter().map(|(element, value)| Edit { element, version, value }).collect();
+        for edit in &edits { self.shared.insert(edit.element, (version, edit.value.clone())); }
+        self.send(&Message::Edits { edits });
+    }
+
+    // Gives an id to every shape and connection without one: new ones, and copies still carrying
+    // their original's. Returns the shapes' ids.
+    pub fn identify(&mut self, diagram: &mut Diagram) -> Vec<u64> {
+        let Some(peer) = self.peer else { return Vec::new(); };
+        let mut fresh_id = || { self.next_id += 1; (u64::from(peer) + 1) << ID_PEER_SHIFT | self.next_id };
+        let mut seen = HashSet::new();
+        for shape in &mut diagram.shapes {
+            if shape.id == 0 || !seen.insert(shape.id) { shape.id = fresh_id(); seen.insert(shape.id); }
+        }
+        seen.clear();
+        for connection in &mut diagram.connections {
+            if connection.id == 0 || !seen.insert(connection.id) { connection.id = fresh_id(); seen.insert(connection.id); }
+        }
+        diagram.shapes.iter().map(|shape| shape.id).collect()
+    }
+
+    // The shared diagram as it stands
+    fn shared_diagram(&self) -> Diagram {
+        let mut diagram = Diagram::new();
+        for (&element, (_, value)) in &self.shared { apply_element(&mut diagram, element, value.as_ref()); }
+        diagram
+    }
+
+    // Sends our cursor (None while another diagram is showing) and selected shape ids when either changed
+    pub fn share_presence(&mut self, cursor: Option<Vec2>, selection: Vec<u64>, now: Instant) {
+        let Some(peer) = self.peer else { return; };
+        if self.presence.as_ref() == Some(&(cursor, selection.clone())) { return; }
+        if self.presence_at.is_some_and(|at| now.duration_since(at) < PRESENCE_SEND_INTERVAL) { return; }
+        self.send(&Message::Presence { peer, name: self.name.clone(), cursor: cursor.map(|c| c.to_array()), selection: selection.clone() });
+        self.presence = Some((cursor, selection));
+        self.presence_at = Some(now);
+    }
+
+    fn send(&self, message: &Message) {
+        match serde_json::to_string(message) {
+            Ok(line) => { let _ = self.outgoing.send(line); }
+            Err(e) => log::error!(target: logging::IO, "Could not serialize a collaboration message: {}", e),
+        }
+    }
+}
+
+
+// The diagram as shared elements. Connections and steps name shapes by id; indices are left out.
+fn elements(diagram: &Diagram) -> BTreeMap<Element, Value> {
+    let mut elements = BTreeMap::new();
+    let id_of = |index: usize| diagram.shapes.get(index).map(|shape| shape.id);
+    for shape in &diagram.shapes {
+        if let Ok(value) = serde_json::to_value(shape) { elements.insert(Element::Shape(shape.id), value); }
+    }
+    for connection in &diagram.connections {
+        let (Some(from), Some(to)) = (id_of(connection.from_shape_index), id_of(connection.to_shape_index)) else { continue; };
+        let shared = SharedConnection { from, to, connection: Connection { from_shape_index: 0, to_shape_index: 0, ..connection.clone() } };
+        if let Ok(value) = serde_json::to_value(shared) { elements.insert(Element::Connection(connection.id), value); }
+    }
+    let board = SharedBoard {
+        order: diagram.shapes.iter().map(|shape| shape.id).collect(),
+        groups: diagram.groups.clone(),
+        lanes: diagram.lanes.clone(),
+        layers: diagram.layers.clone(),
+        steps: diagram.steps.iter().map(|step| match step {
+            PresentationStep::Shapes(shapes) => SharedStep::Shapes(shapes.iter().filter_map(|&index| id_of(index)).collect()),
+            PresentationStep::Group(group) => SharedStep::Group(*group),
+        }).collect(),
+        annotations: diagram.annotations.clone(),
+    };
+    if let Ok(value) = serde_json::to_value(board) { elements.insert(Element::Board, value); }
+    elements
+}
+
+// Brings one element of the diagram in line with its shared value (None: removed)
+fn apply_element(diagram: &mut Diagram, element: Element, value: Option<&Value>) -> RemoteChange {
+    let index_of = |diagram: &Diagram, id: u64| diagram.shapes.iter().position(|shape| shape.id == id);
+    match element {
+        Element::Shape(id) => {
+            let existing = index_of(diagram, id);
+            let shape = value.and_then(parse::<ShapeData>).map(|shape| ShapeData { id, ..shape });
+            match (existing, shape) {
+                (Some(index), Some(shape)) => { diagram.shapes[index] = shape; RemoteChange::Contents }
+                (None, Some(shape)) => { diagram.add_shape(shape); RemoteChange::Structure }
+                (Some(index), None) => { diagram.delete_shape(index); RemoteChange::Structure }
+                (None, None) => RemoteChange::Nothing,
+            }
+        }
+        Element::Connection(id) => {
+            let existing = diagram.connections.iter().position(|connection| connection.id == id);
+            // Shown only while both its shapes are
+            let connection = value.and_then(parse::<SharedConnection>).and_then(|shared| Some(Connection {
+                from_shape_index: index_of(diagram, shared.from)?,
+                to_shape_index: index_of(diagram, shared.to)?,
+                id,
+                ..shared.connection
+            }));
+            match (existing, connection) {
+                (Some(index), Some(connection)) if diagram.connections[index].same_endpoints(&connection) => { diagram.connections[index] = connection; RemoteChange::Contents }
+                (Some(index), Some(connection)) => { diagram.connections[index] = connection; RemoteChange::Structure }
+                (None, Some(connection)) => { diagram.connections.push(connection); RemoteChange::Structure }
+                (Some(index), None) => { diagram.disconnect(index); RemoteChange::Structure }
+                (None, None) => RemoteChange::Nothing,
+            }
+        }
+        Element::Board => {
+            let Some(board) = value.and_then(parse::<SharedBoard>) else { return RemoteChange::Nothing; };
+            // Shapes the board doesn't know yet stay on top, in the order they have
+            let mut order: Vec<u64> = board.order.iter().copied().filter(|&id| index_of(diagram, id).is_some()).collect();
+            order.extend(diagram.shapes.iter().map(|shape| shape.id).filter(|id| !board.order.contains(id)));
+            let mut change = RemoteChange::Contents;
+            for (position, &id) in order.iter().enumerate() {
+                let Some(index) = index_of(diagram, id).filter(|&index| index != position) else { continue; };
+                diagram.restack_shape(index, position);
+                change = RemoteChange::Structure;
+            }
+            diagram.groups = board.groups;
+            diagram.lanes = board.lanes;
+            diagram.layers = board.layers;
+            diagram.steps = board.steps.into_iter().map(|step| match step {
+                SharedStep::Shapes(ids) => PresentationStep::Shapes(ids.into_iter().filter_map(|id| index_of(diagram, id)).collect()),
+                SharedStep::Group(group) => PresentationStep::Group(group),
+            }).collect();
+            diagram.annotations = board.annotations;
+            change
+        }
+    }
+}
+
+fn parse<T: serde::de::DeserializeOwned>(value: &Value) -> Option<T> {
+    serde_json::from_value(value.clone()).map_err(|e| log::warn!(target: logging::IO, "Ignoring a shared edit: {}", e)).ok()
+}
+
+// --- Network threads: one router per editor, plus a reader per connection ---
+enum RouterEvent {
+    Connected(TcpStream),
+    Line(u32, String), // Read from the connection with this id
+    Closed(u32),
+    Send(String), // From this editor, to every connection
+}
+
+// Hands lines the editor sends to the router
+fn forward_to(router: Sender<RouterEvent>) -> Sender<String> {
+    let (outgoing, lines) = mpsc::channel::<String>();
+    thread::spawn(move || {
+        for line in lines {
+            if router.send(RouterEvent::Send(line)).is_err() { break; }
+        }
+    });
+    outgoing
+}
+
+// The router owns every connection. A line read from one goes to the editor and, when hosting, out
+// to all the others; a host numbers each connection and tells its peer the number as its id.
+fn spawn_router(hosting: bool) -> (Sender<RouterEvent>, Receiver<NetEvent>) {
+    let (router, events) = mpsc::channel();
+    let (deliver, incoming) = mpsc::channel();
+    let readers = router.clone();
+    thread::spawn(move || {
+        let mut connections: BTreeMap<u32, TcpStream> = BTreeMap::new();
+        let mut next_id = HOST_PEER + 1;
+        for event in events {
+            let delivered = match event {
+                RouterEvent::Connected(stream) => {
+                    let id = next_id;
+                    next_id += 1;
+                    match stream.try_clone() {
+                        Ok(reader) => spawn_reader(reader, id, readers.clone()),
+                        Err(e) => { log::error!(target: logging::IO, "Could not read from a peer: {}", e); continue; }
+                    }
+                    connections.insert(id, stream);
+                    if !hosting { continue; }
+                    if let Ok(welcome) = serde_json::to_string(&Message::Welcome { peer: id }) { write_line(&mut connections, id, &welcome); }
+                    NetEvent::Joined(id)
+                }
+                RouterEvent::Line(id, line) => {
+                    if hosting {
+                        for other in connections.keys().copied().filter(|&other| other != id).collect::<Vec<_>>() { write_line(&mut connections, other, &line); }
+                    }
+                    NetEvent::Line(line)
+                }
+                RouterEvent::Closed(id) => {
+                    if connections.remove(&id).is_none() { continue; }
+                    if !hosting { NetEvent::Disconnected } else {
+                        if let Ok(left) = serde_json::to_string(&Message::Left { peer: id }) {
+                            for other in connections.keys().copied().collect::<Vec<_>>() { write_line(&mut connections, other, &left); }
+                        }
+                        NetEvent::Left(id)
+                    }
+                }
+                RouterEvent::Send(line) => {
+                    for id in connections.keys().copied().collect::<Vec<_>>() { write_line(&mut connections, id, &line); }
+                    continue;
+                }
+            };
+            if deliver.send(delivered).is_err() { break; }
+        }
+    });
+    (router, incoming)
+}
+
+// A connection that can't be written to is shut down; its reader then reports it closed
+fn write_line(connections: &mut BTreeMap<u32, TcpStream>, id: u32, line: &str) {
+    let Some(stream) = connections.get_mut(&id) else { return; };
+    if writeln!(stream, "{}", line).is_err() { let _ = stream.shutdown(std::net::Shutdown::Both); }
+}
+
+fn spawn_reader(stream: TcpStream, id: u32, router: Sender<RouterEvent>) {
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else { break; };
+            if router.send(RouterEvent::Line(id, line)).is_err() { return; }
+        }
+        let _ = router.send(RouterEvent::Closed(id));
+    });
+}
@@ -0,0 +1,397 @@
+// commands.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::Rect;
+use ggez::input::keyboard::{KeyCode, KeyMods};
+
+use crate::tools::Tool;
+use crate::ui::{self, ListOverlay, Widget};
+
+const COMMAND_PALETTE_WIDTH: f32 = 420.0;
+const COMMAND_PALETTE_TOP: f32 = 60.0;
+const COMMAND_PALETTE_ROWS: usize = 12; // Matches listed at once; the list scrolls with the selection
+const CONTEXT_MENU_WIDTH: f32 = 240.0;
+
+// --- Every action the editor offers ---
+// Keyboard shortcuts, the toolbar and the command palette all run these through AppState::run_command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Save,
+    Open,
+    OpenRecent,
+    NewTab,
+    CloseTab,
+    NextTab,
+    PreviousTab,
+    ExportSvg,
+    ExportDot,
+    ExportPng,
+    ExportCsv,
+    ExportAdjacency,
+    ExportRust,
+    ImportCsv,
+    Undo,
+    Redo,
+    Copy,
+    Cut,
+    Paste,
+    Duplicate,
+    Delete,
+    SpliceOut, // Delete the selected shape, joining its incoming connections to its outgoing ones
+    Rename,
+    CycleShapeKind,
+    BringToFront,
+    SendToBack,
+    BringForward,
+    SendBackward,
+    ToggleRouting,
+    ReverseConnector,
+    SplitConnector,
+    Layout,
+    LayoutInstant,
+    Group,
+    Ungroup,
+    AddNote,
+    OpenSubdiagram,
+    ParentDiagram,
+    AddLane,
+    AddVerticalLane,
+    ToggleGrid,
+    CycleTheme,
+    ToggleFlowAnimation,
+    ToggleBridges,
+    ToggleInspector,
+    ToggleLayers,
+    ToggleStats,
+    ToggleCodePanel,
+    ToggleDebugOverlay,
+    ToggleReadOnly,
+    Simulate,
+    AddPresentationStep,
+    ClearPresentationSteps,
+    ToggleAnnotations,
+    ClearAnnotations,
+    AddLayer,
+    MoveToActiveLayer,
+    ZoomToFit,
+    ZoomToSelection,
+    Find,
+    ToggleFocus,
+    CycleReachHighlight,
+    ToggleOrderBadges,
+    ToggleCriticalPath,
+    UseTool(Tool),
+    RunScript(usize), // Index into the scripts found in the scripts directories
+    OpenPalette,
+}
+
+impl Command {
+    // Everything listed in the command palette, in the order shown for an empty query
+    pub fn all() -> Vec<Command> {
+        let mut commands = vec![
+            Command::Save, Command::Open, Command::OpenRecent, Command::ExportSvg, Command::ExportDot, Command::ExportPng, Command::ExportCsv, Command::ExportAdjacency, Command::ExportRust, Command::ImportCsv,
+            Command::NewTab, Command::CloseTab, Command::NextTab, Command::PreviousTab,
+            Command::Undo, Command::Redo, Command::Copy, Command::Cut, Command::Paste, Command::Duplicate, Command::Delete, Command::SpliceOut,
+            Command::Rename, Command::CycleShapeKind,
+            Command::BringToFront, Command::SendToBack, Command::BringForward, Command::SendBackward,
+            Command::ToggleRouting, Command::ReverseConnector, Command::SplitConnector,
+            Command::Layout, Command::LayoutInstant, Command::Group, Command::Ungroup,
+            Command::AddNote, Command::OpenSubdiagram, Command::ParentDiagram, Command::AddLane, Command::AddVerticalLane,
+            Command::ToggleGrid, Command::CycleTheme, Command::ToggleFlowAnimation, Command::ToggleBridges, Command::ToggleInspector, Command::ToggleLayers, Command::ToggleStats, Command::ToggleCodePanel, Command::ToggleDebugOverlay, Command::ToggleReadOnly, Command::Simulate, Command::AddPresentationStep, Command::ClearPresentationSteps, Command::ToggleAnnotations, Command::ClearAnnotations, Command::AddLayer, Command::MoveToActiveLayer,
+            Command::ZoomToFit, Command::ZoomToSelection,
+            Command::Find, Command::ToggleFocus, Command::CycleReachHighlight, Command::ToggleOrderBadges, Command::ToggleCriticalPath,
+        ];
+        commands.extend(Tool::ALL.map(Command::UseTool));
+        commands
+    }
+
+    pub fn name(self) -> String {
+        let name = match self {
+            Command::Save => "Save diagram",
+            Command::Open => "Open diagram",
+            Command::OpenRecent => "Open recent diagram",
+            Command::NewTab => "New tab",
+            Command::CloseTab => "Close tab",
+            Command::NextTab => "Next tab",
+            Command::PreviousTab => "Previous tab",
+            Command::ExportSvg => "Export SVG",
+            Command::ExportDot => "Export Graphviz DOT",
+            Command::ExportPng => "Export PNG",
+            Command::ExportCsv => "Export connections as CSV",
+            Command::ExportAdjacency => "Export JSON adjacency list",
+            Command::ExportRust => "Export as a Rust state machine",
+            Command::ImportCsv => "Import CSV edges into this diagram",
+            Command::Undo => "Undo",
+            Command::Redo => "Redo",
+            Command::Copy => "Copy",
+            Command::Cut => "Cut",
+            Command::Paste => "Paste",
+            Command::Duplicate => "Duplicate selection",
+            Command::Delete => "Delete selection",
+            Command::SpliceOut => "Delete shape, keeping its chain connected",
+            Command::Rename => "Rename shape",
+            Command::CycleShapeKind => "Cycle shape kind",
+            Command::BringToFront => "Bring to front",
+            Command::SendToBack => "Send to back",
+            Command::BringForward => "Bring forward",
+            Command::SendBackward => "Send backward",
+            Command::ToggleRouting => "Toggle connector routing",
+            Command::ReverseConnector => "Reverse connector",
+            Command::SplitConnector => "Split connector with a new shape",
+            Command::Layout => "Auto layout",
+            Command::LayoutInstant => "Auto layout without animation",
+            Command::Group => "Group selection",
+            Command::Ungroup => "Ungroup",
+            Command::AddNote => "Add a sticky note at the pointer",
+            Command::OpenSubdiagram => "Open the selected shape's sub-diagram (Ctrl+double-click), creating it if needed",
+            Command::ParentDiagram => "Back up to the diagram this one was opened from",
+            Command::AddLane => "Add horizontal swimlane",
+            Command::AddVerticalLane => "Add vertical swimlane",
+            Command::ToggleGrid => "Toggle grid",
+            Command::CycleTheme => "Cycle color theme",
+            Command::ToggleFlowAnimation => "Toggle flow animation",
+            Command::ToggleBridges => "Toggle connector bridges at crossings",
+            Command::ToggleInspector => "Toggle inspector",
+            Command::ToggleLayers => "Toggle layers panel",
+            Command::ToggleStats => "Toggle graph statistics",
+            Command::ToggleCodePanel => "Toggle diagram-as-code panel",
+            Command::ToggleDebugOverlay => "Toggle debug overlay (FPS and frame times)",
+            Command::ToggleReadOnly => "Toggle read-only mode (pan, zoom and select only; panels hidden)",
+            Command::Simulate => "Simulate: walk a token through the diagram (Space to step)",
+            Command::AddPresentationStep => "Add selection as a presentation step (Left/Right in read-only mode)",
+            Command::ClearPresentationSteps => "Clear presentation steps",
+            Command::ToggleAnnotations => "Show or hide annotations (pen and highlighter strokes)",
+            Command::ClearAnnotations => "Clear all annotations",
+            Command::AddLayer => "Add layer",
+            Command::MoveToActiveLayer => "Move selection to active layer",
+            Command::ZoomToFit => "Zoom to fit",
+            Command::ZoomToSelection => "Zoom to selection",
+            Command::Find => "Find shape by text",
+            Command::ToggleFocus => "Dim shapes not matching a search (toggle)",
+            Command::CycleReachHighlight => "Highlight what the selected shape reaches (downstream, upstream, both, off)",
+            Command::ToggleOrderBadges => "Toggle topological order badges",
+            Command::ToggleCriticalPath => "Toggle critical path highlight",
+            Command::UseTool(tool) => return format!("Tool: {}", tool.name()),
+            Command::RunScript(index) => return format!("Script {}", index + 1),
+            Command::OpenPalette => "Command palette",
+        };
+        name.to_string()
+    }
+
+    // The command bound to a key press, if any. Modifiers must match exactly.
+    pub fn from_key(keycode: KeyCode, mods: KeyMods) -> Option<Command> {
+        let mods = mods & (KeyMods::CTRL | KeyMods::SHIFT | KeyMods::ALT);
+        key_bindings().into_iter().find(|&(_, key, binding_mods)| key == keycode && binding_mods == mods).map(|(command, _, _)| command)
+    }
+
+    // Its first key binding written out, e.g. "Ctrl+Shift+E"; empty when it has none
+    pub fn shortcut(self) -> String {
+        let Some((_, keycode, mods)) = key_bindings().into_iter().find(|&(command, _, _)| command == self) else {
+            return String::new();
+        };
+        let mut shortcut = String::new();
+        if mods.contains(KeyMods::CTRL) { shortcut.push_str("Ctrl+"); }
+        if mods.contains(KeyMods::ALT) { shortcut.push_str("Alt+"); }
+        if mods.contains(KeyMods::SHIFT) { shortcut.push_str("Shift+"); }
+        shortcut.push_str(&format!("{:?}", keycode));
+        shortcut
+    }
+}
+
+fn key_bindings() -> Vec<(Command, KeyCode, KeyMods)> {
+    let (none, ctrl, shift) = (KeyMods::empty(), KeyMods::CTRL, KeyMods::SHIFT);
+    let mut bindings = vec![
+        (Command::Save, KeyCode::S, ctrl),
+        (Command::Open, KeyCode::O, ctrl),
+        (Command::OpenRecent, KeyCode::O, ctrl | shift),
+        (Command::NewTab, KeyCode::T, ctrl),
+        (Command::CloseTab, KeyCode::W, ctrl),
+        (Command::NextTab, KeyCode::Tab, ctrl),
+        (Command::PreviousTab, KeyCode::Tab, ctrl | shift),
+        (Command::ExportSvg, KeyCode::E, ctrl),
+        (Command::ExportDot, KeyCode::E, ctrl | shift),
+        (Command::ExportPng, KeyCode::P, ctrl),
+        (Command::Undo, KeyCode::Z, ctrl),
+        (Command::Redo, KeyCode::Y, ctrl),
+        (Command::Redo, KeyCode::Z, ctrl | shift),
+        (Command::Copy, KeyCode::C, ctrl),
+        (Command::Cut, KeyCode::X, ctrl),
+        (Command::Paste, KeyCode::V, ctrl),
+        (Command::Duplicate, KeyCode::D, ctrl),
+        (Command::Delete, KeyCode::Delete, none),
+        (Command::Delete, KeyCode::Back, none),
+        (Command::SpliceOut, KeyCode::Delete, shift),
+        (Command::SpliceOut, KeyCode::Back, shift),
+        (Command::Rename, KeyCode::F2, none),
+        (Command::CycleShapeKind, KeyCode::K, none),
+        (Command::BringToFront, KeyCode::PageUp, none),
+        (Command::SendToBack, KeyCode::PageDown, none),
+        (Command::BringForward, KeyCode::PageUp, shift),
+        (Command::SendBackward, KeyCode::PageDown, shift),
+        (Command::ToggleRouting, KeyCode::R, ctrl),
+        (Command::ReverseConnector, KeyCode::R, none),
+        (Command::SplitConnector, KeyCode::Insert, none),
+        (Command::Layout, KeyCode::L, ctrl),
+        (Command::LayoutInstant, KeyCode::L, ctrl | shift),
+        (Command::Group, KeyCode::G, ctrl),
+        (Command::Ungroup, KeyCode::G, ctrl | shift),
+        (Command::AddNote, KeyCode::N, shift),
+        (Command::OpenSubdiagram, KeyCode::Return, ctrl),
+        (Command::ParentDiagram, KeyCode::Up, ctrl),
+        (Command::AddLane, KeyCode::W, none),
+        (Command::AddVerticalLane, KeyCode::W, shift),
+        (Command::ToggleGrid, KeyCode::G, none),
+        (Command::CycleTheme, KeyCode::T, none),
+        (Command::ToggleFlowAnimation, KeyCode::A, none),
+        (Command::ToggleBridges, KeyCode::B, none),
+        (Command::ToggleInspector, KeyCode::I, none),
+        (Command::ToggleLayers, KeyCode::L, none),
+        (Command::ToggleStats, KeyCode::S, none),
+        (Command::ToggleCodePanel, KeyCode::D, ctrl | shift),
+        (Command::ToggleDebugOverlay, KeyCode::F3, none),
+        (Command::ToggleReadOnly, KeyCode::F5, none),
+        (Command::Simulate, KeyCode::F6, none),
+        (Command::ToggleAnnotations, KeyCode::P, shift),
+        (Command::MoveToActiveLayer, KeyCode::M, none),
+        (Command::ZoomToFit, KeyCode::F, none),
+        (Command::ZoomToSelection, KeyCode::F, shift),
+        (Command::Find, KeyCode::F, ctrl),
+        (Command::ToggleFocus, KeyCode::F, ctrl | shift),
+        (Command::CycleReachHighlight, KeyCode::U, none),
+        (Command::ToggleOrderBadges, KeyCode::O, none),
+        (Command::ToggleCriticalPath, KeyCode::O, shift),
+        (Command::OpenPalette, KeyCode::P, ctrl | shift),
+    ];
+    bindings.extend(Tool::ALL.map(|tool| (Command::UseTool(tool), tool.shortcut(), none)));
+    bindings
+}
+
+// How well `query` matches `candidate`, or None if it doesn't: the query's characters must appear
+// in order (case and spaces ignored). Runs of adjacent characters and word starts score higher.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i32> {
+    let candidate: Vec<char> = candidate.chars().flat_map(char::to_lowercase).collect();
+    let mut score = 0;
+    let mut next = 0; // Where to look for the next query character
+    let mut previous_match: Option<usize> = None;
+    for wanted in query.chars().flat_map(char::to_lowercase).filter(|c| !c.is_whitespace()) {
+        let found = next + candidate.get(next..)?.iter().position(|&c| c == wanted)?;
+        score += 1;
+        if previous_match.is_some_and(|previous| previous + 1 == found) { score += 5; }
+        if found == 0 || !candidate[found - 1].is_alphanumeric() { score += 3; }
+        previous_match = Some(found);
+        next = found + 1;
+    }
+    Some(score)
+}
+
+// --- Ctrl+Shift+P overlay: type to filter the commands, Up/Down to choose, Enter to run ---
+#[derive(Default)]
+pub struct CommandPalette {
+    pub query: String,
+    list: ListOverlay, // Over matches()
+    script_titles: Vec<String>, // Listed after the built-in commands as Command::RunScript
+}
+
+impl CommandPalette {
+    pub fn with_scripts(script_titles: Vec<String>) -> CommandPalette {
+        CommandPalette { script_titles, ..CommandPalette::default() }
+    }
+
+    fn title(&self, command: Command) -> String {
+        match command {
+            Command::RunScript(index) => format!("Script: {}", self.script_titles.get(index).map_or("?", String::as_str)),
+            _ => command.name(),
+        }
+    }
+
+    // Commands matching the query, best first; ties keep the order of Command::all, then the scripts
+    pub fn matches(&self) -> Vec<Command> {
+        let scripts = (0..self.script_titles.len()).map(Command::RunScript);
+        let mut scored: Vec<(i32, Command)> = Command::all().into_iter().chain(scripts)
+            .filter_map(|command| Some((fuzzy_score(&self.query, &self.title(command))?, command)))
+            .collect();
+        scored.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+        scored.into_iter().map(|(_, command)| command).collect()
+    }
+
+    pub fn chosen(&self) -> Option<Command> {
+        self.matches().get(self.list.selected()).copied()
+    }
+
+    pub fn type_char(&mut self, character: char) {
+        self.query.push(character);
+        self.list = ListOverlay::default();
+    }
+
+    pub fn backspace(&mut self) {
+        self.query.pop();
+        self.list = ListOverlay::default();
+    }
+
+    // Moves the highlight up (negative) or down, wrapping around the list
+    pub fn move_selection(&mut self, delta: isize) {
+        self.list.move_selection(self.matches().len(), delta);
+    }
+
+    // Panel centered near the top of the screen: the query, then one button per visible match.
+    // Button ids are indices into matches().
+    pub fn widgets(&self, screen_size: Vec2) -> (Rect, Vec<Widget<usize>>) {
+        let items = self.matches().into_iter().map(|command| {
+            let shortcut = command.shortcut();
+            if shortcut.is_empty() { self.title(command) } else { format!("{}   ({})", self.title(command), shortcut) }
+        }).collect();
+        let width = COMMAND_PALETTE_WIDTH.min(screen_size.x);
+        let origin = ui::centered_origin(screen_size, width, COMMAND_PALETTE_TOP);
+        self.list.widgets(origin, width, COMMAND_PALETTE_ROWS, vec![format!("> {}|", self.query)], items)
+    }
+
+    // Makes the clicked match the chosen one
+    pub fn select(&mut self, index: usize) {
+        self.list.select(index);
+    }
+}
+
+// --- Long-press menu: the commands that act on what's under the finger, listed where it was held ---
+pub struct ContextMenu {
+    pub position: Vec2, // Screen position the menu hangs from
+    pub commands: Vec<Command>,
+    list: ListOverlay, // Over `commands`
+}
+
+impl ContextMenu {
+    // Commands for a selected shape, a selected connector, or the empty canvas when neither is
+    pub fn new(position: Vec2, shape_selected: bool, connector_selected: bool) -> ContextMenu {
+        let commands = if shape_selected {
+            vec![Command::Rename, Command::CycleShapeKind, Command::Copy, Command::Cut, Command::Duplicate, Command::BringToFront, Command::SendToBack, Command::Delete]
+        } else if connector_selected {
+            vec![Command::ReverseConnector, Command::SplitConnector, Command::ToggleRouting, Command::Delete]
+        } else {
+            vec![Command::Paste, Command::AddNote, Command::Layout, Command::ZoomToFit, Command::OpenPalette]
+        };
+        ContextMenu { position, commands, list: ListOverlay::default() }
+    }
+
+    pub fn chosen(&self) -> Option<Command> {
+        self.commands.get(self.list.selected()).copied()
+    }
+
+    // Moves the highlight up (negative) or down, wrapping around the list
+    pub fn move_selection(&mut self, delta: isize) {
+        self.list.move_selection(self.commands.len(), delta);
+    }
+
+    // Makes the clicked command the chosen one
+    pub fn select(&mut self, index: usize) {
+        self.list.select(index);
+    }
+
+    // Panel below and right of the press, pushed back on screen near the edges. Button ids are
+    // indices into `commands`.
+    pub fn widgets(&self, screen_size: Vec2) -> (Rect, Vec<Widget<usize>>) {
+        let width = CONTEXT_MENU_WIDTH.min(screen_size.x);
+        let height = ui::panel_height(self.commands.len());
+        let origin = self.position.min(screen_size - Vec2::new(width, height)).max(Vec2::ZERO);
+        let items = self.commands.iter().map(|command| command.name()).collect();
+        self.list.widgets(origin, width, self.commands.len(), Vec::new(), items)
+    }
+}
@@ -0,0 +1,66 @@
+// config.rs
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+const CONFIG_DIR_NAME: &str = "rust_visual_mouse_app";
+
+// --- Where settings come from, lowest priority first ---
+// The built-in defaults, then the per-user config, then config.toml in the working directory, then
+// the file given with --config. Each file only needs the keys it changes; tables merge key by key
+// and anything else (numbers, colors, lists) is replaced whole.
+pub fn layer_paths(explicit: Option<&Path>) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = user_config_path().into_iter().collect();
+    paths.push(PathBuf::from(CONFIG_FILE_NAME));
+    paths.extend(explicit.map(Path::to_path_buf));
+    paths
+}
+
+// $XDG_CONFIG_HOME, else ~/.config; %APPDATA% on Windows, ~/Library/Application Support on macOS
+pub fn user_config_path() -> Option<PathBuf> {
+    let home = || env::var_os("HOME").map(PathBuf::from);
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home().map(|home| home.join("Library").join("Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from).or_else(|| home().map(|home| home.join(".config")))
+    }?;
+    Some(base.join(CONFIG_DIR_NAME).join(CONFIG_FILE_NAME))
+}
+
+// Merges the layers in `paths` that exist over `defaults`. Also returns the files that were read.
+pub fn load_layers<T: Serialize + DeserializeOwned>(defaults: &T, paths: &[PathBuf]) -> Result<(T, Vec<PathBuf>), String> {
+    let mut merged = toml::Table::try_from(defaults).map_err(|e| format!("Could not serialize default config: {}", e))?;
+    let mut read = Vec::new();
+    for path in paths {
+        let Ok(contents) = fs::read_to_string(path) else { continue; };
+        let layer: toml::Table = toml::from_str(&contents).map_err(|e| format!("Failed to parse {}: {}", path.display(), e.message()))?;
+        merge(&mut merged, layer);
+        read.push(path.clone());
+    }
+    let config = toml::Value::Table(merged).try_into().map_err(|e: toml::de::Error| format!("Invalid config: {}", e.message()))?;
+    Ok((config, read))
+}
+
+// Writes `config` to `path`, creating its folder
+pub fn write_config<T: Serialize>(path: &Path, config: &T) -> Result<(), String> {
+    let toml_string = toml::to_string_pretty(config).map_err(|e| format!("Could not serialize default config: {}", e))?;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+    }
+    fs::write(path, toml_string).map_err(|e| format!("Could not write default {}: {}", path.display(), e))
+}
+
+fn merge(base: &mut toml::Table, layer: toml::Table) {
+    for (key, value) in layer {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(layer_table)) => merge(base_table, layer_table),
+            (_, value) => { base.insert(key, value); }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+// debug_overlay.rs
+// The F3 overlay: frame rate, where the draw time goes and how well the mesh caches are doing.
+
+use ggez::graphics::Rect;
+use std::time::Duration;
+
+use crate::ui::{self, PanelLayout, Widget};
+
+const DEBUG_OVERLAY_WIDTH: f32 = 300.0;
+
+// --- Where one frame's draw time went, and how often the mesh caches were enough ---
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameStats {
+    pub tessellation: Duration, // Stroking connector lines into triangles
+    pub upload: Duration, // Turning triangles and shape outlines into GPU meshes
+    pub total: Duration, // All of draw(), the two above included
+    pub connector_hits: u32, // Connector meshes reused from the last frame
+    pub connector_misses: u32, // ... and rebuilt
+    pub shape_mesh_hits: u32,
+    pub shape_mesh_misses: u32,
+}
+
+impl FrameStats {
+    // The rest of the frame: issuing draw calls and submitting them
+    pub fn draw_calls(&self) -> Duration {
+        self.total.saturating_sub(self.tessellation + self.upload)
+    }
+}
+
+fn milliseconds(duration: Duration) -> String {
+    format!("{:.2} ms", duration.as_secs_f64() * 1000.0)
+}
+
+fn hit_rate(hits: u32, misses: u32) -> String {
+    match hits + misses {
+        0 => String::from("-"),
+        total => format!("{:.0}% of {}", hits as f64 * 100.0 / total as f64, total),
+    }
+}
+
+// Read-only F3 panel docked top-right of the canvas: against `right`, just below `top`. `stats` is
+// the last finished frame's; `fps` and `frame_time` are ggez's running averages.
+pub fn overlay_widgets(stats: &FrameStats, fps: f64, frame_time: Duration, counts: (usize, usize), right: f32, top: f32) -> (Rect, Vec<Widget<()>>) {
+    let lines = [
+        format!("{:.0} FPS, {} per frame", fps, milliseconds(frame_time)),
+        format!("Draw: {}", milliseconds(stats.total)),
+        format!("  Tessellation: {}", milliseconds(stats.tessellation)),
+        format!("  Mesh upload: {}", milliseconds(stats.upload)),
+        format!("  Draw calls: {}", milliseconds(stats.draw_calls())),
+        format!("Shapes: {}, connections: {}", counts.0, counts.1),
+        format!("Connector mesh hits: {}", hit_rate(stats.connector_hits, stats.connector_misses)),
+        format!("Shape mesh hits: {}", hit_rate(stats.shape_mesh_hits, stats.shape_mesh_misses)),
+    ];
+    let height = ui::panel_height(lines.len());
+    let panel = Rect::new(right - DEBUG_OVERLAY_WIDTH, top, DEBUG_OVERLAY_WIDTH, height);
+    let mut layout = PanelLayout::new(panel);
+    for line in lines { layout.label(line); }
+    (panel, layout.widgets)
+}
@@ -0,0 +1,1408 @@
+// diagram.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::Rect;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use crate::geometry;
+use crate::persistence;
+use crate::ports::{self, Port, PortId, PortSide};
+use crate::shape_kind::ShapeKind;
+use crate::{GROUP_PADDING, GROUP_TITLE_HEIGHT, LANE_TITLE_SIZE, PARALLEL_CONNECTOR_SPACING};
+
+const LANE_MARGIN: f32 = 20.0; // Space left around the shapes when the first lane is placed
+const LANE_THICKNESS_IN_SHAPES: f32 = 2.0; // New lanes are this many default shapes high (or wide)
+
+// --- Data structure for individual shapes ---
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ShapeData {
+    #[serde(with = "persistence::vec2_as_array")]
+    pub center_position: Vec2,
+    pub text: Option<String>,
+    #[serde(default)]
+    pub kind: ShapeKind,
+    #[serde(default = "ports::default_ports")]
+    pub ports: Vec<Port>,
+    // Per-shape style overrides; None uses the configured defaults
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fill_rgb: Option<[u8; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outline_rgb: Option<[u8; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub corner_radius: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub font_size: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_rgb: Option<[u8; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<[f32; 2]>, // [width, height]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<usize>, // Innermost group the shape belongs to
+    #[serde(default, skip_serializing_if = "is_base_layer")]
+    pub layer: usize, // Index into Diagram::layers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, String>, // e.g. owner=alice, status=todo
+    #[serde(default, skip_serializing_if = "is_unrotated")]
+    pub rotation: f32, // Radians about the center, clockwise on screen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subdiagram: Option<PathBuf>, // Diagram file the shape stands for, relative to this diagram's folder
+    #[serde(skip)]
+    pub id: u64, // Names the shape to collaborators whatever its index; 0 until it is shared, and never saved
+}
+
+impl ShapeData {
+    // An unlabeled shape of the default kind, with the default ports and style
+    pub fn new(center_position: Vec2) -> ShapeData {
+        ShapeData {
+            center_position,
+            text: None,
+            kind: ShapeKind::default(),
+            ports: ports::default_ports(),
+            fill_rgb: None,
+            outline_rgb: None,
+            corner_radius: None,
+            font_size: None,
+            text_rgb: None,
+            size: None,
+            group: None,
+            layer: 0,
+            tags: Vec::new(),
+            attributes: BTreeMap::new(),
+            rotation: 0.0,
+            subdiagram: None,
+            id: 0,
+        }
+    }
+
+    // Tags, then key=value attributes, as they are typed into the inspector and searched
+    pub fn metadata_entries(&self) -> Vec<String> {
+        let attributes = self.attributes.iter().map(|(key, value)| format!("{}={}", key, value));
+        self.tags.iter().cloned().chain(attributes).collect()
+    }
+
+    pub fn metadata_text(&self) -> String {
+        self.metadata_entries().join(", ")
+    }
+
+    // Replaces the tags and attributes from comma-separated entries: `key=value` sets an
+    // attribute, anything else is a tag. Blank entries and repeated tags are dropped.
+    pub fn set_metadata_from_text(&mut self, text: &str) {
+        self.tags.clear();
+        self.attributes.clear();
+        for entry in text.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.split_once('=') {
+                Some((key, value)) if !key.trim().is_empty() => { self.attributes.insert(key.trim().to_string(), value.trim().to_string()); }
+                _ => if !self.tags.iter().any(|tag| tag == entry) { self.tags.push(entry.to_string()); },
+            }
+        }
+    }
+
+    // Bounding box of the shape, using `default_size` unless it has its own size
+    pub fn rect(&self, default_size: Vec2) -> Rect {
+        let size = self.size.map_or(default_size, Vec2::from);
+        Rect::new(self.center_position.x - size.x / 2.0, self.center_position.y - size.y / 2.0, size.x, size.y)
+    }
+
+    // --- Rotation: `rect` is the shape before it is turned about its center ---
+    // Kept within one turn, so a shape turned back upright is saved without a rotation
+    pub fn set_rotation(&mut self, rotation: f32) {
+        let rotation = rotation.rem_euclid(std::f32::consts::TAU);
+        self.rotation = if rotation < std::f32::consts::TAU { rotation } else { 0.0 };
+    }
+
+    // A world point in the unrotated frame of `rect`, for hit tests
+    pub fn to_local(&self, point: Vec2) -> Vec2 {
+        self.center_position + Vec2::from_angle(-self.rotation).rotate(point - self.center_position)
+    }
+
+    // Where a point given in the unrotated frame ends up once the shape is turned
+    pub fn to_world(&self, point: Vec2) -> Vec2 {
+        self.center_position + Vec2::from_angle(self.rotation).rotate(point - self.center_position)
+    }
+
+    pub fn contains(&self, point: Vec2, default_size: Vec2) -> bool {
+        self.kind.contains(self.rect(default_size), self.to_local(point))
+    }
+
+    // Axis-aligned box around the turned shape; the same as `rect` when it isn't rotated
+    pub fn bounds(&self, default_size: Vec2) -> Rect {
+        let rect = self.rect(default_size);
+        if self.rotation == 0.0 { return rect; }
+        let (sin, cos) = self.rotation.sin_cos();
+        let half_size = Vec2::new(rect.w * cos.abs() + rect.h * sin.abs(), rect.w * sin.abs() + rect.h * cos.abs()) / 2.0;
+        Rect::new(self.center_position.x - half_size.x, self.center_position.y - half_size.y, half_size.x * 2.0, half_size.y * 2.0)
+    }
+}
+
+// --- Data structure for user-defined connections ---
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Connection {
+    pub from_shape_index: usize,
+    #[serde(default = "ports::default_outgoing_port")]
+    pub from_port: PortId,
+    pub to_shape_index: usize,
+    #[serde(default = "ports::default_incoming_port")]
+    pub to_port: PortId,
+    #[serde(default)]
+    pub smart_routed: bool, // Drawn as an orthogonal route around other shapes instead of a curve
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "is_base_layer")]
+    pub layer: usize,
+    // Per-connection style overrides; None uses the configured connector color and CONNECTOR_LINE_WIDTH
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color_rgb: Option<[u8; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line_width: Option<f32>,
+    #[serde(default, skip_serializing_if = "LineDash::is_solid")]
+    pub dash: LineDash,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bend: Option<f32>, // Sideways push of the curve's middle, set by dragging its handle; unused when smart routed
+    #[serde(skip)]
+    pub id: u64, // As ShapeData::id
+}
+
+impl Connection {
+    pub fn new(from_shape_index: usize, from_port: PortId, to_shape_index: usize, to_port: PortId) -> Connection {
+        Connection {
+            from_shape_index, from_port, to_shape_index, to_port,
+            smart_routed: false, label: None, layer: 0, color_rgb: None, line_width: None, dash: LineDash::Solid, bend: None, id: 0,
+        }
+    }
+
+    // The same connection pointing the other way, ports and all. The bend is measured to the side of
+    // the direction of travel, so it flips too to keep the curve in place.
+    pub fn reversed(&self) -> Connection {
+        Connection {
+            from_shape_index: self.to_shape_index,
+            from_port: self.to_port,
+            to_shape_index: self.from_shape_index,
+            to_port: self.from_port,
+            bend: self.bend.map(|bend| -bend),
+            ..self.clone()
+        }
+    }
+
+    pub fn same_endpoints(&self, other: &Connection) -> bool {
+        self.from_shape_index == other.from_shape_index && self.to_shape_index == other.to_shape_index
+    }
+}
+
+// How far each connection's curve bows sideways, so connections joining the same two shapes (either
+// way round) fan out instead of overlapping. Offsets are relative to each connection's own direction
+// and are 0 for a connection without parallels.
+pub fn parallel_offsets(connections: &[Connection]) -> Vec<f32> {
+    let pair = |c: &Connection| (c.from_shape_index.min(c.to_shape_index), c.from_shape_index.max(c.to_shape_index));
+    let mut counts: HashMap<(usize, usize), usize> = HashMap::new();
+    for connection in connections {
+        *counts.entry(pair(connection)).or_default() += 1;
+    }
+    let mut seen: HashMap<(usize, usize), usize> = HashMap::new();
+    connections.iter().map(|connection| {
+        let key = pair(connection);
+        let slot = seen.entry(key).or_default();
+        // Slots spread evenly around the middle, measured in the lower-index shape's direction
+        let offset = (*slot as f32 - (counts[&key] - 1) as f32 / 2.0) * PARALLEL_CONNECTOR_SPACING;
+        *slot += 1;
+        if connection.from_shape_index <= connection.to_shape_index { offset } else { -offset }
+    }).collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DisplayedEnd {
+    Shape(usize),
+    Group(usize), // A collapsed group, standing in for the shapes inside it
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineDash {
+    #[default]
+    Solid,
+    Dashed,
+    Dotted,
+}
+
+impl LineDash {
+    pub const ALL: [LineDash; 3] = [LineDash::Solid, LineDash::Dashed, LineDash::Dotted];
+
+    fn is_solid(&self) -> bool {
+        *self == LineDash::Solid
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LineDash::Solid => "solid",
+            LineDash::Dashed => "dashed",
+            LineDash::Dotted => "dotted",
+        }
+    }
+
+    // [dash, gap] lengths in multiples of the line width; None for a continuous line
+    pub fn pattern(self) -> Option<[f32; 2]> {
+        match self {
+            LineDash::Solid => None,
+            LineDash::Dashed => Some([4.0, 3.0]),
+            LineDash::Dotted => Some([1.0, 2.0]),
+        }
+    }
+
+    pub fn next(self) -> LineDash {
+        let index = LineDash::ALL.iter().position(|&d| d == self).unwrap_or(0);
+        LineDash::ALL[(index + 1) % LineDash::ALL.len()]
+    }
+}
+
+// --- Counts describing the graph the connections form ---
+#[derive(Clone, Debug, PartialEq)]
+pub struct GraphStats {
+    pub node_count: usize,
+    pub edge_count: usize,
+    pub in_degree: Vec<usize>, // Per shape, connections arriving at it
+    pub out_degree: Vec<usize>, // Per shape, connections leaving it
+    pub component_count: usize, // Groups of shapes linked by connections in either direction
+    pub acyclic: bool,
+}
+
+// --- Which way connections are followed from a shape when highlighting what it reaches ---
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reach {
+    Downstream, // Along connections: what the shape feeds
+    Upstream, // Against them: what feeds the shape
+    Both,
+}
+
+impl Reach {
+    pub fn name(self) -> &'static str {
+        match self {
+            Reach::Downstream => "downstream",
+            Reach::Upstream => "upstream",
+            Reach::Both => "upstream and downstream",
+        }
+    }
+
+    // Downstream, upstream, both, then None to stop highlighting
+    pub fn next(self) -> Option<Reach> {
+        match self {
+            Reach::Downstream => Some(Reach::Upstream),
+            Reach::Upstream => Some(Reach::Both),
+            Reach::Both => None,
+        }
+    }
+}
+
+// --- A named container drawn as a frame behind its shapes ---
+// Shapes name their innermost group and groups name the group they sit in, so groups nest.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Group {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<usize>,
+    #[serde(default)]
+    pub collapsed: bool, // Contents hidden; drawn as one box that outside connectors attach to
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LaneOrientation {
+    #[default]
+    Horizontal, // Stacked top to bottom, title along the left edge
+    Vertical, // Side by side, title along the top edge
+}
+
+// --- A named layer that shapes and connections sit on ---
+// A diagram without layers has one implicit base layer, visible and unlocked.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Layer {
+    pub name: String,
+    #[serde(default = "default_visible")]
+    pub visible: bool,
+    #[serde(default)]
+    pub locked: bool, // Drawn, but its shapes and connections can't be selected or edited
+}
+
+impl Layer {
+    pub fn new(name: &str) -> Layer {
+        Layer { name: name.to_string(), visible: true, locked: false }
+    }
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+fn is_base_layer(layer: &usize) -> bool {
+    *layer == 0
+}
+
+fn is_unrotated(rotation: &f32) -> bool {
+    *rotation == 0.0
+}
+
+// --- A titled background band; shapes whose center lies in it belong to it ---
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Lane {
+    pub title: String,
+    #[serde(default)]
+    pub orientation: LaneOrientation,
+    pub bounds: [f32; 4], // [x, y, width, height]
+}
+
+impl Lane {
+    pub fn rect(&self) -> Rect {
+        let [x, y, w, h] = self.bounds;
+        Rect::new(x, y, w, h)
+    }
+
+    pub fn set_rect(&mut self, rect: Rect) {
+        self.bounds = [rect.x, rect.y, rect.w, rect.h];
+    }
+
+    // Strip holding the title: along the left edge of a horizontal lane, the top of a vertical one
+    pub fn title_rect(&self) -> Rect {
+        let rect = self.rect();
+        match self.orientation {
+            LaneOrientation::Horizontal => Rect::new(rect.x, rect.y, LANE_TITLE_SIZE.min(rect.w), rect.h),
+            LaneOrientation::Vertical => Rect::new(rect.x, rect.y, rect.w, LANE_TITLE_SIZE.min(rect.h)),
+        }
+    }
+}
+
+// --- A freehand pen or highlighter stroke, drawn over the diagram on its own annotation layer ---
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Annotation {
+    #[serde(with = "persistence::vec2s_as_arrays")]
+    pub points: Vec<Vec2>,
+    pub color_rgb: [u8; 3],
+    pub width: f32,
+    #[serde(default)]
+    pub highlight: bool, // Drawn see-through, HIGHLIGHT_ALPHA opaque
+}
+
+impl Annotation {
+    // Box around the stroke's ink
+    pub fn bounds(&self) -> Option<Rect> {
+        let (min, max) = geometry::shapes_bounds(self.points.iter().map(|p| Rect::new(p.x, p.y, 0.0, 0.0)))?;
+        let half_width = self.width / 2.0;
+        Some(Rect::new(min.x - half_width, min.y - half_width, max.x - min.x + self.width, max.y - min.y + self.width))
+    }
+
+    // Whether the stroke passes within `radius` of `point`, counting its width
+    pub fn touches(&self, point: Vec2, radius: f32) -> bool {
+        geometry::closest_point_on_polyline(&self.points, point).is_some_and(|closest| closest.distance(point) <= radius + self.width / 2.0)
+    }
+}
+
+// --- One stop of a presentation walk-through: some shapes, or a group and everything in it ---
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PresentationStep {
+    Shapes(Vec<usize>),
+    Group(usize),
+}
+
+// Lane a point belongs to; where lanes overlap, the one added last wins
+pub fn lane_containing(lanes: &[Lane], point: Vec2) -> Option<usize> {
+    lanes.iter().rposition(|lane| lane.rect().contains(point))
+}
+
+// --- A whole diagram: its shapes, the connections between them and the groups and lanes around them ---
+// Connections refer to shapes by index, so removing a shape re-indexes the connections after it.
+// This is also the on-disk format and what the clipboard holds.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Diagram {
+    pub shapes: Vec<ShapeData>,
+    pub connections: Vec<Connection>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<Group>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lanes: Vec<Lane>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub layers: Vec<Layer>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<PresentationStep>, // Walked through in order in read-only mode
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<Annotation>,
+}
+
+impl Diagram {
+    pub fn new() -> Diagram {
+        Diagram::default()
+    }
+
+    // Returns the new shape's index
+    pub fn add_shape(&mut self, shape: ShapeData) -> usize {
+        self.shapes.push(shape);
+        self.shapes.len() - 1
+    }
+
+    pub fn move_shape(&mut self, index: usize, center_position: Vec2) -> bool {
+        let Some(shape) = self.shapes.get_mut(index) else { return false; };
+        shape.center_position = center_position;
+        true
+    }
+
+    // Removes a shape, dropping its connections and re-indexing the remaining ones
+    pub fn delete_shape(&mut self, index: usize) -> Option<ShapeData> {
+        if index >= self.shapes.len() { return None; }
+        let removed = self.shapes.remove(index);
+        self.connections.retain(|conn| conn.from_shape_index != index && conn.to_shape_index != index);
+        for conn in &mut self.connections {
+            if conn.from_shape_index > index { conn.from_shape_index -= 1; }
+            if conn.to_shape_index > index { conn.to_shape_index -= 1; }
+        }
+        for step in &mut self.steps {
+            if let PresentationStep::Shapes(shapes) = step {
+                shapes.retain(|&i| i != index);
+                for i in shapes.iter_mut().filter(|i| **i > index) { *i -= 1; }
+            }
+        }
+        self.prune_empty_groups();
+        self.steps.retain(|step| step != &PresentationStep::Shapes(Vec::new()));
+        Some(removed)
+    }
+
+    // Moves a shape to another place in the drawing order (later shapes draw on top), keeping its
+    // connections attached. Returns false if either index is out of range.
+    pub fn restack_shape(&mut self, index: usize, new_index: usize) -> bool {
+        if index >= self.shapes.len() || new_index >= self.shapes.len() { return false; }
+        let shape = self.shapes.remove(index);
+        self.shapes.insert(new_index, shape);
+        let remap = |i: usize| {
+            if i == index { new_index }
+            else if index < i && i <= new_index { i - 1 }
+            else if new_index <= i && i < index { i + 1 }
+            else { i }
+        };
+        for conn in &mut self.connections {
+            conn.from_shape_index = remap(conn.from_shape_index);
+            conn.to_shape_index = remap(conn.to_shape_index);
+        }
+        for step in &mut self.steps {
+            if let PresentationStep::Shapes(shapes) = step { shapes.iter_mut().for_each(|i| *i = remap(*i)); }
+        }
+        true
+    }
+
+    // Where a shape's port sits and which side it faces, or None if either doesn't exist
+    pub fn port_anchor(&self, shape_index: usize, port_id: PortId, default_size: Vec2) -> Option<(Vec2, PortSide)> {
+        let shape = self.shapes.get(shape_index).filter(|shape| shape.kind.has_ports())?;
+        let port = shape.ports.get(port_id)?;
+        Some((shape.to_world(port.position(shape.kind, shape.rect(default_size))), port.side.rotated(shape.rotation)))
+    }
+
+    pub fn has_port(&self, shape_index: usize, port: PortId) -> bool {
+        self.shapes.get(shape_index).is_some_and(|s| s.kind.has_ports() && port < s.ports.len())
+    }
+
+    // Adds a connection and returns its index. None if either endpoint doesn't exist.
+    pub fn connect(&mut self, from_shape_index: usize, from_port: PortId, to_shape_index: usize, to_port: PortId) -> Option<usize> {
+        if !self.has_port(from_shape_index, from_port) || !self.has_port(to_shape_index, to_port) { return None; }
+        self.connections.push(Connection::new(from_shape_index, from_port, to_shape_index, to_port));
+        Some(self.connections.len() - 1)
+    }
+
+    pub fn disconnect(&mut self, index: usize) -> Option<Connection> {
+        (index < self.connections.len()).then(|| self.connections.remove(index))
+    }
+
+    // Shapes the shape connects to (`outgoing`) or is connected from, in connection order, without repeats
+    pub fn neighbors(&self, shape_index: usize, outgoing: bool) -> Vec<usize> {
+        let mut neighbors = Vec::new();
+        for connection in &self.connections {
+            let (near, far) = if outgoing {
+                (connection.from_shape_index, connection.to_shape_index)
+            } else {
+                (connection.to_shape_index, connection.from_shape_index)
+            };
+            if near == shape_index && far != shape_index && !neighbors.contains(&far) { neighbors.push(far); }
+        }
+        neighbors
+    }
+
+    // Per shape, whether it can be reached from `start` following connections the `reach` way,
+    // `start` itself included
+    pub fn reachable(&self, start: usize, reach: Reach) -> Vec<bool> {
+        let mut reached = vec![false; self.shapes.len()];
+        if start >= reached.len() { return reached; }
+        reached[start] = true;
+        let mut pending = vec![start];
+        while let Some(shape_index) = pending.pop() {
+            for connection in &self.connections {
+                let (from, to) = (connection.from_shape_index, connection.to_shape_index);
+                let next = match reach {
+                    Reach::Downstream => (from == shape_index).then_some(to),
+                    Reach::Upstream => (to == shape_index).then_some(from),
+                    Reach::Both if from == shape_index => Some(to),
+                    Reach::Both => (to == shape_index).then_some(from),
+                };
+                if let Some(next) = next.filter(|&next| next < reached.len() && !reached[next]) {
+                    reached[next] = true;
+                    pending.push(next);
+                }
+            }
+        }
+        reached
+    }
+
+    // Strongly connected component of each shape (Kosaraju): shapes share a component exactly when
+    // each can reach the other along connections
+    pub fn strongly_connected_components(&self) -> Vec<usize> {
+        let count = self.shapes.len();
+        let mut outgoing = vec![Vec::new(); count];
+        let mut incoming = vec![Vec::new(); count];
+        for connection in self.connections.iter().filter(|c| c.from_shape_index < count && c.to_shape_index < count) {
+            outgoing[connection.from_shape_index].push(connection.to_shape_index);
+            incoming[connection.to_shape_index].push(connection.from_shape_index);
+        }
+        // First pass: shapes in the order their depth-first search along connections finishes
+        let mut finished = Vec::with_capacity(count);
+        let mut visited = vec![false; count];
+        for root in 0..count {
+            if visited[root] { continue; }
+            visited[root] = true;
+            let mut stack = vec![(root, 0)]; // (shape, next outgoing edge to follow)
+            while let Some((shape, edge)) = stack.pop() {
+                match outgoing[shape].get(edge) {
+                    Some(&next) => {
+                        stack.push((shape, edge + 1));
+                        if !visited[next] { visited[next] = true; stack.push((next, 0)); }
+                    }
+                    None => finished.push(shape),
+                }
+            }
+        }
+        // Second pass: against the connections, latest finisher first; each search collects one component
+        let mut component = vec![usize::MAX; count];
+        let mut next_component = 0;
+        for &root in finished.iter().rev() {
+            if component[root] != usize::MAX { continue; }
+            component[root] = next_component;
+            let mut pending = vec![root];
+            while let Some(shape) = pending.pop() {
+                for &previous in &incoming[shape] {
+                    if component[previous] == usize::MAX { component[previous] = next_component; pending.push(previous); }
+                }
+            }
+            next_component += 1;
+        }
+        component
+    }
+
+    // Connections lying on a cycle: self-loops, and those between shapes of the same strongly connected component
+    pub fn cycle_connections(&self) -> Vec<usize> {
+        let component = self.strongly_connected_components();
+        self.connections.iter().enumerate()
+            .filter(|(_, c)| matches!((component.get(c.from_shape_index), component.get(c.to_shape_index)), (Some(a), Some(b)) if a == b))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    // Shapes in an order where every connection points forwards (Kahn's algorithm, lower indices
+    // first among equals). Shapes on a cycle, or fed by one, can't be placed and are left out, as are notes.
+    pub fn topological_order(&self) -> Vec<usize> {
+        let count = self.shapes.len();
+        let edges: Vec<&Connection> = self.connections.iter().filter(|c| c.from_shape_index < count && c.to_shape_index < count).collect();
+        let mut unplaced_inputs = vec![0; count];
+        for connection in &edges { unplaced_inputs[connection.to_shape_index] += 1; }
+        let mut ready: std::collections::BTreeSet<usize> = (0..count).filter(|&i| unplaced_inputs[i] == 0 && !self.is_note(i)).collect();
+        let mut order = Vec::with_capacity(count);
+        while let Some(shape) = ready.pop_first() {
+            order.push(shape);
+            for connection in edges.iter().filter(|c| c.from_shape_index == shape) {
+                unplaced_inputs[connection.to_shape_index] -= 1;
+                if unplaced_inputs[connection.to_shape_index] == 0 { ready.insert(connection.to_shape_index); }
+            }
+        }
+        order
+    }
+
+    // Heaviest chain of connected shapes, as shapes in order and the connections between them.
+    // `weight` gives each shape's cost, e.g. its duration; shapes left out of the topological order
+    // (cycles) are skipped.
+    pub fn critical_path(&self, weight: impl Fn(&ShapeData) -> f32) -> (Vec<usize>, Vec<usize>) {
+        let order = self.topological_order();
+        let mut total = vec![0.0_f32; self.shapes.len()];
+        let mut via: Vec<Option<usize>> = vec![None; self.shapes.len()]; // Connection the heaviest chain arrives by
+        for &shape in &order {
+            total[shape] += weight(&self.shapes[shape]);
+            for (index, connection) in self.connections.iter().enumerate().filter(|(_, c)| c.from_shape_index == shape && c.to_shape_index != shape) {
+                let Some(&current) = total.get(connection.to_shape_index) else { continue; };
+                if via[connection.to_shape_index].is_none() || total[shape] > current {
+                    total[connection.to_shape_index] = total[shape];
+                    via[connection.to_shape_index] = Some(index);
+                }
+            }
+        }
+        let Some(mut shape) = order.iter().copied().max_by(|&a, &b| total[a].total_cmp(&total[b])) else { return (Vec::new(), Vec::new()); };
+        let (mut shapes, mut connections) = (vec![shape], Vec::new());
+        while let Some(index) = via[shape] {
+            connections.push(index);
+            shape = self.connections[index].from_shape_index;
+            shapes.push(shape);
+        }
+        shapes.reverse();
+        connections.reverse();
+        (shapes, connections)
+    }
+
+    // Notes aren't counted as nodes
+    pub fn graph_stats(&self) -> GraphStats {
+        let count = self.shapes.len();
+        let (mut in_degree, mut out_degree) = (vec![0; count], vec![0; count]);
+        // Union-find over the shapes, ignoring which way connections point
+        let mut parent: Vec<usize> = (0..count).collect();
+        fn root(parent: &mut [usize], mut shape: usize) -> usize {
+            while parent[shape] != shape { parent[shape] = parent[parent[shape]]; shape = parent[shape]; }
+            shape
+        }
+        let node_count = (0..count).filter(|&i| !self.is_note(i)).count();
+        let (mut component_count, mut edge_count) = (node_count, 0);
+        for connection in self.connections.iter().filter(|c| c.from_shape_index < count && c.to_shape_index < count && !self.is_note(c.from_shape_index) && !self.is_note(c.to_shape_index)) {
+            edge_count += 1;
+            out_degree[connection.from_shape_index] += 1;
+            in_degree[connection.to_shape_index] += 1;
+            let (a, b) = (root(&mut parent, connection.from_shape_index), root(&mut parent, connection.to_shape_index));
+            if a != b { parent[a] = b; component_count -= 1; }
+        }
+        GraphStats {
+            node_count,
+            edge_count,
+            in_degree,
+            out_degree,
+            component_count,
+            acyclic: self.cycle_connections().is_empty(),
+        }
+    }
+
+    // Replaces a connection with `reconnected` (e.g. one end moved to another shape). Refused when
+    // an endpoint doesn't exist.
+    pub fn reconnect(&mut self, index: usize, reconnected: Connection) -> Result<(), String> {
+        if index >= self.connections.len() {
+            return Err(format!("No connection {}", index));
+        }
+        if !self.has_port(reconnected.from_shape_index, reconnected.from_port) || !self.has_port(reconnected.to_shape_index, reconnected.to_port) {
+            return Err(String::from("that port doesn't exist"));
+        }
+        self.connections[index] = reconnected;
+        Ok(())
+    }
+
+    // Swaps a connection's ends and ports so it points the other way
+    pub fn reverse_connection(&mut self, index: usize) -> Result<(), String> {
+        let reversed = self.connections.get(index).ok_or_else(|| format!("No connection {}", index))?.reversed();
+        self.reconnect(index, reversed)
+    }
+
+    // Joins each predecessor of a shape to each of its successors, so deleting B from A→B→C leaves A→C.
+    // The new connections keep the ports used on A and C.
+    pub fn reconnect_through(&mut self, shape_index: usize) {
+        let incoming: Vec<Connection> = self.connections.iter()
+            .filter(|c| c.to_shape_index == shape_index && c.from_shape_index != shape_index).cloned().collect();
+        let outgoing: Vec<Connection> = self.connections.iter()
+            .filter(|c| c.from_shape_index == shape_index && c.to_shape_index != shape_index).cloned().collect();
+        for before in &incoming {
+            for after in &outgoing {
+                if before.from_shape_index == after.to_shape_index { continue; }
+                let mut bridge = Connection::new(before.from_shape_index, before.from_port, after.to_shape_index, after.to_port);
+                bridge.smart_routed = before.smart_routed && after.smart_routed;
+                if !self.connections.iter().any(|c| c.same_endpoints(&bridge)) { self.connections.push(bridge); }
+            }
+        }
+    }
+
+    // Replaces A→C with A→N→C, entering N at `in_port` and leaving it at `out_port`.
+    // The label stays on the first half. Returns the index of the second half.
+    pub fn insert_into_connection(&mut self, conn_index: usize, shape_index: usize, in_port: PortId, out_port: PortId) -> Option<usize> {
+        if !self.has_port(shape_index, in_port) || !self.has_port(shape_index, out_port) { return None; }
+        let first_half = self.connections.get_mut(conn_index)?;
+        let mut second_half = Connection::new(shape_index, out_port, first_half.to_shape_index, first_half.to_port);
+        second_half.smart_routed = first_half.smart_routed;
+        first_half.to_shape_index = shape_index;
+        first_half.to_port = in_port;
+        self.connections.push(second_half);
+        Some(self.connections.len() - 1)
+    }
+
+    // Copies the given shapes plus the connections running between them into a self-contained
+    // diagram whose connection indices refer to positions inside it.
+    pub fn extract(&self, selected: &[usize]) -> Diagram {
+        let fragment_index_of = |shape_index: usize| selected.iter().position(|&i| i == shape_index);
+        // Groups aren't copied, so the copies start out ungrouped
+        let shapes = selected.iter().filter_map(|&i| Some(ShapeData { group: None, ..self.shapes.get(i)?.clone() })).collect();
+        let connections = self.connections
+            .iter()
+            .filter_map(|conn| {
+                Some(Connection {
+                    from_shape_index: fragment_index_of(conn.from_shape_index)?,
+                    to_shape_index: fragment_index_of(conn.to_shape_index)?,
+                    ..conn.clone()
+                })
+            })
+            .collect();
+        Diagram { shapes, connections, ..Diagram::default() }
+    }
+
+    // Adds all of `fragment`, moved by `translation`. Returns the indices of the newly added shapes.
+    // The copies are new shapes and connections, so they don't keep their originals' ids.
+    pub fn append(&mut self, fragment: &Diagram, translation: Vec2) -> Vec<usize> {
+        let base_index = self.shapes.len();
+        for shape in &fragment.shapes {
+            let mut new_shape = shape.clone();
+            new_shape.center_position += translation;
+            new_shape.id = 0;
+            self.shapes.push(new_shape);
+        }
+        for conn in &fragment.connections {
+            self.connections.push(Connection {
+                from_shape_index: conn.from_shape_index + base_index,
+                to_shape_index: conn.to_shape_index + base_index,
+                id: 0,
+                ..conn.clone()
+            });
+        }
+        (base_index..self.shapes.len()).collect()
+    }
+
+    // Drops connections whose endpoints don't refer to an existing shape and port, and group
+    // references that don't refer to an existing group or would make groups contain themselves.
+    // Anything on a layer that doesn't exist moves to the base layer.
+    pub fn validated(mut self) -> Diagram {
+        let shapes = &self.shapes;
+        let has_port = |shape_index: usize, port| shapes.get(shape_index).is_some_and(|s: &ShapeData| port < s.ports.len());
+        self.connections.retain(|conn| has_port(conn.from_shape_index, conn.from_port) && has_port(conn.to_shape_index, conn.to_port));
+        let group_count = self.groups.len();
+        for shape in &mut self.shapes {
+            shape.group = shape.group.filter(|&g| g < group_count);
+        }
+        let layer_count = self.layer_count();
+        for shape in &mut self.shapes {
+            if shape.layer >= layer_count { shape.layer = 0; }
+        }
+        for conn in &mut self.connections {
+            if conn.layer >= layer_count { conn.layer = 0; }
+        }
+        for group in 0..group_count {
+            let parent = self.groups[group].parent.filter(|&p| p < group_count);
+            self.groups[group].parent = parent;
+            if self.group_chain(parent).any(|g| g == group) { self.groups[group].parent = None; }
+        }
+        self.prune_empty_groups();
+        self
+    }
+
+    // --- Layers ---
+    pub fn layer_count(&self) -> usize {
+        self.layers.len().max(1)
+    }
+
+    pub fn layer_name(&self, layer: usize) -> String {
+        self.layers.get(layer).map_or_else(|| format!("Layer {}", layer + 1), |l| l.name.clone())
+    }
+
+    pub fn layer_visible(&self, layer: usize) -> bool {
+        self.layers.get(layer).is_none_or(|l| l.visible)
+    }
+
+    pub fn layer_locked(&self, layer: usize) -> bool {
+        self.layers.get(layer).is_some_and(|l| l.locked)
+    }
+
+    pub fn shape_on_hidden_layer(&self, shape_index: usize) -> bool {
+        self.shapes.get(shape_index).is_some_and(|shape| !self.layer_visible(shape.layer))
+    }
+
+    pub fn shape_locked(&self, shape_index: usize) -> bool {
+        self.shapes.get(shape_index).is_some_and(|shape| self.layer_locked(shape.layer))
+    }
+
+    // Adds a layer on top and returns its index; the implicit base layer becomes a real one first
+    pub fn add_layer(&mut self) -> usize {
+        self.materialize_base_layer();
+        let name = format!("Layer {}", self.layers.len() + 1);
+        self.layers.push(Layer::new(&name));
+        self.layers.len() - 1
+    }
+
+    // The layer to change, making the implicit base layer a real one if needed
+    pub fn layer_mut(&mut self, layer: usize) -> Option<&mut Layer> {
+        self.materialize_base_layer();
+        self.layers.get_mut(layer)
+    }
+
+    fn materialize_base_layer(&mut self) {
+        if self.layers.is_empty() { self.layers.push(Layer::new("Layer 1")); }
+    }
+
+    // --- Groups ---
+    // `group` and the groups enclosing it, innermost first
+    pub fn group_chain(&self, group: Option<usize>) -> impl Iterator<Item = usize> + '_ {
+        std::iter::successors(group.filter(|&g| g < self.groups.len()), |&g| self.groups[g].parent.filter(|&p| p < self.groups.len()))
+            .take(self.groups.len()) // Stops on parent cycles in hand-edited files
+    }
+
+    // Whether the shape is in `group`, directly or through nested groups
+    pub fn shape_in_group(&self, shape_index: usize, group: usize) -> bool {
+        self.shapes.get(shape_index).is_some_and(|shape| self.group_chain(shape.group).any(|g| g == group))
+    }
+
+    // How many groups enclose `group`
+    pub fn group_depth(&self, group: usize) -> usize {
+        self.group_chain(Some(group)).count().saturating_sub(1)
+    }
+
+    // Outermost collapsed group the shape is hidden in
+    pub fn collapsed_group_of_shape(&self, shape_index: usize) -> Option<usize> {
+        self.group_chain(self.shapes.get(shape_index)?.group).filter(|&g| self.groups[g].collapsed).last()
+    }
+
+    // A group is hidden when a group around it is collapsed
+    pub fn group_hidden(&self, group: usize) -> bool {
+        self.groups.get(group).is_some_and(|g| self.group_chain(g.parent).any(|outer| self.groups[outer].collapsed))
+    }
+
+    // Frame drawn for a group: its contents plus padding and a title strip, or a default-sized box
+    // around their center when collapsed. None for a group with no shapes in it.
+    pub fn group_frame(&self, group: usize, default_size: Vec2) -> Option<Rect> {
+        let shape_rects = self.shapes.iter().filter(|s| s.group == Some(group)).map(|s| s.bounds(default_size));
+        let child_frames = (0..self.groups.len())
+            .filter(|&g| g != group && self.groups[g].parent == Some(group))
+            .filter_map(|g| self.group_frame(g, default_size));
+        let content = shape_rects.chain(child_frames).reduce(|a, b| a.combine_with(b))?;
+        if self.groups.get(group)?.collapsed {
+            let center = Vec2::new(content.x + content.w / 2.0, content.y + content.h / 2.0);
+            return Some(Rect::new(center.x - default_size.x / 2.0, center.y - default_size.y / 2.0, default_size.x, default_size.y));
+        }
+        Some(Rect::new(
+            content.x - GROUP_PADDING,
+            content.y - GROUP_PADDING - GROUP_TITLE_HEIGHT,
+            content.w + GROUP_PADDING * 2.0,
+            content.h + GROUP_PADDING * 2.0 + GROUP_TITLE_HEIGHT,
+        ))
+    }
+
+    // Like port_anchor, but a shape hidden in a collapsed group lends its ports to the group's box,
+    // so connectors from outside end on the box instead
+    pub fn displayed_port_anchor(&self, shape_index: usize, port_id: PortId, default_size: Vec2) -> Option<(Vec2, PortSide)> {
+        let Some(group) = self.collapsed_group_of_shape(shape_index) else {
+            return self.port_anchor(shape_index, port_id, default_size);
+        };
+        let port = self.shapes.get(shape_index)?.ports.get(port_id)?;
+        Some((port.position(ShapeKind::Rectangle, self.group_frame(group, default_size)?), port.side))
+    }
+
+    // Whether both ends of a connection are hidden in the same collapsed group
+    pub fn connection_hidden(&self, connection: &Connection) -> bool {
+        if !self.layer_visible(connection.layer) || self.shape_on_hidden_layer(connection.from_shape_index) || self.shape_on_hidden_layer(connection.to_shape_index) {
+            return true;
+        }
+        let from_group = self.collapsed_group_of_shape(connection.from_shape_index);
+        from_group.is_some() && from_group == self.collapsed_group_of_shape(connection.to_shape_index)
+    }
+
+    // What a connection's end is drawn on: its own shape, or the collapsed group the shape is hidden in
+    pub fn displayed_end(&self, shape_index: usize) -> DisplayedEnd {
+        self.collapsed_group_of_shape(shape_index).map_or(DisplayedEnd::Shape(shape_index), DisplayedEnd::Group)
+    }
+
+    // Connections crossing a collapsed group's boundary are drawn as one per pair of displayed ends
+    // and direction. Per connection: how many it stands for (1 for ordinary ones), or 0 when it is
+    // folded into an earlier connection of its bundle. Expanding the group unfolds them again.
+    pub fn bundle_sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![1; self.connections.len()];
+        let mut bundles: HashMap<(DisplayedEnd, DisplayedEnd), usize> = HashMap::new();
+        for (index, connection) in self.connections.iter().enumerate() {
+            if self.connection_hidden(connection) { continue; }
+            let ends = (self.displayed_end(connection.from_shape_index), self.displayed_end(connection.to_shape_index));
+            if matches!(ends, (DisplayedEnd::Shape(_), DisplayedEnd::Shape(_))) { continue; }
+            match bundles.get(&ends) {
+                Some(&first) => { sizes[first] += 1; sizes[index] = 0; }
+                None => { bundles.insert(ends, index); }
+            }
+        }
+        sizes
+    }
+
+    // Puts shapes into a new group and returns its index. The group nests inside the innermost group
+    // all the shapes were already in, if any. None when none of the shapes exist.
+    pub fn group_shapes(&mut self, shape_indices: &[usize], name: &str) -> Option<usize> {
+        let members: Vec<usize> = shape_indices.iter().copied().filter(|&i| i < self.shapes.len()).collect();
+        let first = *members.first()?;
+        let parent = self.group_chain(self.shapes[first].group).find(|&g| members.iter().all(|&i| self.shape_in_group(i, g)));
+        self.groups.push(Group { name: name.to_string(), parent, collapsed: false });
+        let new_group = self.groups.len() - 1;
+        for index in members {
+            self.shapes[index].group = Some(new_group);
+        }
+        // Groups the shapes were taken out of may be left empty; the new group stays last
+        self.prune_empty_groups();
+        Some(self.groups.len() - 1)
+    }
+
+    // Removes a group, handing its shapes and nested groups to the group around it
+    pub fn ungroup(&mut self, group: usize) -> bool {
+        if group >= self.groups.len() { return false; }
+        let parent = self.groups[group].parent;
+        let reindex = |g: Option<usize>| match g {
+            Some(g) if g == group => parent.map(|p| if p > group { p - 1 } else { p }),
+            Some(g) if g > group => Some(g - 1),
+            other => other,
+        };
+        // A step showing the group shows the same shapes once it's gone
+        let members: Vec<usize> = (0..self.shapes.len()).filter(|&s| self.shape_in_group(s, group)).collect();
+        for step in &mut self.steps {
+            match step {
+                PresentationStep::Group(g) if *g == group => *step = PresentationStep::Shapes(members.clone()),
+                PresentationStep::Group(g) if *g > group => *g -= 1,
+                _ => {}
+            }
+        }
+        for shape in &mut self.shapes {
+            shape.group = reindex(shape.group);
+        }
+        self.groups.remove(group);
+        for other in &mut self.groups {
+            other.parent = reindex(other.parent);
+        }
+        true
+    }
+
+    fn prune_empty_groups(&mut self) {
+        while let Some(empty) = (0..self.groups.len()).find(|&g| !(0..self.shapes.len()).any(|s| self.shape_in_group(s, g))) {
+            self.ungroup(empty);
+        }
+    }
+
+    // --- Presentation steps ---
+    // The shapes a step shows, leaving out any that no longer exist
+    pub fn step_shapes(&self, step: usize) -> Vec<usize> {
+        match self.steps.get(step) {
+            Some(PresentationStep::Shapes(shapes)) => shapes.iter().copied().filter(|&i| i < self.shapes.len()).collect(),
+            Some(&PresentationStep::Group(group)) => (0..self.shapes.len()).filter(|&i| self.shape_in_group(i, group)).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // --- Sticky notes: comments beside the graph rather than part of it ---
+    pub fn is_note(&self, shape_index: usize) -> bool {
+        self.shapes.get(shape_index).is_some_and(|shape| shape.kind == ShapeKind::Note)
+    }
+
+    // A copy without its notes (and anything attached to them), for graph exports
+    pub fn without_notes(&self) -> Diagram {
+        let mut diagram = self.clone();
+        for index in (0..diagram.shapes.len()).rev().filter(|&i| self.is_note(i)) {
+            diagram.delete_shape(index);
+        }
+        diagram
+    }
+
+    // --- Annotations ---
+    // Removes every stroke passing within `radius` of `point`; returns how many went
+    pub fn erase_annotations(&mut self, point: Vec2, radius: f32) -> usize {
+        let before = self.annotations.len();
+        self.annotations.retain(|annotation| !annotation.touches(point, radius));
+        before - self.annotations.len()
+    }
+
+    // --- Lanes ---
+    pub fn lane_of_shape(&self, shape_index: usize) -> Option<usize> {
+        lane_containing(&self.lanes, self.shapes.get(shape_index)?.center_position)
+    }
+
+    // Adds a lane after the last one of the same orientation (below it, or to its right). The first
+    // one spans the shapes along its length. Returns the new lane's index.
+    pub fn add_lane(&mut self, orientation: LaneOrientation, default_size: Vec2) -> usize {
+        let previous = self.lanes.iter().rev().find(|lane| lane.orientation == orientation).map(Lane::rect);
+        let rect = match (previous, orientation) {
+            (Some(previous), LaneOrientation::Horizontal) => Rect::new(previous.x, previous.bottom(), previous.w, previous.h),
+            (Some(previous), LaneOrientation::Vertical) => Rect::new(previous.right(), previous.y, previous.w, previous.h),
+            (None, _) => {
+                let (min, max) = geometry::shapes_bounds(self.shapes.iter().map(|s| s.bounds(default_size)))
+                    .unwrap_or((Vec2::ZERO, default_size));
+                let (min, max) = (min - Vec2::splat(LANE_MARGIN), max + Vec2::splat(LANE_MARGIN));
+                let thickness = default_size * LANE_THICKNESS_IN_SHAPES + Vec2::splat(LANE_MARGIN * 2.0);
+                match orientation {
+                    LaneOrientation::Horizontal => Rect::new(min.x - LANE_TITLE_SIZE, min.y, max.x - min.x + LANE_TITLE_SIZE, thickness.y),
+                    LaneOrientation::Vertical => Rect::new(min.x, min.y - LANE_TITLE_SIZE, thickness.x, max.y - min.y + LANE_TITLE_SIZE),
+                }
+            }
+        };
+        self.lanes.push(Lane { title: format!("Lane {}", self.lanes.len() + 1), orientation, bounds: [rect.x, rect.y, rect.w, rect.h] });
+        self.lanes.len() - 1
+    }
+
+    // Moves a lane together with the shapes in it
+    pub fn move_lane(&mut self, lane: usize, delta: Vec2) {
+        if lane >= self.lanes.len() { return; }
+        let members: Vec<usize> = (0..self.shapes.len()).filter(|&i| self.lane_of_shape(i) == Some(lane)).collect();
+        let rect = self.lanes[lane].rect();
+        self.lanes[lane].set_rect(Rect::new(rect.x + delta.x, rect.y + delta.y, rect.w, rect.h));
+        for index in members {
+            self.shapes[index].center_position += delta;
+        }
+    }
+
+    // Moves every shape in the group, including those in nested groups
+    pub fn move_group(&mut self, group: usize, delta: Vec2) {
+        for index in 0..self.shapes.len() {
+            if self.shape_in_group(index, group) { self.shapes[index].center_position += delta; }
+        }
+    }
+}
+
+// A→B→C laid out left to right, shared with other modules' tests
+#[cfg(test)]
+pub(crate) fn chain() -> Diagram {
+    let mut diagram = Diagram::new();
+    for x in [0.0, 200.0, 400.0] {
+        diagram.add_shape(ShapeData::new(Vec2::new(x, 0.0)));
+    }
+    diagram.connect(0, ports::default_outgoing_port(), 1, ports::default_incoming_port());
+    diagram.connect(1, ports::default_outgoing_port(), 2, ports::default_incoming_port());
+    diagram
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoints(diagram: &Diagram) -> Vec<(usize, usize)> {
+        diagram.connections.iter().map(|c| (c.from_shape_index, c.to_shape_index)).collect()
+    }
+
+    #[test]
+    fn connect_allows_parallels_but_rejects_missing_endpoints() {
+        let mut diagram = chain();
+        assert_eq!(diagram.connect(0, 1, 7, 0), None);
+        assert_eq!(diagram.connect(0, 99, 2, 0), None);
+        assert_eq!(diagram.connect(0, 1, 2, 0), Some(2));
+        assert_eq!(endpoints(&diagram), vec![(0, 1), (1, 2), (0, 2)]);
+        assert_eq!(diagram.connect(0, 1, 1, 0), Some(3));
+        diagram.disconnect(3);
+        assert_eq!(diagram.neighbors(0, true), vec![1, 2]);
+        assert_eq!(diagram.neighbors(2, false), vec![1, 0]);
+        assert!(diagram.neighbors(2, true).is_empty());
+    }
+
+    #[test]
+    fn metadata_text_round_trips_tags_and_attributes() {
+        let mut shape = ShapeData::new(Vec2::ZERO);
+        shape.set_metadata_from_text(" backend, status = todo,, owner=alice, backend, api ");
+        assert_eq!(shape.tags, vec!["backend", "api"]);
+        assert_eq!(shape.attributes.get("status").map(String::as_str), Some("todo"));
+        assert_eq!(shape.metadata_text(), "backend, api, owner=alice, status=todo");
+        let reparsed = shape.metadata_text();
+        shape.set_metadata_from_text(&reparsed);
+        assert_eq!(shape.metadata_text(), reparsed);
+    }
+
+    #[test]
+    fn reachable_follows_connections_the_chosen_way() {
+        let mut diagram = chain();
+        diagram.add_shape(ShapeData::new(Vec2::new(0.0, 200.0)));
+        assert_eq!(diagram.reachable(1, Reach::Downstream), vec![false, true, true, false]);
+        assert_eq!(diagram.reachable(1, Reach::Upstream), vec![true, true, false, false]);
+        assert_eq!(diagram.reachable(1, Reach::Both), vec![true, true, true, false]);
+        assert_eq!(diagram.reachable(9, Reach::Both), vec![false; 4]);
+        assert_eq!(Reach::Both.next(), None);
+    }
+
+    #[test]
+    fn cycle_connections_finds_loops_but_not_branches_into_them() {
+        let mut diagram = chain();
+        assert!(diagram.cycle_connections().is_empty());
+        diagram.add_shape(ShapeData::new(Vec2::new(0.0, 200.0)));
+        diagram.connect(2, ports::default_outgoing_port(), 1, ports::default_incoming_port());
+        diagram.connect(3, ports::default_outgoing_port(), 1, ports::default_incoming_port());
+        diagram.connect(3, ports::default_outgoing_port(), 3, ports::default_incoming_port());
+        assert_eq!(diagram.cycle_connections(), vec![1, 2, 4]);
+        let components = diagram.strongly_connected_components();
+        assert_eq!(components[1], components[2]);
+        assert_ne!(components[0], components[1]);
+    }
+
+    #[test]
+    fn topological_order_and_critical_path_follow_connections() {
+        let mut diagram = chain();
+        diagram.add_shape(ShapeData::new(Vec2::new(0.0, 200.0)));
+        diagram.connect(3, ports::default_outgoing_port(), 2, ports::default_incoming_port());
+        assert_eq!(diagram.topological_order(), vec![0, 1, 3, 2]);
+        assert_eq!(diagram.critical_path(|_| 1.0), (vec![0, 1, 2], vec![0, 1]));
+        diagram.shapes[3].attributes.insert(String::from("duration"), String::from("5"));
+        let duration = |shape: &ShapeData| shape.attributes.get("duration").and_then(|d| d.parse().ok()).unwrap_or(1.0);
+        assert_eq!(diagram.critical_path(duration), (vec![3, 2], vec![2]));
+        diagram.connect(2, ports::default_outgoing_port(), 1, ports::default_incoming_port());
+        assert_eq!(diagram.topological_order(), vec![0, 3]);
+    }
+
+    #[test]
+    fn graph_stats_count_degrees_components_and_cycles() {
+        let mut diagram = chain();
+        diagram.add_shape(ShapeData::new(Vec2::new(0.0, 200.0)));
+        let stats = diagram.graph_stats();
+        assert_eq!((stats.node_count, stats.edge_count, stats.component_count, stats.acyclic), (4, 2, 2, true));
+        assert_eq!((stats.in_degree, stats.out_degree), (vec![0, 1, 1, 0], vec![1, 1, 0, 0]));
+        diagram.connect(2, ports::default_outgoing_port(), 0, ports::default_incoming_port());
+        assert!(!diagram.graph_stats().acyclic);
+    }
+
+    #[test]
+    fn notes_stay_out_of_the_graph() {
+        let mut diagram = chain();
+        let note = diagram.add_shape(ShapeData { kind: ShapeKind::Note, ports: Vec::new(), ..ShapeData::new(Vec2::new(0.0, 200.0)) });
+        assert_eq!(diagram.connect(0, ports::default_outgoing_port(), note, ports::default_incoming_port()), None);
+        let stats = diagram.graph_stats();
+        assert_eq!((stats.node_count, stats.edge_count, stats.component_count), (3, 2, 1));
+        assert_eq!(diagram.topological_order(), vec![0, 1, 2]);
+        let graph = diagram.without_notes();
+        assert_eq!((graph.shapes.len(), graph.connections.len()), (3, 2));
+    }
+
+    #[test]
+    fn delete_shape_drops_its_connections_and_reindexes() {
+        let mut diagram = chain();
+        diagram.connect(0, 1, 2, 0);
+        assert!(diagram.delete_shape(1).is_some());
+        assert_eq!(diagram.shapes.len(), 2);
+        assert_eq!(endpoints(&diagram), vec![(0, 1)]);
+        assert!(diagram.delete_shape(5).is_none());
+    }
+
+    #[test]
+    fn presentation_steps_follow_deletions_and_ungrouping() {
+        let mut diagram = chain();
+        let group = diagram.group_shapes(&[1, 2], "Tail").unwrap();
+        diagram.steps = vec![PresentationStep::Shapes(vec![0]), PresentationStep::Shapes(vec![0, 2]), PresentationStep::Group(group)];
+        assert_eq!(diagram.step_shapes(2), vec![1, 2]);
+        diagram.delete_shape(0);
+        assert_eq!(diagram.steps, vec![PresentationStep::Shapes(vec![1]), PresentationStep::Group(group)]);
+        diagram.ungroup(group);
+        assert_eq!(diagram.steps[1], PresentationStep::Shapes(vec![0, 1]));
+        assert!(diagram.step_shapes(5).is_empty());
+    }
+
+    #[test]
+    fn restack_shape_keeps_connections_attached() {
+        let mut diagram = chain();
+        diagram.shapes[0].text = Some(String::from("first"));
+        assert!(diagram.restack_shape(0, 2));
+        assert_eq!(diagram.shapes[2].text.as_deref(), Some("first"));
+        assert_eq!(endpoints(&diagram), vec![(2, 0), (0, 1)]);
+        assert!(diagram.restack_shape(2, 0));
+        assert_eq!(endpoints(&diagram), vec![(0, 1), (1, 2)]);
+        assert!(!diagram.restack_shape(0, 3));
+    }
+
+    #[test]
+    fn connection_style_survives_reversing_copying_and_saving() {
+        let mut diagram = chain();
+        diagram.connections[0].color_rgb = Some([250, 150, 80]);
+        diagram.connections[0].line_width = Some(3.5);
+        diagram.connections[0].dash = LineDash::Dotted;
+        diagram.reverse_connection(0).unwrap();
+        let copied = diagram.extract(&[0, 1]);
+        assert_eq!(copied.connections[0].dash, LineDash::Dotted);
+        let saved = serde_json::to_string(&copied).unwrap();
+        let loaded: Diagram = serde_json::from_str(&saved).unwrap();
+        assert_eq!((loaded.connections[0].color_rgb, loaded.connections[0].line_width), (Some([250, 150, 80]), Some(3.5)));
+        assert!(!serde_json::to_string(&chain()).unwrap().contains("dash"));
+        assert_eq!(LineDash::Dotted.next(), LineDash::Solid);
+    }
+
+    #[test]
+    fn groups_nest_and_ungroup_into_their_parent() {
+        let mut diagram = chain();
+        let outer = diagram.group_shapes(&[0, 1, 2], "Outer").unwrap();
+        let inner = diagram.group_shapes(&[1, 2], "Inner").unwrap();
+        assert_eq!(diagram.groups[inner].parent, Some(outer));
+        assert!(diagram.shape_in_group(2, outer));
+        assert_eq!(diagram.group_depth(inner), 1);
+        assert!(diagram.ungroup(outer));
+        assert_eq!(diagram.groups.len(), 1);
+        assert_eq!(diagram.groups[0].parent, None);
+        assert_eq!(diagram.shapes[0].group, None);
+        assert_eq!(diagram.shapes[1].group, Some(0));
+        // Deleting its last shapes removes the group
+        diagram.delete_shape(2);
+        diagram.delete_shape(1);
+        assert!(diagram.groups.is_empty());
+    }
+
+    #[test]
+    fn collapsed_group_lends_ports_and_hides_inner_connections() {
+        let mut diagram = chain();
+        let group = diagram.group_shapes(&[1, 2], "Tail").unwrap();
+        diagram.groups[group].collapsed = true;
+        let size = Vec2::new(100.0, 50.0);
+        let frame = diagram.group_frame(group, size).unwrap();
+        assert_eq!((frame.w, frame.h), (100.0, 50.0));
+        let (anchor, side) = diagram.displayed_port_anchor(1, ports::default_incoming_port(), size).unwrap();
+        assert_eq!(side, PortSide::Top);
+        assert_eq!(anchor, Vec2::new(frame.x + frame.w / 2.0, frame.y));
+        assert!(!diagram.connection_hidden(&diagram.connections[0]));
+        assert!(diagram.connection_hidden(&diagram.connections[1]));
+    }
+
+    #[test]
+    fn collapsing_bundles_connections_crossing_into_the_group() {
+        let mut diagram = chain();
+        diagram.connect(0, ports::default_outgoing_port(), 2, ports::default_incoming_port());
+        assert_eq!(diagram.bundle_sizes(), vec![1, 1, 1]);
+        let group = diagram.group_shapes(&[1, 2], "Tail").unwrap();
+        diagram.groups[group].collapsed = true;
+        assert_eq!(diagram.displayed_end(2), DisplayedEnd::Group(group));
+        assert_eq!(diagram.bundle_sizes(), vec![2, 1, 0]);
+        diagram.groups[group].collapsed = false;
+        assert_eq!(diagram.bundle_sizes(), vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn rotated_shapes_turn_their_ports_and_outline() {
+        let mut diagram = chain();
+        let size = Vec2::new(100.0, 50.0);
+        let center = diagram.shapes[0].center_position;
+        diagram.shapes[0].rotation = std::f32::consts::FRAC_PI_2;
+        let (anchor, side) = diagram.port_anchor(0, ports::default_outgoing_port(), size).unwrap();
+        assert_eq!(side, PortSide::Left);
+        assert!(anchor.distance(center + Vec2::new(-25.0, 0.0)) < 1e-3);
+        // Standing on end, the shape reaches further up and down than across
+        assert!(diagram.shapes[0].contains(center + Vec2::new(0.0, 45.0), size));
+        assert!(!diagram.shapes[0].contains(center + Vec2::new(45.0, 0.0), size));
+        let bounds = diagram.shapes[0].bounds(size);
+        assert!((bounds.w - 50.0).abs() < 1e-3 && (bounds.h - 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn erasing_removes_strokes_near_the_point() {
+        let mut diagram = Diagram::new();
+        let stroke = |points: Vec<Vec2>| Annotation { points, color_rgb: [255, 0, 0], width: 4.0, highlight: false };
+        diagram.annotations.push(stroke(vec![Vec2::new(0.0, 0.0), Vec2::new(100.0, 0.0)]));
+        diagram.annotations.push(stroke(vec![Vec2::new(0.0, 50.0), Vec2::new(100.0, 50.0)]));
+        assert_eq!(diagram.erase_annotations(Vec2::new(50.0, 20.0), 5.0), 0);
+        assert_eq!(diagram.erase_annotations(Vec2::new(50.0, 45.0), 5.0), 1);
+        assert_eq!(diagram.annotations[0].points[1], Vec2::new(100.0, 0.0));
+    }
+
+    #[test]
+    fn lanes_stack_and_carry_their_shapes() {
+        let mut diagram = chain();
+        let size = Vec2::new(100.0, 50.0);
+        let first = diagram.add_lane(LaneOrientation::Horizontal, size);
+        let second = diagram.add_lane(LaneOrientation::Horizontal, size);
+        let (first_rect, second_rect) = (diagram.lanes[first].rect(), diagram.lanes[second].rect());
+        assert_eq!(second_rect.y, first_rect.bottom());
+        assert!((0..3).all(|i| diagram.lane_of_shape(i) == Some(first)));
+        diagram.move_lane(first, Vec2::new(0.0, 10.0));
+        assert_eq!(diagram.shapes[1].center_position, Vec2::new(200.0, 10.0));
+        assert_eq!(diagram.lanes[first].rect().y, first_rect.y + 10.0);
+        assert_eq!(lane_containing(&diagram.lanes, Vec2::new(0.0, second_rect.y + 20.0)), Some(second));
+    }
+
+    #[test]
+    fn move_shape_updates_position() {
+        let mut diagram = chain();
+        assert!(diagram.move_shape(2, Vec2::new(10.0, 20.0)));
+        assert_eq!(diagram.shapes[2].center_position, Vec2::new(10.0, 20.0));
+        assert!(!diagram.move_shape(3, Vec2::ZERO));
+    }
+
+    #[test]
+    fn reconnect_through_splices_out_the_middle_shape() {
+        let mut diagram = chain();
+        diagram.reconnect_through(1);
+        diagram.delete_shape(1);
+        assert_eq!(endpoints(&diagram), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn reconnect_refuses_missing_ports() {
+        let mut diagram = chain();
+        let mut moved = diagram.connections[1].clone();
+        moved.from_shape_index = 0;
+        assert!(diagram.reconnect(1, moved.clone()).is_ok());
+        let mut missing = moved;
+        missing.to_port = 99;
+        assert!(diagram.reconnect(1, missing).is_err());
+        assert_eq!(endpoints(&diagram), vec![(0, 1), (0, 2)]);
+    }
+
+    #[test]
+    fn parallel_connections_fan_out_around_the_middle() {
+        let mut diagram = chain();
+        assert_eq!(parallel_offsets(&diagram.connections), vec![0.0, 0.0]);
+        diagram.connect(1, 1, 0, 0);
+        diagram.connect(0, 2, 1, 3);
+        let offsets = parallel_offsets(&diagram.connections);
+        let spacing = PARALLEL_CONNECTOR_SPACING;
+        // 0→1, 1→0 and 0→1 again take slots -1, 0 and +1; 1→2 has no parallels
+        assert_eq!(offsets, vec![-spacing, 0.0, 0.0, spacing]);
+        diagram.connections.swap(0, 2);
+        assert_eq!(parallel_offsets(&diagram.connections)[0], spacing); // Reversed, so measured the other way
+    }
+
+    #[test]
+    fn reverse_connection_swaps_ends() {
+        let mut diagram = chain();
+        diagram.connections[0].label = Some(String::from("yes"));
+        diagram.connections[0].bend = Some(30.0);
+        assert!(diagram.reverse_connection(0).is_ok());
+        assert_eq!(endpoints(&diagram), vec![(1, 0), (1, 2)]);
+        assert_eq!((diagram.connections[0].from_port, diagram.connections[0].to_port), (0, 1));
+        assert_eq!(diagram.connections[0].label.as_deref(), Some("yes"));
+        assert_eq!(diagram.connections[0].bend, Some(-30.0), "the curve stays where it was");
+        assert!(diagram.reverse_connection(9).is_err());
+    }
+
+    #[test]
+    fn insert_into_connection_routes_through_new_shape() {
+        let mut diagram = chain();
+        diagram.connections[0].label = Some(String::from("yes"));
+        let middle = diagram.add_shape(ShapeData::new(Vec2::new(100.0, 0.0)));
+        assert_eq!(diagram.insert_into_connection(0, middle, 0, 1), Some(2));
+        assert_eq!(endpoints(&diagram), vec![(0, middle), (1, 2), (middle, 1)]);
+        assert_eq!(diagram.connections[0].label.as_deref(), Some("yes"));
+        assert_eq!(diagram.connections[2].label, None);
+    }
+
+    #[test]
+    fn extract_and_append_keep_inner_connections() {
+        let mut diagram = chain();
+        let fragment = diagram.extract(&[1, 2]);
+        assert_eq!(endpoints(&fragment), vec![(0, 1)]);
+        let added = diagram.append(&fragment, Vec2::new(0.0, 100.0));
+        assert_eq!(added, vec![3, 4]);
+        assert_eq!(diagram.shapes[3].center_position, Vec2::new(200.0, 100.0));
+        assert_eq!(endpoints(&diagram), vec![(0, 1), (1, 2), (3, 4)]);
+    }
+
+    #[test]
+    fn validated_drops_dangling_connections() {
+        let mut diagram = chain();
+        diagram.connections.push(Connection::new(0, 1, 9, 0));
+        diagram.connections.push(Connection::new(0, 42, 2, 0));
+        assert_eq!(endpoints(&diagram.validated()), vec![(0, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn hidden_layers_hide_their_shapes_and_connections() {
+        let mut diagram = chain();
+        let top = diagram.add_layer();
+        assert_eq!((top, diagram.layer_count()), (1, 2));
+        diagram.shapes[2].layer = top;
+        diagram.layer_mut(top).unwrap().visible = false;
+        assert!(diagram.shape_on_hidden_layer(2) && !diagram.shape_on_hidden_layer(1));
+        assert!(!diagram.connection_hidden(&diagram.connections[0]));
+        assert!(diagram.connection_hidden(&diagram.connections[1]));
+
+        diagram.shapes[0].layer = 7;
+        diagram.layer_mut(0).unwrap().locked = true;
+        let diagram = diagram.validated();
+        assert_eq!(diagram.shapes[0].layer, 0);
+        assert!(diagram.shape_locked(0) && !diagram.shape_locked(2));
+    }
+}
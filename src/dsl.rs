@@ -0,0 +1,314 @@
+// dsl.rs
+// Diagram-as-code: a line-based text form of the graph, for editing side by side with the canvas.
+//
+//     # comments start with '#'
+//     Start @ 0, 0                 a shape, with its position
+//     Check                        a shape placed automatically
+//     Start -> Check: ok           a connection, with an optional label
+//     "Says: hi" -> _4             quoted names; `_4` is shape 4, for shapes without a usable name
+//
+// A shape's name is the first line of its text. Shapes without text, and all but the first of
+// shapes sharing a name, go by their index instead.
+
+use ggez::glam::Vec2;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+
+use crate::layout::{self, LayoutSpacing};
+use crate::{ports, Diagram, ShapeData};
+
+// --- How the text refers to a shape ---
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NodeName {
+    Label(String),
+    Shape(usize), // `_N`: the shape at index N
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DslNode {
+    pub name: NodeName,
+    pub position: Option<Vec2>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DslEdge {
+    pub from: NodeName,
+    pub to: NodeName,
+    pub label: Option<String>,
+}
+
+// --- A parsed document: shapes in order of first mention, and connections ---
+#[derive(Debug, Default, PartialEq)]
+pub struct DslGraph {
+    pub nodes: Vec<DslNode>,
+    pub edges: Vec<DslEdge>,
+}
+
+impl DslGraph {
+    fn mention(&mut self, name: &NodeName) -> usize {
+        match self.nodes.iter().position(|node| node.name == *name) {
+            Some(index) => index,
+            None => { self.nodes.push(DslNode { name: name.clone(), position: None }); self.nodes.len() - 1 }
+        }
+    }
+}
+
+// --- A line the parser couldn't read (line numbers from 1) ---
+#[derive(Clone, Debug, PartialEq)]
+pub struct DslError {
+    pub line: usize,
+    pub message: String,
+}
+
+// Name each shape goes by in the text
+pub fn node_names(diagram: &Diagram) -> Vec<NodeName> {
+    let mut seen = HashSet::new();
+    diagram.shapes.iter().enumerate().map(|(index, shape)| {
+        match shape.text.as_deref().and_then(|t| t.lines().next()).map(str::trim).filter(|t| !t.is_empty()) {
+            Some(label) if seen.insert(label.to_string()) => NodeName::Label(label.to_string()),
+            _ => NodeName::Shape(index),
+        }
+    }).collect()
+}
+
+fn write_name(out: &mut String, name: &NodeName) {
+    match name {
+        NodeName::Shape(index) => { let _ = write!(out, "_{}", index); }
+        NodeName::Label(label) if needs_quotes(label) => {
+            let _ = write!(out, "\"{}\"", label.replace('\\', "\\\\").replace('"', "\\\""));
+        }
+        NodeName::Label(label) => out.push_str(label),
+    }
+}
+
+// Labels the bare form would misread: with separators in them, or looking like a comment or `_N`
+fn needs_quotes(label: &str) -> bool {
+    label.contains(['"', '@', ':', '#']) || label.contains("->") || label.trim() != label || shape_reference(label).is_some()
+}
+
+fn shape_reference(bare: &str) -> Option<usize> {
+    bare.strip_prefix('_').filter(|digits| !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()))?.parse().ok()
+}
+
+// The diagram as text: every shape with its position, then every connection
+pub fn render(diagram: &Diagram) -> String {
+    let names = node_names(diagram);
+    let mut out = String::new();
+    for (shape, name) in diagram.shapes.iter().zip(&names) {
+        write_name(&mut out, name);
+        let _ = writeln!(out, " @ {}, {}", shape.center_position.x.round(), shape.center_position.y.round());
+    }
+    if !diagram.connections.is_empty() && !diagram.shapes.is_empty() { out.push('\n'); }
+    for connection in diagram.connections.iter().filter(|c| c.from_shape_index < names.len() && c.to_shape_index < names.len()) {
+        write_name(&mut out, &names[connection.from_shape_index]);
+        out.push_str(" -> ");
+        write_name(&mut out, &names[connection.to_shape_index]);
+        if let Some(label) = connection.label.as_deref().filter(|l| !l.is_empty()) {
+            let _ = write!(out, ": {}", label.replace('\n', " "));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// --- Reading one line ---
+struct LineParser<'a> {
+    rest: &'a str,
+}
+
+impl LineParser<'_> {
+    fn skip_spaces(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_spaces();
+        match self.rest.strip_prefix(token) {
+            Some(rest) => { self.rest = rest; true }
+            None => false,
+        }
+    }
+
+    fn name(&mut self) -> Result<NodeName, String> {
+        self.skip_spaces();
+        if let Some(quoted) = self.rest.strip_prefix('"') {
+            let mut label = String::new();
+            let mut chars = quoted.char_indices();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '"' => { self.rest = &quoted[i + 1..]; return Ok(NodeName::Label(label)); }
+                    '\\' => label.extend(chars.next().map(|(_, escaped)| escaped)),
+                    _ => label.push(c),
+                }
+            }
+            return Err(String::from("unterminated quoted name"));
+        }
+        let end = [self.rest.find("->"), self.rest.find('@'), self.rest.find(':')].into_iter().flatten().min().unwrap_or(self.rest.len());
+        let bare = self.rest[..end].trim();
+        self.rest = &self.rest[end..];
+        if bare.is_empty() { return Err(String::from("expected a name")); }
+        Ok(shape_reference(bare).map_or_else(|| NodeName::Label(bare.to_string()), NodeName::Shape))
+    }
+
+    fn position(&mut self) -> Result<Vec2, String> {
+        let mut coordinates = self.rest.split(',').map(|part| part.trim().parse::<f32>());
+        match (coordinates.next(), coordinates.next(), coordinates.next()) {
+            (Some(Ok(x)), Some(Ok(y)), None) => { self.rest = ""; Ok(Vec2::new(x, y)) }
+            _ => Err(String::from("expected a position 'x, y' after '@'")),
+        }
+    }
+}
+
+pub fn parse(source: &str) -> Result<DslGraph, DslError> {
+    let mut graph = DslGraph::default();
+    for (number, line) in source.lines().enumerate() {
+        let error = |message: String| DslError { line: number + 1, message };
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') { continue; }
+        let mut parser = LineParser { rest: trimmed };
+        let from = parser.name().map_err(error)?;
+        if parser.eat("->") {
+            let to = parser.name().map_err(error)?;
+            let label = if parser.eat(":") { Some(parser.rest.trim().to_string()).filter(|l| !l.is_empty()) } else { None };
+            if label.is_none() && !parser.rest.trim().is_empty() { return Err(error(format!("unexpected '{}'", parser.rest.trim()))); }
+            graph.mention(&from);
+            graph.mention(&to);
+            graph.edges.push(DslEdge { from, to, label });
+        } else if parser.eat("@") {
+            let position = parser.position().map_err(error)?;
+            let index = graph.mention(&from);
+            graph.nodes[index].position = Some(position);
+        } else if parser.rest.trim().is_empty() {
+            graph.mention(&from);
+        } else {
+            return Err(error(format!("unexpected '{}'", parser.rest.trim())));
+        }
+    }
+    Ok(graph)
+}
+
+// Makes the diagram match the text, changing as little as possible: shapes and connections the text
+// still names keep their styling, the rest are removed, and new ones are added (and laid out unless
+// positioned). A new name on the line of a shape whose name is gone, at that shape's position,
+// renames the shape. Positions are only applied where they differ from the rounded ones `render`
+// writes. Returns None if nothing changed, or else the index the added connections start at.
+pub fn apply(diagram: &mut Diagram, graph: &DslGraph, spacing: &LayoutSpacing) -> Option<usize> {
+    let mut names = node_names(diagram);
+    let wanted: HashSet<&NodeName> = graph.nodes.iter().map(|node| &node.name).collect();
+    let mut changed = false;
+    for node in &graph.nodes {
+        let (NodeName::Label(label), Some(position)) = (&node.name, node.position) else { continue; };
+        if names.contains(&node.name) { continue; }
+        let renamed = (0..names.len()).find(|&i| !wanted.contains(&names[i]) && diagram.shapes[i].center_position.round() == position);
+        if let Some(index) = renamed {
+            rename(&mut diagram.shapes[index], label);
+            names[index] = node.name.clone();
+            changed = true;
+        }
+    }
+    for index in (0..names.len()).rev().filter(|&i| !wanted.contains(&names[i])) {
+        diagram.delete_shape(index);
+        changed = true;
+    }
+    let mut index_of: HashMap<&NodeName, usize> = names.iter().filter(|name| wanted.contains(name)).enumerate().map(|(new, name)| (name, new)).collect();
+    let mut added = Vec::new();
+    for node in &graph.nodes {
+        let index = match index_of.get(&node.name) {
+            Some(&index) => index,
+            None => {
+                let text = match &node.name { NodeName::Label(label) => Some(label.clone()), NodeName::Shape(_) => None };
+                let index = diagram.add_shape(ShapeData { text, ..ShapeData::new(node.position.unwrap_or(Vec2::ZERO)) });
+                if node.position.is_none() { added.push(index); }
+                index_of.insert(&node.name, index);
+                changed = true;
+                index
+            }
+        };
+        if let Some(position) = node.position {
+            if diagram.shapes[index].center_position.round() != position {
+                diagram.move_shape(index, position);
+                changed = true;
+            }
+        }
+    }
+
+    // Connections: keep exact matches, then relabel ones between the same shapes, then add/remove the rest
+    let edges: Vec<(usize, usize, Option<&str>)> = graph.edges.iter()
+        .map(|edge| (index_of[&edge.from], index_of[&edge.to], edge.label.as_deref()))
+        .collect();
+    let mut kept = vec![false; diagram.connections.len()];
+    let mut pending = Vec::new();
+    for &(from, to, label) in &edges {
+        let exact = diagram.connections.iter().enumerate().position(|(i, c)| {
+            !kept[i] && c.from_shape_index == from && c.to_shape_index == to && c.label.as_deref().filter(|l| !l.is_empty()) == label
+        });
+        match exact {
+            Some(i) => kept[i] = true,
+            None => pending.push((from, to, label)),
+        }
+    }
+    let mut missing = Vec::new();
+    for (from, to, label) in pending {
+        match diagram.connections.iter().enumerate().position(|(i, c)| !kept[i] && c.from_shape_index == from && c.to_shape_index == to) {
+            Some(i) => { kept[i] = true; diagram.connections[i].label = label.map(String::from); changed = true; }
+            None => missing.push((from, to, label)),
+        }
+    }
+    for index in (0..kept.len()).rev().filter(|&i| !kept[i]) {
+        diagram.disconnect(index);
+        changed = true;
+    }
+    let first_new_connection = diagram.connections.len();
+    for (from, to, label) in missing {
+        if let Some(index) = diagram.connect(from, ports::default_outgoing_port(), to, ports::default_incoming_port()) {
+            diagram.connections[index].label = label.map(String::from);
+            changed = true;
+        }
+    }
+    layout::place_added_shapes(diagram, &added, spacing);
+    changed.then_some(first_new_connection)
+}
+
+// Swaps the first line of a shape's text, the part the text names it by, keeping the rest
+fn rename(shape: &mut ShapeData, label: &str) {
+    let rest = shape.text.as_deref().and_then(|text| text.split_once('\n')).map(|(_, rest)| rest.to_string());
+    shape.text = Some(match rest {
+        Some(rest) => format!("{}\n{}", label, rest),
+        None => label.to_string(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dsl_text_round_trips_and_applies_minimal_changes() {
+        let spacing = LayoutSpacing { horizontal: 200.0, vertical: 150.0 };
+        let mut diagram = Diagram::new();
+        let graph = parse("# plan\nStart @ 0, 0\nStart -> \"Says: hi\": go\n\"Says: hi\" -> _7\n").unwrap();
+        assert!(apply(&mut diagram, &graph, &spacing).is_some());
+        assert_eq!(diagram.shapes.len(), 3);
+        assert_eq!(diagram.shapes[1].text.as_deref(), Some("Says: hi"));
+        assert_eq!(diagram.shapes[2].text, None);
+        assert_eq!(diagram.connections[0].label.as_deref(), Some("go"));
+
+        diagram.connections[0].line_width = Some(4.0);
+        let text = render(&diagram);
+        assert!(apply(&mut diagram, &parse(&text).unwrap(), &spacing).is_none());
+        let relabelled = text.replace(": go", ": stop").replace("Start @ 0, 0", "Start @ 40, 0");
+        assert!(apply(&mut diagram, &parse(&relabelled).unwrap(), &spacing).is_some());
+        assert_eq!((diagram.connections[0].label.as_deref(), diagram.connections[0].line_width), (Some("stop"), Some(4.0)));
+        assert_eq!(diagram.shapes[0].center_position, Vec2::new(40.0, 0.0));
+
+        // A new name in place of an old one, at its position, renames the shape rather than replacing it
+        diagram.shapes[0].tags.push(String::from("entry"));
+        let renamed = render(&diagram).replace("Start", "Begin");
+        assert!(apply(&mut diagram, &parse(&renamed).unwrap(), &spacing).is_some());
+        assert_eq!((diagram.shapes[0].text.as_deref(), diagram.shapes[0].tags.as_slice()), (Some("Begin"), &[String::from("entry")][..]));
+        assert_eq!((diagram.shapes.len(), diagram.connections[0].line_width), (3, Some(4.0)));
+
+        let error = parse("A\nB -> \n").unwrap_err();
+        assert_eq!(error.line, 2);
+    }
+}
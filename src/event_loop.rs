@@ -0,0 +1,149 @@
+// event_loop.rs
+// The editor's own winit event loop. It dispatches to the EventHandler just like ggez's
+// `event::run`, and also passes on what ggez drops: touchpad pinches, two-finger scrolls (pixel
+// deltas) kept apart from mouse wheel notches (line deltas), which finger a touch belongs to, the
+// input method's composition, files dropped onto the window, and changes of the window's scale factor.
+
+use ggez::event::{self, ErrorOrigin, EventHandler};
+use ggez::input::keyboard::KeyInput;
+use ggez::winit::event::{ElementState, Event, Ime, KeyboardInput, MouseScrollDelta, TouchPhase, WindowEvent};
+use ggez::winit::event_loop::{ControlFlow, EventLoop};
+use ggez::{Context, GameError, GameResult};
+use std::path::PathBuf;
+use std::time::Instant;
+
+use crate::logging;
+
+// --- Gestures on top of ggez's events ---
+pub trait GestureHandler: EventHandler<GameError> {
+    // Touchpad pinch: `delta` is the change in magnification, e.g. 0.05 for 5% larger
+    fn pinch_event(&mut self, ctx: &mut Context, delta: f32) -> GameResult;
+    // Two-finger touchpad scroll, in physical pixels
+    fn scroll_pan_event(&mut self, ctx: &mut Context, dx: f32, dy: f32) -> GameResult;
+    // Touchscreen finger `id`, in physical pixels
+    fn finger_event(&mut self, ctx: &mut Context, id: u64, phase: TouchPhase, x: f32, y: f32) -> GameResult;
+    // Input method text being composed, and the byte range of its caret if shown; empty once it is
+    // committed (as text_input_event) or cancelled
+    fn preedit_event(&mut self, ctx: &mut Context, text: String, cursor: Option<(usize, usize)>) -> GameResult;
+    // A file dragged in from outside and let go over the window
+    fn file_drop_event(&mut self, ctx: &mut Context, path: PathBuf) -> GameResult;
+    // The window's physical pixels per logical one changed, e.g. on moving to a HiDPI monitor
+    fn scale_factor_event(&mut self, ctx: &mut Context, scale_factor: f32) -> GameResult;
+    // When the next update and draw are due; the loop sleeps until then unless an event comes in
+    fn next_frame_at(&mut self) -> Instant;
+}
+
+pub fn run<S: GestureHandler + 'static>(mut ctx: Context, event_loop: EventLoop<()>, mut state: S) -> ! {
+    event_loop.run(move |mut event, _, control_flow| {
+        let ctx = &mut ctx;
+        if !ctx.continuing {
+            *control_flow = ControlFlow::Exit;
+            return;
+        }
+        // Keeps ggez's keyboard, mouse and window state up to date
+        event::process_event(ctx, &mut event);
+        match event {
+            Event::WindowEvent { event, .. } => {
+                if let Some((origin, result)) = window_event(ctx, &mut state, event) {
+                    if failed(ctx, &mut state, result, origin) { *control_flow = ControlFlow::Exit; }
+                }
+            }
+            // Every batch of events ends here: a frame runs if one is due, then the loop waits for the next
+            Event::MainEventsCleared => {
+                if Instant::now() >= state.next_frame_at() && !frame(ctx, &mut state) {
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+                *control_flow = ControlFlow::WaitUntil(state.next_frame_at());
+            }
+            _ => {}
+        }
+    })
+}
+
+// The handler's result for an event the editor handles, tagged with where it came from
+fn window_event<S: GestureHandler>(ctx: &mut Context, state: &mut S, event: WindowEvent) -> Option<(ErrorOrigin, GameResult)> {
+    let handled = match event {
+        WindowEvent::Resized(size) => (ErrorOrigin::ResizeEvent, state.resize_event(ctx, size.width as f32, size.height as f32)),
+        WindowEvent::CloseRequested => {
+            let result = state.quit_event(ctx).map(|keep_open| if !keep_open { ctx.continuing = false; });
+            (ErrorOrigin::QuitEvent, result)
+        }
+        WindowEvent::Focused(gained) => (ErrorOrigin::FocusEvent, state.focus_event(ctx, gained)),
+        WindowEvent::ReceivedCharacter(character) => (ErrorOrigin::TextInputEvent, state.text_input_event(ctx, character)),
+        WindowEvent::Ime(Ime::Preedit(text, cursor)) => (ErrorOrigin::TextInputEvent, state.preedit_event(ctx, text, cursor)),
+        WindowEvent::Ime(Ime::Commit(text)) => {
+            (ErrorOrigin::TextInputEvent, text.chars().try_for_each(|character| state.text_input_event(ctx, character)))
+        }
+        WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode, scancode, .. }, .. } => {
+            let input = KeyInput { scancode, keycode: virtual_keycode, mods: ctx.keyboard.active_mods() };
+            let repeated = ctx.keyboard.is_key_repeated();
+            (ErrorOrigin::KeyDownEvent, state.key_down_event(ctx, input, repeated))
+        }
+        WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Released, virtual_keycode, scancode, .. }, .. } => {
+            let input = KeyInput { scancode, keycode: virtual_keycode, mods: ctx.keyboard.active_mods() };
+            (ErrorOrigin::KeyUpEvent, state.key_up_event(ctx, input))
+        }
+        WindowEvent::MouseWheel { delta: MouseScrollDelta::LineDelta(x, y), .. } => (ErrorOrigin::MouseWheelEvent, state.mouse_wheel_event(ctx, x, y)),
+        WindowEvent::MouseWheel { delta: MouseScrollDelta::PixelDelta(delta), .. } => {
+            (ErrorOrigin::MouseWheelEvent, state.scroll_pan_event(ctx, delta.x as f32, delta.y as f32))
+        }
+        WindowEvent::TouchpadMagnify { delta, .. } => (ErrorOrigin::MouseWheelEvent, state.pinch_event(ctx, delta as f32)),
+        WindowEvent::MouseInput { state: ElementState::Pressed, button, .. } => {
+            let position = ctx.mouse.position();
+            (ErrorOrigin::MouseButtonDownEvent, state.mouse_button_down_event(ctx, button, position.x, position.y))
+        }
+        WindowEvent::MouseInput { state: ElementState::Released, button, .. } => {
+            let position = ctx.mouse.position();
+            (ErrorOrigin::MouseButtonUpEvent, state.mouse_button_up_event(ctx, button, position.x, position.y))
+        }
+        WindowEvent::CursorMoved { .. } => {
+            let (position, delta) = (ctx.mouse.position(), ctx.mouse.last_delta());
+            (ErrorOrigin::MouseMotionEvent, state.mouse_motion_event(ctx, position.x, position.y, delta.x, delta.y))
+        }
+        WindowEvent::Touch(touch) => {
+            (ErrorOrigin::TouchEvent, state.finger_event(ctx, touch.id, touch.phase, touch.location.x as f32, touch.location.y as f32))
+        }
+        WindowEvent::DroppedFile(path) => (ErrorOrigin::MouseButtonUpEvent, state.file_drop_event(ctx, path)),
+        WindowEvent::ScaleFactorChanged { scale_factor, .. } => (ErrorOrigin::ResizeEvent, state.scale_factor_event(ctx, scale_factor as f32)),
+        _ => return None,
+    };
+    Some(handled)
+}
+
+// One update and draw, as ggez's loop does them; false when the editor should close
+fn frame<S: GestureHandler>(ctx: &mut Context, state: &mut S) -> bool {
+    ctx.time.tick();
+    if ctx.quit_requested {
+        ctx.quit_requested = false;
+        match state.quit_event(ctx) {
+            Ok(false) => ctx.continuing = false,
+            Ok(true) => {}
+            result => if failed(ctx, state, result.map(|_| ()), ErrorOrigin::QuitEvent) { return false; },
+        }
+    }
+    let result = state.update(ctx);
+    if failed(ctx, state, result, ErrorOrigin::Update) { return false; }
+    if let Err(e) = ctx.gfx.begin_frame() {
+        log::error!(target: logging::RENDER, "Could not begin a frame: {}", e);
+        return false;
+    }
+    let result = state.draw(ctx);
+    if failed(ctx, state, result, ErrorOrigin::Draw) { return false; }
+    if let Err(e) = ctx.gfx.end_frame() {
+        log::error!(target: logging::RENDER, "Could not present a frame: {}", e);
+        return false;
+    }
+    // Deltas accumulate per frame, and the "just pressed" queries compare against the last one
+    ctx.mouse.reset_delta();
+    ctx.keyboard.save_keyboard_state();
+    ctx.mouse.save_mouse_state();
+    true
+}
+
+// Hands an error to the EventHandler, which decides whether the editor closes
+fn failed<S: GestureHandler>(ctx: &mut Context, state: &mut S, result: GameResult, origin: ErrorOrigin) -> bool {
+    let Err(e) = result else { return false; };
+    log::error!(target: logging::RENDER, "Error in {:?}: {}", origin, e);
+    state.on_error(ctx, origin, e)
+}
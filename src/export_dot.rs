@@ -0,0 +1,91 @@
+// export_dot.rs
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::diagram::lane_containing;
+use crate::shape_kind::ShapeKind;
+use crate::{Connection, Lane, ShapeData};
+
+const RESERVED_NODE_ATTRIBUTES: [&str; 4] = ["label", "shape", "pos", "tags"];
+
+// Node identifier used for a shape in DOT output
+pub fn node_name(shape_index: usize) -> String {
+    format!("n{}", shape_index)
+}
+
+pub fn escape_dot_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Graphviz node shape matching a shape kind
+fn dot_shape(kind: ShapeKind) -> &'static str {
+    match kind {
+        ShapeKind::Rectangle => "box",
+        ShapeKind::Ellipse => "ellipse",
+        ShapeKind::Diamond => "diamond",
+        ShapeKind::Parallelogram => "parallelogram",
+        ShapeKind::Note => "note",
+    }
+}
+
+// A shape's key=value attributes, then its tags joined into `tags`, as extra node attributes.
+// Attributes named like the ones written for every node are left out rather than overriding them.
+fn metadata_attributes(shape: &ShapeData) -> String {
+    let mut attributes = String::new();
+    for (key, value) in shape.attributes.iter().filter(|(key, _)| !RESERVED_NODE_ATTRIBUTES.contains(&key.as_str())) {
+        let _ = write!(attributes, ", \"{}\"=\"{}\"", escape_dot_string(key), escape_dot_string(value));
+    }
+    if !shape.tags.is_empty() {
+        let _ = write!(attributes, ", tags=\"{}\"", escape_dot_string(&shape.tags.join(",")));
+    }
+    attributes
+}
+
+// Positions are written as pinned `pos` attributes with y negated, since Graphviz's y axis points up.
+// Each lane becomes a cluster holding the shapes in it.
+pub fn render_dot(shapes: &[ShapeData], connections: &[Connection], lanes: &[Lane]) -> String {
+    let mut dot = String::from("digraph diagram {\n    node [shape=box, style=rounded];\n");
+    let shape_lanes: Vec<Option<usize>> = shapes.iter().map(|s| lane_containing(lanes, s.center_position)).collect();
+    for (lane_index, lane) in lanes.iter().enumerate() {
+        let _ = writeln!(dot, "    subgraph cluster_lane{} {{\n        label=\"{}\";", lane_index, escape_dot_string(&lane.title));
+        for (index, _) in shape_lanes.iter().enumerate().filter(|(_, l)| **l == Some(lane_index)) {
+            let _ = writeln!(dot, "        {};", node_name(index));
+        }
+        dot.push_str("    }\n");
+    }
+    for (index, shape) in shapes.iter().enumerate() {
+        let label = shape.text.as_deref().unwrap_or("");
+        let _ = writeln!(
+            dot,
+            "    {} [label=\"{}\", shape={}, pos=\"{:.2},{:.2}!\"{}];",
+            node_name(index), escape_dot_string(label), dot_shape(shape.kind), shape.center_position.x, -shape.center_position.y,
+            metadata_attributes(shape)
+        );
+    }
+    for connection in connections {
+        if connection.from_shape_index >= shapes.len() || connection.to_shape_index >= shapes.len() { continue; }
+        let attributes = match connection.label.as_deref() {
+            Some(label) => format!(" [label=\"{}\"]", escape_dot_string(label)),
+            None => String::new(),
+        };
+        let _ = writeln!(dot, "    {} -> {}{};", node_name(connection.from_shape_index), node_name(connection.to_shape_index), attributes);
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+pub fn export_dot(path: &Path, shapes: &[ShapeData], connections: &[Connection], lanes: &[Lane]) -> Result<(), String> {
+    let dot = render_dot(shapes, connections, lanes);
+    fs::write(path, dot).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
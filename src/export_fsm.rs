@@ -0,0 +1,128 @@
+// export_fsm.rs
+// A diagram read as a state machine and written out as Rust: shapes are states, connections are
+// transitions named by their labels. The generated file holds a `State` and an `Event` enum and
+// `State::next`, a match over (state, event), so a simple FSM can be drawn rather than hand-kept.
+
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use unicode_ident::{is_xid_continue, is_xid_start};
+
+use crate::{Connection, ShapeData};
+
+// UpperCamelCase identifier from a label: "waiting for input" -> WaitingForInput. Words are runs of
+// characters Rust allows in identifiers (XID_Continue). None when the label has none to build one from.
+fn variant_name(label: &str) -> Option<String> {
+    let mut name = String::new();
+    for word in label.split(|c: char| !is_xid_continue(c) || c == '_').filter(|word| !word.is_empty()) {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            name.extend(first.to_uppercase());
+            name.push_str(chars.as_str());
+        }
+    }
+    if name.is_empty() { return None; }
+    if !name.starts_with(is_xid_start) { name.insert(0, '_'); }
+    if name == "Self" { name.push('_'); }
+    Some(name)
+}
+
+// Makes `name` unique among `taken` by numbering it, and takes it
+fn unique_name(name: String, taken: &mut BTreeSet<String>) -> String {
+    let unique = if taken.contains(&name) { (2..).map(|n| format!("{}{}", name, n)).find(|n| !taken.contains(n)).unwrap_or_default() } else { name };
+    taken.insert(unique.clone());
+    unique
+}
+
+// The Rust source, plus what was left out and why (unlabeled transitions, an event leading two
+// ways from one state). The initial state is the first shape nothing leads into, else the first shape.
+pub fn render_rust_fsm(shapes: &[ShapeData], connections: &[Connection]) -> (String, Vec<String>) {
+    let mut warnings = Vec::new();
+    let mut taken = BTreeSet::new();
+    let states: Vec<String> = shapes.iter().enumerate()
+        .map(|(index, shape)| unique_name(shape.text.as_deref().and_then(variant_name).unwrap_or_else(|| format!("State{}", index)), &mut taken))
+        .collect();
+
+    let mut events: Vec<String> = Vec::new();
+    let mut transitions: Vec<(usize, usize, usize)> = Vec::new(); // (from state, event, to state)
+    for connection in connections {
+        if connection.from_shape_index >= shapes.len() || connection.to_shape_index >= shapes.len() { continue; }
+        let (from, to) = (connection.from_shape_index, connection.to_shape_index);
+        let Some(event_name) = connection.label.as_deref().and_then(variant_name) else {
+            warnings.push(format!("The transition from {} to {} has no label to name its event; leaving it out.", states[from], states[to]));
+            continue;
+        };
+        let event = events.iter().position(|e| *e == event_name).unwrap_or_else(|| { events.push(event_name); events.len() - 1 });
+        if let Some(&(_, _, existing)) = transitions.iter().find(|&&(f, e, _)| f == from && e == event) {
+            warnings.push(format!("{} on {} already leads to {}; leaving out the transition to {}.", states[from], events[event], states[existing], states[to]));
+            continue;
+        }
+        transitions.push((from, event, to));
+    }
+
+    let initial = (0..shapes.len()).find(|&index| !connections.iter().any(|c| c.to_shape_index == index && c.from_shape_index != index)).unwrap_or(0);
+    let mut code = String::from("// Generated from a diagram; edit the diagram and export again rather than changing this file.\n\n");
+    code.push_str("#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]\npub enum State {\n");
+    for (state, shape) in states.iter().zip(shapes) {
+        if let Some(label) = shape.text.as_deref().filter(|label| label.trim() != state) { let _ = writeln!(code, "    /// {}", label.replace('\n', " ")); }
+        let _ = writeln!(code, "    {},", state);
+    }
+    code.push_str("}\n\n#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]\npub enum Event {\n");
+    for event in &events { let _ = writeln!(code, "    {},", event); }
+    code.push_str("}\n\nimpl State {\n");
+    if let Some(initial) = states.get(initial) { let _ = writeln!(code, "    pub const INITIAL: State = State::{};\n", initial); }
+    code.push_str("    /// The state `event` leads to from this one, or None where it doesn't apply.\n");
+    let unused = if transitions.is_empty() { "_" } else { "" };
+    let _ = writeln!(code, "    pub fn next(self, {}event: Event) -> Option<State> {{", unused);
+    if transitions.is_empty() {
+        code.push_str("        None\n");
+    } else {
+        code.push_str("        match (self, event) {\n");
+        for &(from, event, to) in &transitions {
+            let _ = writeln!(code, "            (State::{}, Event::{}) => Some(State::{}),", states[from], events[event], states[to]);
+        }
+        // Every pair is covered when each state handles each event; a catch-all would then be unreachable
+        if transitions.len() < states.len() * events.len() { code.push_str("            _ => None,\n"); }
+        code.push_str("        }\n");
+    }
+    code.push_str("    }\n}\n");
+    (code, warnings)
+}
+
+pub fn export_rust_fsm(path: &Path, shapes: &[ShapeData], connections: &[Connection]) -> Result<Vec<String>, String> {
+    let (code, warnings) = render_rust_fsm(shapes, connections);
+    fs::write(path, code).map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::LayoutSpacing;
+    use crate::Diagram;
+
+    #[test]
+    fn rust_state_machine_export_names_states_and_transitions() {
+        let spacing = LayoutSpacing { horizontal: 200.0, vertical: 150.0 };
+        let mut diagram = Diagram::new();
+        let graph = crate::dsl::parse("idle -> \"running job\": start\n\"running job\" -> idle: stop\n\"running job\" -> done: stop\ndone -> idle\n").unwrap();
+        crate::dsl::apply(&mut diagram, &graph, &spacing);
+        let (code, warnings) = render_rust_fsm(&diagram.shapes, &diagram.connections);
+        assert!(code.contains("pub enum State {\n    /// idle\n    Idle,\n    /// running job\n    RunningJob,"));
+        assert!(code.contains("pub enum Event {\n    Start,\n    Stop,\n}"));
+        assert!(code.contains("(State::RunningJob, Event::Stop) => Some(State::Idle),"));
+        assert!(!code.contains("Some(State::Done)"));
+        assert_eq!(warnings.len(), 2); // The second `stop` out of "running job", and the unlabeled one
+    }
+
+    #[test]
+    fn variant_names_keep_only_identifier_characters() {
+        assert_eq!(variant_name("waiting for input").as_deref(), Some("WaitingForInput"));
+        assert_eq!(variant_name("naïve état").as_deref(), Some("NaïveÉtat"));
+        assert_eq!(variant_name("✓ done_now").as_deref(), Some("DoneNow"));
+        assert_eq!(variant_name("3 ways").as_deref(), Some("_3Ways"));
+        assert_eq!(variant_name("self").as_deref(), Some("Self_"));
+        assert_eq!(variant_name("— ✓ —"), None);
+    }
+}
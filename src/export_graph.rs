@@ -0,0 +1,76 @@
+// export_graph.rs
+// The bare graph structure for scripts and spreadsheets: connections as CSV rows, and shapes as a
+// JSON adjacency list. Positions and styling are left out; the native format keeps those.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{Connection, ShapeData};
+
+const CSV_HEADER: &str = "from_label,to_label,edge_label";
+
+// A CSV field, quoted when it holds a comma, quote or line break (quotes doubled inside)
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn shape_label(shapes: &[ShapeData], index: usize) -> &str {
+    shapes[index].text.as_deref().unwrap_or("")
+}
+
+// One `from_label,to_label,edge_label` row per connection, after a header row
+pub fn render_csv(shapes: &[ShapeData], connections: &[Connection]) -> String {
+    let mut csv = format!("{}\n", CSV_HEADER);
+    for connection in connections {
+        if connection.from_shape_index >= shapes.len() || connection.to_shape_index >= shapes.len() { continue; }
+        let row = [shape_label(shapes, connection.from_shape_index), shape_label(shapes, connection.to_shape_index), connection.label.as_deref().unwrap_or("")];
+        csv.push_str(&row.map(csv_field).join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+// --- JSON adjacency list: one entry per shape, listing the connections leaving it ---
+#[derive(Serialize)]
+struct AdjacencyNode<'a> {
+    id: usize, // The shape's index; `to` in edges refers to it
+    label: &'a str,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    tags: &'a [String],
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    attributes: &'a BTreeMap<String, String>,
+    edges: Vec<AdjacencyEdge<'a>>,
+}
+
+#[derive(Serialize)]
+struct AdjacencyEdge<'a> {
+    to: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<&'a str>,
+}
+
+pub fn render_adjacency_json(shapes: &[ShapeData], connections: &[Connection]) -> String {
+    let mut nodes: Vec<AdjacencyNode> = shapes.iter().enumerate()
+        .map(|(id, shape)| AdjacencyNode { id, label: shape.text.as_deref().unwrap_or(""), tags: &shape.tags, attributes: &shape.attributes, edges: Vec::new() })
+        .collect();
+    for connection in connections {
+        if connection.from_shape_index >= shapes.len() || connection.to_shape_index >= shapes.len() { continue; }
+        nodes[connection.from_shape_index].edges.push(AdjacencyEdge { to: connection.to_shape_index, label: connection.label.as_deref() });
+    }
+    serde_json::to_string_pretty(&nodes).unwrap_or_default()
+}
+
+pub fn export_csv(path: &Path, shapes: &[ShapeData], connections: &[Connection]) -> Result<(), String> {
+    fs::write(path, render_csv(shapes, connections)).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
+
+pub fn export_adjacency_json(path: &Path, shapes: &[ShapeData], connections: &[Connection]) -> Result<(), String> {
+    fs::write(path, render_adjacency_json(shapes, connections)).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
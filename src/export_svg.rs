@@ -0,0 +1,284 @@
+// export_svg.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::{Color, Rect};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::diagram::{self, Annotation};
+use crate::geometry;
+use crate::shape_kind::ShapeKind;
+use crate::{Connection, Lane, LaneOrientation, ShapeData, CONNECTOR_ARROWHEAD_WIDTH_RATIO, CONNECTOR_LABEL_FONT_SIZE, CONNECTOR_LINE_WIDTH, HIGHLIGHT_ALPHA, NOTE_FILL_RGB, NOTE_TEXT_PADDING, NOTE_TEXT_RGB, SHAPE_OUTLINE_WIDTH};
+
+const SVG_MARGIN: f32 = 20.0;
+const SVG_FONT_SIZE: f32 = 18.0;
+const SVG_LINE_HEIGHT: f32 = 1.2; // In em
+const SVG_LANE_TITLE_FONT_SIZE: f32 = 14.0;
+const SVG_CHAR_WIDTH: f32 = 0.6; // In em; the app's font is monospaced, so note text wraps where it does on screen
+
+// --- Visual parameters the exporter needs from the app ---
+pub struct SvgStyle {
+    pub shape_width: f32,
+    pub shape_height: f32,
+    pub corner_radius: f32,
+    pub background_color: Color,
+    pub shape_color: Color,
+    pub text_color: Color,
+    pub connector_line_color: Color,
+}
+
+fn hex_color(color: Color) -> String {
+    let (r, g, b) = color.to_rgb();
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Greedy word wrap to at most `max_chars` per line; words longer than a line are broken up
+fn wrap_line(line: &str, max_chars: usize) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    for word in line.split(' ') {
+        let current = lines.last_mut().expect("starts with a line");
+        let current_len = current.chars().count();
+        if current_len > 0 && current_len + 1 + word.chars().count() > max_chars {
+            lines.push(String::new());
+        } else if current_len > 0 {
+            current.push(' ');
+        }
+        for c in word.chars() {
+            if lines.last().is_some_and(|l| l.chars().count() >= max_chars) { lines.push(String::new()); }
+            lines.last_mut().expect("starts with a line").push(c);
+        }
+    }
+    lines
+}
+
+fn connector_color(connection: &Connection, style: &SvgStyle) -> Color {
+    connection.color_rgb.map_or(style.connector_line_color, |[r, g, b]| Color::from_rgb(r, g, b))
+}
+
+// stroke, stroke-width and, for dashed lines, stroke-dasharray of a connector
+fn connector_stroke_attributes(connection: &Connection, style: &SvgStyle) -> String {
+    let color = connector_color(connection, style);
+    let width = connection.line_width.unwrap_or(CONNECTOR_LINE_WIDTH);
+    let mut attributes = format!(r#"stroke="{}" stroke-width="{}""#, hex_color(color), width);
+    if let Some([dash, gap]) = connection.dash.pattern() {
+        let _ = write!(attributes, r#" stroke-dasharray="{} {}""#, dash * width, gap * width);
+    }
+    attributes
+}
+
+// id of the arrowhead marker ending a connector; connectors of the same color and width share one
+fn arrowhead_marker_id(connection: &Connection, style: &SvgStyle) -> String {
+    let width = connection.line_width.unwrap_or(CONNECTOR_LINE_WIDTH);
+    format!("arrow-{}-{}", &hex_color(connector_color(connection, style))[1..], (width * 100.0).round() as u32)
+}
+
+// The <marker> drawing a connector's arrowhead, its tip on the end of the line
+fn arrowhead_marker(connection: &Connection, style: &SvgStyle) -> String {
+    let length = geometry::arrowhead_length(connection.line_width.unwrap_or(CONNECTOR_LINE_WIDTH));
+    let width = length * CONNECTOR_ARROWHEAD_WIDTH_RATIO;
+    format!(
+        r#"    <marker id="{}" viewBox="0 0 {:.2} {:.2}" refX="{:.2}" refY="{:.2}" markerWidth="{:.2}" markerHeight="{:.2}" markerUnits="userSpaceOnUse" orient="auto"><path d="M 0 0 L {:.2} {:.2} L 0 {:.2} z" fill="{}"/></marker>"#,
+        arrowhead_marker_id(connection, style), length, width, length, width / 2.0, length, width,
+        length, width / 2.0, width, hex_color(connector_color(connection, style))
+    )
+}
+
+fn shape_rect(shape: &ShapeData, style: &SvgStyle) -> Rect {
+    shape.rect(Vec2::new(style.shape_width, style.shape_height))
+}
+
+// A turned shape is written unrotated and turned about its center; empty when it isn't rotated
+fn rotation_attribute(shape: &ShapeData) -> String {
+    if shape.rotation == 0.0 { return String::new(); }
+    format!(r#" transform="rotate({:.2} {:.2} {:.2})""#, shape.rotation.to_degrees(), shape.center_position.x, shape.center_position.y)
+}
+
+// `routes` holds, per connection, the polyline of a smart-routed connector (None draws the usual curve)
+pub fn render_svg(shapes: &[ShapeData], connections: &[Connection], lanes: &[Lane], annotations: &[Annotation], routes: &[Option<Vec<Vec2>>], style: &SvgStyle) -> String {
+    // Bounding box of all shapes, lanes and annotations plus a margin; an empty diagram gets a small blank canvas
+    let shape_rects = shapes.iter().map(|s| s.bounds(Vec2::new(style.shape_width, style.shape_height)));
+    let (min, max) = geometry::shapes_bounds(shape_rects.chain(lanes.iter().map(Lane::rect)).chain(annotations.iter().filter_map(Annotation::bounds)))
+        .unwrap_or((Vec2::ZERO, Vec2::new(style.shape_width, style.shape_height)));
+    let (min, max) = (min - Vec2::splat(SVG_MARGIN), max + Vec2::splat(SVG_MARGIN));
+    let size = max - min;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{:.0}" height="{:.0}" viewBox="{:.2} {:.2} {:.2} {:.2}">"#,
+        size.x, size.y, min.x, min.y, size.x, size.y
+    );
+    let _ = writeln!(
+        svg,
+        r#"  <rect x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" fill="{}"/>"#,
+        min.x, min.y, size.x, size.y, hex_color(style.background_color)
+    );
+
+    // Lanes beneath everything, with horizontal lanes' titles reading bottom to top
+    for lane in lanes {
+        let rect = lane.rect();
+        let title_rect = lane.title_rect();
+        let _ = writeln!(
+            svg,
+            r#"  <rect x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" fill="{}" fill-opacity="0.06" stroke="{}" stroke-opacity="0.5"/>"#,
+            rect.x, rect.y, rect.w, rect.h, hex_color(style.text_color), hex_color(style.connector_line_color)
+        );
+        let (cx, cy) = (title_rect.x + title_rect.w / 2.0, title_rect.y + title_rect.h / 2.0);
+        let rotation = match lane.orientation {
+            LaneOrientation::Horizontal => format!(r#" transform="rotate(-90 {:.2} {:.2})""#, cx, cy),
+            LaneOrientation::Vertical => String::new(),
+        };
+        let _ = writeln!(
+            svg,
+            r#"  <text x="{:.2}" y="{:.2}" font-size="{}" text-anchor="middle" dominant-baseline="middle" fill="{}"{}>{}</text>"#,
+            cx, cy, SVG_LANE_TITLE_FONT_SIZE, hex_color(style.connector_line_color), rotation, escape_xml(&lane.title)
+        );
+    }
+
+    // One arrowhead marker per look of connector
+    let mut marker_ids = Vec::new();
+    for connection in connections {
+        let id = arrowhead_marker_id(connection, style);
+        if marker_ids.contains(&id) { continue; }
+        if marker_ids.is_empty() { svg.push_str("  <defs>\n"); }
+        let _ = writeln!(svg, "{}", arrowhead_marker(connection, style));
+        marker_ids.push(id);
+    }
+    if !marker_ids.is_empty() { svg.push_str("  </defs>\n"); }
+
+    // Connectors first so shapes are drawn on top, matching the on-screen order
+    let parallel_offsets = diagram::parallel_offsets(connections);
+    for (conn_idx, connection) in connections.iter().enumerate() {
+        let (Some(from), Some(to)) = (shapes.get(connection.from_shape_index), shapes.get(connection.to_shape_index)) else {
+            continue;
+        };
+        let stroke = format!(r#"{} marker-end="url(#{})""#, connector_stroke_attributes(connection, style), arrowhead_marker_id(connection, style));
+        let label_anchor = if let Some(Some(route)) = routes.get(conn_idx) {
+            let points: Vec<String> = route.iter().map(|p| format!("{:.2},{:.2}", p.x, p.y)).collect();
+            let _ = writeln!(svg, r#"  <polyline points="{}" fill="none" {}/>"#, points.join(" "), stroke);
+            geometry::polyline_midpoint(route)
+        } else {
+            let (Some(from_port), Some(to_port)) = (from.ports.get(connection.from_port), to.ports.get(connection.to_port)) else {
+                continue;
+            };
+            let start = from.to_world(from_port.position(from.kind, shape_rect(from, style)));
+            let end = to.to_world(to_port.position(to.kind, shape_rect(to, style)));
+            let (start_side, end_side) = (from_port.side.rotated(from.rotation), to_port.side.rotated(to.rotation));
+            let curve = geometry::bow_curve(geometry::connector_curve(start, start_side, end, end_side), parallel_offsets[conn_idx] + connection.bend.unwrap_or(0.0));
+            let [p0, p1, p2, p3] = curve;
+            let _ = writeln!(
+                svg,
+                r#"  <path d="M {:.2} {:.2} C {:.2} {:.2}, {:.2} {:.2}, {:.2} {:.2}" fill="none" {}/>"#,
+                p0.x, p0.y, p1.x, p1.y, p2.x, p2.y, p3.x, p3.y, stroke
+            );
+            geometry::point_on_cubic_bezier(&curve, 0.5)
+        };
+        if let Some(label) = connection.label.as_deref().filter(|l| !l.is_empty()) {
+            let _ = writeln!(
+                svg,
+                r#"  <text x="{:.2}" y="{:.2}" font-size="{}" text-anchor="middle" dominant-baseline="middle" fill="{}" stroke="{}" stroke-width="4" paint-order="stroke">{}</text>"#,
+                label_anchor.x, label_anchor.y, CONNECTOR_LABEL_FONT_SIZE, hex_color(connector_color(connection, style)),
+                hex_color(style.background_color), escape_xml(label)
+            );
+        }
+    }
+
+    for shape in shapes {
+        let rect = shape_rect(shape, style);
+        let is_note = shape.kind == ShapeKind::Note;
+        let default_fill = if is_note { Color::from_rgb(NOTE_FILL_RGB[0], NOTE_FILL_RGB[1], NOTE_FILL_RGB[2]) } else { style.shape_color };
+        let fill = shape.fill_rgb.map_or(default_fill, |rgb| Color::from_rgb(rgb[0], rgb[1], rgb[2]));
+        let corner_radius = shape.corner_radius.unwrap_or(style.corner_radius);
+        let rotation = rotation_attribute(shape);
+        // Fill plus, for shapes with their own outline color, a stroke
+        let paint = match shape.outline_rgb {
+            Some(rgb) => format!(
+                r#"fill="{}" stroke="{}" stroke-width="{}"{}"#,
+                hex_color(fill), hex_color(Color::from_rgb(rgb[0], rgb[1], rgb[2])), SHAPE_OUTLINE_WIDTH, rotation
+            ),
+            None => format!(r#"fill="{}"{}"#, hex_color(fill), rotation),
+        };
+        if let Some(corners) = shape.kind.polygon(rect) {
+            let points: Vec<String> = corners.iter().map(|p| format!("{:.2},{:.2}", p.x, p.y)).collect();
+            let _ = writeln!(svg, r#"  <polygon points="{}" {}/>"#, points.join(" "), paint);
+        } else if shape.kind == ShapeKind::Ellipse {
+            let _ = writeln!(
+                svg,
+                r#"  <ellipse cx="{:.2}" cy="{:.2}" rx="{:.2}" ry="{:.2}" {}/>"#,
+                shape.center_position.x, shape.center_position.y, rect.w / 2.0, rect.h / 2.0, paint
+            );
+        } else {
+            let _ = writeln!(
+                svg,
+                r#"  <rect x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" rx="{:.2}" ry="{:.2}" {}/>"#,
+                rect.x, rect.y, rect.w, rect.h, corner_radius, corner_radius, paint
+            );
+        }
+        let text_color = if is_note { Color::from_rgb(NOTE_TEXT_RGB[0], NOTE_TEXT_RGB[1], NOTE_TEXT_RGB[2]) } else { style.text_color };
+        let text_color = shape.text_rgb.map_or(text_color, |rgb| Color::from_rgb(rgb[0], rgb[1], rgb[2]));
+        let font_size = shape.font_size.unwrap_or(SVG_FONT_SIZE);
+        if let Some(text) = shape.text.as_deref().filter(|t| !t.is_empty() && is_note) {
+            // Wrapped to the note and hanging from its padded top-left corner
+            let max_chars = (((rect.w - NOTE_TEXT_PADDING * 2.0) / (font_size * SVG_CHAR_WIDTH)).floor() as usize).max(1);
+            let (x, y) = (rect.x + NOTE_TEXT_PADDING, rect.y + NOTE_TEXT_PADDING);
+            let tspans: String = text.split('\n').flat_map(|line| wrap_line(line, max_chars)).enumerate().map(|(i, line)| {
+                format!(r#"<tspan x="{:.2}" dy="{:.2}em">{}</tspan>"#, x, if i == 0 { 0.0 } else { SVG_LINE_HEIGHT }, escape_xml(&line))
+            }).collect();
+            let _ = writeln!(
+                svg,
+                r#"  <text x="{:.2}" y="{:.2}" font-size="{}" text-anchor="start" dominant-baseline="hanging" fill="{}"{}>{}</text>"#,
+                x, y, font_size, hex_color(text_color), rotation, tspans
+            );
+        } else if let Some(text) = shape.text.as_deref().filter(|t| !t.is_empty()) {
+            // One tspan per line, with the block vertically centered on the shape
+            let lines: Vec<&str> = text.split('\n').collect();
+            let first_line_offset = -(lines.len() as f32 - 1.0) / 2.0 * SVG_LINE_HEIGHT;
+            let tspans: String = lines.iter().enumerate().map(|(i, line)| {
+                let dy = if i == 0 { first_line_offset } else { SVG_LINE_HEIGHT };
+                format!(r#"<tspan x="{:.2}" dy="{:.2}em">{}</tspan>"#, shape.center_position.x, dy, escape_xml(line))
+            }).collect();
+            let _ = writeln!(
+                svg,
+                r#"  <text x="{:.2}" y="{:.2}" font-size="{}" text-anchor="middle" dominant-baseline="middle" fill="{}"{}>{}</text>"#,
+                shape.center_position.x, shape.center_position.y, font_size,
+                hex_color(text_color), rotation, tspans
+            );
+        }
+    }
+
+    // Annotations over everything, as on screen
+    for annotation in annotations {
+        let points: Vec<String> = annotation.points.iter().map(|p| format!("{:.2},{:.2}", p.x, p.y)).collect();
+        let [r, g, b] = annotation.color_rgb;
+        let opacity = if annotation.highlight { HIGHLIGHT_ALPHA } else { 1.0 };
+        let _ = writeln!(
+            svg,
+            r#"  <polyline points="{}" fill="none" stroke="{}" stroke-opacity="{}" stroke-width="{:.2}" stroke-linecap="round" stroke-linejoin="round"/>"#,
+            points.join(" "), hex_color(Color::from_rgb(r, g, b)), opacity, annotation.width
+        );
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+pub fn export_svg(path: &Path, shapes: &[ShapeData], connections: &[Connection], lanes: &[Lane], annotations: &[Annotation], routes: &[Option<Vec<Vec2>>], style: &SvgStyle) -> Result<(), String> {
+    let svg = render_svg(shapes, connections, lanes, annotations, routes, style);
+    fs::write(path, svg).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
@@ -0,0 +1,93 @@
+// find.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::Rect;
+
+use crate::commands::fuzzy_score;
+use crate::ui::{self, ListOverlay, Widget};
+use crate::{Diagram, ShapeData};
+
+const FIND_PANEL_WIDTH: f32 = 360.0;
+const FIND_PANEL_TOP: f32 = 60.0;
+const FIND_PANEL_ROWS: usize = 10; // Matches listed at once; the list scrolls with the selection
+
+// How well a shape matches a search or focus query, or None if it doesn't. `#tag` asks for that
+// exact tag and `key=value` for that exact attribute (case ignored); anything else is matched
+// fuzzily against the shape's text, tags and attributes.
+pub fn match_score(query: &str, shape: &ShapeData) -> Option<i32> {
+    let query = query.trim();
+    if let Some(tag) = query.strip_prefix('#') {
+        return shape.tags.iter().any(|t| t.eq_ignore_ascii_case(tag.trim())).then_some(i32::MAX);
+    }
+    if let Some((key, value)) = query.split_once('=') {
+        let value_matches = |v: &String| v.eq_ignore_ascii_case(value.trim());
+        return shape.attributes.iter().any(|(k, v)| k.eq_ignore_ascii_case(key.trim()) && value_matches(v)).then_some(i32::MAX);
+    }
+    let metadata = shape.metadata_entries();
+    shape.text.iter().chain(&metadata).filter_map(|candidate| fuzzy_score(query, candidate)).max()
+}
+
+// --- Ctrl+F overlay: type part of a shape's text, Up/Down to choose a match, Enter to jump to it,
+// Ctrl+Enter to keep the query as a focus filter that dims everything not matching it ---
+#[derive(Default)]
+pub struct ShapeSearch {
+    pub query: String,
+    list: ListOverlay, // Over matches()
+}
+
+impl ShapeSearch {
+    // Visible shapes whose text, tags or attributes fuzzily match the query, best first; none for an empty query
+    pub fn matches(&self, diagram: &Diagram) -> Vec<usize> {
+        if self.query.trim().is_empty() { return Vec::new(); }
+        let mut scored: Vec<(i32, usize)> = diagram.shapes.iter().enumerate()
+            .filter(|&(index, _)| !diagram.shape_on_hidden_layer(index) && diagram.collapsed_group_of_shape(index).is_none())
+            .filter_map(|(index, shape)| Some((match_score(&self.query, shape)?, index)))
+            .collect();
+        scored.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+        scored.into_iter().map(|(_, index)| index).collect()
+    }
+
+    pub fn chosen(&self, diagram: &Diagram) -> Option<usize> {
+        self.matches(diagram).get(self.list.selected()).copied()
+    }
+
+    pub fn type_char(&mut self, character: char) {
+        self.query.push(character);
+        self.list = ListOverlay::default();
+    }
+
+    pub fn backspace(&mut self) {
+        self.query.pop();
+        self.list = ListOverlay::default();
+    }
+
+    // Moves the highlight up (negative) or down, wrapping around the list
+    pub fn move_selection(&mut self, diagram: &Diagram, delta: isize) {
+        self.list.move_selection(self.matches(diagram).len(), delta);
+    }
+
+    // Makes the clicked match the chosen one
+    pub fn select(&mut self, index: usize) {
+        self.list.select(index);
+    }
+
+    // Panel centered near the top of the screen: the query, then one button per visible match.
+    // Button ids are indices into matches().
+    pub fn widgets(&self, screen_size: Vec2, diagram: &Diagram) -> (Rect, Vec<Widget<usize>>) {
+        let matches = self.matches(diagram);
+        let summary = match matches.len() {
+            _ if self.query.trim().is_empty() => String::from("Find shape"),
+            0 => String::from("No matches"),
+            1 => String::from("1 match"),
+            count => format!("{} matches", count),
+        };
+        let items = matches.iter().map(|&shape_index| {
+            let shape = &diagram.shapes[shape_index];
+            let text = shape.text.as_deref().unwrap_or("").replace('\n', " ");
+            if shape.tags.is_empty() && shape.attributes.is_empty() { text } else { format!("{}   [{}]", text, shape.metadata_text()) }
+        }).collect();
+        let width = FIND_PANEL_WIDTH.min(screen_size.x);
+        let origin = ui::centered_origin(screen_size, width, FIND_PANEL_TOP);
+        self.list.widgets(origin, width, FIND_PANEL_ROWS, vec![format!("Find: {}|", self.query), summary], items)
+    }
+}
@@ -0,0 +1,115 @@
+// font_fallback.rs
+// Font fallback for labels: every char is drawn with the first font in the chain that has a glyph
+// for it, so mixed-script text and emoji don't come out as empty boxes ("tofu"). The editor
+// registers each fallback font with ggez under the name given here and draws a label as one text
+// fragment per run.
+
+use ab_glyph::{Font, FontArc};
+
+// Liberation Mono, ggez's built-in font, covers Latin, Greek and Cyrillic. Its bytes aren't
+// reachable through ggez, so while it is the primary font this range stands in for its glyph
+// table. It is a heuristic: the font has gaps below the bound (those chars come out as tofu rather
+// than falling back) and a few glyphs above it (punctuation, box drawing) that a fallback font draws instead.
+const BUILT_IN_FONT_COVERAGE_END: char = '\u{0530}';
+
+#[derive(Default)]
+pub struct FontFallback {
+    primary: Option<FontArc>, // The font_path font, if one was loaded; None for the built-in font
+    fallbacks: Vec<(String, FontArc)>, // ggez font name and parsed font, most preferred first
+}
+
+impl FontFallback {
+    pub fn set_primary(&mut self, bytes: Vec<u8>) -> Result<(), String> {
+        self.primary = Some(FontArc::try_from_vec(bytes).map_err(|e| e.to_string())?);
+        Ok(())
+    }
+
+    pub fn push_fallback(&mut self, name: impl Into<String>, bytes: Vec<u8>) -> Result<(), String> {
+        self.fallbacks.push((name.into(), FontArc::try_from_vec(bytes).map_err(|e| e.to_string())?));
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fallbacks.is_empty()
+    }
+
+    fn primary_covers(&self, c: char) -> bool {
+        match &self.primary {
+            Some(font) => font.glyph_id(c).0 != 0,
+            None => c < BUILT_IN_FONT_COVERAGE_END,
+        }
+    }
+
+    // Name of the fallback font to draw `c` with; None for the primary font, which also draws
+    // chars no font in the chain has
+    fn font_for(&self, c: char) -> Option<&str> {
+        if self.primary_covers(c) { return None; }
+        self.fallbacks.iter().find(|(_, font)| font.glyph_id(c).0 != 0).map(|(name, _)| name.as_str())
+    }
+
+    // Splits text into runs drawn with the same font (None: the primary font)
+    pub fn runs<'a>(&self, text: &'a str) -> Vec<(Option<&str>, &'a str)> {
+        if self.is_empty() { return vec![(None, text)]; }
+        split_runs(text, |c| self.font_for(c))
+    }
+}
+
+// Splits text where `font_for` changes. Whitespace and control chars stay in the run they're in
+// (leading ones join the first run), so a line of one script is a single run. Empty text is one
+// empty run in the primary font.
+fn split_runs<'a, 'f>(text: &'a str, font_for: impl Fn(char) -> Option<&'f str>) -> Vec<(Option<&'f str>, &'a str)> {
+    let mut runs = Vec::new();
+    let mut run_start = 0;
+    let mut run_font: Option<Option<&str>> = None; // None until the first char that picks a font
+    for (offset, c) in text.char_indices() {
+        if c.is_whitespace() || c.is_control() { continue; }
+        // Variation selectors and joiners belong to the emoji before them
+        if matches!(c, '\u{FE00}'..='\u{FE0F}' | '\u{200D}') { continue; }
+        let font = font_for(c);
+        if let Some(current) = run_font.filter(|&current| current != font) {
+            runs.push((current, &text[run_start..offset]));
+            run_start = offset;
+        }
+        run_font = Some(font);
+    }
+    runs.push((run_font.flatten(), &text[run_start..]));
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The built-in font's range, then CJK and emoji fonts
+    fn fake_coverage(c: char) -> Option<&'static str> {
+        match c {
+            _ if c < BUILT_IN_FONT_COVERAGE_END => None,
+            '\u{2600}'..='\u{2FFF}' | '\u{1F000}'.. => Some("emoji"),
+            _ => Some("cjk"),
+        }
+    }
+
+    #[test]
+    fn mixed_scripts_split_into_runs() {
+        assert_eq!(split_runs("Hello 世界!", fake_coverage), [(None, "Hello "), (Some("cjk"), "世界"), (None, "!")]);
+    }
+
+    #[test]
+    fn leading_whitespace_joins_the_first_run() {
+        assert_eq!(split_runs(" \t世界 x", fake_coverage), [(Some("cjk"), " \t世界 "), (None, "x")]);
+        assert_eq!(split_runs("   ", fake_coverage), [(None, "   ")]);
+    }
+
+    #[test]
+    fn joiners_and_variation_selectors_stay_with_their_emoji() {
+        assert_eq!(split_runs("a☺\u{FE0F}b", fake_coverage), [(None, "a"), (Some("emoji"), "☺\u{FE0F}"), (None, "b")]);
+        let family = "👨\u{200D}👩\u{200D}👧";
+        assert_eq!(split_runs(&format!("x{}", family), fake_coverage), [(None, "x"), (Some("emoji"), family)]);
+    }
+
+    #[test]
+    fn empty_text_is_one_empty_run() {
+        assert_eq!(split_runs("", fake_coverage), [(None, "")]);
+        assert_eq!(FontFallback::default().runs(""), [(None, "")]);
+    }
+}
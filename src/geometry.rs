@@ -0,0 +1,275 @@
+// geometry.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::Rect;
+
+use crate::ports::PortSide;
+use crate::{CONNECTOR_ARROWHEAD_LENGTH, CONNECTOR_ARROWHEAD_WIDTH_RATIO, CONNECTOR_CURVE_OFFSET_MAX, CONNECTOR_CURVE_OFFSET_MIN, CONNECTOR_CURVE_OFFSET_RATIO, CONNECTOR_LINE_WIDTH};
+
+// The four control points [p0, p1, p2, p3] of the cubic bezier drawn for a connector.
+// The curve leaves and enters perpendicular to the sides its ports sit on.
+pub fn connector_curve(start: Vec2, start_side: PortSide, end: Vec2, end_side: PortSide) -> [Vec2; 4] {
+    // Far-apart ends get longer handles so the curve isn't pinched; close ones shorter so it doesn't loop
+    let offset = (start.distance(end) * CONNECTOR_CURVE_OFFSET_RATIO).clamp(CONNECTOR_CURVE_OFFSET_MIN, CONNECTOR_CURVE_OFFSET_MAX);
+    let cp1 = start + start_side.outward_normal() * offset;
+    let cp2 = end + end_side.outward_normal() * offset;
+    [start, cp1, cp2, end]
+}
+
+// The curve with its middle pushed `offset` sideways, square to the line between its ends; negative
+// offsets push the other way. Only the inner control points move, so the ends stay put.
+pub fn bow_curve(curve: [Vec2; 4], offset: f32) -> [Vec2; 4] {
+    if offset == 0.0 { return curve; }
+    let [p0, p1, p2, p3] = curve;
+    // The middle of a cubic moves by 3/4 of what its inner control points move
+    let shift = (p3 - p0).normalize_or_zero().perp() * (offset / 0.75);
+    [p0, p1 + shift, p2 + shift, p3]
+}
+
+// Point on a cubic Bezier curve at parameter t in [0, 1]
+pub fn point_on_cubic_bezier(curve: &[Vec2; 4], t: f32) -> Vec2 {
+    let [p0, p1, p2, p3] = *curve;
+    let t_inv = 1.0 - t;
+    let t_inv_sq = t_inv * t_inv;
+    let t_inv_cub = t_inv_sq * t_inv;
+    let t_sq = t * t;
+    let t_cub = t_sq * t;
+    p0 * t_inv_cub + p1 * (3.0 * t_inv_sq * t) + p2 * (3.0 * t_inv * t_sq) + p3 * t_cub
+}
+
+// Polyline following the curve to within `tolerance`, found by splitting it in halves until each
+// piece is flat enough. Long or sharply bent curves get more points than short straight ones.
+pub fn flatten_cubic_bezier(curve: &[Vec2; 4], tolerance: f32) -> Vec<Vec2> {
+    let mut points = vec![curve[0]];
+    flatten_into(*curve, tolerance.max(0.01), 0, &mut points);
+    points
+}
+
+fn flatten_into(curve: [Vec2; 4], tolerance: f32, depth: u32, points: &mut Vec<Vec2>) {
+    let [p0, p1, p2, p3] = curve;
+    // The curve stays within the control points' distance from the chord
+    let flat = distance_to_segment(p1, p0, p3).max(distance_to_segment(p2, p0, p3)) <= tolerance;
+    if flat || depth >= 16 {
+        points.push(p3);
+        return;
+    }
+    // de Casteljau split at t = 0.5
+    let (p01, p12, p23) = (p0.lerp(p1, 0.5), p1.lerp(p2, 0.5), p2.lerp(p3, 0.5));
+    let (p012, p123) = (p01.lerp(p12, 0.5), p12.lerp(p23, 0.5));
+    let mid = p012.lerp(p123, 0.5);
+    flatten_into([p0, p01, p012, mid], tolerance, depth + 1, points);
+    flatten_into([mid, p123, p23, p3], tolerance, depth + 1, points);
+}
+
+// Point on the curve closest to `point`, accurate to within `tolerance`
+pub fn closest_point_on_cubic_bezier(curve: &[Vec2; 4], point: Vec2, tolerance: f32) -> Vec2 {
+    closest_point_on_polyline(&flatten_cubic_bezier(curve, tolerance), point).unwrap_or(curve[0])
+}
+
+pub fn closest_point_on_polyline(points: &[Vec2], point: Vec2) -> Option<Vec2> {
+    if let [only] = points { return Some(*only); }
+    points.windows(2)
+        .map(|seg| closest_point_on_segment(point, seg[0], seg[1]))
+        .min_by(|a, b| a.distance_squared(point).total_cmp(&b.distance_squared(point)))
+}
+
+// Parameter along a1→a2 where it crosses b1→b2, if the segments cross. Ranges are half-open so a
+// crossing at a shared vertex of consecutive segments is found once.
+fn segment_crossing(a1: Vec2, a2: Vec2, b1: Vec2, b2: Vec2) -> Option<f32> {
+    let (r, s) = (a2 - a1, b2 - b1);
+    let denominator = r.perp_dot(s);
+    if denominator.abs() <= f32::EPSILON { return None; } // Parallel
+    let t = (b1 - a1).perp_dot(s) / denominator;
+    let u = (b1 - a1).perp_dot(r) / denominator;
+    ((0.0..1.0).contains(&t) && (0.0..1.0).contains(&u)).then_some(t)
+}
+
+// Where polyline `a` crosses polyline `b`, as distances along `a`
+pub fn polyline_crossings(a: &[Vec2], b: &[Vec2]) -> Vec<f32> {
+    let mut crossings = Vec::new();
+    let mut travelled = 0.0;
+    for seg in a.windows(2) {
+        let length = seg[0].distance(seg[1]);
+        for other in b.windows(2) {
+            if let Some(t) = segment_crossing(seg[0], seg[1], other[0], other[1]) { crossings.push(travelled + t * length); }
+        }
+        travelled += length;
+    }
+    crossings
+}
+
+// The polyline with a semicircular hop of `radius` over each crossing (given as distances along it).
+// Hops are bent towards the top of the screen; ones that would reach an end are left out and
+// overlapping ones merge into a single wider hop.
+pub fn bridge_polyline(points: &[Vec2], crossings: &[f32], radius: f32, arc_segments: usize) -> Vec<Vec2> {
+    let total = polyline_length(points);
+    let mut sorted = crossings.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let mut hops: Vec<(f32, f32)> = Vec::new();
+    for crossing in sorted {
+        if crossing - radius <= 0.0 || crossing + radius >= total { continue; }
+        match hops.last_mut() {
+            Some(last) if crossing - radius <= last.1 => last.1 = crossing + radius,
+            _ => hops.push((crossing - radius, crossing + radius)),
+        }
+    }
+    if hops.is_empty() { return points.to_vec(); }
+
+    let mut travelled = 0.0;
+    let distances: Vec<f32> = std::iter::once(0.0)
+        .chain(points.windows(2).map(|seg| { travelled += seg[0].distance(seg[1]); travelled }))
+        .collect();
+    let vertices_between = |from: f32, to: f32| {
+        points.iter().zip(&distances).filter(move |&(_, &d)| d > from && d < to).map(|(&p, _)| p)
+    };
+    let mut bridged = Vec::new();
+    let mut resume = -1.0; // Distance the line picks up again after the previous hop
+    for (start, end) in hops {
+        bridged.extend(vertices_between(resume, start));
+        let (from, to) = (point_along_polyline(points, start), point_along_polyline(points, end));
+        let (center, half_chord) = ((from + to) / 2.0, (to - from) / 2.0);
+        let normal = if half_chord.perp().y > 0.0 { -half_chord.perp() } else { half_chord.perp() };
+        for step in 0..=arc_segments {
+            let angle = std::f32::consts::PI * step as f32 / arc_segments.max(1) as f32;
+            bridged.push(center - half_chord * angle.cos() + normal * angle.sin());
+        }
+        resume = end;
+    }
+    bridged.extend(vertices_between(resume, total + 1.0));
+    bridged
+}
+
+// Points every `spacing` units along a polyline, the first `offset` units from its start
+pub fn points_along_polyline(points: &[Vec2], offset: f32, spacing: f32) -> Vec<Vec2> {
+    let mut found = Vec::new();
+    if spacing <= 0.0 { return found; }
+    let mut next = offset.rem_euclid(spacing); // Distance from the current segment's start to the next point
+    for seg in points.windows(2) {
+        let length = seg[0].distance(seg[1]);
+        while next <= length && length > 0.0 {
+            found.push(seg[0].lerp(seg[1], next / length));
+            next += spacing;
+        }
+        next -= length;
+    }
+    found
+}
+
+// Splits a polyline into the visible pieces of a dash pattern: `dash` units drawn, then `gap` skipped
+pub fn dash_polyline(points: &[Vec2], dash: f32, gap: f32) -> Vec<Vec<Vec2>> {
+    let mut dashes = Vec::new();
+    if dash <= 0.0 || gap <= 0.0 { return vec![points.to_vec()]; }
+    let mut current: Vec<Vec2> = points.first().map(|&p| vec![p]).unwrap_or_default();
+    let mut drawing = true;
+    let mut remaining = dash; // Length left in the current dash or gap
+    for seg in points.windows(2) {
+        let (mut start, end) = (seg[0], seg[1]);
+        let mut length = start.distance(end);
+        while length >= remaining {
+            let split = start.lerp(end, remaining / length);
+            if drawing {
+                current.push(split);
+                dashes.push(std::mem::take(&mut current));
+            } else {
+                current = vec![split];
+            }
+            length -= remaining;
+            start = split;
+            drawing = !drawing;
+            remaining = if drawing { dash } else { gap };
+        }
+        remaining -= length;
+        if drawing { current.push(end); }
+    }
+    if drawing && current.len() >= 2 { dashes.push(current); }
+    dashes
+}
+
+// Min and max corners of the box enclosing all shape rects, or None when there are no shapes
+pub fn shapes_bounds(shape_rects: impl IntoIterator<Item = Rect>) -> Option<(Vec2, Vec2)> {
+    shape_rects.into_iter().fold(None, |bounds, rect| {
+        let (shape_min, shape_max) = (Vec2::new(rect.left(), rect.top()), Vec2::new(rect.right(), rect.bottom()));
+        Some(match bounds {
+            Some((min, max)) => (shape_min.min(min), shape_max.max(max)),
+            None => (shape_min, shape_max),
+        })
+    })
+}
+
+// Rounds a position to the nearest grid intersection
+pub fn snap_to_grid(position: Vec2, spacing: f32) -> Vec2 {
+    (position / spacing).round() * spacing
+}
+
+// Shortest distance from `point` to the segment a-b
+pub fn closest_point_on_segment(point: Vec2, a: Vec2, b: Vec2) -> Vec2 {
+    let segment = b - a;
+    let length_sq = segment.length_squared();
+    if length_sq <= f32::EPSILON {
+        return a;
+    }
+    let t = ((point - a).dot(segment) / length_sq).clamp(0.0, 1.0);
+    a + segment * t
+}
+
+pub fn distance_to_segment(point: Vec2, a: Vec2, b: Vec2) -> f32 {
+    point.distance(closest_point_on_segment(point, a, b))
+}
+
+pub fn polyline_length(points: &[Vec2]) -> f32 {
+    points.windows(2).map(|seg| seg[0].distance(seg[1])).sum()
+}
+
+// Point halfway along a polyline, measured by length
+pub fn polyline_midpoint(points: &[Vec2]) -> Vec2 {
+    point_along_polyline(points, polyline_length(points) / 2.0)
+}
+
+// Point `distance` along a polyline from its start, clamped to its ends
+pub fn point_along_polyline(points: &[Vec2], distance: f32) -> Vec2 {
+    let mut remaining = distance.max(0.0);
+    for seg in points.windows(2) {
+        let length = seg[0].distance(seg[1]);
+        if length >= remaining && length > 0.0 {
+            return seg[0].lerp(seg[1], remaining / length);
+        }
+        remaining -= length;
+    }
+    points.last().copied().unwrap_or(Vec2::ZERO)
+}
+
+// Length of the arrowhead on a connector `line_width` wide. Heads grow with the line, but slower, so
+// heavy lines don't end in huge arrows.
+pub fn arrowhead_length(line_width: f32) -> f32 {
+    CONNECTOR_ARROWHEAD_LENGTH * (line_width / CONNECTOR_LINE_WIDTH).max(0.0).sqrt()
+}
+
+// Corners of the arrowhead ending a polyline drawn `line_width` wide: the tip on the last point,
+// then its two barbs. None when the line has no length to point along.
+pub fn arrowhead(points: &[Vec2], line_width: f32) -> Option<[Vec2; 3]> {
+    let length = arrowhead_length(line_width);
+    let tip = *points.last()?;
+    let base = point_along_polyline(points, polyline_length(points) - length);
+    let direction = (tip - base).try_normalize()?;
+    let barb = direction.perp() * length * CONNECTOR_ARROWHEAD_WIDTH_RATIO / 2.0;
+    let base = tip - direction * length;
+    Some([tip, base + barb, base - barb])
+}
+
+// The polyline cut short by `length` at its end, or None when nothing would be left
+pub fn trim_polyline_end(points: &[Vec2], length: f32) -> Option<Vec<Vec2>> {
+    let kept = polyline_length(points) - length;
+    if kept <= 0.0 { return None; }
+    let mut trimmed = vec![*points.first()?];
+    let mut travelled = 0.0;
+    for seg in points.windows(2) {
+        let segment_length = seg[0].distance(seg[1]);
+        if travelled + segment_length >= kept {
+            trimmed.push(seg[0].lerp(seg[1], (kept - travelled) / segment_length));
+            break;
+        }
+        trimmed.push(seg[1]);
+        travelled += segment_length;
+    }
+    Some(trimmed)
+}
@@ -0,0 +1,50 @@
+// history.rs
+// Undo and redo. Each step is a copy of the whole diagram with its fingerprint. The editor records
+// the diagram whenever it has settled after a change (no drag or text edit under way), so a drag or
+// a typed label is one step.
+
+use crate::Diagram;
+
+const MAX_UNDO_STEPS: usize = 100; // Older steps are forgotten
+
+#[derive(Default)]
+pub struct History {
+    undo: Vec<(u64, Diagram)>,
+    redo: Vec<(u64, Diagram)>,
+    settled: Option<(u64, Diagram)>, // The diagram as last recorded
+}
+
+impl History {
+    // Starts over from `diagram`, e.g. one just loaded
+    pub fn reset(&mut self, fingerprint: u64, diagram: &Diagram) {
+        self.undo.clear();
+        self.redo.clear();
+        self.settled = Some((fingerprint, diagram.clone()));
+    }
+
+    // Takes note of the settled diagram. If it changed since last time, what it was becomes an undo
+    // step and nothing can be redone any more.
+    pub fn record(&mut self, fingerprint: u64, diagram: &Diagram) {
+        if self.settled.as_ref().is_some_and(|(settled, _)| *settled == fingerprint) { return; }
+        let Some(previous) = self.settled.replace((fingerprint, diagram.clone())) else { return; };
+        self.undo.push(previous);
+        if self.undo.len() > MAX_UNDO_STEPS { self.undo.remove(0); }
+        self.redo.clear();
+    }
+
+    // The diagram before the last change to `diagram`, if any
+    pub fn undo(&mut self, fingerprint: u64, diagram: &Diagram) -> Option<Diagram> {
+        self.record(fingerprint, diagram);
+        let previous = self.undo.pop()?;
+        self.redo.extend(self.settled.replace(previous.clone()));
+        Some(previous.1)
+    }
+
+    // The diagram the last undo went back from, unless `diagram` changed since
+    pub fn redo(&mut self, fingerprint: u64, diagram: &Diagram) -> Option<Diagram> {
+        self.record(fingerprint, diagram);
+        let next = self.redo.pop()?;
+        self.undo.extend(self.settled.replace(next.clone()));
+        Some(next.1)
+    }
+}
@@ -0,0 +1,176 @@
+// import_csv.rs
+// Builds or extends a diagram from CSV: an edge list (`from_label,to_label[,edge_label]`, as written
+// by export_graph) and optionally a node list (`label[,tags][,key columns...]`). Shapes are matched
+// by label, so importing into an existing diagram connects to the shapes already there.
+
+use ggez::glam::Vec2;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::layout::{self, LayoutSpacing};
+use crate::{ports, Diagram, ShapeData};
+
+pub struct CsvEdge {
+    pub from: String,
+    pub to: String,
+    pub label: Option<String>,
+}
+
+pub struct CsvNode {
+    pub label: String,
+    pub tags: Vec<String>,
+    pub attributes: Vec<(String, String)>,
+}
+
+// --- How much an import added ---
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CsvImportSummary {
+    pub added_shapes: Vec<usize>,
+    pub added_connections: usize,
+}
+
+// Rows of fields; fields may be quoted, with "" standing for a quote inside them. Blank lines are skipped.
+pub fn parse_rows(source: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let (mut row, mut field) = (Vec::new(), String::new());
+    let (mut quoted, mut line) = (false, 1);
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => { chars.next(); field.push('"'); }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            '\n' if quoted => { field.push(c); line += 1; }
+            _ if quoted => field.push(c),
+            ',' => row.push(std::mem::take(&mut field)),
+            '\r' => {}
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                if row.iter().any(|f| !f.trim().is_empty()) { rows.push(std::mem::take(&mut row)); } else { row.clear(); }
+                line += 1;
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted { return Err(format!("Unterminated quoted field on line {}", line)); }
+    row.push(field);
+    if row.iter().any(|f| !f.trim().is_empty()) { rows.push(row); }
+    Ok(rows)
+}
+
+// A first row naming its columns rather than holding data
+fn is_header(row: &[String], first_column: &[&str]) -> bool {
+    row.first().is_some_and(|f| first_column.contains(&f.trim().to_ascii_lowercase().as_str()))
+}
+
+pub fn parse_edges(source: &str) -> Result<Vec<CsvEdge>, String> {
+    let mut rows = parse_rows(source)?;
+    if rows.first().is_some_and(|row| is_header(row, &["from_label", "from", "source"])) { rows.remove(0); }
+    rows.into_iter().enumerate().map(|(index, row)| {
+        let field = |i: usize| row.get(i).map(|f| f.trim().to_string()).filter(|f| !f.is_empty());
+        match (field(0), field(1)) {
+            (Some(from), Some(to)) => Ok(CsvEdge { from, to, label: field(2) }),
+            _ => Err(format!("Edge row {} needs a from and a to label", index + 1)),
+        }
+    }).collect()
+}
+
+// Without a header the first column is the label and the rest are ignored. With one, a `tags`
+// column is split on ';' and every other named column becomes an attribute.
+pub fn parse_nodes(source: &str) -> Result<Vec<CsvNode>, String> {
+    let mut rows = parse_rows(source)?;
+    let columns: Vec<String> = match rows.first() {
+        Some(row) if is_header(row, &["label", "name", "id"]) => rows.remove(0).iter().map(|c| c.trim().to_string()).collect(),
+        _ => Vec::new(),
+    };
+    let mut nodes = Vec::new();
+    for row in rows {
+        let Some(label) = row.first().map(|f| f.trim().to_string()).filter(|f| !f.is_empty()) else { continue; };
+        let mut node = CsvNode { label, tags: Vec::new(), attributes: Vec::new() };
+        for (column, value) in columns.iter().zip(&row).skip(1).filter(|(c, v)| !c.is_empty() && !v.trim().is_empty()) {
+            if column.eq_ignore_ascii_case("tags") {
+                node.tags.extend(value.split(';').map(str::trim).filter(|t| !t.is_empty()).map(String::from));
+            } else {
+                node.attributes.push((column.clone(), value.trim().to_string()));
+            }
+        }
+        nodes.push(node);
+    }
+    Ok(nodes)
+}
+
+// Adds the nodes and edges to `diagram`, reusing any shape whose first text line equals a label.
+// An edge already there with the same label isn't added again, so importing a file twice adds nothing.
+// New shapes are laid out among themselves with the layout engine, below whatever was there.
+pub fn merge(diagram: &mut Diagram, nodes: &[CsvNode], edges: &[CsvEdge], spacing: &LayoutSpacing) -> CsvImportSummary {
+    let mut by_label: HashMap<String, usize> = HashMap::new();
+    for (index, shape) in diagram.shapes.iter().enumerate().rev() {
+        if let Some(label) = shape.text.as_deref().and_then(|t| t.lines().next()) { by_label.insert(label.trim().to_string(), index); }
+    }
+    let mut summary = CsvImportSummary::default();
+    let mut shape_for = |diagram: &mut Diagram, label: &str| -> usize {
+        *by_label.entry(label.to_string()).or_insert_with(|| {
+            summary.added_shapes.push(diagram.shapes.len());
+            diagram.add_shape(ShapeData { text: Some(label.to_string()), ..ShapeData::new(Vec2::ZERO) })
+        })
+    };
+    for node in nodes {
+        let index = shape_for(diagram, &node.label);
+        let shape = &mut diagram.shapes[index];
+        for tag in &node.tags { if !shape.tags.contains(tag) { shape.tags.push(tag.clone()); } }
+        for (key, value) in &node.attributes { shape.attributes.insert(key.clone(), value.clone()); }
+    }
+    for edge in edges {
+        let (from, to) = (shape_for(diagram, &edge.from), shape_for(diagram, &edge.to));
+        let exists = diagram.connections.iter()
+            .any(|c| (c.from_shape_index, c.to_shape_index) == (from, to) && c.label == edge.label);
+        if exists { continue; }
+        if let Some(index) = diagram.connect(from, ports::default_outgoing_port(), to, ports::default_incoming_port()) {
+            diagram.connections[index].label = edge.label.clone();
+            summary.added_connections += 1;
+        }
+    }
+    layout::place_added_shapes(diagram, &summary.added_shapes, spacing);
+    summary
+}
+
+// Reads an edge CSV, and the node CSV if given, into `diagram`
+pub fn import_csv(diagram: &mut Diagram, edges_path: &Path, nodes_path: Option<&Path>, spacing: &LayoutSpacing) -> Result<CsvImportSummary, String> {
+    let read = |path: &Path| fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e));
+    let edges = parse_edges(&read(edges_path)?).map_err(|e| format!("{}: {}", edges_path.display(), e))?;
+    let nodes = match nodes_path {
+        Some(path) => parse_nodes(&read(path)?).map_err(|e| format!("{}: {}", path.display(), e))?,
+        None => Vec::new(),
+    };
+    Ok(merge(diagram, &nodes, &edges, spacing))
+}
+
+// Node list next to an edge CSV: `graph.csv` pairs with `graph.nodes.csv`, if it exists
+pub fn sibling_nodes_path(edges_path: &Path) -> Option<PathBuf> {
+    Some(edges_path.with_extension("nodes.csv")).filter(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_edges_round_trip_and_merge_by_label() {
+        let spacing = LayoutSpacing { horizontal: 200.0, vertical: 150.0 };
+        let mut diagram = Diagram::new();
+        let edges = parse_edges("from_label,to_label,edge_label\nStart,\"Check, twice\",yes\n\nStart,End,\n").unwrap();
+        let nodes = parse_nodes("label,tags,owner\nEnd,done;final,ops\n").unwrap();
+        let summary = merge(&mut diagram, &nodes, &edges, &spacing);
+        assert_eq!((summary.added_shapes.len(), summary.added_connections), (3, 2));
+        assert_eq!(diagram.shapes[0].text.as_deref(), Some("End"));
+        assert_eq!(diagram.shapes[0].tags, vec!["done", "final"]);
+        assert_eq!(diagram.shapes[0].attributes.get("owner").map(String::as_str), Some("ops"));
+
+        let csv = crate::export_graph::render_csv(&diagram.shapes, &diagram.connections);
+        assert_eq!(csv, "from_label,to_label,edge_label\nStart,\"Check, twice\",yes\nStart,End,\n");
+        let again = merge(&mut diagram, &[], &parse_edges(&csv).unwrap(), &spacing);
+        assert_eq!((again.added_shapes.len(), again.added_connections), (0, 0));
+        assert!(parse_edges("a,\"b").is_err());
+    }
+}
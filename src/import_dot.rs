@@ -0,0 +1,259 @@
+// import_dot.rs
+
+use ggez::glam::Vec2;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::ports;
+use crate::shape_kind::ShapeKind;
+use crate::{Connection, Diagram, ShapeData};
+
+const GRID_COLUMNS: usize = 6; // Columns used when auto-placing nodes that have no `pos`
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Id(String),
+    Arrow, // `->` or `--`
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Equals,
+    Separator, // `;` or `,`
+}
+
+// --- Result of an import: the diagram plus whether every node came with coordinates ---
+pub struct ImportedDiagram {
+    pub diagram: Diagram,
+    pub fully_positioned: bool,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => { chars.next(); }
+            '#' => { while chars.next().is_some_and(|c| c != '\n') {} }
+            '/' => {
+                chars.next();
+                match chars.next() {
+                    Some('/') => { while chars.next().is_some_and(|c| c != '\n') {} }
+                    Some('*') => {
+                        let mut previous = ' ';
+                        for c in chars.by_ref() {
+                            if previous == '*' && c == '/' { break; }
+                            previous = c;
+                        }
+                    }
+                    _ => return Err("Unexpected '/'".to_string()),
+                }
+            }
+            '-' => {
+                chars.next();
+                match chars.peek() {
+                    Some('>') | Some('-') => { chars.next(); tokens.push(Token::Arrow); }
+                    _ => {
+                        // Negative number, e.g. an unquoted coordinate
+                        let mut id = String::from("-");
+                        while let Some(&c) = chars.peek() {
+                            if c.is_alphanumeric() || c == '_' || c == '.' { id.push(c); chars.next(); } else { break; }
+                        }
+                        tokens.push(Token::Id(id));
+                    }
+                }
+            }
+            '[' => { chars.next(); tokens.push(Token::LBracket); }
+            ']' => { chars.next(); tokens.push(Token::RBracket); }
+            '{' => { chars.next(); tokens.push(Token::LBrace); }
+            '}' => { chars.next(); tokens.push(Token::RBrace); }
+            '=' => { chars.next(); tokens.push(Token::Equals); }
+            ';' | ',' => { chars.next(); tokens.push(Token::Separator); }
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => text.push('\n'),
+                            Some(escaped) => text.push(escaped),
+                            None => return Err("Unterminated string".to_string()),
+                        },
+                        Some(c) => text.push(c),
+                        None => return Err("Unterminated string".to_string()),
+                    }
+                }
+                tokens.push(Token::Id(text));
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '.' => {
+                let mut id = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' || c == '.' { id.push(c); chars.next(); } else { break; }
+                }
+                tokens.push(Token::Id(id));
+            }
+            other => return Err(format!("Unexpected character '{}'", other)),
+        }
+    }
+    Ok(tokens)
+}
+
+// --- Builds shapes/connections from a DOT subset or plain `a -> b` edge lines ---
+#[derive(Default)]
+struct GraphBuilder {
+    node_indices: HashMap<String, usize>,
+    shapes: Vec<ShapeData>,
+    positioned: Vec<bool>,
+    connections: Vec<Connection>,
+}
+
+impl GraphBuilder {
+    fn node(&mut self, name: &str) -> usize {
+        if let Some(&index) = self.node_indices.get(name) {
+            return index;
+        }
+        let index = self.shapes.len();
+        self.node_indices.insert(name.to_string(), index);
+        self.shapes.push(ShapeData { text: Some(name.to_string()), ..ShapeData::new(Vec2::ZERO) });
+        self.positioned.push(false);
+        index
+    }
+
+    fn apply_node_attributes(&mut self, index: usize, attributes: &[(String, String)]) {
+        for (key, value) in attributes {
+            match key.as_str() {
+                "label" => self.shapes[index].text = if value.is_empty() { None } else { Some(value.clone()) },
+                "shape" => self.shapes[index].kind = match value.as_str() {
+                    "ellipse" | "oval" | "circle" => ShapeKind::Ellipse,
+                    "diamond" => ShapeKind::Diamond,
+                    "parallelogram" => ShapeKind::Parallelogram,
+                    _ => ShapeKind::Rectangle,
+                },
+                "pos" => {
+                    if let Some(position) = parse_pos(value) {
+                        self.shapes[index].center_position = position;
+                        self.positioned[index] = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// Parses a Graphviz `pos` value ("x,y" or "x,y!"), flipping y back into screen orientation
+fn parse_pos(value: &str) -> Option<Vec2> {
+    let mut parts = value.trim_end_matches('!').split(',');
+    let x = parts.next()?.trim().parse::<f32>().ok()?;
+    let y = parts.next()?.trim().parse::<f32>().ok()?;
+    Some(Vec2::new(x, -y))
+}
+
+fn parse_attribute_list(tokens: &[Token], pos: &mut usize) -> Result<Vec<(String, String)>, String> {
+    let mut attributes = Vec::new();
+    while *pos < tokens.len() {
+        match &tokens[*pos] {
+            Token::RBracket => { *pos += 1; return Ok(attributes); }
+            Token::Separator => { *pos += 1; }
+            Token::Id(key) => {
+                let key = key.clone();
+                *pos += 1;
+                if tokens.get(*pos) == Some(&Token::Equals) {
+                    *pos += 1;
+                    match tokens.get(*pos) {
+                        Some(Token::Id(value)) => { attributes.push((key, value.clone())); *pos += 1; }
+                        _ => return Err(format!("Missing value for attribute '{}'", key)),
+                    }
+                } else {
+                    attributes.push((key, String::from("true")));
+                }
+            }
+            other => return Err(format!("Unexpected {:?} in attribute list", other)),
+        }
+    }
+    Err("Unterminated attribute list".to_string())
+}
+
+pub fn parse_dot(source: &str, grid_spacing: Vec2) -> Result<ImportedDiagram, String> {
+    let tokens = tokenize(source)?;
+    let mut builder = GraphBuilder::default();
+    let mut pos = 0;
+
+    // Optional `strict? (di)graph name? {` header; plain edge lists have none.
+    // Repeated edges become parallel connections, except in strict graphs.
+    let strict = matches!(tokens.first(), Some(Token::Id(word)) if word == "strict");
+    if strict { pos += 1; }
+    if matches!(tokens.get(pos), Some(Token::Id(word)) if word == "digraph" || word == "graph") {
+        pos += 1;
+        if matches!(tokens.get(pos), Some(Token::Id(_))) { pos += 1; }
+        if tokens.get(pos) != Some(&Token::LBrace) { return Err("Expected '{' after graph header".to_string()); }
+        pos += 1;
+    }
+
+    while pos < tokens.len() {
+        match &tokens[pos] {
+            Token::Separator | Token::RBrace | Token::LBrace => { pos += 1; }
+            Token::Id(word) if matches!(word.as_str(), "node" | "edge" | "graph") && tokens.get(pos + 1) == Some(&Token::LBracket) => {
+                // Default attribute statements are accepted but ignored
+                pos += 2;
+                parse_attribute_list(&tokens, &mut pos)?;
+            }
+            Token::Id(first) => {
+                let first = first.clone();
+                pos += 1;
+                if tokens.get(pos) == Some(&Token::Equals) {
+                    // Graph-level `key = value`, ignored
+                    pos += 2;
+                    continue;
+                }
+                let mut chain = vec![builder.node(&first)];
+                while tokens.get(pos) == Some(&Token::Arrow) {
+                    match tokens.get(pos + 1) {
+                        Some(Token::Id(next)) => { chain.push(builder.node(next)); pos += 2; }
+                        _ => return Err(format!("Expected node name after '{} ->'", first)),
+                    }
+                }
+                let attributes = if tokens.get(pos) == Some(&Token::LBracket) {
+                    pos += 1;
+                    parse_attribute_list(&tokens, &mut pos)?
+                } else {
+                    Vec::new()
+                };
+                if chain.len() == 1 {
+                    builder.apply_node_attributes(chain[0], &attributes);
+                } else {
+                    let label = attributes.iter().find(|(key, _)| key == "label").map(|(_, value)| value.clone());
+                    for pair in chain.windows(2) {
+                        let mut connection = Connection::new(pair[0], ports::default_outgoing_port(), pair[1], ports::default_incoming_port());
+                        connection.label = label.clone().filter(|l| !l.is_empty());
+                        if !strict || !builder.connections.iter().any(|c| c.same_endpoints(&connection)) { builder.connections.push(connection); }
+                    }
+                }
+            }
+            other => return Err(format!("Unexpected {:?}", other)),
+        }
+    }
+
+    // Nodes without coordinates go on a grid below/right of the origin
+    let mut grid_slot = 0;
+    for (index, shape) in builder.shapes.iter_mut().enumerate() {
+        if builder.positioned[index] { continue; }
+        let column = (grid_slot % GRID_COLUMNS) as f32;
+        let row = (grid_slot / GRID_COLUMNS) as f32;
+        shape.center_position = Vec2::new((column + 1.0) * grid_spacing.x, (row + 1.0) * grid_spacing.y);
+        grid_slot += 1;
+    }
+
+    let fully_positioned = builder.positioned.iter().all(|&p| p);
+    Ok(ImportedDiagram {
+        diagram: Diagram { shapes: builder.shapes, connections: builder.connections, ..Diagram::default() },
+        fully_positioned,
+    })
+}
+
+pub fn import_dot(path: &Path, grid_spacing: Vec2) -> Result<ImportedDiagram, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    parse_dot(&contents, grid_spacing).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+}
@@ -0,0 +1,136 @@
+// inspector.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::{Color, Rect};
+
+use crate::ui::{PanelLayout, Widget};
+use crate::{Connection, ShapeData};
+
+const INSPECTOR_WIDTH: f32 = 240.0;
+
+// --- Editable properties shown in the inspector; a click on a widget reports one of these ---
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InspectorField {
+    ShapeText,
+    ShapeMetadata, // Tags and key=value attributes, comma separated
+    ShapeSubdiagram, // Path of the diagram the shape drills into
+    ShapeX,
+    ShapeY,
+    ShapeWidth,
+    ShapeHeight,
+    ShapeFill(Option<usize>), // Palette index; None resets to the default color
+    ShapeOutline(Option<usize>),
+    ShapeFontSize,
+    ShapeTextColor(Option<usize>), // Palette index; None resets to the theme's text color
+    ConnectionFromPort,
+    ConnectionToPort,
+    ConnectionLabel,
+    ConnectionRouting,
+    ConnectionColor(Option<usize>), // Palette index; None resets to the configured connector color
+    ConnectionWidth,
+    ConnectionDash,
+}
+
+// What the inspector is showing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InspectedItem {
+    Shape(usize),
+    Connection(usize),
+}
+
+// A text field being typed into, and the item it belongs to
+pub struct InspectorEdit {
+    pub field: InspectorField,
+    pub item: InspectedItem,
+    pub text: String,
+}
+
+#[derive(Default)]
+pub struct Inspector {
+    pub visible: bool,
+    pub editing: Option<InspectorEdit>,
+}
+
+impl Inspector {
+    // Screen-space area of the panel, docked to the right edge, or None while hidden
+    pub fn panel_rect(&self, screen_width: f32, screen_height: f32) -> Option<Rect> {
+        self.visible.then(|| Rect::new(screen_width - INSPECTOR_WIDTH, 0.0, INSPECTOR_WIDTH, screen_height))
+    }
+
+    // Text shown in a field: the edit buffer while it is being typed into, else the stored value
+    fn field_text(&self, field: InspectorField, item: InspectedItem, stored: &str) -> (String, bool) {
+        match &self.editing {
+            Some(edit) if edit.field == field && edit.item == item => (edit.text.clone(), true),
+            _ => (stored.to_string(), false),
+        }
+    }
+
+    pub fn widgets(
+        &self,
+        panel: Rect,
+        item: Option<InspectedItem>,
+        shapes: &[ShapeData],
+        connections: &[Connection],
+        default_size: Vec2,
+        palette: &[[u8; 3]],
+    ) -> Vec<Widget<InspectorField>> {
+        let mut layout = PanelLayout::new(panel);
+        match item {
+            Some(InspectedItem::Shape(index)) if index < shapes.len() => {
+                let shape = &shapes[index];
+                let size = shape.size.map_or(default_size, Vec2::from);
+                layout.label(format!("Shape {} ({})", index, shape.kind.name()));
+                let (text, focused) = self.field_text(InspectorField::ShapeText, InspectedItem::Shape(index), shape.text.as_deref().unwrap_or(""));
+                layout.text_field(InspectorField::ShapeText, "Text", &text, focused);
+                let (metadata, focused) = self.field_text(InspectorField::ShapeMetadata, InspectedItem::Shape(index), &shape.metadata_text());
+                layout.text_field(InspectorField::ShapeMetadata, "Tags", &metadata, focused);
+                let subdiagram = shape.subdiagram.as_ref().map_or(String::new(), |path| path.display().to_string());
+                let (subdiagram, focused) = self.field_text(InspectorField::ShapeSubdiagram, InspectedItem::Shape(index), &subdiagram);
+                layout.text_field(InspectorField::ShapeSubdiagram, "Links to", &subdiagram, focused);
+                layout.stepper(InspectorField::ShapeX, "X", format!("{:.0}", shape.center_position.x));
+                layout.stepper(InspectorField::ShapeY, "Y", format!("{:.0}", shape.center_position.y));
+                layout.stepper(InspectorField::ShapeWidth, "Width", format!("{:.0}", size.x));
+                layout.stepper(InspectorField::ShapeHeight, "Height", format!("{:.0}", size.y));
+                let font_size = shape.font_size.map_or(String::from("default"), |s| format!("{:.0}", s));
+                layout.stepper(InspectorField::ShapeFontSize, "Font size", font_size);
+                for (caption, current, make_field) in [
+                    ("Fill", shape.fill_rgb, InspectorField::ShapeFill as fn(Option<usize>) -> InspectorField),
+                    ("Outline", shape.outline_rgb, InspectorField::ShapeOutline),
+                    ("Text", shape.text_rgb, InspectorField::ShapeTextColor),
+                ] {
+                    layout.label(caption);
+                    let reset = (make_field(None), None, current.is_none());
+                    let swatches = palette.iter().enumerate().map(|(i, &rgb)| {
+                        (make_field(Some(i)), Some(Color::from_rgb(rgb[0], rgb[1], rgb[2])), current == Some(rgb))
+                    });
+                    layout.swatch_row(std::iter::once(reset).chain(swatches));
+                }
+            }
+            Some(InspectedItem::Connection(index)) if index < connections.len() => {
+                let connection = &connections[index];
+                // "shape:port side", e.g. "3:1 Bottom"
+                let port_description = |shape_index: usize, port_id: usize| {
+                    let side = shapes.get(shape_index).and_then(|s| s.ports.get(port_id)).map(|p| p.side);
+                    format!("{}:{} {}", shape_index, port_id, side.map_or(String::from("?"), |s| format!("{:?}", s)))
+                };
+                layout.label(format!("Connection {}", index));
+                layout.stepper(InspectorField::ConnectionFromPort, "From", port_description(connection.from_shape_index, connection.from_port));
+                layout.stepper(InspectorField::ConnectionToPort, "To", port_description(connection.to_shape_index, connection.to_port));
+                let (label, focused) = self.field_text(InspectorField::ConnectionLabel, InspectedItem::Connection(index), connection.label.as_deref().unwrap_or(""));
+                layout.text_field(InspectorField::ConnectionLabel, "Label", &label, focused);
+                layout.button(InspectorField::ConnectionRouting, if connection.smart_routed { "Routing: smart" } else { "Routing: curve" });
+                let width = connection.line_width.map_or(String::from("default"), |w| format!("{:.1}", w));
+                layout.stepper(InspectorField::ConnectionWidth, "Width", width);
+                layout.button(InspectorField::ConnectionDash, format!("Line: {}", connection.dash.name()));
+                layout.label("Color");
+                let reset = (InspectorField::ConnectionColor(None), None, connection.color_rgb.is_none());
+                let swatches = palette.iter().enumerate().map(|(i, &rgb)| {
+                    (InspectorField::ConnectionColor(Some(i)), Some(Color::from_rgb(rgb[0], rgb[1], rgb[2])), connection.color_rgb == Some(rgb))
+                });
+                layout.swatch_row(std::iter::once(reset).chain(swatches));
+            }
+            _ => layout.label("Nothing selected"),
+        }
+        layout.widgets
+    }
+}
@@ -0,0 +1,36 @@
+// layers_panel.rs
+
+use ggez::graphics::Rect;
+
+use crate::tools::TOOLBAR_HEIGHT;
+use crate::ui::{self, PanelLayout, Widget};
+use crate::Diagram;
+
+const LAYERS_PANEL_WIDTH: f32 = 220.0;
+
+// --- What a click in the layers panel does ---
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayerAction {
+    Activate(usize), // New shapes and connections go on the active layer
+    ToggleVisible(usize),
+    ToggleLocked(usize),
+    Add,
+}
+
+// Panel docked on the left under the toolbar: one row per layer, topmost first, then an add button.
+// Each row is the layer's name (highlighted when active) and its visibility and lock toggles.
+pub fn layers_widgets(diagram: &Diagram, active_layer: usize) -> (Rect, Vec<Widget<LayerAction>>) {
+    let rows = diagram.layer_count() + 2;
+    let panel = Rect::new(0.0, TOOLBAR_HEIGHT, LAYERS_PANEL_WIDTH, ui::panel_height(rows));
+    let mut layout = PanelLayout::new(panel);
+    layout.label("Layers");
+    for layer in (0..diagram.layer_count()).rev() {
+        layout.button_row([
+            (LayerAction::Activate(layer), diagram.layer_name(layer), layer == active_layer, 3.0),
+            (LayerAction::ToggleVisible(layer), String::from("Vis"), diagram.layer_visible(layer), 1.2),
+            (LayerAction::ToggleLocked(layer), String::from("Lock"), diagram.layer_locked(layer), 1.2),
+        ]);
+    }
+    layout.button(LayerAction::Add, "+ Layer");
+    (panel, layout.widgets)
+}
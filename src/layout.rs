@@ -0,0 +1,185 @@
+// layout.rs
+
+use ggez::glam::Vec2;
+use std::collections::{HashMap, VecDeque};
+
+use crate::{Connection, Diagram};
+
+const ORDERING_SWEEPS: usize = 8; // Barycenter passes (alternating down/up) to reduce crossings
+
+// --- Distances between neighbouring shape centers in the layout ---
+pub struct LayoutSpacing {
+    pub horizontal: f32,
+    pub vertical: f32,
+}
+
+// Layered layout of a whole diagram, centered horizontally on its shapes and starting at the topmost one
+pub fn diagram_layout(diagram: &Diagram, spacing: &LayoutSpacing) -> Vec<Vec2> {
+    let shapes = &diagram.shapes;
+    if shapes.is_empty() { return Vec::new(); }
+    let origin = Vec2::new(
+        shapes.iter().map(|s| s.center_position.x).sum::<f32>() / shapes.len() as f32,
+        shapes.iter().map(|s| s.center_position.y).fold(f32::INFINITY, f32::min),
+    );
+    hierarchical_layout(shapes.len(), &diagram.connections, origin, spacing)
+}
+
+// Lays out just the shapes at `added` (e.g. fresh from an import) by the connections among them,
+// one layer below the lowest of the other shapes; those stay where they are
+pub fn place_added_shapes(diagram: &mut Diagram, added: &[usize], spacing: &LayoutSpacing) {
+    if added.is_empty() { return; }
+    let local: HashMap<usize, usize> = added.iter().enumerate().map(|(local, &index)| (index, local)).collect();
+    let existing: Vec<Vec2> = diagram.shapes.iter().enumerate().filter(|(i, _)| !local.contains_key(i)).map(|(_, s)| s.center_position).collect();
+    let origin = match existing.is_empty() {
+        true => Vec2::ZERO,
+        false => Vec2::new(
+            existing.iter().map(|p| p.x).sum::<f32>() / existing.len() as f32,
+            existing.iter().map(|p| p.y).fold(f32::NEG_INFINITY, f32::max) + spacing.vertical,
+        ),
+    };
+    let local_connections: Vec<Connection> = diagram.connections.iter()
+        .filter_map(|c| Some(Connection { from_shape_index: *local.get(&c.from_shape_index)?, to_shape_index: *local.get(&c.to_shape_index)?, ..c.clone() }))
+        .collect();
+    let positions = hierarchical_layout(added.len(), &local_connections, origin, spacing);
+    for (&index, position) in added.iter().zip(positions) { diagram.shapes[index].center_position = position; }
+}
+
+// Layered (Sugiyama-style) arrangement driven by the connections: cycles are broken,
+// nodes are assigned to layers by longest path, layers are ordered with the barycenter
+// heuristic, and each layer is centered horizontally on `origin.x` starting at `origin.y`.
+// Returns one new center position per shape index.
+pub fn hierarchical_layout(shape_count: usize, connections: &[Connection], origin: Vec2, spacing: &LayoutSpacing) -> Vec<Vec2> {
+    let edges: Vec<(usize, usize)> = connections
+        .iter()
+        .filter(|c| c.from_shape_index != c.to_shape_index && c.from_shape_index < shape_count && c.to_shape_index < shape_count)
+        .map(|c| (c.from_shape_index, c.to_shape_index))
+        .collect();
+    let edges = break_cycles(shape_count, &edges);
+    let layer_of = assign_layers(shape_count, &edges);
+    let layers = order_layers(shape_count, &edges, &layer_of);
+
+    let mut positions = vec![origin; shape_count];
+    for (layer_index, layer) in layers.iter().enumerate() {
+        let half_width = (layer.len() as f32 - 1.0) / 2.0;
+        for (slot, &node) in layer.iter().enumerate() {
+            positions[node] = Vec2::new(
+                origin.x + (slot as f32 - half_width) * spacing.horizontal,
+                origin.y + layer_index as f32 * spacing.vertical,
+            );
+        }
+    }
+    positions
+}
+
+// Reverses DFS back edges so the remaining graph is acyclic
+fn break_cycles(node_count: usize, edges: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut outgoing: Vec<Vec<usize>> = vec![Vec::new(); node_count];
+    for (edge_index, &(from, _)) in edges.iter().enumerate() {
+        outgoing[from].push(edge_index);
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Visit { New, OnStack, Done }
+    let mut visit = vec![Visit::New; node_count];
+    let mut reversed = vec![false; edges.len()];
+
+    for root in 0..node_count {
+        if visit[root] != Visit::New { continue; }
+        // Iterative DFS: (node, index of the next outgoing edge to explore)
+        let mut stack = vec![(root, 0usize)];
+        visit[root] = Visit::OnStack;
+        while let Some((node, next)) = stack.pop() {
+            if let Some(&edge_index) = outgoing[node].get(next) {
+                stack.push((node, next + 1));
+                let target = edges[edge_index].1;
+                match visit[target] {
+                    Visit::New => {
+                        visit[target] = Visit::OnStack;
+                        stack.push((target, 0));
+                    }
+                    Visit::OnStack => reversed[edge_index] = true,
+                    Visit::Done => {}
+                }
+            } else {
+                visit[node] = Visit::Done;
+            }
+        }
+    }
+
+    edges
+        .iter()
+        .zip(reversed)
+        .map(|(&(from, to), rev)| if rev { (to, from) } else { (from, to) })
+        .collect()
+}
+
+// Longest-path layering: every node sits one layer below its deepest predecessor
+fn assign_layers(node_count: usize, edges: &[(usize, usize)]) -> Vec<usize> {
+    let mut successors: Vec<Vec<usize>> = vec![Vec::new(); node_count];
+    let mut in_degree = vec![0usize; node_count];
+    for &(from, to) in edges {
+        successors[from].push(to);
+        in_degree[to] += 1;
+    }
+
+    let mut layer_of = vec![0usize; node_count];
+    let mut queue: VecDeque<usize> = (0..node_count).filter(|&n| in_degree[n] == 0).collect();
+    while let Some(node) = queue.pop_front() {
+        for &next in &successors[node] {
+            layer_of[next] = layer_of[next].max(layer_of[node] + 1);
+            in_degree[next] -= 1;
+            if in_degree[next] == 0 {
+                queue.push_back(next);
+            }
+        }
+    }
+    layer_of
+}
+
+// Orders nodes within each layer by the average slot of their neighbours in the layer above
+// (downward sweeps) or below (upward sweeps)
+fn order_layers(node_count: usize, edges: &[(usize, usize)], layer_of: &[usize]) -> Vec<Vec<usize>> {
+    let layer_count = layer_of.iter().max().map_or(0, |&max| max + 1);
+    let mut layers: Vec<Vec<usize>> = vec![Vec::new(); layer_count];
+    for (node, &layer) in layer_of.iter().enumerate() {
+        layers[layer].push(node);
+    }
+
+    let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); node_count];
+    let mut successors: Vec<Vec<usize>> = vec![Vec::new(); node_count];
+    for &(from, to) in edges {
+        successors[from].push(to);
+        predecessors[to].push(from);
+    }
+
+    let mut slot_of = vec![0usize; node_count];
+    for layer in &layers {
+        for (slot, &node) in layer.iter().enumerate() {
+            slot_of[node] = slot;
+        }
+    }
+
+    for sweep in 0..ORDERING_SWEEPS {
+        let downward = sweep % 2 == 0;
+        let neighbours = if downward { &predecessors } else { &successors };
+        let layer_indices: Vec<usize> = if downward { (1..layer_count).collect() } else { (0..layer_count.saturating_sub(1)).rev().collect() };
+        for layer_index in layer_indices {
+            let layer = &mut layers[layer_index];
+            let barycenter = |node: usize| -> f32 {
+                let adjacent = &neighbours[node];
+                if adjacent.is_empty() {
+                    slot_of[node] as f32
+                } else {
+                    adjacent.iter().map(|&n| slot_of[n] as f32).sum::<f32>() / adjacent.len() as f32
+                }
+            };
+            let mut keyed: Vec<(f32, usize)> = layer.iter().map(|&node| (barycenter(node), node)).collect();
+            keyed.sort_by(|a, b| a.0.total_cmp(&b.0));
+            for (slot, &(_, node)) in keyed.iter().enumerate() {
+                layer[slot] = node;
+                slot_of[node] = slot;
+            }
+        }
+    }
+    layers
+}
@@ -0,0 +1,50 @@
+// lib.rs
+// Diagram model, geometry, file formats and exporters. Nothing here needs a window, so diagrams
+// can be built, edited and exported programmatically; main.rs is the interactive editor on top.
+
+pub mod camera;
+pub mod clipboard;
+pub mod diagram;
+pub mod dsl;
+pub mod export_dot;
+pub mod export_fsm;
+pub mod export_graph;
+pub mod export_svg;
+pub mod font_fallback;
+pub mod geometry;
+pub mod import_csv;
+pub mod import_dot;
+pub mod layout;
+pub mod logging;
+pub mod persistence;
+pub mod ports;
+pub mod routing;
+pub mod scripting;
+pub mod shape_kind;
+pub mod spatial;
+pub mod stress;
+pub mod tessellation;
+pub mod text_edit;
+pub mod tween;
+pub mod validation;
+
+pub use diagram::{Connection, Diagram, Lane, LaneOrientation, LineDash, ShapeData};
+
+// --- Visual constants shared by the editor and the exporters ---
+pub const CONNECTOR_LINE_WIDTH: f32 = 2.0;
+pub const CONNECTOR_ARROWHEAD_LENGTH: f32 = 12.0; // At the default line width
+pub const CONNECTOR_ARROWHEAD_WIDTH_RATIO: f32 = 0.8; // An arrowhead's width at its base, as a share of its length
+// How far a curve's control points sit out from its ports: a share of the distance between the ends, within limits
+pub const CONNECTOR_CURVE_OFFSET_RATIO: f32 = 0.35;
+pub const CONNECTOR_CURVE_OFFSET_MIN: f32 = 20.0;
+pub const CONNECTOR_CURVE_OFFSET_MAX: f32 = 160.0;
+pub const CONNECTOR_LABEL_FONT_SIZE: f32 = 14.0;
+pub const PARALLEL_CONNECTOR_SPACING: f32 = 24.0; // Gap between the middles of connectors joining the same two shapes
+pub const SHAPE_OUTLINE_WIDTH: f32 = 2.0; // Width of a shape's own outline, when it has an outline color
+pub const GROUP_PADDING: f32 = 16.0; // Space between a group's frame and its contents
+pub const GROUP_TITLE_HEIGHT: f32 = 20.0; // Strip above the contents holding the group's name
+pub const NOTE_FILL_RGB: [u8; 3] = [255, 232, 128]; // Sticky notes without their own fill color
+pub const NOTE_TEXT_RGB: [u8; 3] = [60, 50, 20];
+pub const NOTE_TEXT_PADDING: f32 = 10.0; // Between a note's edges and its top-left aligned text
+pub const HIGHLIGHT_ALPHA: f32 = 0.35; // Opacity of highlighter annotations
+pub const LANE_TITLE_SIZE: f32 = 28.0; // Width (horizontal lanes) or height (vertical lanes) of a lane's title strip
@@ -0,0 +1,76 @@
+// logging.rs
+// Log targets, so one area can be turned up without the rest, e.g. RUST_LOG=input=debug,info
+
+use std::sync::Mutex;
+
+pub const INPUT: &str = "input"; // Clicks, keys, tools and what they select or cancel
+pub const MODEL: &str = "model"; // Changes to the diagram
+pub const RENDER: &str = "render"; // Drawing, view toggles and window setup
+pub const IO: &str = "io"; // Files, config, exports and the clipboard
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+// --- Messages for the user rather than the terminal ---
+// Anything may queue one; the editor takes them each frame and shows them as toasts. Warnings and
+// errors logged through `log` are queued too, since stderr is out of sight in a GUI.
+#[derive(Clone, Debug)]
+pub struct Notification {
+    pub severity: Severity,
+    pub message: String,
+}
+
+static NOTIFICATIONS: Mutex<Vec<Notification>> = Mutex::new(Vec::new());
+
+pub fn notify(severity: Severity, message: impl Into<String>) {
+    if let Ok(mut queue) = NOTIFICATIONS.lock() {
+        queue.push(Notification { severity, message: message.into() });
+    }
+}
+
+// Everything queued since the last call, oldest first
+pub fn take_notifications() -> Vec<Notification> {
+    NOTIFICATIONS.lock().map(|mut queue| std::mem::take(&mut *queue)).unwrap_or_default()
+}
+
+// env_logger, plus a notification for every warning and error whatever the filter says
+struct NotifyingLogger {
+    inner: env_logger::Logger,
+}
+
+impl log::Log for NotifyingLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.inner.log(record);
+        let severity = match record.level() {
+            log::Level::Error => Severity::Error,
+            log::Level::Warn => Severity::Warning,
+            _ => return,
+        };
+        notify(severity, record.args().to_string());
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+// Logs to stderr, filtered by RUST_LOG. Without it, debug builds show info and up, release builds
+// only warnings and errors.
+pub fn init() {
+    let default_filter = if cfg!(debug_assertions) { "info" } else { "warn" };
+    let inner = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_filter))
+        .format_timestamp(None)
+        .build();
+    let max_level = inner.filter().max(log::LevelFilter::Warn);
+    if log::set_boxed_logger(Box::new(NotifyingLogger { inner })).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
@@ -1,26 +1,85 @@
 // main.rs
 
+mod cli;
+mod code_panel;
+mod collab;
+mod commands;
+mod config;
+mod debug_overlay;
+mod event_loop;
+mod find;
+mod history;
+mod inspector;
+mod layers_panel;
+mod mesh_cache;
+mod palette;
+mod recent_files;
+mod session;
+mod simulation;
+mod stats_panel;
+mod status_bar;
+mod tabs;
+mod theme;
+mod toasts;
+mod tools;
+mod touch;
+mod ui;
+
 use ggez::conf::{WindowMode, WindowSetup, NumSamples};
-use ggez::event::{self, EventHandler, MouseButton};
+use ggez::event::{EventHandler, MouseButton};
 use ggez::glam::Vec2;
-use ggez::graphics::{self, Color, DrawMode, Mesh, MeshData, Rect, Text, TextLayout, Vertex};
-use ggez::input::keyboard::{KeyCode, KeyInput};
+use ggez::graphics::{self, Color, DrawMode, Drawable, Mesh, MeshData, Rect, Text, TextFragment, TextLayout, Vertex};
+use ggez::input::keyboard::{KeyCode, KeyInput, KeyMods};
+use ggez::winit::event::TouchPhase;
+use ggez::winit::window::CursorIcon;
 use ggez::{Context, ContextBuilder, GameResult};
 use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::time::Instant;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use rust_visual_mouse_app::{camera, clipboard, diagram, dsl, export_dot, export_fsm, export_graph, export_svg, font_fallback, geometry, import_csv, layout, logging, persistence, ports, routing, scripting, shape_kind, spatial, stress, tessellation, text_edit, tween, validation};
+use rust_visual_mouse_app::{Connection, Diagram, LaneOrientation, LineDash, ShapeData, CONNECTOR_LABEL_FONT_SIZE, CONNECTOR_LINE_WIDTH, GROUP_TITLE_HEIGHT, HIGHLIGHT_ALPHA, NOTE_FILL_RGB, NOTE_TEXT_PADDING, NOTE_TEXT_RGB, SHAPE_OUTLINE_WIDTH};
 
-// Lyon imports
-use lyon_path::path::Builder as LyonPathBuilder;
-use lyon_path::math::Point as LyonPoint;
-use lyon_tessellation::{
-    StrokeTessellator, StrokeOptions, StrokeVertex, VertexBuffers,
-    BuffersBuilder
-};
+use camera::Camera;
+use code_panel::CodePanel;
+use collab::Collaboration;
+use diagram::{Annotation, GraphStats, PresentationStep, Reach};
+use commands::{Command, CommandPalette, ContextMenu};
+use debug_overlay::FrameStats;
+use event_loop::GestureHandler;
+use find::ShapeSearch;
+use font_fallback::FontFallback;
+use history::History;
+use inspector::{InspectedItem, Inspector, InspectorEdit, InspectorField};
+use layers_panel::LayerAction;
+use mesh_cache::{MeshCache, ShapeMeshKey};
+use palette::NodeTemplate;
+use recent_files::{RecentFiles, RecentFilesMenu};
+use simulation::Simulation;
+use session::{RecordedButton, Recorder, Replayer, SessionEvent};
+use status_bar::StatusInfo;
+use tabs::TabAction;
+use theme::{Theme, ThemeColors};
+use toasts::Toasts;
+use touch::{TouchGesture, TouchTracker};
+use ports::{Port, PortId, PortSide};
+use shape_kind::ShapeKind;
+use spatial::SpatialHash;
+use tessellation::{ConnectorPath, ConnectorStroke, StrokeCache};
+use lyon_tessellation::VertexBuffers;
+use text_edit::TextEdit;
+use tween::{Easing, Tween};
+use validation::ConnectionValidator;
+use tools::Tool;
+use ui::WidgetHit;
 
 
 // --- Configuration Structs ---
-#[derive(Deserialize, Serialize, Debug, Clone)] 
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 struct ColorsConfig {
     connector_line_rgb: Option<[u8; 3]>,
     selected_connector_line_rgb: Option<[u8; 3]>,
@@ -28,19 +87,7 @@ struct ColorsConfig {
     default_port_rgb: Option<[u8; 3]>,
     selected_connector_port_rgb: Option<[u8; 3]>,
     active_new_line_start_port_rgb: Option<[u8; 3]>,
-}
-
-impl Default for ColorsConfig {
-    fn default() -> Self {
-        ColorsConfig {
-            connector_line_rgb: None,
-            selected_connector_line_rgb: None,
-            preview_connector_line_rgb: None,
-            default_port_rgb: None,
-            selected_connector_port_rgb: None,
-            active_new_line_start_port_rgb: None,
-        }
-    }
+    shape_palette_rgb: Option<Vec<[u8; 3]>>, // Colors picked with number keys 1-9
 }
 
 
@@ -50,7 +97,10 @@ struct WindowConfig {
     height: f32,
     title: String,
     msaa_level: Option<u8>, 
-    ui_scale_factor: Option<f32>, 
+    ui_scale_factor: Option<f32>, // Multiplies the monitor's own scale factor, which is applied automatically
+    max_fps: Option<u32>, // Frame rate cap while something is changing. Defaults to uncapped with vsync, DEFAULT_UNSYNCED_MAX_FPS without.
+    vsync: Option<bool>, // Defaults to true
+    render_on_demand: Option<bool>, // Throttle frames while nothing changes. Defaults to true; false redraws continuously at max_fps.
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -58,9 +108,67 @@ struct ShapeConfig {
     width: f32,
     height: f32,
     corner_radius: f32,
-    base_color_rgb: [u8; 3], // Changed from color_r, color_g, color_b
+    base_color_rgb: Option<[u8; 3]>, // Overrides the theme's shape color
     selection_outline_color_rgb: Option<[u8; 3]>, // Changed from _r, _g, _b options
     selection_outline_width: Option<f32>,
+    ports: Option<Vec<Port>>, // Ports given to new shapes. Defaults to one centered on each side.
+    auto_size: Option<bool>, // Grow shapes to fit their text. Defaults to false.
+    auto_size_max_width: Option<f32>, // Widest an auto-sized shape gets before wrapping. Defaults to `width` (height only grows).
+    auto_name: Option<bool>, // Label new shapes "Node 1", "Node 2", ... Defaults to false.
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+struct GridConfig {
+    enabled: Option<bool>, // Show the grid and snap to it on startup. Defaults to false.
+    spacing: Option<f32>,
+    color_rgb: Option<[u8; 3]>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+struct ThemeConfig {
+    name: Option<String>, // "dark", "light", a theme file's path, or the name of one in <config dir>/themes. Defaults to "dark".
+    palette: Option<String>, // Highlight colors for color vision deficiencies: deuteranopia, protanopia or tritanopia. Defaults to "default".
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+struct TextConfig {
+    enter_inserts_newline: Option<bool>, // Swap Enter and Shift+Enter while editing. Defaults to false.
+    font_path: Option<PathBuf>, // TTF/OTF file used for all text instead of the built-in font
+    fallback_fonts: Option<Vec<PathBuf>>, // Fonts tried in order for label chars the main font lacks (other scripts, emoji)
+    shape_font_size: Option<f32>, // Size of shape labels. Defaults to 18.
+    status_font_size: Option<f32>, // Size of the status bar text. Defaults to 15.
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+struct ConnectionsConfig {
+    allow_self_loops: Option<bool>, // Defaults to false
+    allow_reverse_duplicates: Option<bool>, // Allow B→A next to A→B. Defaults to true.
+    dag_mode: Option<bool>, // Refuse connections that close a cycle. Defaults to false.
+    flow_animation: Option<bool>, // Start with dots running along connectors. Defaults to false.
+    bridges: Option<bool>, // Draw a hop where a connector crosses one drawn before it. Defaults to false.
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+struct ExportConfig {
+    png_scale: Option<u32>, // 1, 2 or 4. Defaults to 1.
+    include_notes: Option<bool>, // Keep sticky notes in graph exports (DOT, CSV, adjacency). Defaults to false.
+}
+
+// What the mouse wheel does without modifiers
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+enum WheelAction {
+    #[default]
+    Zoom,
+    Pan, // Vertically, or sideways with Shift; Ctrl+wheel zooms
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+struct InputConfig {
+    wheel: Option<WheelAction>, // Defaults to zoom
+    zoom_sensitivity: Option<f32>, // Multiplies the zoom per wheel notch or pinch. Defaults to 1.0.
+    invert_zoom: Option<bool>, // Wheel up zooms out. Defaults to false.
+    invert_pan: Option<bool>, // Wheel up pans the view down, in pan mode. Defaults to false.
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -68,603 +176,5100 @@ struct AppConfig {
     window: WindowConfig,
     shape: ShapeConfig,
     colors: Option<ColorsConfig>, 
+    theme: Option<ThemeConfig>,
+    grid: Option<GridConfig>,
+    export: Option<ExportConfig>,
+    text: Option<TextConfig>,
+    connections: Option<ConnectionsConfig>,
+    input: Option<InputConfig>,
+    node_templates: Option<Vec<NodeTemplate>>, // [[node_templates]] entries, offered in the palette
+}
+
+// --- The [shape], [colors] and [theme] settings as AppState uses them; re-applied when config.toml changes ---
+struct Appearance {
+    theme: Theme,
+    theme_overrides: Vec<ThemeColors>, // The [theme] palette, then colors set in [shape], [colors] and [grid]; kept over any theme switched to
+    default_shape_width: f32,
+    default_shape_height: f32,
+    default_shape_corner_radius: f32,
+    default_shape_ports: Vec<Port>,
+    auto_size_shapes: bool,
+    auto_name_shapes: bool,
+    auto_size_max_width: f32,
+    selection_outline_width: f32,
+    shape_palette: Vec<[u8; 3]>,
+}
+
+impl Appearance {
+    // Invalid values fall back to their defaults, with a warning for each
+    fn from_config(app_config: &AppConfig) -> (Appearance, Vec<String>) {
+        let mut warnings = Vec::new();
+        let shape_config = &app_config.shape;
+        let colors_config = app_config.colors.clone().unwrap_or_default();
+
+        let selection_outline_width = shape_config.selection_outline_width.unwrap_or(2.0);
+
+        let default_shape_ports = match &shape_config.ports {
+            Some(configured) if !configured.is_empty() => configured.iter().map(|p| Port { port_type: p.port_type.clone(), direction: p.direction, ..Port::new(p.side, p.offset) }).collect(),
+            Some(_) => {
+                warnings.push(String::from("Empty ports list in config.toml. Defaulting to one port per side."));
+                ports::default_ports()
+            }
+            None => ports::default_ports(),
+        };
+
+        let auto_size_max_width = match shape_config.auto_size_max_width {
+            Some(max_width) if max_width >= shape_config.width => max_width,
+            Some(_) => {
+                warnings.push(String::from("Invalid auto_size_max_width in config.toml. Must be >= the shape width. Defaulting to the shape width."));
+                shape_config.width
+            }
+            None => shape_config.width,
+        };
+
+        let shape_palette = match &colors_config.shape_palette_rgb {
+            Some(palette) if !palette.is_empty() => palette.clone(),
+            Some(_) => {
+                warnings.push(String::from("Empty shape_palette_rgb in config.toml. Defaulting to the built-in palette."));
+                DEFAULT_SHAPE_PALETTE.to_vec()
+            }
+            None => DEFAULT_SHAPE_PALETTE.to_vec(),
+        };
+
+        let theme_name = app_config.theme.as_ref().and_then(|t| t.name.clone()).unwrap_or_else(|| String::from("dark"));
+        let base_theme = Theme::load(&theme_name).unwrap_or_else(|e| {
+            warnings.push(format!("{}. Defaulting to the dark theme.", e));
+            Theme::dark()
+        });
+        let palette_name = app_config.theme.as_ref().and_then(|t| t.palette.clone()).unwrap_or_else(|| String::from("default"));
+        let palette = theme::palette(&palette_name).unwrap_or_else(|e| {
+            warnings.push(format!("{}. Defaulting to the theme's own colors.", e));
+            ThemeColors::default()
+        });
+        let config_colors = ThemeColors {
+            shape_rgb: shape_config.base_color_rgb,
+            selection_rgb: shape_config.selection_outline_color_rgb,
+            connector_rgb: colors_config.connector_line_rgb,
+            selected_connector_rgb: colors_config.selected_connector_line_rgb,
+            preview_connector_rgb: colors_config.preview_connector_line_rgb,
+            port_rgb: colors_config.default_port_rgb,
+            selected_port_rgb: colors_config.selected_connector_port_rgb,
+            active_port_rgb: colors_config.active_new_line_start_port_rgb,
+            grid_rgb: app_config.grid.as_ref().and_then(|g| g.color_rgb),
+            ..ThemeColors::default()
+        };
+        let theme_overrides = vec![palette, config_colors];
+
+        let appearance = Appearance {
+            theme: base_theme.with_all_overrides(&theme_overrides),
+            theme_overrides,
+            default_shape_width: shape_config.width,
+            default_shape_height: shape_config.height,
+            default_shape_corner_radius: shape_config.corner_radius,
+            default_shape_ports,
+            auto_size_shapes: shape_config.auto_size.unwrap_or(false),
+            auto_name_shapes: shape_config.auto_name.unwrap_or(false),
+            auto_size_max_width,
+            selection_outline_width,
+            shape_palette,
+        };
+        (appearance, warnings)
+    }
 }
 
 // --- Constants for non-color visual properties ---
 const DOUBLE_CLICK_MAX_DELAY_MS: u128 = 500;
 const DOUBLE_CLICK_MAX_DISTANCE: f32 = 10.0;
+const NEW_LINE_DRAG_THRESHOLD: f32 = 6.0; // Movement that turns a press on a port into a drag-to-connect
 const TEXT_PADDING: f32 = 8.0;
-const CONNECTOR_LINE_WIDTH: f32 = 2.0;
-const CONNECTOR_CURVE_OFFSET: f32 = 40.0; 
+const NOTE_SIZE: f32 = 160.0; // Side of a new sticky note
+const SUBDIAGRAM_BADGE_SIZE: f32 = 14.0;
+const SUBDIAGRAM_BADGE_INSET: f32 = 5.0; // From the shape's bottom-right corner
+const DEFAULT_SHAPE_FONT_SIZE: f32 = 18.0;
+const DEFAULT_FONT_NAME: &str = "LiberationMono-Regular"; // The font ggez draws Text with unless told otherwise
+const SCRIPTS_DIR_NAME: &str = "scripts"; // Rhai scripts offered in the command palette
+const TEXT_CARET_WIDTH: f32 = 1.5;
+const CORNER_RADIUS_STEP: f32 = 2.0; // Change per [ / ] key press
+const FONT_SIZE_STEP: f32 = 2.0; // Change per + / - key press and inspector step
+const MIN_SHAPE_FONT_SIZE: f32 = 6.0;
+const MAX_SHAPE_FONT_SIZE: f32 = 96.0;
+const NUDGE_STEP: f32 = 1.0; // Arrow key movement of the selection
+const NUDGE_STEP_LARGE: f32 = 10.0; // With Shift
+const INSPECTOR_POSITION_STEP: f32 = 10.0; // Position stepper increment while the grid is off
+const INSPECTOR_SIZE_STEP: f32 = 10.0;
+const INSPECTOR_LINE_WIDTH_STEP: f32 = 0.5;
+const MIN_CONNECTOR_LINE_WIDTH: f32 = 0.5;
+const MAX_CONNECTOR_LINE_WIDTH: f32 = 12.0;
+const MIN_SHAPE_SIZE: f32 = 20.0;
+const DEFAULT_SHAPE_PALETTE: [[u8; 3]; 9] = [
+    [100, 200, 255], [120, 220, 140], [250, 210, 90], [250, 150, 80], [235, 100, 100],
+    [200, 130, 230], [150, 150, 160], [240, 240, 240], [60, 60, 70],
+];
 
 const PORT_DRAW_RADIUS_DEFAULT: f32 = 4.0; 
 const PORT_DRAW_RADIUS_HOVER: f32 = 8.0;  
 const PORT_CLICK_RADIUS: f32 = 8.0;     
 const PORT_HOVER_DETECT_DISTANCE: f32 = 15.0; 
 
-const CONNECTOR_POINT_HORIZONTAL_OFFSET: f32 = 15.0;
-const CONNECTOR_SELECTION_RADIUS: f32 = CONNECTOR_LINE_WIDTH * 4.0; 
-const CONNECTOR_SAMPLE_POINTS: usize = 10;
+const CONNECTOR_SELECTION_RADIUS: f32 = CONNECTOR_LINE_WIDTH * 4.0; // On screen; scaled by zoom when picking
+const MAX_CONNECTOR_PICK_RADIUS: f32 = CONNECTOR_SELECTION_RADIUS / camera::MIN_ZOOM; // In world units, fully zoomed out
+const CONNECTOR_PICK_TOLERANCE: f32 = 0.25; // Screen pixels a flattened curve may stray from the true one
+const CONNECTOR_HANDLE_SIZE: f32 = 10.0; // Endpoint grab handles of the selected connector
+const BEND_SNAP_DISTANCE: f32 = 6.0; // On screen; a bend handle dropped this close to the unbent curve straightens it
+const ROTATION_HANDLE_DISTANCE: f32 = 24.0; // Above the top edge of the selected shape, before it is turned
+const ROTATION_SNAP_STEP: f32 = std::f32::consts::PI / 12.0; // 15 degrees; dragged rotations snap to it while the grid is on
+const PEN_WIDTH: f32 = 3.0; // On screen as drawn; the stroke keeps its width in the diagram when zoomed later
+const HIGHLIGHTER_WIDTH: f32 = 18.0;
+const PEN_RGB: [u8; 3] = [230, 60, 60];
+const HIGHLIGHTER_RGB: [u8; 3] = [255, 214, 0];
+const PEN_POINT_SPACING: f32 = 2.0; // On screen; pointer samples closer than this to the last point are skipped
+const ERASER_RADIUS: f32 = 8.0; // On screen
+const ROTATION_WHEEL_STEP: f32 = std::f32::consts::PI / 12.0; // Alt + wheel over the selection turns it this much per notch
+const FLOW_DOT_SPACING: f32 = 40.0; // Flow animation: distance between dots along a connector
+const FLOW_DOT_SPEED: f32 = 60.0; // World units per second
+const FLOW_DOT_RADIUS: f32 = 3.0; // At least; grows with thicker lines
+const BRIDGE_RADIUS: f32 = 6.0; // Half the width of the hop a connector makes over another
+const BRIDGE_ARC_SEGMENTS: usize = 8;
+const SEARCH_MATCH_COLOR: Color = Color { r: 1.0, g: 0.85, b: 0.3, a: 0.45 }; // Halo around shapes matching the Ctrl+F query
+const SEARCH_MATCH_MARGIN: f32 = 6.0;
+const CRITICAL_PATH_COLOR: Color = Color { r: 1.0, g: 0.55, b: 0.1, a: 1.0 };
+const ORDER_BADGE_RADIUS: f32 = 10.0; // Badge on a shape's top-left corner showing its topological position
+const ORDER_BADGE_FONT_SIZE: f32 = 12.0;
+const SIMULATION_COLOR: Color = Color { r: 0.2, g: 0.75, b: 0.35, a: 1.0 }; // The token, its shape and the connections it took
+const SIMULATION_BRANCH_COLOR: Color = Color { r: 0.95, g: 0.6, b: 0.1, a: 1.0 }; // Connections offered at a branch
+const SIMULATION_TOKEN_RADIUS: f32 = 9.0;
+const SIMULATION_TRAIL_WIDTH: f32 = 4.0;
+const PEER_CURSOR_ARROW: [[f32; 2]; 3] = [[0.0, 0.0], [0.0, 16.0], [11.0, 11.0]]; // A collaborator's pointer, in screen units
+const PEER_NAME_FONT_SIZE: f32 = 13.0;
+const BUNDLE_BADGE_RADIUS: f32 = 10.0; // Count on a connector standing for several that cross into a collapsed group
+const DIMMED_ALPHA: f32 = 0.2; // Opacity of shapes and connectors outside the focus
+const SPATIAL_CELL_SIZE: f32 = 150.0; // Bucket size of the hit-testing grids
+const GROUP_TITLE_FONT_SIZE: f32 = 14.0;
+const GROUP_FRAME_OUTLINE_WIDTH: f32 = 1.5;
+const LANE_TITLE_FONT_SIZE: f32 = 14.0;
+const LANE_EDGE_GRAB_DISTANCE: f32 = 6.0; // How close to a lane's right or bottom edge a press resizes it
+const LANE_MIN_SIZE: f32 = 40.0;
+
+const GRID_MIN_SCREEN_SPACING: f32 = 4.0; // Grid lines closer than this on screen are not drawn
+const ZOOM_TO_FIT_MARGIN: f32 = 40.0; // Screen-space gap left around fitted content
+const SAVE_PROMPT_WIDTH: f32 = 420.0;
+
+
+const PNG_EXPORT_MARGIN: f32 = 20.0;
+const PNG_EXPORT_MAX_DIMENSION: u32 = 8192; // Common GPU texture size limit
+
+const LAYOUT_HORIZONTAL_GAP: f32 = 40.0; // Extra space between shapes in the same layer
+const LAYOUT_VERTICAL_GAP: f32 = 60.0; // Extra space between layers
+const LAYOUT_ANIMATION_DURATION: Duration = Duration::from_millis(400);
+const CAMERA_MOVE_DURATION: Duration = Duration::from_millis(300); // Zoom to fit and scrolling to a shape
+const PRESENTATION_STEP_DURATION: Duration = Duration::from_millis(700);
+const STEP_FIT_MARGIN: f32 = 80.0; // Room around a presentation step's shapes when the camera fits them
+const STEP_HIGHLIGHT_MARGIN: f32 = 8.0;
+
+const IDLE_FRAME_INTERVAL: Duration = Duration::from_millis(100); // Redraw rate while nothing changes
+const WHEEL_PAN_STEP: f32 = 60.0; // Screen distance panned per wheel notch when the wheel pans
+const TOUCHPAD_PIXELS_PER_WHEEL_NOTCH: f32 = 50.0; // Ctrl + two-finger scroll zooms one wheel step per this many pixels
+const DEFAULT_UNSYNCED_MAX_FPS: u32 = 120; // Cap when vsync is off and max_fps isn't set, rather than as fast as the GPU goes
+const STRESS_REPORT_INTERVAL: Duration = Duration::from_secs(5); // --stress: how often timings are logged
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(1); // How often config.toml is checked for changes
+
+
+// (from shape, from port, to shape, to port): parallel connections between different ports get their own routes
+type RouteKey = (usize, PortId, usize, PortId);
+
+fn route_key(connection: &Connection) -> RouteKey {
+    (connection.from_shape_index, connection.from_port, connection.to_shape_index, connection.to_port)
+}
+
+// --- Cached obstacle-avoiding route of a smart-routed connection ---
+struct CachedRoute {
+    start: Vec2,
+    end: Vec2,
+    obstacles_signature: u64,
+    points: Option<Vec<Vec2>>, // None when no route exists; the plain curve is drawn instead
+}
+
+// --- Topological order and critical path, recomputed only when the connections change ---
+struct GraphAnalysis {
+    signature: u64, // graph_signature() it was computed for
+    order_badges: Vec<Option<usize>>, // Per shape, its 1-based position in the topological order; None on a cycle
+    critical_shapes: Vec<usize>,
+    critical_connections: Vec<usize>,
+}
+
+// --- --stress timings, summed between reports ---
+struct StressTiming {
+    since: Instant,
+    frames: u32,
+    draw_time: Duration,
+    hit_tests: u32, // One shape and one connector lookup per mouse move
+    hit_test_time: Duration,
+}
+
+impl StressTiming {
+    fn new() -> StressTiming {
+        StressTiming { since: Instant::now(), frames: 0, draw_time: Duration::ZERO, hit_tests: 0, hit_test_time: Duration::ZERO }
+    }
+}
+
+// --- A lane being dragged by its title strip or resized by its right / bottom edge ---
+#[derive(Clone, Copy)]
+enum LaneDrag {
+    Move { lane: usize, last_pos: Vec2 },
+    Resize { lane: usize, right: bool, bottom: bool },
+}
 
+// --- In-progress camera move: zoom to fit, scrolling to a shape, presentation steps ---
+struct CameraAnimation {
+    from: Camera,
+    to: Camera,
+    logical_size: Vec2, // Of the screen when it started; interpolation keeps the view centered on it
+    tween: Tween,
+}
+
+// --- In-progress animated transition to a new layout ---
+struct LayoutAnimation {
+    start_positions: Vec<Vec2>,
+    target_positions: Vec<Vec2>,
+    tween: Tween,
+}
+
+
+// --- Answers to the prompt shown when quitting or closing a tab with unsaved changes ---
+#[derive(Clone, Copy, PartialEq)]
+enum SaveChoice {
+    Save,
+    Discard,
+    Cancel,
+}
+
+// What the save prompt goes on to do once the changes are saved or discarded
+#[derive(Clone, Copy, PartialEq)]
+enum SavePrompt {
+    Quit,
+    CloseTab,
+}
 
-// --- Data structure for individual shapes ---
-#[derive(Clone, Debug)]
-struct ShapeData {
-    center_position: Vec2,
-    text: Option<String>,
+// --- A diagram open in a tab, with its own selection, view and connector caches ---
+// The active tab's state lives in AppState's fields; switching tabs swaps it with the parked one here.
+struct Document {
+    diagram: Diagram,
+    diagram_path: PathBuf,
+    saved_fingerprint: u64,
+    dirty: bool,
+    camera: Camera,
+    active_layer: usize,
+    selected_shape_index: Option<usize>,
+    extra_selected_shapes: Vec<usize>,
+    selected_connector_index: Option<usize>,
+    selected_group: Option<usize>,
+    selected_lane: Option<usize>,
+    navigation_origin: Option<(usize, bool)>,
+    breadcrumb: Vec<PathBuf>,
+    text_fit_signature: Option<u64>,
+    connector_routes: HashMap<RouteKey, CachedRoute>,
+    parallel_offsets: Vec<f32>,
+    connection_bundles: Vec<usize>,
+    connector_crossings: Vec<Vec<(usize, f32)>>,
+    crossing_paths: Vec<Option<Vec<Vec2>>>,
+    connector_meshes: StrokeCache<Mesh>,
+    annotation_meshes: StrokeCache<Mesh>,
+    shape_grid: SpatialHash,
+    connector_grid: SpatialHash,
+    spatial_signature: Option<u64>,
+    shape_mesh_keys: Vec<ShapeMeshKey>, // Kept in the shared mesh cache while the tab is parked
+    history: History,
 }
 
-// --- Data structure for user-defined connections ---
-#[derive(Clone, Debug, PartialEq, Eq)]
-struct UserConnection {
-    from_shape_index: usize,
-    to_shape_index: usize,
+impl Document {
+    fn new(diagram_path: PathBuf) -> Document {
+        let diagram = Diagram::new();
+        Document {
+            saved_fingerprint: diagram_fingerprint(&diagram),
+            diagram,
+            diagram_path,
+            dirty: false,
+            camera: Camera::default(),
+            active_layer: 0,
+            selected_shape_index: None,
+            extra_selected_shapes: Vec::new(),
+            selected_connector_index: None,
+            selected_group: None,
+            selected_lane: None,
+            navigation_origin: None,
+            breadcrumb: Vec::new(),
+            text_fit_signature: None,
+            connector_routes: HashMap::new(),
+            parallel_offsets: Vec::new(),
+            connection_bundles: Vec::new(),
+            connector_crossings: Vec::new(),
+            crossing_paths: Vec::new(),
+            connector_meshes: StrokeCache::default(),
+            annotation_meshes: StrokeCache::default(),
+            shape_grid: SpatialHash::new(SPATIAL_CELL_SIZE),
+            connector_grid: SpatialHash::new(SPATIAL_CELL_SIZE),
+            spatial_signature: None,
+            shape_mesh_keys: Vec::new(),
+            history: History::default(),
+        }
+    }
+
+    fn tab_title(&self) -> String {
+        tab_title(&self.diagram_path, self.dirty)
+    }
 }
 
+// File name shown on a tab, with a "*" while it has unsaved changes
+fn tab_title(path: &Path, dirty: bool) -> String {
+    let name = path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned());
+    if dirty { format!("{}*", name) } else { name }
+}
 
 // --- AppState Struct ---
 struct AppState {
-    live_mouse_pos: Vec2, 
-    clicked_shapes: Vec<ShapeData>,
-    default_shape_color: Color,
+    live_mouse_pos: Vec2, // World coordinates
+    live_mouse_screen_pos: Vec2, // Logical screen coordinates
+    diagram: Diagram, // Shapes and connections being edited
+    theme: Theme, // Every color the canvas is drawn with
+    theme_overrides: Vec<ThemeColors>, // Palette and colors config.toml sets over whichever theme is active
     default_shape_width: f32,
     default_shape_height: f32,
     default_shape_corner_radius: f32,
-    selection_outline_color: Color,
+    default_shape_ports: Vec<Port>,
+    auto_size_shapes: bool, // Shape sizes follow their text instead of the defaults
+    auto_name_shapes: bool, // New shapes start out labelled "Node N"
+    auto_size_max_width: f32,
+    text_fit_signature: Option<u64>, // Texts the shape sizes were last fitted to
+    new_shape_kind: ShapeKind, // Kind given to shapes created by double-click; K cycles it
+    node_templates: Vec<NodeTemplate>,
+    palette_drag: Option<usize>, // Template being dragged from the palette onto the canvas
+    shape_palette: Vec<[u8; 3]>,
+    inspector: Inspector,
+    code_panel: CodePanel, // Diagram-as-code text beside the canvas
+    scripts: Vec<scripting::ScriptCommand>, // Listed in the command palette
+    recorder: Option<Recorder>, // --record: input events being written to a session file
+    replay: Option<Replayer>, // --replay: recorded input being fed back; live input is ignored meanwhile
+    replaying: bool, // Set while a recorded event is being handled
+    replayed_keys: (KeyMods, bool), // Modifiers and Space as recorded with the click being replayed
+    quit_after_replay: bool, // A headless replay has finished; quit without asking about unsaved changes
+    tool: Tool,
+    touch: TouchTracker, // Fingers on a touchscreen, acting as the mouse
+    read_only: bool, // F5 or --readonly: panels hidden, input limited to panning, zooming and selecting
+    // Annotations: pen and highlighter strokes on their own layer above the diagram
+    annotations_visible: bool,
+    pen_stroke: Option<Annotation>, // Being drawn; added to the diagram when the button is released
+    erasing: bool,
+    presentation_step: Option<usize>, // Index into diagram.steps shown in read-only mode, once Left/Right is pressed
+    camera_animation: Option<CameraAnimation>,
+    // [input]: wheel and gesture behavior
+    wheel_action: WheelAction,
+    zoom_sensitivity: f32,
+    invert_zoom: bool,
+    invert_pan: bool,
     selection_outline_width: f32,
     
-    ui_scale: f32, 
+    ui_scale: f32, // Physical pixels per logical one: the window's scale factor times ui_scale_multiplier
+    ui_scale_multiplier: f32, // From ui_scale_factor
+    window_scale_factor: f32, // As last read from the window; 1.0 until there is one
+    png_export_scale: u32,
+    include_notes_in_exports: bool,
+
+    camera: Camera,
+    grid_enabled: bool, // Grid is drawn and positions snap to it
+    grid_spacing: f32,
+    pan_last_screen_pos: Option<Vec2>, // Set while a middle-mouse or space+left drag is panning
+    cursor_icon: CursorIcon, // As last set on the window
 
-    // Colors loaded from config or defaulted
-    connector_line_color: Color,
-    selected_connector_line_color: Color,
-    preview_connector_line_color: Color,
-    default_port_color: Color,
-    selected_connector_port_color: Color,
-    active_new_line_start_port_color: Color,
 
     last_click_time: Option<Instant>,
     last_click_pos: Option<Vec2>, 
     selected_shape_index: Option<usize>,
+    extra_selected_shapes: Vec<usize>, // Selected along with selected_shape_index by Shift+click
     dragged_shape_index: Option<usize>,
     drag_offset: Option<Vec2>, 
+    drag_start_position: Option<Vec2>, // Where the dragged shape was when the drag began; Escape puts it back
     editing_shape_index: Option<usize>,
-    current_input_text: String,
+    text_edit: TextEdit, // Text, caret and selection of the shape being edited
+    enter_inserts_newline: bool, // Otherwise Shift+Enter inserts a newline and Enter commits
+    shape_font_size: f32,
+    status_font_size: f32,
+    editing_glyph_rects: Vec<Rect>, // World-space box of each char of the edited text, refreshed every frame
+    ime_position: Option<Vec2>, // Where the input method's candidate window was last placed, in physical pixels
+    ime_preedit: String, // Text the input method is composing, not yet committed; shown underlined at the caret
+    ime_preedit_cursor: Option<usize>, // Byte index of the caret inside ime_preedit, if the input method shows one
+    selecting_text: bool, // Mouse is held down inside the edited shape, extending the selection
 
-    connections: Vec<UserConnection>, 
     selected_connector_index: Option<usize>, 
+    selected_group: Option<usize>,
+    dragged_group: Option<(usize, Vec2)>, // (group, last mouse world position)
+    selected_lane: Option<usize>,
+    lane_drag: Option<LaneDrag>,
+    navigation_origin: Option<(usize, bool)>, // Shape the last Alt+arrow jump left from, and whether it went along outgoing connections
+    breadcrumb: Vec<PathBuf>, // Diagrams drilled down through to reach this one, root first
+    command_palette: Option<CommandPalette>, // Open Ctrl+Shift+P overlay
+    shape_search: Option<ShapeSearch>, // Open Ctrl+F overlay
+    focus_filter: Option<String>, // Query kept with Ctrl+Enter in the search; shapes not matching it are dimmed
+    dag_mode: bool, // Cycles are refused when connecting, and any already there are flagged
+    cycle_connections: Vec<usize>, // DAG mode: connections on a cycle, drawn in the warning color
+    cycle_signature: Option<u64>, // Connections cycle_connections was computed for
+    show_order_badges: bool,
+    show_critical_path: bool,
+    graph_analysis: Option<GraphAnalysis>, // Present while the badges or the critical path are shown
+    reach_highlight: Option<(usize, Reach)>, // Shape whose connected shapes stay lit while the rest is dimmed
+    focused_shapes: Option<Vec<bool>>, // Per shape, whether it is drawn at full strength; None when nothing is dimmed
+    recent_files: RecentFiles,
+    recent_menu: Option<RecentFilesMenu>, // Open Ctrl+Shift+O overlay
+    context_menu: Option<ContextMenu>, // Open long-press menu
+    simulation: Option<Simulation>, // F6 token walk-through; takes the canvas and keys while on
+    history: History, // Ctrl+Z / Ctrl+Y steps through the diagram's changes; each tab keeps its own
+    collaboration: Option<Collaboration>, // --host or --join: the diagram shared live with other editors
+    active_layer: usize, // Layer new shapes and connections go on
+    layers_panel_visible: bool,
+    stats_panel_visible: bool,
+    graph_stats: Option<(u64, GraphStats)>, // With the graph_signature() it was computed for; only kept while the panel is shown
 
     drawing_new_line: bool,
-    new_line_start_info: Option<(usize, bool)>, 
+    new_line_start_info: Option<(usize, PortId)>, 
     new_line_preview_end_pos: Option<Vec2>,
+    new_line_press_pos: Option<Vec2>, // Where the line was started, while that press is still held
+    dragged_connector_endpoint: Option<(usize, bool)>, // (connection index, true if dragging the source end)
+    bending_connector: Option<(usize, Option<f32>)>, // Connection whose middle handle is dragged, and its bend before the drag
+    rotating_shape: Option<(usize, f32)>, // Shape whose rotation handle is dragged, and its rotation before the drag
+
+    diagram_path: PathBuf,
+    saved_fingerprint: u64, // diagram_fingerprint at the last save or load
+    dirty: bool, // The diagram differs from the last save or load; refreshed on frames with input
+    window_title: String, // Configured title; gets a "*" while dirty
+    save_prompt: Option<SavePrompt>, // Open "save changes?" overlay
+    // Open diagrams. The active one's slot is a stale placeholder; its state is in the fields above.
+    tabs: Vec<Document>,
+    active_tab: usize,
+    clipboard: Option<Diagram>,
+    system_clipboard: clipboard::SystemClipboard, // Plain text shared with other applications
+    layout_animation: Option<LayoutAnimation>,
+    flow_animation: bool, // Dots run along every connector from source to target
+    flow_phase: f32, // How far the dots have travelled, wrapped to FLOW_DOT_SPACING
+    connector_routes: HashMap<RouteKey, CachedRoute>,
+    parallel_offsets: Vec<f32>, // Sideways bow of each connection's curve, by connection index
+    connection_bundles: Vec<usize>, // Per connection, how many it stands for across collapsed groups; 0 if folded into another (Diagram::bundle_sizes)
+    bridges_enabled: bool,
+    // Per connection, where earlier connectors cross it as (earlier connection, distance along this one),
+    // and the polyline that was checked
+    connector_crossings: Vec<Vec<(usize, f32)>>,
+    crossing_paths: Vec<Option<Vec<Vec2>>>,
+    connector_meshes: StrokeCache<Mesh>, // By connection index
+    annotation_meshes: StrokeCache<Mesh>, // By index into diagram.annotations
+    mesh_cache: MeshCache, // Shape and port meshes reused across frames
+    connection_validator: ConnectionValidator, // Consulted before a connection is added or re-attached
+    // Hit-testing grids over shapes (including their ports) and connectors, rebuilt when either changes
+    shape_grid: SpatialHash,
+    connector_grid: SpatialHash,
+    spatial_signature: Option<u64>,
+    // The config layers are polled for changes, which re-apply their [shape] and [colors]
+    config_paths: Vec<PathBuf>,
+    config_modified: Vec<Option<SystemTime>>, // By config_paths index
+    last_config_check: Instant,
+    config_problem: Option<String>, // Why the last reload failed or what it defaulted; shown in the status bar
+    toasts: Toasts, // Notifications taken from logging's queue
+    // Frame pacing: input and animation mark the frame dirty; idle frames are throttled
+    needs_redraw: bool,
+    max_frame_interval: Option<Duration>, // From max_fps
+    render_on_demand: bool, // False: every frame counts as dirty
+    last_frame_at: Instant,
+    frame_stats: FrameStats, // Being collected for the frame being drawn
+    debug_overlay: Option<FrameStats>, // F3: the last finished frame's, while the overlay is shown
+    stress_timing: Option<StressTiming>, // --stress: redraw every frame and log how long drawing and hit-testing take
+    font_fallback: FontFallback, // Which font draws each char of a label; empty until AppState::new loads the fonts
 }
 
 impl AppState {
-    fn new(_ctx: &mut Context, app_config: &AppConfig) -> GameResult<AppState> {
-        let shape_config = &app_config.shape;
-        let colors_config = app_config.colors.clone().unwrap_or_default(); 
-
-        // Shape base color
-        let default_shape_color = Color::from_rgb(
-            shape_config.base_color_rgb[0],
-            shape_config.base_color_rgb[1],
-            shape_config.base_color_rgb[2],
-        );
+    fn new(ctx: &mut Context, app_config: &AppConfig) -> GameResult<AppState> {
+        let mut font_fallback = FontFallback::default();
+        if let Some(font_path) = app_config.text.as_ref().and_then(|text| text.font_path.as_ref()) {
+            match load_font(ctx, DEFAULT_FONT_NAME, font_path).and_then(|bytes| font_fallback.set_primary(bytes)) {
+                Ok(()) => log::info!(target: logging::RENDER, "Using font {}", font_path.display()),
+                Err(e) => log::warn!(target: logging::IO, "{}. Using the built-in font.", e),
+            }
+        }
+        let fallback_paths = app_config.text.as_ref().and_then(|text| text.fallback_fonts.clone()).unwrap_or_default();
+        for (index, path) in fallback_paths.iter().enumerate() {
+            let name = format!("fallback-{}", index);
+            match load_font(ctx, &name, path).and_then(|bytes| font_fallback.push_fallback(name, bytes)) {
+                Ok(()) => log::info!(target: logging::RENDER, "Using fallback font {}", path.display()),
+                Err(e) => log::warn!(target: logging::IO, "{}. Skipping it.", e),
+            }
+        }
+        let mut app_state = AppState::from_config(app_config);
+        app_state.font_fallback = font_fallback;
+        app_state.load_template_files();
+        app_state.set_window_scale_factor(ctx.gfx.window().scale_factor() as f32);
+        Ok(app_state)
+    }
 
-        // Shape selection outline color
-        let selection_outline_color = shape_config.selection_outline_color_rgb
-            .map_or(Color::from_rgb(255, 255, 0), |rgb| Color::from_rgb(rgb[0], rgb[1], rgb[2])); // Default Yellow
+    // Everything but the font needs no graphics context (meshes are built on the first draw), so
+    // the editor can also be set up headless, as the tests do
+    fn from_config(app_config: &AppConfig) -> AppState {
+        let shape_config = &app_config.shape;
+        let (appearance, warnings) = Appearance::from_config(app_config);
+        for warning in warnings { log::warn!(target: logging::IO, "{}", warning); }
+        let Appearance { theme, theme_overrides, default_shape_ports, auto_size_max_width, selection_outline_width, shape_palette, .. } = appearance;
 
-        let selection_outline_width = shape_config.selection_outline_width.unwrap_or(2.0);
+        let node_templates: Vec<NodeTemplate> = app_config.node_templates.iter().flatten().cloned()
+            .map(|mut template| {
+                if template.size.is_some_and(|[w, h]| w < MIN_SHAPE_SIZE || h < MIN_SHAPE_SIZE) {
+                    log::warn!(target: logging::IO, "Invalid size for node template '{}' in config.toml. Must be at least {}. Defaulting to the shape size.", template.name, MIN_SHAPE_SIZE);
+                    template.size = None;
+                }
+                template
+            })
+            .collect();
 
-        let ui_scale = match app_config.window.ui_scale_factor {
+        let ui_scale_multiplier = match app_config.window.ui_scale_factor {
             Some(factor) if factor > 0.0 => factor,
             Some(_) => {
-                println!("Warning: Invalid ui_scale_factor in config.toml. Must be > 0. Defaulting to 1.0.");
+                log::warn!(target: logging::IO, "Invalid ui_scale_factor in config.toml. Must be > 0. Defaulting to 1.0.");
                 1.0
             }
             None => 1.0,
         };
-        println!("Using UI Scale Factor: {}", ui_scale);
+        log::info!(target: logging::RENDER, "Using UI Scale Factor: {} (times the monitor's)", ui_scale_multiplier);
 
-        // Load other colors or use defaults
-        let connector_line_color = colors_config.connector_line_rgb
-            .map_or(Color::WHITE, |rgb| Color::from_rgb(rgb[0], rgb[1], rgb[2]));
-        let selected_connector_line_color = colors_config.selected_connector_line_rgb
-            .map_or(Color::CYAN, |rgb| Color::from_rgb(rgb[0], rgb[1], rgb[2]));
-        
-        // Preview line color: use RGB from config, hardcode alpha
-        let preview_connector_line_color_rgb = colors_config.preview_connector_line_rgb
-            .unwrap_or([204, 204, 204]); // Default light gray RGB
-        let preview_connector_line_color = Color::from_rgba(
-            preview_connector_line_color_rgb[0],
-            preview_connector_line_color_rgb[1],
-            preview_connector_line_color_rgb[2],
-            178, // Alpha for ~0.7 opacity (0-255 range)
-        );
+        let vsync = app_config.window.vsync.unwrap_or(true);
+        let unset_max_fps = if vsync { None } else { Some(DEFAULT_UNSYNCED_MAX_FPS) };
+        let max_fps = match app_config.window.max_fps {
+            Some(fps) if fps > 0 => Some(fps),
+            Some(_) => {
+                log::warn!(target: logging::IO, "Invalid max_fps in config.toml. Must be > 0. Defaulting to {}.",
+                    unset_max_fps.map_or(String::from("uncapped"), |fps| fps.to_string()));
+                unset_max_fps
+            }
+            None => unset_max_fps,
+        };
+        let max_frame_interval = max_fps.map(|fps| Duration::from_secs_f64(1.0 / fps as f64));
+        let render_on_demand = app_config.window.render_on_demand.unwrap_or(true);
+        log::info!(target: logging::RENDER, "Frame pacing: vsync {}, max_fps {}, render_on_demand {}",
+            if vsync { "on" } else { "off" }, max_fps.map_or(String::from("uncapped"), |fps| fps.to_string()), render_on_demand);
+
+        let connections_config = app_config.connections.clone().unwrap_or_default();
+        let mut connection_validator = ConnectionValidator::new();
+        connection_validator.add_rule(validation::PortTypesMatch);
+        connection_validator.add_rule(validation::PortDirectionsMatch);
+        if !connections_config.allow_self_loops.unwrap_or(false) { connection_validator.add_rule(validation::NoSelfLoops); }
+        if !connections_config.allow_reverse_duplicates.unwrap_or(true) { connection_validator.add_rule(validation::NoReverseDuplicates); }
+        let dag_mode = connections_config.dag_mode.unwrap_or(false);
+        if dag_mode { connection_validator.add_rule(validation::Acyclic); }
+
+        let text_config = app_config.text.clone().unwrap_or_default();
+        let font_size = |configured: Option<f32>, name: &str, default: f32| match configured {
+            Some(size) if size > 0.0 => size,
+            Some(_) => {
+                log::warn!(target: logging::IO, "Invalid {} in config.toml. Must be > 0. Defaulting to {}.", name, default);
+                default
+            }
+            None => default,
+        };
+        let shape_font_size = font_size(text_config.shape_font_size, "shape_font_size", DEFAULT_SHAPE_FONT_SIZE);
+        let status_font_size = font_size(text_config.status_font_size, "status_font_size", status_bar::DEFAULT_STATUS_FONT_SIZE);
+
+        let grid_config = app_config.grid.clone().unwrap_or_default();
+        let grid_enabled = grid_config.enabled.unwrap_or(false);
+        let grid_spacing = match grid_config.spacing {
+            Some(spacing) if spacing > 0.0 => spacing,
+            Some(_) => {
+                log::warn!(target: logging::IO, "Invalid grid spacing in config.toml. Must be > 0. Defaulting to 20.");
+                20.0
+            }
+            None => 20.0,
+        };
 
-        let default_port_color = colors_config.default_port_rgb
-            .map_or(Color::WHITE, |rgb| Color::from_rgb(rgb[0], rgb[1], rgb[2]));
-        let selected_connector_port_color = colors_config.selected_connector_port_rgb
-            .map_or(Color::CYAN, |rgb| Color::from_rgb(rgb[0], rgb[1], rgb[2]));
-        let active_new_line_start_port_color = colors_config.active_new_line_start_port_rgb
-            .map_or(Color::from_rgb(50, 205, 50), |rgb| Color::from_rgb(rgb[0], rgb[1], rgb[2]));
+        let png_export_scale = match app_config.export.as_ref().and_then(|e| e.png_scale) {
+            Some(scale @ (1 | 2 | 4)) => scale,
+            Some(other) => {
+                log::warn!(target: logging::IO, "Invalid png_scale '{}' in config.toml. Valid options are 1, 2 or 4. Defaulting to 1.", other);
+                1
+            }
+            None => 1,
+        };
 
+        let input_config = app_config.input.clone().unwrap_or_default();
+        let zoom_sensitivity = match input_config.zoom_sensitivity {
+            Some(sensitivity) if sensitivity > 0.0 => sensitivity,
+            Some(_) => {
+                log::warn!(target: logging::IO, "Invalid zoom_sensitivity in config.toml. Must be > 0. Defaulting to 1.0.");
+                1.0
+            }
+            None => 1.0,
+        };
 
-        Ok(AppState {
+        AppState {
             live_mouse_pos: Vec2::new(0.0, 0.0),
-            clicked_shapes: Vec::new(),
-            default_shape_color, // Use loaded/defaulted shape color
+            live_mouse_screen_pos: Vec2::new(0.0, 0.0),
+            diagram: Diagram::new(),
+            theme,
+            theme_overrides,
             default_shape_width: shape_config.width,
             default_shape_height: shape_config.height,
             default_shape_corner_radius: shape_config.corner_radius,
-            selection_outline_color,
+            default_shape_ports,
+            auto_size_shapes: shape_config.auto_size.unwrap_or(false),
+            auto_name_shapes: shape_config.auto_name.unwrap_or(false),
+            auto_size_max_width,
+            node_templates,
+            palette_drag: None,
+            text_fit_signature: None,
+            new_shape_kind: ShapeKind::default(),
+            shape_palette,
+            inspector: Inspector::default(),
+            code_panel: CodePanel::default(),
+            scripts: Vec::new(),
+            recorder: None,
+            replay: None,
+            replaying: false,
+            replayed_keys: (KeyMods::empty(), false),
+            quit_after_replay: false,
+            tool: Tool::default(),
+            touch: TouchTracker::default(),
+            read_only: false,
+            annotations_visible: true,
+            pen_stroke: None,
+            erasing: false,
+            presentation_step: None,
+            camera_animation: None,
+            wheel_action: input_config.wheel.unwrap_or_default(),
+            zoom_sensitivity,
+            invert_zoom: input_config.invert_zoom.unwrap_or(false),
+            invert_pan: input_config.invert_pan.unwrap_or(false),
             selection_outline_width,
-            ui_scale, 
-            connector_line_color,
-            selected_connector_line_color,
-            preview_connector_line_color,
-            default_port_color,
-            selected_connector_port_color,
-            active_new_line_start_port_color,
+            ui_scale: ui_scale_multiplier,
+            ui_scale_multiplier,
+            window_scale_factor: 1.0,
+            png_export_scale,
+            include_notes_in_exports: app_config.export.as_ref().and_then(|e| e.include_notes).unwrap_or(false),
+            camera: Camera::default(),
+            grid_enabled,
+            grid_spacing,
+            pan_last_screen_pos: None,
+            cursor_icon: CursorIcon::Default,
             last_click_time: None,
             last_click_pos: None,
             selected_shape_index: None,
+            extra_selected_shapes: Vec::new(),
             dragged_shape_index: None,
             drag_offset: None,
+            drag_start_position: None,
             editing_shape_index: None,
-            current_input_text: String::new(),
-            connections: Vec::new(), 
+            text_edit: TextEdit::default(),
+            enter_inserts_newline: text_config.enter_inserts_newline.unwrap_or(false),
+            shape_font_size,
+            status_font_size,
+            editing_glyph_rects: Vec::new(),
+            ime_position: None,
+            ime_preedit: String::new(),
+            ime_preedit_cursor: None,
+            selecting_text: false,
             selected_connector_index: None, 
+            selected_group: None,
+            dragged_group: None,
+            selected_lane: None,
+            navigation_origin: None,
+            breadcrumb: Vec::new(),
+            command_palette: None,
+            shape_search: None,
+            focus_filter: None,
+            dag_mode,
+            cycle_connections: Vec::new(),
+            cycle_signature: None,
+            show_order_badges: false,
+            show_critical_path: false,
+            graph_analysis: None,
+            reach_highlight: None,
+            focused_shapes: None,
+            recent_files: RecentFiles::load(),
+            recent_menu: None,
+            context_menu: None,
+            simulation: None,
+            history: History::default(),
+            collaboration: None,
+            active_layer: 0,
+            layers_panel_visible: false,
+            stats_panel_visible: false,
+            graph_stats: None,
+            lane_drag: None,
             drawing_new_line: false,
             new_line_start_info: None,
             new_line_preview_end_pos: None,
-        })
+            new_line_press_pos: None,
+            dragged_connector_endpoint: None,
+            bending_connector: None,
+            rotating_shape: None,
+            diagram_path: PathBuf::from(persistence::DEFAULT_DIAGRAM_PATH),
+            saved_fingerprint: diagram_fingerprint(&Diagram::new()),
+            dirty: false,
+            window_title: app_config.window.title.clone(),
+            save_prompt: None,
+            tabs: vec![Document::new(PathBuf::from(persistence::DEFAULT_DIAGRAM_PATH))],
+            active_tab: 0,
+            clipboard: None,
+            system_clipboard: clipboard::SystemClipboard::default(),
+            layout_animation: None,
+            flow_animation: connections_config.flow_animation.unwrap_or(false),
+            flow_phase: 0.0,
+            connector_routes: HashMap::new(),
+            parallel_offsets: Vec::new(),
+            connection_bundles: Vec::new(),
+            bridges_enabled: connections_config.bridges.unwrap_or(false),
+            connector_crossings: Vec::new(),
+            crossing_paths: Vec::new(),
+            connector_meshes: StrokeCache::default(),
+            annotation_meshes: StrokeCache::default(),
+            mesh_cache: MeshCache::default(),
+            connection_validator,
+            config_paths: Vec::new(),
+            config_modified: Vec::new(),
+            last_config_check: Instant::now(),
+            config_problem: None,
+            toasts: Toasts::default(),
+            needs_redraw: true,
+            max_frame_interval,
+            render_on_demand,
+            last_frame_at: Instant::now(),
+            frame_stats: FrameStats::default(),
+            debug_overlay: None,
+            stress_timing: None,
+            font_fallback: FontFallback::default(),
+            shape_grid: SpatialHash::new(SPATIAL_CELL_SIZE),
+            connector_grid: SpatialHash::new(SPATIAL_CELL_SIZE),
+            spatial_signature: None,
+        }
     }
 
-    // Helper to get port coordinates
-    fn get_port_point(&self, shape_index: usize, is_outgoing_port: bool) -> Option<Vec2> {
-        if shape_index < self.clicked_shapes.len() {
-            let shape_data = &self.clicked_shapes[shape_index];
-            let x_base = shape_data.center_position.x - self.default_shape_width / 2.0;
-            let y_base = shape_data.center_position.y;
-            if is_outgoing_port { // Bottom-left port
-                Some(Vec2::new(x_base + CONNECTOR_POINT_HORIZONTAL_OFFSET, y_base + self.default_shape_height / 2.0))
-            } else { // Top-left port
-                Some(Vec2::new(x_base + CONNECTOR_POINT_HORIZONTAL_OFFSET, y_base - self.default_shape_height / 2.0))
+    fn save_diagram(&mut self) {
+        match persistence::save_diagram(&self.diagram_path, &self.diagram) {
+            Ok(()) => {
+                self.saved_fingerprint = diagram_fingerprint(&self.diagram);
+                self.dirty = false;
+                self.remember_recent_file(&self.diagram_path.clone());
+                notify_io(format!("Diagram saved to {}", self.diagram_path.display()));
             }
-        } else {
-            None
+            Err(e) => log::error!(target: logging::IO, "{}", e),
         }
     }
-}
 
-// Helper function to get a point on a cubic Bezier curve
-fn get_point_on_cubic_bezier(p0: LyonPoint, p1: LyonPoint, p2: LyonPoint, p3: LyonPoint, t: f32) -> LyonPoint {
-    let t_inv = 1.0 - t;
-    let t_inv_sq = t_inv * t_inv;
-    let t_inv_cub = t_inv_sq * t_inv;
-    let t_sq = t * t;
-    let t_cub = t_sq * t;
-    let x = t_inv_cub * p0.x + 3.0 * t_inv_sq * t * p1.x + 3.0 * t_inv * t_sq * p2.x + t_cub * p3.x;
-    let y = t_inv_cub * p0.y + 3.0 * t_inv_sq * t * p1.y + 3.0 * t_inv * t_sq * p2.y + t_cub * p3.y;
-    LyonPoint::new(x, y)
-}
+    // --- Config hot reload ---
+    // Watches every layer, including ones that don't exist yet
+    fn watch_config(&mut self, config_paths: Vec<PathBuf>) {
+        self.config_modified = config_paths.iter().map(|path| file_modified_time(path)).collect();
+        self.config_paths = config_paths;
+    }
 
+    // Checked from update; reloads once a layer's modification time moves or it appears or goes away
+    fn poll_config(&mut self) {
+        if self.last_config_check.elapsed() < CONFIG_POLL_INTERVAL { return; }
+        self.last_config_check = Instant::now();
+        let modified: Vec<Option<SystemTime>> = self.config_paths.iter().map(|path| file_modified_time(path)).collect();
+        if modified == self.config_modified { return; }
+        self.config_modified = modified;
+        self.reload_config();
+        self.needs_redraw = true;
+    }
 
-// --- EventHandler Implementation ---
-impl EventHandler<ggez::GameError> for AppState {
-    fn update(&mut self, _ctx: &mut Context) -> GameResult {
-        if self.drawing_new_line {
-            self.new_line_preview_end_pos = Some(self.live_mouse_pos);
+    // Re-applies [shape] and [colors]; a layer that doesn't parse leaves the current settings alone
+    fn reload_config(&mut self) {
+        match config::load_layers(&default_app_config(), &self.config_paths) {
+            Ok((config, read)) => {
+                let (appearance, warnings) = Appearance::from_config(&config);
+                self.apply_appearance(appearance);
+                self.config_problem = (!warnings.is_empty()).then(|| warnings.join(" "));
+                log::info!(target: logging::IO, "Reloaded [shape], [colors] and [theme] from {}", display_paths(&read));
+            }
+            Err(e) => {
+                log::warn!(target: logging::IO, "Config not reloaded: {}", e);
+                self.config_problem = Some(format!("Config not reloaded: {}", e));
+            }
         }
-        Ok(())
     }
 
-    fn draw(&mut self, ctx: &mut Context) -> GameResult {
-        let mut canvas = graphics::Canvas::from_frame(ctx, graphics::Color::from_rgb(30, 30, 40));
+    fn apply_appearance(&mut self, appearance: Appearance) {
+        self.theme = appearance.theme;
+        self.theme_overrides = appearance.theme_overrides;
+        self.default_shape_width = appearance.default_shape_width;
+        self.default_shape_height = appearance.default_shape_height;
+        self.default_shape_corner_radius = appearance.default_shape_corner_radius;
+        self.default_shape_ports = appearance.default_shape_ports;
+        self.auto_size_shapes = appearance.auto_size_shapes;
+        self.auto_name_shapes = appearance.auto_name_shapes;
+        self.auto_size_max_width = appearance.auto_size_max_width;
+        self.selection_outline_width = appearance.selection_outline_width;
+        self.shape_palette = appearance.shape_palette;
+        // Default sizes feed text fitting, routing and hit-testing, in every tab
+        self.text_fit_signature = None;
+        self.spatial_signature = None;
+        self.connector_routes.clear();
+        for tab in &mut self.tabs {
+            tab.text_fit_signature = None;
+            tab.spatial_signature = None;
+            tab.connector_routes.clear();
+        }
+    }
 
-        let (physical_width, physical_height) = ctx.gfx.drawable_size();
-        let logical_width = physical_width / self.ui_scale;
-        let logical_height = physical_height / self.ui_scale;
-        canvas.set_screen_coordinates(Rect::new(0.0, 0.0, logical_width, logical_height));
+    // --- Status bar ---
+    // The status bar stops short of the inspector docked on the right
+    fn status_area(&self, screen_size: Vec2) -> Rect {
+        Rect::new(0.0, 0.0, self.canvas_right_edge(screen_size), screen_size.y)
+    }
 
-        // --- Draw Existing Connector Lines ---
-        for (conn_idx, connection) in self.connections.iter().enumerate() {
-            if let (Some(start_point_ggez), Some(end_point_ggez)) = (
-                self.get_port_point(connection.from_shape_index, true), 
-                self.get_port_point(connection.to_shape_index, false)    
-            ) {
-                let start_point_lyon = LyonPoint::new(start_point_ggez.x, start_point_ggez.y);
-                let end_point_lyon = LyonPoint::new(end_point_ggez.x, end_point_ggez.y);
-
-                let direction_multiplier = if end_point_lyon.x > start_point_lyon.x { 1.0 } else { -1.0 };
-                let cp1 = LyonPoint::new(start_point_lyon.x + CONNECTOR_CURVE_OFFSET * direction_multiplier, start_point_lyon.y);
-                let cp2 = LyonPoint::new(end_point_lyon.x - CONNECTOR_CURVE_OFFSET * direction_multiplier, end_point_lyon.y);
-
-                let mut path_builder = LyonPathBuilder::new();
-                path_builder.begin(start_point_lyon);
-                path_builder.cubic_bezier_to(cp1, cp2, end_point_lyon);
-                path_builder.end(false); 
-                let lyon_path = path_builder.build();
-
-                let current_line_color = if self.selected_connector_index == Some(conn_idx) {
-                    self.selected_connector_line_color
-                } else {
-                    self.connector_line_color
-                };
-                
-                let mut geometry: VertexBuffers<Vertex, u32> = VertexBuffers::new();
-                let mut stroke_tess = StrokeTessellator::new();
-                let stroke_options = StrokeOptions::default().with_line_width(CONNECTOR_LINE_WIDTH);
-                let line_color_arr = [
-                    current_line_color.r, current_line_color.g, current_line_color.b, current_line_color.a,
-                ];
+    // Toasts stack up from just above the status bar
+    fn toast_area(&self, screen_size: Vec2) -> Rect {
+        let status_area = self.status_area(screen_size);
+        Rect::new(status_area.x, status_area.y, status_area.w, status_area.h - status_bar::bar_height(self.status_font_size))
+    }
 
-                stroke_tess.tessellate_path( &lyon_path, &stroke_options,
-                    &mut BuffersBuilder::new(&mut geometry, |vertex: StrokeVertex| {
-                        Vertex { position: [vertex.position().x, vertex.position().y], uv: [0.0, 0.0], color: line_color_arr, }
-                    }),
-                ).unwrap_or_else(|e| {println!("Lyon tessellation error: {:?}", e);});
+    fn status_info(&self) -> StatusInfo {
+        StatusInfo {
+            alert: self.config_problem.clone().or_else(|| self.cycle_alert()),
+            hint: self.status_hint(),
+            selection: self.selection_description(),
+            zoom: self.camera.zoom,
+            shape_count: self.diagram.shapes.len(),
+            connection_count: self.diagram.connections.len(),
+            unsaved: self.dirty,
+            font_size: self.status_font_size,
+            breadcrumb: self.breadcrumb_names(),
+        }
+    }
 
-                if !geometry.vertices.is_empty() && !geometry.indices.is_empty() {
-                    let mesh_data = MeshData { vertices: &geometry.vertices, indices: &geometry.indices };
-                    let line_mesh = Mesh::from_data(ctx, mesh_data); 
-                    canvas.draw(&line_mesh, graphics::DrawParam::default());
-                }
+    // What the keys and mouse do in the current state, most specific first
+    fn status_hint(&self) -> String {
+        let hint = if self.save_prompt.is_some() {
+            "Enter to save, D to discard changes, Esc to keep editing"
+        } else if let Some(simulation) = &self.simulation {
+            if simulation.branch.is_some() {
+                "Up/Down or 1-9 to pick a branch, Enter or Space to take it, Esc to stop simulating"
+            } else if simulation.token.is_none() {
+                "Click the shape to start from, Esc to stop simulating"
+            } else {
+                "Space to step, click a shape to restart there, Esc to stop simulating"
             }
+        } else if self.drawing_new_line {
+            "Click a port to connect, Esc to cancel line"
+        } else if self.dragged_connector_endpoint.is_some() {
+            "Drop on a port to reattach, Esc to cancel"
+        } else if self.bending_connector.is_some() {
+            "Drag to bend, Esc to cancel"
+        } else if self.rotating_shape.is_some() {
+            "Drag to rotate (snaps to 15° with the grid on), Esc to cancel"
+        } else if self.dragged_shape_index.is_some() {
+            "Esc to cancel move"
+        } else if self.editing_shape_index.is_some() {
+            "Typing into shape, Esc or click outside to finish"
+        } else if self.code_panel.focused {
+            "Typing into the code panel: `A -> B: label`, `A @ x, y`; Esc or click the canvas to finish"
+        } else if self.command_palette.is_some() || self.shape_search.is_some() || self.recent_menu.is_some() || self.context_menu.is_some() {
+            "Up/Down to choose, Enter to run, Esc to close"
+        } else if self.selected_connector_index.is_some() {
+            "R to reverse, Ctrl+R to route, drag the middle handle to bend, Del to delete"
+        } else if self.selected_shape_index.is_some() {
+            "F2 to rename, arrows to nudge, Alt+wheel to rotate, Tab for next shape, Del to delete"
+        } else if let Some((_, reach)) = self.reach_highlight {
+            return format!("Highlighting {}, U for the next direction", reach.name());
+        } else if let Some(query) = &self.focus_filter {
+            return format!("Focused on '{}', Ctrl+Shift+F to show everything", query);
+        } else if self.tool == Tool::Create {
+            return format!("Click empty space to add a {}", self.new_shape_kind.name().to_lowercase());
+        } else {
+            self.tool.hint()
+        };
+        hint.to_string()
+    }
+
+    // First line of a shape's text, or "Shape 3" when it has none
+    fn shape_name(&self, index: usize) -> String {
+        self.diagram.shapes.get(index).and_then(|s| s.text.as_deref()).filter(|t| !t.trim().is_empty())
+            .map_or(format!("Shape {}", index), |text| text.lines().next().unwrap_or_default().to_string())
+    }
+
+    // "Shape 3: Start (ellipse)", "Connection: Start → End", ...; empty with nothing selected
+    fn selection_description(&self) -> String {
+        if !self.extra_selected_shapes.is_empty() {
+            return format!("{} shapes selected", self.selected_shape_indices().len());
         }
-        
-        // --- Draw Preview Connector Line ---
-        if self.drawing_new_line {
-            if let (Some((start_shape_idx, start_is_outgoing)), Some(preview_end_pos)) = (self.new_line_start_info, self.new_line_preview_end_pos) {
-                if let Some(start_pos) = self.get_port_point(start_shape_idx, start_is_outgoing) {
-                     let line_preview_mesh = Mesh::new_line(ctx, &[start_pos, preview_end_pos], CONNECTOR_LINE_WIDTH / 2.0, self.preview_connector_line_color)?;
-                     canvas.draw(&line_preview_mesh, graphics::DrawParam::default());
-                }
-            }
+        if let Some(shape) = self.selected_shape_index.and_then(|i| self.diagram.shapes.get(i).map(|s| (i, s))) {
+            return format!("Shape {}: {} ({})", shape.0, self.shape_name(shape.0), shape.1.kind.name());
         }
+        if let Some(connection) = self.selected_connector_index.and_then(|i| self.diagram.connections.get(i)) {
+            return format!("Connection: {} → {}", self.shape_name(connection.from_shape_index), self.shape_name(connection.to_shape_index));
+        }
+        if let Some(group) = self.selected_group.and_then(|g| self.diagram.groups.get(g)) {
+            return format!("Group: {}", group.name);
+        }
+        if let Some(lane) = self.selected_lane.and_then(|l| self.diagram.lanes.get(l)) {
+            return format!("Lane: {}", lane.title);
+        }
+        String::new()
+    }
 
+    // --- Unsaved changes ---
+    // Compares the diagram with the last save or load and puts a "*" in the window title while they differ
+    // Returns the diagram's fingerprint
+    fn refresh_dirty_flag(&mut self, ctx: &mut Context) -> u64 {
+        let fingerprint = diagram_fingerprint(&self.diagram);
+        let dirty = self.saved_fingerprint != fingerprint;
+        if dirty != self.dirty {
+            self.dirty = dirty;
+            self.update_window_title(ctx);
+        }
+        fingerprint
+    }
 
-        // --- Draw Shapes, Outlines, Text, and Ports on Shapes ---
-        for (index, shape_data) in self.clicked_shapes.iter().enumerate() {
-            let rect = Rect::new(
-                shape_data.center_position.x - self.default_shape_width / 2.0,
-                shape_data.center_position.y - self.default_shape_height / 2.0,
-                self.default_shape_width,
-                self.default_shape_height,
-            );
-            let rounded_rect_mesh = Mesh::new_rounded_rectangle(ctx, DrawMode::fill(), rect, self.default_shape_corner_radius, self.default_shape_color)?;
-            canvas.draw(&rounded_rect_mesh, graphics::DrawParam::default());
+    fn update_window_title(&self, ctx: &mut Context) {
+        let title = if self.dirty { format!("{}*", self.window_title) } else { self.window_title.clone() };
+        ctx.gfx.set_window_title(&title);
+    }
 
-            // Determine port colors and radii
-            let mut outgoing_port_color = self.default_port_color;
-            let mut incoming_port_color = self.default_port_color;
-            let mut outgoing_port_radius = PORT_DRAW_RADIUS_DEFAULT;
-            let mut incoming_port_radius = PORT_DRAW_RADIUS_DEFAULT;
+    // Centered "save changes?" panel with Save / Discard / Cancel
+    fn save_prompt_widgets(&self, screen_size: Vec2) -> (Rect, Vec<ui::Widget<SaveChoice>>) {
+        let width = SAVE_PROMPT_WIDTH.min(screen_size.x);
+        let height = ui::panel_height(2);
+        let panel = Rect::new((screen_size.x - width) / 2.0, (screen_size.y - height) / 2.0, width, height);
+        let mut layout = ui::PanelLayout::new(panel);
+        layout.label(format!("Save changes to {}?", self.diagram_path.display()));
+        layout.button_row([
+            (SaveChoice::Save, String::from("Save"), true, 1.0),
+            (SaveChoice::Discard, String::from("Discard"), false, 1.0),
+            (SaveChoice::Cancel, String::from("Cancel"), false, 1.0),
+        ]);
+        (panel, layout.widgets)
+    }
 
-            if let Some(conn_idx) = self.selected_connector_index {
-                if conn_idx < self.connections.len() {
-                    let selected_conn = &self.connections[conn_idx];
-                    if selected_conn.from_shape_index == index { outgoing_port_color = self.selected_connector_port_color; }
-                    if selected_conn.to_shape_index == index { incoming_port_color = self.selected_connector_port_color; }
-                }
+    fn answer_save_prompt(&mut self, ctx: &mut Context, choice: SaveChoice) {
+        let Some(prompt) = self.save_prompt.take() else { return; };
+        match choice {
+            SaveChoice::Save => {
+                self.save_diagram();
+                // A failed save keeps the changes and the editor open
+                if self.dirty { return; }
             }
-            if let Some((start_idx, is_out)) = self.new_line_start_info {
-                if start_idx == index {
-                    if is_out { outgoing_port_color = self.active_new_line_start_port_color; }
-                    else { incoming_port_color = self.active_new_line_start_port_color; }
-                }
+            SaveChoice::Discard => {
+                log::info!(target: logging::IO, "Discarding unsaved changes to {}.", self.diagram_path.display());
+                self.saved_fingerprint = diagram_fingerprint(&self.diagram);
+                self.dirty = false;
             }
+            SaveChoice::Cancel => return,
+        }
+        match prompt {
+            // Asks again about the next tab with changes, if any
+            SavePrompt::Quit => ctx.request_quit(),
+            SavePrompt::CloseTab => self.close_active_tab(ctx),
+        }
+    }
 
-            // Check for hover on outgoing port
-            if let Some(outgoing_point_ggez) = self.get_port_point(index, true) {
-                if self.live_mouse_pos.distance(outgoing_point_ggez) <= PORT_HOVER_DETECT_DISTANCE {
-                    outgoing_port_radius = PORT_DRAW_RADIUS_HOVER;
-                }
-                let outgoing_port_mesh = Mesh::new_circle(ctx, DrawMode::fill(), outgoing_point_ggez, outgoing_port_radius, 0.1, outgoing_port_color)?;
-                canvas.draw(&outgoing_port_mesh, graphics::DrawParam::default());
-            }
+    // Puts up the save prompt for the first tab with unsaved changes; returns false when there is none
+    fn ask_before_quit(&mut self, ctx: &mut Context) -> bool {
+        self.refresh_dirty_flag(ctx);
+        if !self.dirty {
+            let Some(index) = self.tabs.iter().enumerate().position(|(index, tab)| index != self.active_tab && tab.dirty) else {
+                return false;
+            };
+            self.switch_tab(ctx, index);
+        }
+        self.save_prompt = Some(SavePrompt::Quit);
+        true
+    }
 
-            // Check for hover on incoming port
-            if let Some(incoming_point_ggez) = self.get_port_point(index, false) {
-                 if self.live_mouse_pos.distance(incoming_point_ggez) <= PORT_HOVER_DETECT_DISTANCE {
-                    incoming_port_radius = PORT_DRAW_RADIUS_HOVER;
-                }
-                let incoming_port_mesh = Mesh::new_circle(ctx, DrawMode::fill(), incoming_point_ggez, incoming_port_radius, 0.1, incoming_port_color)?;
-                canvas.draw(&incoming_port_mesh, graphics::DrawParam::default());
-            }
+    // --- Tabs ---
+    fn tab_titles(&self) -> Vec<String> {
+        let mut titles: Vec<String> = self.tabs.iter().map(Document::tab_title).collect();
+        titles[self.active_tab] = tab_title(&self.diagram_path, self.dirty);
+        titles
+    }
 
+    fn tab_widgets(&self) -> (Rect, Vec<ui::Widget<TabAction>>) {
+        let left = self.palette_widgets().map_or_else(|| tools::toolbar_widgets(self.tool).0.right(), |(strip, _)| strip.right());
+        tabs::tab_widgets(left, &self.tab_titles(), self.active_tab)
+    }
 
-            if self.selected_shape_index == Some(index) && self.editing_shape_index != Some(index) {
-                let center_x = rect.x + rect.w / 2.0;
-                let center_y = rect.y + rect.h / 2.0;
-                let outline_w = rect.w * 1.05;
-                let outline_h = rect.h * 1.05;
-                let outline_bounds = Rect::new(center_x - outline_w / 2.0, center_y - outline_h / 2.0, outline_w, outline_h);
-                let outline_rect_mesh = Mesh::new_rounded_rectangle(ctx, DrawMode::stroke(self.selection_outline_width), outline_bounds, self.default_shape_corner_radius * 1.05, self.selection_outline_color)?;
-                canvas.draw(&outline_rect_mesh, graphics::DrawParam::default());
-            }
+    // Exchanges the active document's state with the one parked in tab `index`
+    fn swap_document(&mut self, index: usize) {
+        let document = &mut self.tabs[index];
+        std::mem::swap(&mut self.diagram, &mut document.diagram);
+        std::mem::swap(&mut self.diagram_path, &mut document.diagram_path);
+        std::mem::swap(&mut self.saved_fingerprint, &mut document.saved_fingerprint);
+        std::mem::swap(&mut self.dirty, &mut document.dirty);
+        std::mem::swap(&mut self.camera, &mut document.camera);
+        std::mem::swap(&mut self.active_layer, &mut document.active_layer);
+        std::mem::swap(&mut self.selected_shape_index, &mut document.selected_shape_index);
+        std::mem::swap(&mut self.extra_selected_shapes, &mut document.extra_selected_shapes);
+        std::mem::swap(&mut self.selected_connector_index, &mut document.selected_connector_index);
+        std::mem::swap(&mut self.selected_group, &mut document.selected_group);
+        std::mem::swap(&mut self.selected_lane, &mut document.selected_lane);
+        std::mem::swap(&mut self.navigation_origin, &mut document.navigation_origin);
+        std::mem::swap(&mut self.breadcrumb, &mut document.breadcrumb);
+        std::mem::swap(&mut self.text_fit_signature, &mut document.text_fit_signature);
+        std::mem::swap(&mut self.connector_routes, &mut document.connector_routes);
+        std::mem::swap(&mut self.parallel_offsets, &mut document.parallel_offsets);
+        std::mem::swap(&mut self.connection_bundles, &mut document.connection_bundles);
+        std::mem::swap(&mut self.connector_crossings, &mut document.connector_crossings);
+        std::mem::swap(&mut self.crossing_paths, &mut document.crossing_paths);
+        std::mem::swap(&mut self.connector_meshes, &mut document.connector_meshes);
+        std::mem::swap(&mut self.annotation_meshes, &mut document.annotation_meshes);
+        std::mem::swap(&mut self.shape_grid, &mut document.shape_grid);
+        std::mem::swap(&mut self.connector_grid, &mut document.connector_grid);
+        std::mem::swap(&mut self.spatial_signature, &mut document.spatial_signature);
+        std::mem::swap(&mut self.history, &mut document.history);
+    }
 
-            let text_to_display = if self.editing_shape_index == Some(index) {
-                format!("{}|", self.current_input_text)
-            } else {
-                shape_data.text.clone().unwrap_or_default()
-            };
+    // Parks the active document and brings in tab `index`. Its caches come back with it, so nothing
+    // needs rebuilding.
+    fn switch_tab(&mut self, ctx: &mut Context, index: usize) {
+        if index == self.active_tab || index >= self.tabs.len() { return; }
+        self.refresh_dirty_flag(ctx);
+        self.bring_in_tab(index);
+        self.update_window_title(ctx);
+    }
 
-            if !text_to_display.is_empty() {
-                let wrap_width = self.default_shape_width - (TEXT_PADDING * 2.0);
-                let mut text_obj = Text::new(text_to_display);
-                text_obj.set_layout(TextLayout::center());
-                text_obj.set_scale(18.0);
-                text_obj.set_bounds(Vec2::new(wrap_width, f32::INFINITY));
-                let text_dest = shape_data.center_position;
-                canvas.draw(&text_obj, graphics::DrawParam::default().dest(text_dest).color(Color::BLACK));
-            }
-        }
-
-        let status_text = format!(
-            "Mouse: {:.0}, {:.0} | Shapes: {} {}{}{}{}", 
-            self.live_mouse_pos.x, 
-            self.live_mouse_pos.y,
-            self.clicked_shapes.len(),
-            if self.editing_shape_index.is_some() { "[EDITING SHAPE]" } else { "" },
-            if self.selected_shape_index.is_some() && self.editing_shape_index.is_none() { "[SHAPE SELECTED]" } else { "" },
-            if self.selected_connector_index.is_some() { "[CONN SELECTED]" } else { "" },
-            if self.drawing_new_line { "[DRAWING LINE]" } else { "" }
-        );
-        let mut text_display = graphics::Text::new(status_text);
-        text_display.set_scale(20.0); 
-        canvas.draw(&text_display, graphics::DrawParam::default().dest(Vec2::new(10.0, 10.0)).color(Color::WHITE));
-        
-        canvas.finish(ctx)?;
-        Ok(())
+    // switch_tab without the window: the dirty flag must be current
+    fn bring_in_tab(&mut self, index: usize) {
+        if index == self.active_tab || index >= self.tabs.len() { return; }
+        self.cancel_interactions();
+        self.simulation = None;
+        self.history.record(diagram_fingerprint(&self.diagram), &self.diagram); // So the last change can be undone on coming back
+        let parked_keys = self.shape_mesh_keys();
+        self.swap_document(self.active_tab);
+        self.tabs[self.active_tab].shape_mesh_keys = parked_keys;
+        self.swap_document(index);
+        self.active_tab = index;
     }
 
-    fn mouse_button_down_event(&mut self, _ctx: &mut Context, button: MouseButton, x: f32, y: f32) -> GameResult {
-        let logical_x = x / self.ui_scale;
-        let logical_y = y / self.ui_scale;
-        let current_click_pos = Vec2::new(logical_x, logical_y);
-        let current_click_time = Instant::now();
+    fn new_tab(&mut self, ctx: &mut Context) {
+        self.tabs.push(Document::new(self.untitled_path()));
+        self.switch_tab(ctx, self.tabs.len() - 1);
+    }
 
-        if button == MouseButton::Left {
-            // --- Priority 1: Completing a new line ---
-            if self.drawing_new_line {
-                let mut connected_to_target = false;
-                if let Some((start_shape_idx, _start_is_outgoing)) = self.new_line_start_info {
-                    for (target_idx, _target_shape_data) in self.clicked_shapes.iter().enumerate() {
-                        if target_idx == start_shape_idx { continue; } 
-
-                        if let Some(target_incoming_pos) = self.get_port_point(target_idx, false) { 
-                            if current_click_pos.distance(target_incoming_pos) <= PORT_CLICK_RADIUS { 
-                                let new_connection = UserConnection { from_shape_index: start_shape_idx, to_shape_index: target_idx };
-                                if !self.connections.contains(&new_connection) { self.connections.push(new_connection); }
-                                connected_to_target = true; break;
-                            }
-                        }
-                        if let Some(target_outgoing_pos) = self.get_port_point(target_idx, true) { 
-                             if current_click_pos.distance(target_outgoing_pos) <= PORT_CLICK_RADIUS { 
-                                let new_connection = UserConnection { from_shape_index: start_shape_idx, to_shape_index: target_idx };
-                                 if !self.connections.contains(&new_connection) { self.connections.push(new_connection); }
-                                connected_to_target = true; break;
-                            }
-                        }
-                    }
-                }
-                self.drawing_new_line = false; self.new_line_start_info = None; self.new_line_preview_end_pos = None;
-                if !connected_to_target { println!("New line drawing cancelled."); }
-                return Ok(());
-            }
+    // diagram-2.json, diagram-3.json, ...: the first name neither open nor on disk
+    fn untitled_path(&self) -> PathBuf {
+        let default_path = Path::new(persistence::DEFAULT_DIAGRAM_PATH);
+        let stem = default_path.file_stem().and_then(|s| s.to_str()).unwrap_or("diagram");
+        (2..).map(|n| default_path.with_file_name(format!("{}-{}.json", stem, n)))
+            .find(|path| !path.exists() && *path != self.diagram_path && self.tabs.iter().all(|tab| tab.diagram_path != *path))
+            .unwrap_or_else(|| default_path.to_path_buf())
+    }
 
-            // --- Priority 2: Interacting with a shape body ---
-            let mut clicked_on_shape_body_details: Option<(usize, Vec2)> = None;
-            for (index, shape_data) in self.clicked_shapes.iter().enumerate().rev() {
-                let shape_rect = Rect::new(
-                    shape_data.center_position.x - self.default_shape_width / 2.0,
-                    shape_data.center_position.y - self.default_shape_height / 2.0,
-                    self.default_shape_width, self.default_shape_height);
-                if shape_rect.contains(current_click_pos) {
-                    clicked_on_shape_body_details = Some((index, shape_data.center_position));
-                    break;
-                }
-            }
+    fn cycle_tab(&mut self, ctx: &mut Context, delta: isize) {
+        let index = (self.active_tab as isize + delta).rem_euclid(self.tabs.len() as isize) as usize;
+        self.switch_tab(ctx, index);
+    }
 
-            if let Some((clicked_idx, clicked_shape_center)) = clicked_on_shape_body_details {
-                self.selected_connector_index = None;
-                if self.editing_shape_index.is_some() && self.editing_shape_index != Some(clicked_idx) {
-                    if let Some(editing_idx_val) = self.editing_shape_index.take() {
-                        self.clicked_shapes[editing_idx_val].text = if self.current_input_text.is_empty() { None } else { Some(self.current_input_text.clone()) };
-                        self.current_input_text.clear();
-                    }
-                }
-                self.selected_shape_index = Some(clicked_idx);
-                let mut is_double_click_for_edit = false;
-                if let (Some(last_time), Some(last_pos_val)) = (self.last_click_time, self.last_click_pos) {
-                    if current_click_time.duration_since(last_time).as_millis() <= DOUBLE_CLICK_MAX_DELAY_MS && current_click_pos.distance(last_pos_val) <= DOUBLE_CLICK_MAX_DISTANCE {
-                        is_double_click_for_edit = true;
-                    }
-                }
-                if is_double_click_for_edit {
-                    self.editing_shape_index = Some(clicked_idx);
-                    self.current_input_text = self.clicked_shapes[clicked_idx].text.clone().unwrap_or_default();
-                    self.dragged_shape_index = None; self.last_click_time = None; self.last_click_pos = None;
-                } else {
-                    self.dragged_shape_index = Some(clicked_idx);
-                    self.drag_offset = Some(clicked_shape_center - current_click_pos);
-                    self.last_click_time = Some(current_click_time); self.last_click_pos = Some(current_click_pos);
-                }
-                return Ok(());
-            }
-            
-            // --- Priority 3: Starting a new line from a port ---
-            for (index, _shape_data) in self.clicked_shapes.iter().enumerate() {
-                if let Some(outgoing_pos) = self.get_port_point(index, true) {
-                    if current_click_pos.distance(outgoing_pos) <= PORT_CLICK_RADIUS { 
-                        self.drawing_new_line = true; self.new_line_start_info = Some((index, true));
-                        self.selected_shape_index = None; self.selected_connector_index = None;
-                        self.last_click_time = None; self.last_click_pos = None;
-                        println!("Starting new line from shape {} (outgoing port).", index); return Ok(());
-                    }
-                }
-                if let Some(incoming_pos) = self.get_port_point(index, false) {
-                    if current_click_pos.distance(incoming_pos) <= PORT_CLICK_RADIUS { 
-                        self.drawing_new_line = true; self.new_line_start_info = Some((index, false));
-                        self.selected_shape_index = None; self.selected_connector_index = None;
-                        self.last_click_time = None; self.last_click_pos = None;
-                        println!("Starting new line from shape {} (incoming port).", index); return Ok(());
-                    }
-                }
-            }
+    // Closes the active tab, asking first if it has unsaved changes. Closing the last one leaves an empty diagram.
+    fn request_close_tab(&mut self, ctx: &mut Context) {
+        self.refresh_dirty_flag(ctx);
+        if self.dirty {
+            self.save_prompt = Some(SavePrompt::CloseTab);
+            return;
+        }
+        self.close_active_tab(ctx);
+    }
 
-            // --- Priority 4: Selecting an existing connector line ---
-            let mut clicked_on_existing_connector_idx: Option<usize> = None;
-            for (conn_idx, connection) in self.connections.iter().enumerate() {
-                 if let (Some(start_point_ggez), Some(end_point_ggez)) = (
-                    self.get_port_point(connection.from_shape_index, true),
-                    self.get_port_point(connection.to_shape_index, false)
-                ) {
-                    let p0 = LyonPoint::new(start_point_ggez.x, start_point_ggez.y);
-                    let p3 = LyonPoint::new(end_point_ggez.x, end_point_ggez.y);
-                    let dir_mult = if p3.x > p0.x { 1.0 } else { -1.0 };
-                    let p1 = LyonPoint::new(p0.x + CONNECTOR_CURVE_OFFSET * dir_mult, p0.y);
-                    let p2 = LyonPoint::new(p3.x - CONNECTOR_CURVE_OFFSET * dir_mult, p3.y);
-                    for j in 0..=CONNECTOR_SAMPLE_POINTS {
-                        let t = j as f32 / CONNECTOR_SAMPLE_POINTS as f32;
-                        let curve_point = get_point_on_cubic_bezier(p0, p1, p2, p3, t);
-                        if current_click_pos.distance(Vec2::new(curve_point.x, curve_point.y)) <= CONNECTOR_SELECTION_RADIUS {
-                            clicked_on_existing_connector_idx = Some(conn_idx); break;
-                        }
-                    }
-                }
-                if clicked_on_existing_connector_idx.is_some() { break; }
-            }
+    fn close_active_tab(&mut self, ctx: &mut Context) {
+        self.close_active_document();
+        self.update_window_title(ctx);
+    }
 
-            if let Some(conn_idx) = clicked_on_existing_connector_idx {
-                self.selected_connector_index = Some(conn_idx);
-                self.selected_shape_index = None; 
-                self.editing_shape_index = None; 
-                if let Some(editing_idx_val) = self.editing_shape_index.take() {
-                     self.clicked_shapes[editing_idx_val].text = if self.current_input_text.is_empty() { None } else { Some(self.current_input_text.clone()) };
-                     self.current_input_text.clear();
-                }
-                println!("Connector {} selected.", conn_idx);
-                self.last_click_time = Some(current_click_time); self.last_click_pos = Some(current_click_pos);
-                return Ok(());
-            }
+    // Drops the active document, changes and all, for the tab after it (or before, or a new empty one)
+    fn close_active_document(&mut self) {
+        let closing = self.active_tab;
+        if self.tabs.len() == 1 { self.tabs.push(Document::new(self.untitled_path())); }
+        self.bring_in_tab(if closing + 1 < self.tabs.len() { closing + 1 } else { closing - 1 });
+        let closed = self.tabs.remove(closing);
+        if self.active_tab > closing { self.active_tab -= 1; }
+        log::info!(target: logging::MODEL, "Closed {}", closed.diagram_path.display());
+    }
 
-            // --- Priority 5: Clicking on empty space ---
-            if let Some(editing_idx_val) = self.editing_shape_index.take() { 
-                self.clicked_shapes[editing_idx_val].text = if self.current_input_text.is_empty() { None } else { Some(self.current_input_text.clone()) };
-                self.current_input_text.clear();
-            }
-            self.selected_shape_index = None; self.dragged_shape_index = None; self.selected_connector_index = None;
+    // --- Recent files ---
+    fn remember_recent_file(&mut self, path: &Path) {
+        self.recent_files.remember(path);
+        if let Err(e) = self.recent_files.save() { log::warn!(target: logging::IO, "{}", e); }
+    }
 
-            let mut is_double_click_for_create = false;
-            if let (Some(last_time), Some(last_pos_val)) = (self.last_click_time, self.last_click_pos) {
-                if current_click_time.duration_since(last_time).as_millis() <= DOUBLE_CLICK_MAX_DELAY_MS && current_click_pos.distance(last_pos_val) <= DOUBLE_CLICK_MAX_DISTANCE {
-                    is_double_click_for_create = true;
-                }
-            }
-            if is_double_click_for_create {
-                self.clicked_shapes.push(ShapeData { center_position: current_click_pos, text: None });
-                let new_idx = self.clicked_shapes.len() - 1;
-                self.selected_shape_index = Some(new_idx); self.editing_shape_index = Some(new_idx);
-                self.current_input_text.clear();
-                self.last_click_time = None; self.last_click_pos = None;
-            } else {
-                self.last_click_time = Some(current_click_time); self.last_click_pos = Some(current_click_pos);
-            }
+    // Opens another diagram file, e.g. one picked from the recent files or dropped onto the window, in
+    // a tab of its own: the tab already showing it, else the active one while it is an untouched empty
+    // diagram, else a new one.
+    fn open_diagram_file(&mut self, ctx: &mut Context, path: PathBuf) {
+        self.refresh_dirty_flag(ctx);
+        self.open_diagram_document(path);
+        self.update_window_title(ctx);
+    }
+
+    // open_diagram_file without the window: the dirty flag must be current. The file already showing
+    // stays as it is rather than being read again over any unsaved changes.
+    fn open_diagram_document(&mut self, path: PathBuf) {
+        if !path.exists() {
+            log::warn!(target: logging::IO, "{} no longer exists; removing it from the recent files.", path.display());
+            self.recent_files.forget(&path);
+            if let Err(e) = self.recent_files.save() { log::warn!(target: logging::IO, "{}", e); }
+            return;
+        }
+        let same_file = |open: &Path| fs::canonicalize(open).is_ok_and(|open| fs::canonicalize(&path).is_ok_and(|path| open == path));
+        if same_file(&self.diagram_path) {
+            log::info!(target: logging::IO, "{} is already open.", path.display());
+            return;
+        }
+        if let Some(index) = self.tabs.iter().enumerate().position(|(index, tab)| index != self.active_tab && same_file(&tab.diagram_path)) {
+            self.bring_in_tab(index);
+            return;
+        }
+        let reuse_active = !self.dirty && self.diagram.shapes.is_empty() && self.diagram.lanes.is_empty();
+        if !reuse_active {
+            self.tabs.push(Document::new(self.untitled_path()));
+            self.bring_in_tab(self.tabs.len() - 1);
+        }
+        let previous_path = std::mem::replace(&mut self.diagram_path, path);
+        if self.load_diagram() { return; }
+        if reuse_active {
+            self.diagram_path = previous_path;
+        } else {
+            self.close_active_document();
         }
-        Ok(())
     }
 
-    fn mouse_button_up_event(&mut self, _ctx: &mut Context, button: MouseButton, _x: f32, _y: f32) -> GameResult {
-        if button == MouseButton::Left && self.dragged_shape_index.is_some() {
-            self.dragged_shape_index = None;
-            self.drag_offset = None;
+    // --- Nested diagrams ---
+    // Brings up the diagram at `path`: the tab showing it, else the file opened as open_diagram_file
+    // would, else a new tab that saving will create it from. Whether it is now the active diagram.
+    fn show_diagram(&mut self, ctx: &mut Context, path: &Path) -> bool {
+        if self.diagram_path == path { return true; }
+        if let Some(index) = self.tabs.iter().enumerate().position(|(index, tab)| index != self.active_tab && tab.diagram_path == path) {
+            self.switch_tab(ctx, index);
+        } else if path.exists() {
+            self.open_diagram_file(ctx, path.to_path_buf());
+        } else {
+            self.new_tab(ctx);
+            self.diagram_path = path.to_path_buf();
+            self.update_window_title(ctx);
         }
-        Ok(())
+        self.diagram_path == path
     }
 
-    fn mouse_motion_event(&mut self, _ctx: &mut Context, x: f32, y: f32, _dx: f32, _dy: f32) -> GameResult {
-        let logical_x = x / self.ui_scale;
-        let logical_y = y / self.ui_scale;
-        self.live_mouse_pos = Vec2::new(logical_x, logical_y);
-        if let Some(index) = self.dragged_shape_index {
-            if let Some(offset) = self.drag_offset {
-                if index < self.clicked_shapes.len() {
-                    self.clicked_shapes[index].center_position = self.live_mouse_pos + offset;
-                }
+    // Ctrl+double-click or Ctrl+Enter: opens the diagram the shape stands for, remembering the way
+    // back up. A shape without one is first linked to a new diagram next to this one, named after it.
+    fn open_subdiagram(&mut self, ctx: &mut Context, index: usize) {
+        let Some(shape) = self.diagram.shapes.get(index) else { return; };
+        let relative = match shape.subdiagram.clone() {
+            Some(relative) => relative,
+            None => {
+                let relative = self.new_subdiagram_path(index);
+                log::info!(target: logging::MODEL, "Shape {} now links to {}.", index, relative.display());
+                self.diagram.shapes[index].subdiagram = Some(relative.clone());
+                relative
             }
+        };
+        let path = self.diagram_path.parent().unwrap_or(Path::new("")).join(relative);
+        // Drilling into a diagram already on the way down goes back up to it instead
+        let mut trail = self.breadcrumb.clone();
+        match trail.iter().position(|crumb| *crumb == path) {
+            Some(level) => trail.truncate(level),
+            None => trail.push(self.diagram_path.clone()),
         }
-        Ok(())
+        if self.show_diagram(ctx, &path) { self.breadcrumb = trail; }
+    }
+
+    // "<this diagram>-<shape label>.json", made unique with a number if needed
+    fn new_subdiagram_path(&self, index: usize) -> PathBuf {
+        let stem = self.diagram_path.file_stem().map_or_else(|| String::from("diagram"), |s| s.to_string_lossy().into_owned());
+        let label: String = self.diagram.shapes[index].text.as_deref().unwrap_or("").chars()
+            .map(|c| if c.is_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+            .collect();
+        let label = label.split('-').filter(|part| !part.is_empty()).collect::<Vec<_>>().join("-");
+        let base = if label.is_empty() { format!("{}-{}", stem, index) } else { format!("{}-{}", stem, label) };
+        let folder = self.diagram_path.parent().unwrap_or(Path::new(""));
+        (1..)
+            .map(|n| PathBuf::from(if n == 1 { format!("{}.json", base) } else { format!("{}-{}.json", base, n) }))
+            .find(|name| !folder.join(name).exists() && self.diagram.shapes.iter().all(|shape| shape.subdiagram.as_ref() != Some(name)))
+            .unwrap_or_else(|| PathBuf::from(format!("{}.json", base)))
+    }
+
+    // Goes back up to breadcrumb entry `level` (0: the top diagram), e.g. with Ctrl+Up or a click on it
+    fn open_parent_diagram(&mut self, ctx: &mut Context, level: usize) {
+        let Some(path) = self.breadcrumb.get(level).cloned() else { return; };
+        let trail = self.breadcrumb[..level].to_vec();
+        if self.show_diagram(ctx, &path) {
+            self.breadcrumb = trail;
+        } else {
+            log::warn!(target: logging::IO, "Could not go back up to {}.", path.display());
+        }
+    }
+
+    // File names along the breadcrumb, then the open diagram's; empty when it wasn't drilled into
+    fn breadcrumb_names(&self) -> Vec<String> {
+        if self.breadcrumb.is_empty() { return Vec::new(); }
+        let name = |path: &PathBuf| path.file_stem().map_or_else(|| path.display().to_string(), |s| s.to_string_lossy().into_owned());
+        self.breadcrumb.iter().chain(std::iter::once(&self.diagram_path)).map(name).collect()
+    }
+
+    // Enter opens the highlighted recent file, a click the one clicked
+    fn run_recent_choice(&mut self, ctx: &mut Context, index: Option<usize>) {
+        let Some(mut menu) = self.recent_menu.take() else { return; };
+        if let Some(index) = index { menu.select(index); }
+        if let Some(path) = menu.chosen(&self.recent_files).cloned() { self.open_diagram_file(ctx, path); }
+    }
+
+    // Replaces the whole diagram, clearing any selection or in-progress interaction
+    fn replace_diagram(&mut self, diagram: Diagram) {
+        self.history.reset(diagram_fingerprint(&diagram), &diagram);
+        self.diagram = diagram;
+        self.simulation = None;
+        self.active_layer = 0;
+        self.selected_shape_index = None;
+        self.extra_selected_shapes.clear();
+        self.selected_group = None;
+        self.selected_lane = None;
+        self.selected_connector_index = None;
+        self.cancel_interactions();
+    }
+
+    // Whether the diagram is in the middle of changing: dragged, typed into or animated. Undo steps
+    // are only recorded between these.
+    fn interaction_in_progress(&self) -> bool {
+        self.dragged_shape_index.is_some() || self.dragged_group.is_some() || self.lane_drag.is_some()
+            || self.editing_shape_index.is_some() || self.inspector.editing.is_some()
+            || self.dragged_connector_endpoint.is_some() || self.bending_connector.is_some() || self.rotating_shape.is_some()
+            || self.pen_stroke.is_some() || self.erasing || self.layout_animation.is_some()
+    }
+
+    // Ctrl+Z / Ctrl+Y: goes back to the diagram before the last change, or forward again. Whatever
+    // was under way is finished first and counts as that change.
+    fn step_history(&mut self, forward: bool) {
+        self.clear_selection();
+        self.cancel_interactions();
+        let fingerprint = diagram_fingerprint(&self.diagram);
+        let restored = if forward { self.history.redo(fingerprint, &self.diagram) } else { self.history.undo(fingerprint, &self.diagram) };
+        let Some(diagram) = restored else {
+            log::info!(target: logging::MODEL, "Nothing to {}.", if forward { "redo" } else { "undo" });
+            return;
+        };
+        self.diagram = diagram;
+        self.simulation = None;
+        self.navigation_origin = None;
+        self.active_layer = self.active_layer.min(self.diagram.layer_count() - 1);
+        log::info!(target: logging::MODEL, "{}.", if forward { "Redone" } else { "Undone" });
+    }
+
+    // Drops any drag, text edit or line in progress and the running layout animation
+    fn cancel_interactions(&mut self) {
+        self.dragged_group = None;
+        self.lane_drag = None;
+        self.dragged_shape_index = None;
+        self.drag_offset = None;
+        self.editing_shape_index = None;
+        self.text_edit = TextEdit::default();
+        self.drawing_new_line = false; self.new_line_start_info = None; self.new_line_preview_end_pos = None;
+        self.dragged_connector_endpoint = None; self.bending_connector = None; self.rotating_shape = None;
+        self.pen_stroke = None; self.erasing = false;
+        self.last_click_time = None; self.last_click_pos = None;
+        self.layout_animation = None; self.camera_animation = None;
+        self.reach_highlight = None;
+    }
+
+    // Loads `diagram_path`; `.dot`, `.gv` and `.txt` files are imported as Graphviz / edge lists.
+    // Returns whether it worked.
+    fn load_diagram(&mut self) -> bool {
+        match persistence::open_diagram(&self.diagram_path, &self.layout_spacing()) {
+            Ok((diagram, imported)) => {
+                self.replace_diagram(diagram);
+                self.remember_recent_file(&self.diagram_path.clone());
+                if imported {
+                    log::info!(target: logging::IO, "Imported {} shapes from {}", self.diagram.shapes.len(), self.diagram_path.display());
+                    // Saving goes to a native file next to the import rather than overwriting it
+                    self.diagram_path.set_extension("json");
+                } else {
+                    self.saved_fingerprint = diagram_fingerprint(&self.diagram);
+                    self.dirty = false;
+                    log::info!(target: logging::IO, "Diagram loaded from {}", self.diagram_path.display());
+                }
+                true
+            }
+            Err(e) => {
+                log::warn!(target: logging::IO, "{}", e);
+                false
+            }
+        }
+    }
+
+    // Helper to get port coordinates; ports of shapes in a collapsed group sit on the group's box
+    fn get_port_point(&self, shape_index: usize, port_id: PortId) -> Option<Vec2> {
+        self.diagram.displayed_port_anchor(shape_index, port_id, self.default_shape_size()).map(|(point, _)| point)
+    }
+
+    fn get_port_side(&self, shape_index: usize, port_id: PortId) -> Option<PortSide> {
+        self.diagram.displayed_port_anchor(shape_index, port_id, self.default_shape_size()).map(|(_, side)| side)
+    }
+
+    // Bezier control points of a connection's curve, or None if an endpoint no longer exists
+    fn connection_curve(&self, conn_idx: usize) -> Option<[Vec2; 4]> {
+        let bend = self.diagram.connections.get(conn_idx)?.bend.unwrap_or(0.0);
+        Some(geometry::bow_curve(self.unbent_connection_curve(conn_idx)?, bend))
+    }
+
+    // The curve before the connection's own bend. Connections sharing their pair of shapes already
+    // bow apart here so each stays visible.
+    fn unbent_connection_curve(&self, conn_idx: usize) -> Option<[Vec2; 4]> {
+        let connection = self.diagram.connections.get(conn_idx)?;
+        let curve = geometry::connector_curve(
+            self.get_port_point(connection.from_shape_index, connection.from_port)?,
+            self.get_port_side(connection.from_shape_index, connection.from_port)?,
+            self.get_port_point(connection.to_shape_index, connection.to_port)?,
+            self.get_port_side(connection.to_shape_index, connection.to_port)?,
+        );
+        Some(geometry::bow_curve(curve, self.parallel_offsets.get(conn_idx).copied().unwrap_or(0.0)))
+    }
+
+    fn selected_shape_indices(&self) -> Vec<usize> {
+        self.selected_shape_index.into_iter().chain(self.extra_selected_shapes.iter().copied()).collect()
+    }
+
+    fn is_shape_selected(&self, index: usize) -> bool {
+        self.selected_shape_index == Some(index) || self.extra_selected_shapes.contains(&index)
+    }
+
+    // Shift+click: adds the shape to the selection, or takes it out if already selected
+    fn toggle_shape_in_selection(&mut self, index: usize) {
+        self.commit_shape_text_edit();
+        self.selected_connector_index = None; self.selected_group = None; self.selected_lane = None;
+        if self.selected_shape_index == Some(index) {
+            self.selected_shape_index = self.extra_selected_shapes.pop();
+        } else if let Some(position) = self.extra_selected_shapes.iter().position(|&i| i == index) {
+            self.extra_selected_shapes.remove(position);
+        } else if self.selected_shape_index.is_none() {
+            self.selected_shape_index = Some(index);
+        } else {
+            self.extra_selected_shapes.push(index);
+        }
+        self.last_click_time = None; self.last_click_pos = None;
+    }
+
+    // Removes a shape, dropping its connections and re-indexing the remaining ones. Selection and
+    // drags are dropped; state that outlives them follows its shape to its new index.
+    fn delete_shape(&mut self, deleted_shape_idx: usize) {
+        self.diagram.delete_shape(deleted_shape_idx);
+        let shifted = |index: usize| index_after_removal(index, deleted_shape_idx);
+        self.new_line_start_info = self.new_line_start_info.and_then(|(index, port)| Some((shifted(index)?, port)));
+        if self.new_line_start_info.is_none() {
+            self.drawing_new_line = false; self.new_line_preview_end_pos = None; self.new_line_press_pos = None;
+        }
+        self.reach_highlight = self.reach_highlight.and_then(|(index, reach)| Some((shifted(index)?, reach)));
+        self.navigation_origin = self.navigation_origin.and_then(|(index, outgoing)| Some((shifted(index)?, outgoing)));
+
+        self.selected_shape_index = None;
+        self.extra_selected_shapes.clear();
+        self.selected_group = None; self.dragged_group = None; // Emptied groups are removed
+        self.dragged_shape_index = None; 
+        self.editing_shape_index = None; 
+        self.selected_connector_index = None; 
+        self.dragged_connector_endpoint = None; self.bending_connector = None; self.rotating_shape = None;
+        self.last_click_time = None; 
+        self.last_click_pos = None;
+    }
+
+    // --- Clipboard operations ---
+    fn copy_selection(&mut self) -> bool {
+        let selected = self.selected_shape_indices();
+        if selected.is_empty() { return false; }
+        self.clipboard = Some(self.diagram.extract(&selected));
+        log::info!(target: logging::MODEL, "Copied {} shape(s).", selected.len());
+        true
+    }
+
+    // Puts the selected shape's label on the OS clipboard
+    fn copy_selected_label(&mut self) {
+        let label = self.selected_shape_index.and_then(|i| self.diagram.shapes.get(i)).and_then(|s| s.text.clone());
+        if let Some(label) = label {
+            self.system_clipboard.set_text(&label);
+        }
+    }
+
+    fn cut_selection(&mut self) {
+        if !self.copy_selection() { return; }
+        let mut selected = self.selected_shape_indices();
+        // Delete from the highest index down so earlier indices stay valid
+        selected.sort_unstable_by(|a, b| b.cmp(a));
+        for index in selected {
+            self.delete_shape(index);
+        }
+    }
+
+    // Adds the fragment with its first shape centered on `anchor`, keeping the relative layout of the rest
+    fn paste_fragment_at(&mut self, fragment: &Diagram, anchor: Vec2) {
+        let Some(first_shape) = fragment.shapes.first() else { return; };
+        let first_new_connection = self.diagram.connections.len();
+        let new_indices = self.diagram.append(fragment, anchor - first_shape.center_position);
+        // Pasted copies land on the active layer
+        for &index in &new_indices { self.diagram.shapes[index].layer = self.active_layer; }
+        for connection in &mut self.diagram.connections[first_new_connection..] { connection.layer = self.active_layer; }
+        self.admit_new_connections(first_new_connection);
+        if let Some(&first_new) = new_indices.first() {
+            self.selected_shape_index = Some(first_new);
+            self.selected_connector_index = None;
+            log::info!(target: logging::MODEL, "Pasted {} shape(s).", new_indices.len());
+        }
+    }
+
+    fn paste_clipboard(&mut self) {
+        if let Some(fragment) = self.clipboard.clone() {
+            self.paste_fragment_at(&fragment, self.live_mouse_pos + clipboard::PASTE_OFFSET);
+        }
+    }
+
+    fn duplicate_selection(&mut self) {
+        let selected = self.selected_shape_indices();
+        if let Some(&first) = selected.first() {
+            let fragment = self.diagram.extract(&selected);
+            let anchor = self.diagram.shapes[first].center_position + clipboard::PASTE_OFFSET;
+            self.paste_fragment_at(&fragment, anchor);
+        }
+    }
+
+    // Rearranges all shapes into layers following the connections, optionally animating the move
+    fn apply_hierarchical_layout(&mut self, animate: bool) {
+        if self.diagram.shapes.is_empty() { return; }
+        let target_positions = layout::diagram_layout(&self.diagram, &self.layout_spacing());
+
+        if animate {
+            self.layout_animation = Some(LayoutAnimation {
+                start_positions: self.diagram.shapes.iter().map(|s| s.center_position).collect(),
+                target_positions,
+                tween: Tween::new(LAYOUT_ANIMATION_DURATION, Easing::EaseInOut),
+            });
+        } else {
+            self.layout_animation = None;
+            for (shape, target) in self.diagram.shapes.iter_mut().zip(target_positions) {
+                shape.center_position = target;
+            }
+        }
+        log::info!(target: logging::MODEL, "Applied hierarchical layout to {} shapes.", self.diagram.shapes.len());
+    }
+
+    fn layout_spacing(&self) -> layout::LayoutSpacing {
+        layout_spacing(self.default_shape_size())
+    }
+
+    // Logs the average draw and hit-test times since the last report
+    fn report_stress_timing(&mut self) {
+        let Some(timing) = self.stress_timing.as_mut() else { return; };
+        if timing.since.elapsed() < STRESS_REPORT_INTERVAL { return; }
+        let average = |total: Duration, count: u32| if count == 0 { 0.0 } else { total.as_secs_f64() * 1000.0 / count as f64 };
+        log::info!(
+            target: logging::RENDER,
+            "{} shapes, {} connections: {:.2} ms per draw over {} frames, {:.3} ms per hit test over {}",
+            self.diagram.shapes.len(), self.diagram.connections.len(),
+            average(timing.draw_time, timing.frames), timing.frames,
+            average(timing.hit_test_time, timing.hit_tests), timing.hit_tests,
+        );
+        *timing = StressTiming::new();
+    }
+
+    // --- Session recording and replay ---
+    // Records a live input event; returns false for live input that a running replay should ignore
+    fn observe(&mut self, event: SessionEvent) -> bool {
+        if self.replay.is_some() && !self.replaying { return false; }
+        if let Some(recorder) = self.recorder.as_mut() { recorder.record(event); }
+        true
+    }
+
+    // Modifiers and whether Space is down, for a click: as recorded while replaying, live otherwise
+    fn held_keys(&self, ctx: &Context) -> (KeyMods, bool) {
+        if self.replaying { return self.replayed_keys; }
+        (ctx.keyboard.active_mods(), ctx.keyboard.is_key_pressed(KeyCode::Space))
+    }
+
+    // Feeds the recorded events that are due through the same handlers live input goes through
+    fn feed_replay(&mut self, ctx: &mut Context) -> GameResult {
+        let Some(replay) = self.replay.as_mut() else { return Ok(()); };
+        let events = replay.due();
+        let (finished, headless) = (replay.finished(), replay.headless);
+        self.replaying = true;
+        let result = events.into_iter().try_for_each(|event| self.replay_event(ctx, event));
+        self.replaying = false;
+        result?;
+        if finished {
+            self.replay = None;
+            log::info!(target: logging::INPUT, "Replay finished.");
+            if headless {
+                self.quit_after_replay = true;
+                ctx.request_quit();
+            }
+        }
+        Ok(())
+    }
+
+    fn replay_event(&mut self, ctx: &mut Context, event: SessionEvent) -> GameResult {
+        match event {
+            SessionEvent::MouseDown { button, x, y, mods, space_held } => {
+                self.replayed_keys = (KeyMods::from_bits_truncate(mods), space_held);
+                self.mouse_button_down_event(ctx, button.button(), x, y)
+            }
+            SessionEvent::MouseUp { button, x, y } => self.mouse_button_up_event(ctx, button.button(), x, y),
+            SessionEvent::MouseMotion { x, y, dx, dy } => self.mouse_motion_event(ctx, x, y, dx, dy),
+            SessionEvent::Wheel { x, y, mods } => {
+                self.replayed_keys = (KeyMods::from_bits_truncate(mods), false);
+                self.mouse_wheel_event(ctx, x, y)
+            }
+            SessionEvent::Pinch { delta } => self.pinch_event(ctx, delta),
+            SessionEvent::ScrollPan { dx, dy, mods } => {
+                self.replayed_keys = (KeyMods::from_bits_truncate(mods), false);
+                self.scroll_pan_event(ctx, dx, dy)
+            }
+            SessionEvent::Key { key, mods, repeated } => match session::key_from_name(&key) {
+                Some(keycode) => {
+                    let input = KeyInput { scancode: 0, keycode: Some(keycode), mods: KeyMods::from_bits_truncate(mods) };
+                    self.key_down_event(ctx, input, repeated)
+                }
+                None => {
+                    log::warn!(target: logging::INPUT, "Replay skipped the key '{}', which it can't press.", key);
+                    Ok(())
+                }
+            },
+            SessionEvent::Text { character } => self.text_input_event(ctx, character),
+            // The window reports the new size through resize_event as usual
+            SessionEvent::Resize { width, height } => ctx.gfx.set_drawable_size(width, height),
+        }
+    }
+
+    // When the event loop should run the next frame: at the FPS cap while dirty, after the idle
+    // interval otherwise. Until then it waits for events, which may mark the frame dirty sooner.
+    fn pace_frame(&mut self) -> Instant {
+        if !self.render_on_demand || self.layout_animation.is_some() || self.camera_animation.is_some() || self.flow_animation || self.stress_timing.is_some() || self.simulation.as_ref().is_some_and(|s| s.moving.is_some()) { self.needs_redraw = true; }
+        let frame_interval = if self.needs_redraw { self.max_frame_interval.unwrap_or(Duration::ZERO) } else { IDLE_FRAME_INTERVAL };
+        self.last_frame_at + frame_interval
+    }
+
+    fn step_layout_animation(&mut self) {
+        let Some(animation) = &self.layout_animation else { return; };
+        // Any structural edit while animating invalidates the recorded positions
+        if animation.target_positions.len() != self.diagram.shapes.len() {
+            self.layout_animation = None;
+            return;
+        }
+        let now = Instant::now();
+        let progress = animation.tween.progress(now);
+        for (index, shape) in self.diagram.shapes.iter_mut().enumerate() {
+            shape.center_position = animation.start_positions[index].lerp(animation.target_positions[index], progress);
+        }
+        if animation.tween.finished(now) {
+            self.layout_animation = None;
+        }
+    }
+
+    // Snaps a world position to the grid when snapping is on
+    fn snap_position(&self, position: Vec2) -> Vec2 {
+        if self.grid_enabled { geometry::snap_to_grid(position, self.grid_spacing) } else { position }
+    }
+
+    // --- Per-shape style ---
+    fn shape_corner_radius(&self, shape_data: &ShapeData) -> f32 {
+        shape_data.corner_radius.unwrap_or(self.default_shape_corner_radius)
+    }
+
+    // Sets the selected shape's fill (or outline) to palette entry `slot` (1-based); slot 0 clears it
+    fn apply_palette_color(&mut self, slot: usize, to_outline: bool) {
+        let Some(index) = self.selected_shape_index else { return; };
+        let color = match slot {
+            0 => None,
+            _ => match self.shape_palette.get(slot - 1) {
+                Some(&rgb) => Some(rgb),
+                None => {
+                    log::warn!(target: logging::MODEL, "Palette has no color {} ({} configured).", slot, self.shape_palette.len());
+                    return;
+                }
+            },
+        };
+        let shape = &mut self.diagram.shapes[index];
+        if to_outline { shape.outline_rgb = color; } else { shape.fill_rgb = color; }
+        log::info!(target: logging::MODEL, "Shape {} {} {}.", index, if to_outline { "outline" } else { "fill" }, color.map_or("reset".to_string(), |rgb| format!("set to {:?}", rgb)));
+    }
+
+    fn adjust_selected_corner_radius(&mut self, delta: f32) {
+        let Some(index) = self.selected_shape_index else { return; };
+        let rect = self.shape_rect(&self.diagram.shapes[index]);
+        let max_radius = rect.w.min(rect.h) / 2.0;
+        let radius = (self.shape_corner_radius(&self.diagram.shapes[index]) + delta).clamp(0.0, max_radius);
+        self.diagram.shapes[index].corner_radius = Some(radius);
+        log::info!(target: logging::MODEL, "Shape {} corner radius: {:.0}", index, radius);
+    }
+
+    // Grows or shrinks the selected shape's label; back at the configured size it follows the config again
+    fn adjust_selected_font_size(&mut self, delta: f32) {
+        let Some(index) = self.selected_shape_index else { return; };
+        let size = (self.shape_font_size_of(&self.diagram.shapes[index]) + delta).clamp(MIN_SHAPE_FONT_SIZE, MAX_SHAPE_FONT_SIZE);
+        self.diagram.shapes[index].font_size = (size != self.shape_font_size).then_some(size);
+        log::info!(target: logging::MODEL, "Shape {} font size: {:.0}", index, size);
+    }
+
+    // Changes where the selected shape sits in the drawing order: to the very front or back, or one step
+    fn restack_selected_shape(&mut self, forward: bool, one_step: bool) {
+        let Some(index) = self.selected_shape_index else { return; };
+        let last = self.diagram.shapes.len().saturating_sub(1);
+        let new_index = match (forward, one_step) {
+            (true, false) => last,
+            (false, false) => 0,
+            (true, true) => (index + 1).min(last),
+            (false, true) => index.saturating_sub(1),
+        };
+        if new_index == index || !self.diagram.restack_shape(index, new_index) { return; }
+        // Other shapes shifted too, so anything else holding a shape index is dropped
+        self.selected_shape_index = Some(new_index);
+        self.extra_selected_shapes.clear();
+        self.dragged_shape_index = None; self.drag_offset = None;
+        self.drawing_new_line = false; self.new_line_start_info = None; self.new_line_preview_end_pos = None;
+        self.layout_animation = None;
+        self.last_click_time = None;
+        self.last_click_pos = None;
+        log::info!(target: logging::MODEL, "Shape moved {}.", if one_step { if forward { "forward" } else { "backward" } } else if forward { "to front" } else { "to back" });
+    }
+
+    // Moves the selected shapes (or group) one step in `direction`; with the grid on the step is one
+    // grid cell and shapes land on grid points
+    fn nudge_selection(&mut self, direction: Vec2, large: bool) {
+        let step = if self.grid_enabled { self.grid_spacing } else if large { NUDGE_STEP_LARGE } else { NUDGE_STEP };
+        if let Some(group) = self.selected_group {
+            self.diagram.move_group(group, direction * step);
+            return;
+        }
+        let selected = self.selected_shape_indices();
+        if selected.is_empty() { return; }
+        self.layout_animation = None;
+        for index in selected {
+            let Some(shape) = self.diagram.shapes.get(index) else { continue; };
+            let target = self.snap_position(shape.center_position + direction * step);
+            self.diagram.move_shape(index, target);
+        }
+    }
+
+    // --- Commands: what shortcuts, the toolbar and the command palette run ---
+    fn run_command(&mut self, ctx: &mut Context, command: Command) {
+        match command {
+            Command::Save => self.save_diagram(),
+            Command::Open => { self.load_diagram(); }
+            Command::ExportSvg => self.export_svg(&self.diagram_path.with_extension("svg")),
+            Command::ExportDot => self.export_dot(&self.diagram_path.with_extension("dot")),
+            Command::ExportPng => self.export_png(ctx, &self.diagram_path.with_extension("png")),
+            Command::ExportCsv => self.export_graph(cli::ExportFormat::Csv),
+            Command::ExportAdjacency => self.export_graph(cli::ExportFormat::Adjacency),
+            Command::ExportRust => self.export_rust(),
+            Command::ImportCsv => self.import_csv(),
+            Command::Undo => self.step_history(false),
+            Command::Redo => self.step_history(true),
+            Command::Copy => { self.copy_selection(); self.copy_selected_label(); }
+            Command::Cut => self.cut_selection(),
+            Command::Paste => self.paste_clipboard(),
+            Command::Duplicate => self.duplicate_selection(),
+            Command::Delete => self.delete_selection(false),
+            Command::SpliceOut => self.delete_selection(true),
+            Command::Rename => {
+                if let Some(index) = self.selected_shape_index { self.start_shape_text_edit(index); }
+            }
+            Command::CycleShapeKind => self.cycle_shape_kind(),
+            Command::BringToFront => self.restack_selected_shape(true, false),
+            Command::SendToBack => self.restack_selected_shape(false, false),
+            Command::BringForward => self.restack_selected_shape(true, true),
+            Command::SendBackward => self.restack_selected_shape(false, true),
+            Command::ToggleRouting => self.toggle_selected_connector_routing(),
+            Command::ReverseConnector => {
+                if let Some(connector_idx) = self.selected_connector_index { self.reverse_connection(connector_idx); }
+            }
+            Command::SplitConnector => {
+                // Splits the selected connector at its midpoint
+                let Some(connector_idx) = self.selected_connector_index else { return; };
+                let midpoint = self.connection_label_anchor(connector_idx);
+                if let Some(midpoint) = midpoint { self.split_connection(connector_idx, midpoint); }
+            }
+            Command::Layout => self.apply_hierarchical_layout(true),
+            Command::LayoutInstant => self.apply_hierarchical_layout(false),
+            Command::Group => self.group_selection(),
+            Command::Ungroup => self.ungroup_selection(),
+            Command::AddNote => self.add_note_at(self.live_mouse_pos),
+            Command::OpenSubdiagram => {
+                if let Some(index) = self.selected_shape_index { self.open_subdiagram(ctx, index); }
+            }
+            Command::ParentDiagram => {
+                if let Some(level) = self.breadcrumb.len().checked_sub(1) { self.open_parent_diagram(ctx, level); }
+            }
+            Command::AddLane => self.add_lane(LaneOrientation::Horizontal),
+            Command::AddVerticalLane => self.add_lane(LaneOrientation::Vertical),
+            Command::ToggleGrid => self.toggle_grid(),
+            Command::CycleTheme => self.cycle_theme(),
+            Command::ToggleFlowAnimation => {
+                self.flow_animation = !self.flow_animation;
+                log::info!(target: logging::RENDER, "Flow animation {}.", if self.flow_animation { "on" } else { "off" });
+            }
+            Command::ToggleBridges => {
+                self.bridges_enabled = !self.bridges_enabled;
+                log::info!(target: logging::RENDER, "Connector bridges {}.", if self.bridges_enabled { "on" } else { "off" });
+            }
+            Command::ToggleInspector => self.toggle_inspector(),
+            Command::ToggleLayers => self.toggle_layers_panel(),
+            Command::ToggleCodePanel => self.toggle_code_panel(),
+            Command::ToggleStats => {
+                self.stats_panel_visible = !self.stats_panel_visible;
+                log::info!(target: logging::RENDER, "Graph statistics {}.", if self.stats_panel_visible { "shown" } else { "hidden" });
+            }
+            Command::AddLayer => self.handle_layer_click(LayerAction::Add),
+            Command::MoveToActiveLayer => self.move_selection_to_active_layer(),
+            Command::ZoomToFit => self.zoom_to_fit(ctx, false),
+            Command::ZoomToSelection => self.zoom_to_fit(ctx, true),
+            Command::Find => {
+                self.commit_inspector_edit();
+                self.command_palette = None; self.recent_menu = None;
+                self.shape_search = Some(ShapeSearch::default());
+            }
+            Command::ToggleFocus => {
+                if self.focus_filter.take().is_some() {
+                    log::info!(target: logging::RENDER, "Focus filter cleared.");
+                } else {
+                    self.run_command(ctx, Command::Find);
+                }
+            }
+            Command::CycleReachHighlight => self.cycle_reach_highlight(),
+            Command::ToggleOrderBadges => {
+                self.show_order_badges = !self.show_order_badges;
+                log::info!(target: logging::RENDER, "Topological order badges {}.", if self.show_order_badges { "shown" } else { "hidden" });
+            }
+            Command::ToggleReadOnly => self.set_read_only(!self.read_only),
+            Command::Simulate => self.toggle_simulation(),
+            Command::AddPresentationStep => self.add_presentation_step(),
+            Command::ClearPresentationSteps => {
+                self.diagram.steps.clear();
+                log::info!(target: logging::MODEL, "Presentation steps cleared.");
+            }
+            Command::ToggleAnnotations => {
+                self.annotations_visible = !self.annotations_visible;
+                log::info!(target: logging::RENDER, "Annotations {}.", if self.annotations_visible { "shown" } else { "hidden" });
+            }
+            Command::ClearAnnotations => {
+                let count = self.diagram.annotations.len();
+                self.diagram.annotations.clear();
+                log::info!(target: logging::MODEL, "{} annotations cleared.", count);
+            }
+            Command::ToggleDebugOverlay => {
+                self.debug_overlay = if self.debug_overlay.is_some() { None } else { Some(FrameStats::default()) };
+                log::info!(target: logging::RENDER, "Debug overlay {}.", if self.debug_overlay.is_some() { "shown" } else { "hidden" });
+            }
+            Command::ToggleCriticalPath => {
+                self.show_critical_path = !self.show_critical_path;
+                log::info!(target: logging::RENDER, "Critical path {}.", if self.show_critical_path { "shown" } else { "hidden" });
+            }
+            Command::UseTool(tool) => self.set_tool(tool),
+            Command::NewTab => self.new_tab(ctx),
+            Command::CloseTab => self.request_close_tab(ctx),
+            Command::NextTab => self.cycle_tab(ctx, 1),
+            Command::PreviousTab => self.cycle_tab(ctx, -1),
+            Command::OpenRecent => {
+                self.commit_inspector_edit();
+                self.command_palette = None; self.shape_search = None;
+                self.recent_menu = Some(RecentFilesMenu::default());
+            }
+            Command::OpenPalette => {
+                self.commit_inspector_edit();
+                self.shape_search = None; self.recent_menu = None;
+                self.reload_scripts();
+                self.command_palette = Some(CommandPalette::with_scripts(self.scripts.iter().map(|s| s.title.clone()).collect()));
+            }
+            Command::RunScript(index) => self.run_script(index),
+        }
+    }
+
+    // --- Scripts ---
+    // Looked for in `scripts/` in the working directory, then next to the per-user config. Found
+    // again each time the command palette opens, so new and edited scripts show up without a restart.
+    fn reload_scripts(&mut self) {
+        let mut dirs = vec![PathBuf::from(SCRIPTS_DIR_NAME)];
+        dirs.extend(config::user_config_path().and_then(|path| Some(path.parent()?.join(SCRIPTS_DIR_NAME))));
+        let (scripts, problems) = scripting::discover(&dirs);
+        for problem in problems { log::warn!(target: logging::IO, "{}", problem); }
+        self.scripts = scripts;
+    }
+
+    // Files the script writes go next to the diagram
+    fn run_script(&mut self, index: usize) {
+        let Some(command) = self.scripts.get(index).cloned() else { return; };
+        let output_dir = self.diagram_path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new(".")).to_path_buf();
+        let first_new_connection = self.diagram.connections.len();
+        match scripting::run(&command, &mut self.diagram, &output_dir) {
+            Ok(run) => {
+                for message in run.messages { log::info!(target: logging::MODEL, "[{}] {}", command.title, message); }
+                if run.changed {
+                    self.clear_selection();
+                    self.admit_new_connections(first_new_connection);
+                }
+                log::info!(target: logging::MODEL, "Ran script '{}'{}.", command.title, if run.changed { "" } else { " (diagram unchanged)" });
+            }
+            Err(e) => log::error!(target: logging::MODEL, "{}", e),
+        }
+    }
+
+    // Deletes the selected shape, else the selected connector, else the selected lane. `splice_out`
+    // first joins the shape's incoming connections to its outgoing ones.
+    fn delete_selection(&mut self, splice_out: bool) {
+        if let Some(deleted_shape_idx) = self.selected_shape_index {
+            let before_bridges = self.diagram.connections.len();
+            if splice_out { self.diagram.reconnect_through(deleted_shape_idx); }
+            let bridges = self.diagram.connections.len() - before_bridges;
+            self.delete_shape(deleted_shape_idx);
+            // The bridges stay last; they are checked once the shape and its connections are gone
+            self.admit_new_connections(self.diagram.connections.len() - bridges);
+            log::info!(target: logging::MODEL, "Shape {} deleted, connections updated.", deleted_shape_idx);
+        } else if let Some(connector_idx) = self.selected_connector_index {
+            self.delete_connection(connector_idx);
+        } else if let Some(lane) = self.selected_lane {
+            self.delete_lane(lane);
+        }
+    }
+
+    // Cycles the kind of the selected shape (e.g. one just created) and of shapes created next
+    fn cycle_shape_kind(&mut self) {
+        self.new_shape_kind = match self.selected_shape_index.and_then(|i| self.diagram.shapes.get_mut(i)) {
+            Some(shape) => {
+                // A note was made without ports; the node it becomes gets the usual ones
+                if shape.kind == ShapeKind::Note && shape.ports.is_empty() { shape.ports = self.default_shape_ports.clone(); }
+                shape.kind = shape.kind.next();
+                shape.kind
+            }
+            None => self.new_shape_kind.next(),
+        };
+        log::info!(target: logging::MODEL, "Shape kind: {}", self.new_shape_kind.name());
+    }
+
+    // Enter runs the highlighted match of the open command palette, a click runs the one clicked
+    fn run_palette_choice(&mut self, ctx: &mut Context, index: Option<usize>) {
+        let Some(mut palette) = self.command_palette.take() else { return; };
+        if let Some(index) = index { palette.select(index); }
+        if let Some(command) = palette.chosen() { self.run_command(ctx, command); }
+    }
+
+    // Same for the long-press menu
+    fn run_context_menu_choice(&mut self, ctx: &mut Context, index: Option<usize>) {
+        let Some(mut menu) = self.context_menu.take() else { return; };
+        if let Some(index) = index { menu.select(index); }
+        if let Some(command) = menu.chosen() { self.run_command(ctx, command); }
+    }
+
+    // Enter jumps to the highlighted match of the open search, a click to the one clicked:
+    // the shape is selected and the view centered on it
+    fn run_search_choice(&mut self, screen_size: Vec2, index: Option<usize>) {
+        let Some(mut search) = self.shape_search.take() else { return; };
+        if let Some(index) = index { search.select(index); }
+        let Some(shape_index) = search.chosen(&self.diagram) else { return; };
+        self.select_shape_from_keyboard(shape_index, screen_size);
+        let mut target = self.camera_target();
+        target.center_on(self.diagram.shapes[shape_index].center_position, screen_size);
+        self.animate_camera(target, screen_size, Tween::new(CAMERA_MOVE_DURATION, Easing::EaseOut));
+    }
+
+    // --- Tool handlers: what a left click on the canvas does in each tool ---
+    fn set_tool(&mut self, tool: Tool) {
+        if self.tool == tool { return; }
+        self.drawing_new_line = false; self.new_line_start_info = None; self.new_line_preview_end_pos = None;
+        self.dragged_connector_endpoint = None; self.bending_connector = None; self.rotating_shape = None;
+        self.pen_stroke = None; self.erasing = false;
+        self.last_click_time = None; self.last_click_pos = None;
+        self.tool = tool;
+        log::info!(target: logging::INPUT, "Tool: {}", tool.name());
+    }
+
+    fn select_tool_press(&mut self, pos: Vec2, time: Instant) {
+        // --- Priority 1: Completing a new line ---
+        if self.drawing_new_line {
+            self.finish_new_line(self.new_line_target(pos));
+            return;
+        }
+        // --- Priority 1b: Grabbing an endpoint or the bend handle of the selected connector, or the selected shape's rotation handle ---
+        if self.grab_connector_endpoint(pos) || self.grab_connector_bend_handle(pos) || self.grab_rotation_handle(pos) { return; }
+        // --- Priority 2: Interacting with a shape body ---
+        if let Some(index) = self.shape_at(pos) {
+            self.press_shape(index, pos, time);
+            return;
+        }
+        // --- Priority 3: Starting a new line from a port ---
+        if let Some((index, port_id)) = self.find_port_target(pos, None) {
+            self.start_new_line(index, port_id, pos);
+            return;
+        }
+        // --- Priority 4: Selecting an existing connector line ---
+        if let Some(conn_idx) = self.connector_at(pos) {
+            self.select_connector(conn_idx, pos, time);
+            return;
+        }
+        // --- Priority 4b: A group's frame or collapsed box ---
+        if let Some(group) = self.group_at(pos) {
+            self.press_group(group, pos, time);
+            return;
+        }
+        // --- Priority 4c: A lane's title strip or its right / bottom edge (the rest of a lane is empty space) ---
+        if let Some(drag) = self.lane_drag_at(pos) {
+            self.clear_selection();
+            let (LaneDrag::Move { lane, .. } | LaneDrag::Resize { lane, .. }) = drag;
+            self.selected_lane = Some(lane);
+            self.lane_drag = Some(drag);
+            self.layout_animation = None;
+            self.last_click_time = None; self.last_click_pos = None;
+            return;
+        }
+        // --- Priority 5: Clicking on empty space; a double-click creates a shape ---
+        let double_click = self.is_double_click(pos, time);
+        self.clear_selection();
+        if double_click {
+            self.create_shape_at(pos);
+        } else {
+            self.last_click_time = Some(time); self.last_click_pos = Some(pos);
+        }
+    }
+
+    // First click picks the source, second the target; clicking a shape body uses its nearest port
+    fn connect_tool_press(&mut self, pos: Vec2) {
+        let exclude_shape = self.new_line_start_info.map(|(start_shape_idx, _)| start_shape_idx);
+        let port_target = if self.drawing_new_line { self.new_line_target(pos) } else { self.find_port_target(pos, None) };
+        let target = port_target.or_else(|| self.shape_body_target(pos, exclude_shape));
+        if self.drawing_new_line {
+            self.finish_new_line(target);
+        } else if let Some((index, port_id)) = target {
+            self.start_new_line(index, port_id, pos);
+        } else {
+            self.clear_selection();
+        }
+    }
+
+    fn create_tool_press(&mut self, pos: Vec2, time: Instant) {
+        match self.shape_at(pos) {
+            Some(index) => self.press_shape(index, pos, time),
+            None => {
+                self.clear_selection();
+                self.create_shape_at(pos);
+            }
+        }
+    }
+
+    // Shift starts a highlighter stroke instead of a pen one
+    fn pen_tool_press(&mut self, pos: Vec2, highlight: bool) {
+        self.annotations_visible = true;
+        let (color_rgb, width) = if highlight { (HIGHLIGHTER_RGB, HIGHLIGHTER_WIDTH) } else { (PEN_RGB, PEN_WIDTH) };
+        self.pen_stroke = Some(Annotation { points: vec![pos], color_rgb, width: width / self.camera.zoom, highlight });
+    }
+
+    fn extend_pen_stroke(&mut self, pos: Vec2) {
+        let zoom = self.camera.zoom;
+        let Some(stroke) = self.pen_stroke.as_mut() else { return; };
+        if stroke.points.last().is_some_and(|last| last.distance(pos) * zoom < PEN_POINT_SPACING) { return; }
+        stroke.points.push(pos);
+    }
+
+    fn finish_pen_stroke(&mut self) {
+        let Some(stroke) = self.pen_stroke.take() else { return; };
+        log::info!(target: logging::MODEL, "{} stroke of {} points added.", if stroke.highlight { "Highlighter" } else { "Pen" }, stroke.points.len());
+        self.diagram.annotations.push(stroke);
+    }
+
+    fn erase_annotations_at(&mut self, pos: Vec2) {
+        if !self.annotations_visible { return; }
+        let erased = self.diagram.erase_annotations(pos, ERASER_RADIUS / self.camera.zoom);
+        if erased > 0 { log::info!(target: logging::MODEL, "{} annotation strokes erased.", erased); }
+    }
+
+    fn delete_tool_press(&mut self, pos: Vec2) {
+        if let Some(index) = self.shape_at(pos) {
+            self.delete_shape(index);
+            log::info!(target: logging::MODEL, "Shape {} deleted, connections updated.", index);
+        } else if let Some(conn_idx) = self.connector_at(pos) {
+            self.delete_connection(conn_idx);
+        }
+    }
+
+    fn is_double_click(&self, pos: Vec2, time: Instant) -> bool {
+        match (self.last_click_time, self.last_click_pos) {
+            (Some(last_time), Some(last_pos)) => {
+                time.duration_since(last_time).as_millis() <= DOUBLE_CLICK_MAX_DELAY_MS && pos.distance(last_pos) <= DOUBLE_CLICK_MAX_DISTANCE
+            }
+            _ => false,
+        }
+    }
+
+    // Stores the text being typed in the canvas into its shape and leaves edit mode
+    fn commit_shape_text_edit(&mut self) {
+        if let Some(index) = self.editing_shape_index.take() {
+            if let Some(shape) = self.diagram.shapes.get_mut(index) {
+                shape.text = if self.text_edit.text.is_empty() { None } else { Some(self.text_edit.text.clone()) };
+            }
+            self.text_edit = TextEdit::default();
+        }
+        self.selecting_text = false;
+    }
+
+    fn clear_selection(&mut self) {
+        self.commit_shape_text_edit();
+        self.selected_shape_index = None; self.dragged_shape_index = None; self.selected_connector_index = None;
+        self.extra_selected_shapes.clear();
+        self.selected_group = None; self.selected_lane = None;
+    }
+
+    // --- Keyboard navigation ---
+    // Selects a shape as clicking it would, scrolling it into view if needed
+    fn select_shape_from_keyboard(&mut self, index: usize, screen_size: Vec2) {
+        self.clear_selection();
+        self.selected_shape_index = Some(index);
+        if let Some(shape_data) = self.diagram.shapes.get(index) {
+            let rect = self.shape_bounds(shape_data);
+            let mut target = self.camera_target();
+            target.reveal(Vec2::new(rect.left(), rect.top()), Vec2::new(rect.right(), rect.bottom()), screen_size);
+            self.animate_camera(target, screen_size, Tween::new(CAMERA_MOVE_DURATION, Easing::EaseOut));
+        }
+        log::debug!(target: logging::INPUT, "Shape {} selected.", index);
+    }
+
+    // Tab / Shift+Tab: the next / previous visible shape in drawing order, wrapping around
+    fn cycle_shape_selection(&mut self, backward: bool, screen_size: Vec2) {
+        let count = self.diagram.shapes.len();
+        if count == 0 { return; }
+        let start = self.selected_shape_index.unwrap_or(if backward { 0 } else { count - 1 });
+        let next = (1..=count)
+            .map(|step| if backward { (start + count - step % count) % count } else { (start + step) % count })
+            .find(|&index| self.shape_interactive(index));
+        if let Some(index) = next { self.select_shape_from_keyboard(index, screen_size); }
+    }
+
+    // Alt+Right / Alt+Left jump from the selected shape along its outgoing / incoming connections;
+    // Alt+Down / Alt+Up then step through the other shapes reachable by that same jump
+    fn follow_connection(&mut self, keycode: KeyCode, screen_size: Vec2) {
+        let Some(current) = self.selected_shape_index else { return; };
+        let visible_neighbors = |origin: usize, outgoing: bool| -> Vec<usize> {
+            self.diagram.neighbors(origin, outgoing).into_iter().filter(|&i| self.shape_interactive(i)).collect()
+        };
+        let target = match keycode {
+            KeyCode::Right | KeyCode::Left => {
+                let outgoing = keycode == KeyCode::Right;
+                let target = visible_neighbors(current, outgoing).first().copied();
+                if target.is_none() { log::debug!(target: logging::INPUT, "Shape {} has no {} connections.", current, if outgoing { "outgoing" } else { "incoming" }); }
+                if target.is_some() { self.navigation_origin = Some((current, outgoing)); }
+                target
+            }
+            _ => {
+                let Some((origin, outgoing)) = self.navigation_origin else { return; };
+                let siblings = visible_neighbors(origin, outgoing);
+                let Some(position) = siblings.iter().position(|&i| i == current) else { return; };
+                let step = if keycode == KeyCode::Down { 1 } else { siblings.len() - 1 };
+                Some(siblings[(position + step) % siblings.len()])
+            }
+        };
+        if let Some(index) = target { self.select_shape_from_keyboard(index, screen_size); }
+    }
+
+    // --- Layers ---
+    fn layers_widgets(&self) -> Option<(Rect, Vec<ui::Widget<LayerAction>>)> {
+        self.layers_panel_visible.then(|| layers_panel::layers_widgets(&self.diagram, self.active_layer))
+    }
+
+    fn toggle_layers_panel(&mut self) {
+        self.layers_panel_visible = !self.layers_panel_visible;
+        log::info!(target: logging::RENDER, "Layers panel {}.", if self.layers_panel_visible { "shown" } else { "hidden" });
+    }
+
+    fn handle_layer_click(&mut self, action: LayerAction) {
+        match action {
+            LayerAction::Activate(layer) => {
+                self.active_layer = layer;
+                log::info!(target: logging::MODEL, "Active layer: {}", self.diagram.layer_name(layer));
+            }
+            LayerAction::ToggleVisible(layer) | LayerAction::ToggleLocked(layer) => {
+                let Some(entry) = self.diagram.layer_mut(layer) else { return; };
+                if matches!(action, LayerAction::ToggleVisible(_)) { entry.visible = !entry.visible; } else { entry.locked = !entry.locked; }
+                // Whatever was selected may now be hidden or locked
+                self.clear_selection();
+                self.drawing_new_line = false; self.new_line_start_info = None; self.new_line_preview_end_pos = None;
+            }
+            LayerAction::Add => {
+                self.active_layer = self.diagram.add_layer();
+                log::info!(target: logging::MODEL, "Added {}.", self.diagram.layer_name(self.active_layer));
+            }
+        }
+    }
+
+    // Puts the selected shapes, or the selected connector, on the active layer
+    fn move_selection_to_active_layer(&mut self) {
+        let layer = self.active_layer;
+        for index in self.selected_shape_indices() {
+            if let Some(shape) = self.diagram.shapes.get_mut(index) { shape.layer = layer; }
+        }
+        if let Some(connection) = self.selected_connector_index.and_then(|i| self.diagram.connections.get_mut(i)) {
+            connection.layer = layer;
+        }
+        log::info!(target: logging::MODEL, "Selection moved to {}.", self.diagram.layer_name(layer));
+    }
+
+    // Shapes on locked layers are drawn but can't be picked, selected or connected
+    fn shape_interactive(&self, index: usize) -> bool {
+        !self.shape_hidden(index) && !self.diagram.shape_locked(index)
+    }
+
+    // --- Groups ---
+    // Hidden shapes are those on hidden layers and those inside collapsed groups
+    fn shape_hidden(&self, index: usize) -> bool {
+        self.diagram.shape_on_hidden_layer(index) || self.diagram.collapsed_group_of_shape(index).is_some()
+    }
+
+    // Innermost visible group whose frame (or collapsed box) contains `pos`
+    fn group_at(&self, pos: Vec2) -> Option<usize> {
+        (0..self.diagram.groups.len())
+            .filter(|&group| !self.diagram.group_hidden(group))
+            .filter(|&group| self.diagram.group_frame(group, self.default_shape_size()).is_some_and(|frame| frame.contains(pos)))
+            .max_by_key(|&group| self.diagram.group_depth(group))
+    }
+
+    // Selects the group and starts dragging it; a double-click collapses or expands it
+    fn press_group(&mut self, group: usize, pos: Vec2, time: Instant) {
+        let double_click = self.selected_group == Some(group) && self.is_double_click(pos, time);
+        self.clear_selection();
+        self.selected_group = Some(group);
+        if double_click {
+            let collapsed = !self.diagram.groups[group].collapsed;
+            self.diagram.groups[group].collapsed = collapsed;
+            log::info!(target: logging::MODEL, "Group '{}' {}.", self.diagram.groups[group].name, if collapsed { "collapsed" } else { "expanded" });
+            self.last_click_time = None; self.last_click_pos = None;
+        } else {
+            self.layout_animation = None;
+            self.dragged_group = Some((group, pos));
+            self.last_click_time = Some(time); self.last_click_pos = Some(pos);
+        }
+    }
+
+    // Ctrl+G: puts the selected shapes into a new group and selects it
+    fn group_selection(&mut self) {
+        let selected = self.selected_shape_indices();
+        let name = format!("Group {}", self.diagram.groups.len() + 1);
+        let Some(group) = self.diagram.group_shapes(&selected, &name) else { return; };
+        self.clear_selection();
+        self.selected_group = Some(group);
+        log::info!(target: logging::MODEL, "Grouped {} shape(s) into '{}'.", selected.len(), name);
+    }
+
+    // Ctrl+Shift+G: dissolves the selected group, or the one around the selected shape
+    fn ungroup_selection(&mut self) {
+        let group = self.selected_group.or_else(|| self.diagram.shapes.get(self.selected_shape_index?)?.group);
+        let Some(group) = group else { return; };
+        let name = self.diagram.groups[group].name.clone();
+        if self.diagram.ungroup(group) {
+            self.selected_group = None; self.dragged_group = None;
+            log::info!(target: logging::MODEL, "Ungrouped '{}'.", name);
+        }
+    }
+
+    // --- Swimlanes ---
+    // The drag a press at `pos` starts on the topmost lane it touches, if any
+    fn lane_drag_at(&self, pos: Vec2) -> Option<LaneDrag> {
+        self.diagram.lanes.iter().enumerate().rev().find_map(|(lane, lane_data)| {
+            let rect = lane_data.rect();
+            let near = |edge: f32, value: f32| (edge - value).abs() <= LANE_EDGE_GRAB_DISTANCE;
+            let right = near(rect.right(), pos.x) && pos.y >= rect.top() && pos.y <= rect.bottom();
+            let bottom = near(rect.bottom(), pos.y) && pos.x >= rect.left() && pos.x <= rect.right();
+            if right || bottom {
+                Some(LaneDrag::Resize { lane, right, bottom })
+            } else if lane_data.title_rect().contains(pos) {
+                Some(LaneDrag::Move { lane, last_pos: pos })
+            } else {
+                None
+            }
+        })
+    }
+
+    fn drag_lane(&mut self, pos: Vec2) {
+        match self.lane_drag {
+            Some(LaneDrag::Move { lane, last_pos }) => {
+                self.diagram.move_lane(lane, pos - last_pos);
+                self.lane_drag = Some(LaneDrag::Move { lane, last_pos: pos });
+            }
+            Some(LaneDrag::Resize { lane, right, bottom }) => {
+                let pos = self.snap_position(pos);
+                let Some(lane_data) = self.diagram.lanes.get_mut(lane) else { return; };
+                let mut rect = lane_data.rect();
+                if right { rect.w = (pos.x - rect.x).max(LANE_MIN_SIZE); }
+                if bottom { rect.h = (pos.y - rect.y).max(LANE_MIN_SIZE); }
+                lane_data.set_rect(rect);
+            }
+            None => {}
+        }
+    }
+
+    // W adds a horizontal lane, Shift+W a vertical one
+    fn add_lane(&mut self, orientation: LaneOrientation) {
+        let lane = self.diagram.add_lane(orientation, self.default_shape_size());
+        self.clear_selection();
+        self.selected_lane = Some(lane);
+        log::info!(target: logging::MODEL, "Added {}.", self.diagram.lanes[lane].title);
+    }
+
+    fn delete_lane(&mut self, lane: usize) {
+        if lane < self.diagram.lanes.len() {
+            let removed = self.diagram.lanes.remove(lane);
+            log::info!(target: logging::MODEL, "Deleted {}.", removed.title);
+        }
+        self.selected_lane = None; self.lane_drag = None;
+    }
+
+    // Topmost shape whose outline contains `pos`
+    fn shape_at(&self, pos: Vec2) -> Option<usize> {
+        self.shape_grid.query_point(pos).iter().rev().copied()
+            .filter(|&index| !self.diagram.shape_locked(index))
+            .find(|&index| self.diagram.shapes.get(index).is_some_and(|shape_data| shape_data.contains(pos, self.default_shape_size())))
+    }
+
+    // Connector within the pick radius of `pos`; the radius stays the same on screen at any zoom
+    // but never gets thinner than the line itself
+    fn connector_at(&self, pos: Vec2) -> Option<usize> {
+        let pick_radius = (CONNECTOR_SELECTION_RADIUS / self.camera.zoom).clamp(CONNECTOR_LINE_WIDTH, MAX_CONNECTOR_PICK_RADIUS);
+        self.connector_grid.query_point(pos).iter().copied().find(|&index| {
+            let Some(connection) = self.diagram.connections.get(index) else { return false; };
+            if self.diagram.layer_locked(connection.layer) { return false; }
+            self.nearest_point_on_connector(index, pos).is_some_and(|nearest| nearest.distance(pos) <= pick_radius)
+        })
+    }
+
+    // Nearest port of the shape under `pos`, for the Connect tool's clicks on shape bodies
+    fn shape_body_target(&self, pos: Vec2, exclude_shape: Option<usize>) -> Option<(usize, PortId)> {
+        let index = self.shape_at(pos).filter(|&i| Some(i) != exclude_shape)?;
+        Some((index, self.nearest_port(index, pos)?))
+    }
+
+    fn nearest_port(&self, shape_index: usize, pos: Vec2) -> Option<PortId> {
+        let port_count = self.diagram.shapes.get(shape_index)?.ports.len();
+        (0..port_count)
+            .filter_map(|port_id| Some((port_id, self.get_port_point(shape_index, port_id)?.distance(pos))))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(port_id, _)| port_id)
+    }
+
+    fn create_shape_at(&mut self, pos: Vec2) {
+        let new_idx = self.diagram.add_shape(ShapeData {
+            text: self.auto_shape_name(),
+            kind: self.new_shape_kind,
+            ports: self.default_shape_ports.clone(),
+            layer: self.active_layer,
+            ..ShapeData::new(self.snap_position(pos))
+        });
+        self.selected_shape_index = Some(new_idx);
+        self.start_shape_text_edit(new_idx);
+        self.text_edit.select_all(); // Typing replaces an auto-generated name
+    }
+
+    // A square note with no ports, its text edited straight away
+    fn add_note_at(&mut self, pos: Vec2) {
+        let new_idx = self.diagram.add_shape(ShapeData {
+            kind: ShapeKind::Note,
+            size: Some([NOTE_SIZE, NOTE_SIZE]),
+            ports: Vec::new(),
+            layer: self.active_layer,
+            ..ShapeData::new(self.snap_position(pos))
+        });
+        self.clear_selection();
+        self.selected_shape_index = Some(new_idx);
+        self.start_shape_text_edit(new_idx);
+        log::info!(target: logging::MODEL, "Added a note.");
+    }
+
+    // "Node N" with the lowest N from the shape count up that no shape uses yet, when auto-naming is on
+    fn auto_shape_name(&self) -> Option<String> {
+        if !self.auto_name_shapes { return None; }
+        (self.diagram.shapes.len() + 1..)
+            .map(|n| format!("Node {}", n))
+            .find(|name| !self.diagram.shapes.iter().any(|shape| shape.text.as_deref() == Some(name.as_str())))
+    }
+
+    // Enters text editing on the shape, caret after its text (double-click or F2)
+    fn start_shape_text_edit(&mut self, index: usize) {
+        let Some(shape) = self.diagram.shapes.get(index) else { return; };
+        self.editing_shape_index = Some(index);
+        self.text_edit = TextEdit::new(shape.text.clone().unwrap_or_default());
+        self.dragged_shape_index = None; self.last_click_time = None; self.last_click_pos = None;
+    }
+
+    // Selects the shape; a double-click starts editing its text, otherwise a drag begins.
+    // Inside the shape being edited, a click places the caret and a drag selects text.
+    fn press_shape(&mut self, index: usize, pos: Vec2, time: Instant) {
+        self.selected_connector_index = None;
+        if self.editing_shape_index == Some(index) {
+            self.text_edit.move_to(self.caret_index_at(pos), false);
+            self.selecting_text = true;
+            return;
+        }
+        self.commit_shape_text_edit();
+        self.selected_shape_index = Some(index);
+        self.extra_selected_shapes.clear();
+        self.selected_group = None; self.selected_lane = None;
+        if self.is_double_click(pos, time) {
+            self.start_shape_text_edit(index);
+        } else {
+            self.layout_animation = None;
+            self.dragged_shape_index = Some(index);
+            self.drag_offset = Some(self.diagram.shapes[index].center_position - pos);
+            self.drag_start_position = Some(self.diagram.shapes[index].center_position);
+            self.last_click_time = Some(time); self.last_click_pos = Some(pos);
+        }
+    }
+
+    // The line can be finished by clicking a target, or by dragging onto one and releasing
+    fn start_new_line(&mut self, shape_index: usize, port_id: PortId, press_pos: Vec2) {
+        self.commit_shape_text_edit();
+        self.drawing_new_line = true; self.new_line_start_info = Some((shape_index, port_id));
+        self.new_line_press_pos = Some(press_pos);
+        self.selected_shape_index = None; self.selected_connector_index = None;
+        self.last_click_time = None; self.last_click_pos = None;
+        log::debug!(target: logging::INPUT, "Starting new line from shape {} (port {}).", shape_index, port_id);
+    }
+
+    // Connects the line being drawn to `target`, or cancels it when there is none
+    fn finish_new_line(&mut self, target: Option<(usize, PortId)>) {
+        let mut connected_to_target = false;
+        if let (Some((start_shape_idx, start_port)), Some((target_idx, target_port))) = (self.new_line_start_info, target) {
+            let candidate = Connection::new(start_shape_idx, start_port, target_idx, target_port);
+            match self.connection_validator.check(&self.diagram, &candidate, None) {
+                Ok(()) => {
+                    if let Some(new_idx) = self.diagram.connect(start_shape_idx, start_port, target_idx, target_port) {
+                        self.diagram.connections[new_idx].layer = self.active_layer;
+                    }
+                }
+                Err(e) => log::warn!(target: logging::MODEL, "Connection refused: {}.", e),
+            }
+            connected_to_target = true;
+        }
+        self.drawing_new_line = false; self.new_line_start_info = None; self.new_line_preview_end_pos = None;
+        if !connected_to_target { log::debug!(target: logging::INPUT, "New line drawing cancelled."); }
+    }
+
+    // Starts dragging an endpoint of the selected connector if `pos` is on one of its handles
+    fn grab_connector_endpoint(&mut self, pos: Vec2) -> bool {
+        let (Some(conn_idx), Some((source_pos, target_pos))) = (self.selected_connector_index, self.selected_connector_endpoints()) else {
+            return false;
+        };
+        let grabbed_end = if pos.distance(source_pos) <= PORT_CLICK_RADIUS {
+            Some(true)
+        } else if pos.distance(target_pos) <= PORT_CLICK_RADIUS {
+            Some(false)
+        } else {
+            None
+        };
+        let Some(dragging_source_end) = grabbed_end else { return false; };
+        self.dragged_connector_endpoint = Some((conn_idx, dragging_source_end));
+        self.last_click_time = None; self.last_click_pos = None;
+        true
+    }
+
+    // Middle of the selected connector's curve, where it can be dragged to bend it; routed connectors have none
+    fn connector_bend_handle(&self) -> Option<Vec2> {
+        let conn_idx = self.selected_connector_index?;
+        if self.connector_route(self.diagram.connections.get(conn_idx)?).is_some() { return None; }
+        Some(geometry::point_on_cubic_bezier(&self.connection_curve(conn_idx)?, 0.5))
+    }
+
+    fn grab_connector_bend_handle(&mut self, pos: Vec2) -> bool {
+        let (Some(conn_idx), Some(handle)) = (self.selected_connector_index, self.connector_bend_handle()) else { return false; };
+        if pos.distance(handle) > PORT_CLICK_RADIUS { return false; }
+        self.bending_connector = Some((conn_idx, self.diagram.connections[conn_idx].bend));
+        self.last_click_time = None; self.last_click_pos = None;
+        true
+    }
+
+    // Bends the connector so the middle of its curve passes as close to `pos` as it can;
+    // dragging it back near the unbent curve straightens it again
+    fn bend_connector(&mut self, conn_idx: usize, pos: Vec2) {
+        let Some(unbent) = self.unbent_connection_curve(conn_idx) else { return; };
+        let middle = geometry::point_on_cubic_bezier(&unbent, 0.5);
+        let normal = (unbent[3] - unbent[0]).normalize_or_zero().perp();
+        let bend = (pos - middle).dot(normal);
+        self.diagram.connections[conn_idx].bend = (bend.abs() * self.camera.zoom > BEND_SNAP_DISTANCE).then_some(bend);
+    }
+
+    // Knob above the selected shape that turns it about its center; only for a lone, unlocked selection
+    fn rotation_handle(&self) -> Option<(usize, Vec2)> {
+        if self.read_only || self.tool != Tool::Select || !self.extra_selected_shapes.is_empty() { return None; }
+        let index = self.selected_shape_index.filter(|&i| self.editing_shape_index != Some(i) && !self.diagram.shape_locked(i) && !self.shape_hidden(i))?;
+        let shape_data = self.diagram.shapes.get(index)?;
+        let rect = self.shape_rect(shape_data);
+        Some((index, shape_data.to_world(Vec2::new(rect.center().x, rect.top() - ROTATION_HANDLE_DISTANCE))))
+    }
+
+    fn grab_rotation_handle(&mut self, pos: Vec2) -> bool {
+        let Some((index, handle)) = self.rotation_handle() else { return false; };
+        if pos.distance(handle) > PORT_CLICK_RADIUS { return false; }
+        self.rotating_shape = Some((index, self.diagram.shapes[index].rotation));
+        self.last_click_time = None; self.last_click_pos = None;
+        true
+    }
+
+    // Turns the shape so its rotation handle points at `pos`
+    fn rotate_shape_towards(&mut self, index: usize, pos: Vec2) {
+        let Some(shape_data) = self.diagram.shapes.get_mut(index) else { return; };
+        let offset = pos - shape_data.center_position;
+        if offset == Vec2::ZERO { return; }
+        // The handle starts out straight above the center, a quarter turn back from +x
+        let rotation = offset.y.atan2(offset.x) + std::f32::consts::FRAC_PI_2;
+        shape_data.set_rotation(if self.grid_enabled { (rotation / ROTATION_SNAP_STEP).round() * ROTATION_SNAP_STEP } else { rotation });
+    }
+
+    // Alt + wheel over a selected shape turns each selected shape about its own center
+    fn rotate_selection_by_wheel(&mut self, notches: f32) -> bool {
+        if self.read_only || notches == 0.0 { return false; }
+        let selected = self.selected_shape_indices();
+        if !self.shape_at(self.live_mouse_pos).is_some_and(|index| selected.contains(&index)) { return false; }
+        for index in selected {
+            if self.diagram.shape_locked(index) { continue; }
+            let shape_data = &mut self.diagram.shapes[index];
+            shape_data.set_rotation(shape_data.rotation + notches * ROTATION_WHEEL_STEP);
+        }
+        true
+    }
+
+    fn select_connector(&mut self, conn_idx: usize, pos: Vec2, time: Instant) {
+        // Double-clicking the selected connector inserts a shape into it
+        if self.selected_connector_index == Some(conn_idx) && self.is_double_click(pos, time) {
+            self.split_connection(conn_idx, pos);
+            return;
+        }
+        self.commit_shape_text_edit();
+        self.selected_connector_index = Some(conn_idx);
+        self.selected_shape_index = None;
+        self.extra_selected_shapes.clear();
+        self.selected_group = None; self.selected_lane = None;
+        log::debug!(target: logging::INPUT, "Connector {} selected.", conn_idx);
+        self.last_click_time = Some(time); self.last_click_pos = Some(pos);
+    }
+
+    // Point on the connector's route or curve closest to `pos`
+    fn nearest_point_on_connector(&self, conn_idx: usize, pos: Vec2) -> Option<Vec2> {
+        match self.connector_route(self.diagram.connections.get(conn_idx)?) {
+            Some(route) => geometry::closest_point_on_polyline(route, pos),
+            None => {
+                let curve = self.connection_curve(conn_idx)?;
+                Some(geometry::closest_point_on_cubic_bezier(&curve, pos, CONNECTOR_PICK_TOLERANCE / self.camera.zoom))
+            }
+        }
+    }
+
+    // Replaces A→C with A→N→C through a new shape N placed on the connector near `pos`.
+    // The label stays on the first half; N is left in text editing like any new shape.
+    fn split_connection(&mut self, conn_idx: usize, pos: Vec2) {
+        let Some(connection) = self.diagram.connections.get(conn_idx).cloned() else { return; };
+        let Some(split_point) = self.nearest_point_on_connector(conn_idx, pos) else { return; };
+        let (Some(start), Some(end)) = (
+            self.get_port_point(connection.from_shape_index, connection.from_port),
+            self.get_port_point(connection.to_shape_index, connection.to_port),
+        ) else { return; };
+
+        self.commit_shape_text_edit();
+        self.create_shape_at(split_point);
+        let new_idx = self.diagram.shapes.len() - 1;
+        let in_port = self.nearest_port(new_idx, start).unwrap_or(ports::default_incoming_port());
+        let out_port = self.nearest_port(new_idx, end).unwrap_or(ports::default_outgoing_port());
+
+        let first_half = Connection { to_shape_index: new_idx, to_port: in_port, ..connection.clone() };
+        let second_half = Connection::new(new_idx, out_port, connection.to_shape_index, connection.to_port);
+        let refused = self.connection_validator.check(&self.diagram, &first_half, Some(conn_idx))
+            .and_then(|()| self.connection_validator.check(&self.diagram, &second_half, None));
+        if let Err(e) = refused {
+            log::warn!(target: logging::MODEL, "Cannot split connector: {}.", e);
+            self.delete_shape(new_idx);
+            return;
+        }
+        self.diagram.insert_into_connection(conn_idx, new_idx, in_port, out_port);
+        self.selected_connector_index = None;
+        log::info!(target: logging::MODEL, "Connector {} split by new shape {}.", conn_idx, new_idx);
+    }
+
+    fn delete_connection(&mut self, conn_idx: usize) {
+        if self.diagram.disconnect(conn_idx).is_some() {
+            log::info!(target: logging::MODEL, "Connector {} deleted.", conn_idx);
+        }
+        self.selected_connector_index = None;
+        self.dragged_connector_endpoint = None; self.bending_connector = None; self.rotating_shape = None;
+    }
+
+    // Puts the connections from index `first` on (just pasted, imported, ...) through the connection
+    // rules; the refused ones are dropped and logged
+    fn admit_new_connections(&mut self, first: usize) {
+        for refusal in self.connection_validator.admit_from(&mut self.diagram, first) {
+            log::warn!(target: logging::MODEL, "Left out the connection {}.", refusal);
+        }
+    }
+
+    // Flips a connector's direction
+    fn reverse_connection(&mut self, conn_idx: usize) {
+        let Some(connection) = self.diagram.connections.get(conn_idx) else { return; };
+        if let Err(e) = self.connection_validator.check(&self.diagram, &connection.reversed(), Some(conn_idx)) {
+            log::warn!(target: logging::MODEL, "Cannot reverse connector: {}.", e);
+            return;
+        }
+        match self.diagram.reverse_connection(conn_idx) {
+            Ok(()) => log::info!(target: logging::MODEL, "Connector {} reversed.", conn_idx),
+            Err(e) => log::warn!(target: logging::MODEL, "Cannot reverse connector: {}.", e),
+        }
+    }
+
+    // --- Property inspector ---
+    fn logical_screen_size(&self, ctx: &Context) -> Vec2 {
+        let (physical_width, physical_height) = ctx.gfx.drawable_size();
+        Vec2::new(physical_width / self.ui_scale, physical_height / self.ui_scale)
+    }
+
+    fn inspected_item(&self) -> Option<InspectedItem> {
+        self.selected_shape_index.map(InspectedItem::Shape)
+            .or(self.selected_connector_index.map(InspectedItem::Connection))
+    }
+
+    // Panel area and its widgets for the current selection, or None while the inspector is hidden
+    fn inspector_widgets(&self, screen_size: Vec2) -> Option<(Rect, Vec<ui::Widget<InspectorField>>)> {
+        let panel = self.inspector.panel_rect(screen_size.x, screen_size.y)?;
+        let widgets = self.inspector.widgets(
+            panel, self.inspected_item(), &self.diagram.shapes, &self.diagram.connections, self.default_shape_size(), &self.shape_palette,
+        );
+        Some((panel, widgets))
+    }
+
+    // --- Panels docked on the right: the inspector at the edge, the code panel beside it ---
+    fn code_panel_rect(&self, screen_size: Vec2) -> Option<Rect> {
+        let right_edge = self.inspector.panel_rect(screen_size.x, screen_size.y).map_or(screen_size.x, |panel| panel.x);
+        self.code_panel.panel_rect(right_edge, screen_size.y)
+    }
+
+    // Where the canvas stops being covered by nothing on the right
+    fn canvas_right_edge(&self, screen_size: Vec2) -> f32 {
+        self.code_panel_rect(screen_size).or(self.inspector.panel_rect(screen_size.x, screen_size.y)).map_or(screen_size.x, |panel| panel.x)
+    }
+
+    fn toggle_code_panel(&mut self) {
+        self.blur_code_panel();
+        self.code_panel.visible = !self.code_panel.visible;
+        self.refresh_code_panel();
+        log::info!(target: logging::RENDER, "Diagram-as-code panel {}.", if self.code_panel.visible { "shown" } else { "hidden" });
+    }
+
+    // The panel's text follows the diagram (including shapes being dragged) unless it is being typed into
+    fn refresh_code_panel(&mut self) {
+        if self.code_panel.visible { self.code_panel.follow_diagram(dsl::render(&self.diagram)); }
+    }
+
+    // Hands the keys back to the canvas, applying what was typed
+    fn blur_code_panel(&mut self) {
+        if !self.code_panel.focused { return; }
+        self.code_panel.focused = false;
+        self.apply_code_panel_text();
+    }
+
+    // Applies the panel's text to the diagram if it parses; otherwise the error is shown and the diagram left alone
+    fn apply_code_panel_text(&mut self) {
+        match dsl::parse(&self.code_panel.edit.text) {
+            Ok(graph) => {
+                self.code_panel.error = None;
+                let spacing = self.layout_spacing();
+                if let Some(first_new_connection) = dsl::apply(&mut self.diagram, &graph, &spacing) {
+                    self.admit_new_connections(first_new_connection);
+                    self.clear_selection();
+                    log::debug!(target: logging::MODEL, "Diagram updated from its text.");
+                }
+            }
+            Err(error) => self.code_panel.error = Some(error),
+        }
+    }
+
+    // --- Node palette ---
+    // Adds the [[templates]] of templates.toml in the working directory, then of the one next to the
+    // per-user config, after the config's [[node_templates]]
+    fn load_template_files(&mut self) {
+        let mut paths = vec![PathBuf::from(palette::TEMPLATES_FILE_NAME)];
+        paths.extend(config::user_config_path().and_then(|path| Some(path.parent()?.join(palette::TEMPLATES_FILE_NAME))));
+        let (templates, problems) = palette::load_templates(&paths);
+        for problem in problems { log::warn!(target: logging::IO, "{}", problem); }
+        self.node_templates.extend(templates);
+    }
+
+    fn palette_widgets(&self) -> Option<(Rect, Vec<ui::Widget<usize>>)> {
+        let (toolbar, _) = tools::toolbar_widgets(self.tool);
+        palette::palette_widgets(toolbar.w, &self.node_templates)
+    }
+
+    // Whether a screen position is over the toolbar, palette or inspector rather than the canvas
+    fn over_ui(&self, screen_pos: Vec2, screen_size: Vec2) -> bool {
+        if self.read_only { return false; } // Nothing is drawn over the canvas
+        tools::toolbar_widgets(self.tool).0.contains(screen_pos)
+            || self.palette_widgets().is_some_and(|(strip, _)| strip.contains(screen_pos))
+            || self.tab_widgets().0.contains(screen_pos)
+            || self.inspector.panel_rect(screen_size.x, screen_size.y).is_some_and(|panel| panel.contains(screen_pos))
+            || self.code_panel_rect(screen_size).is_some_and(|panel| panel.contains(screen_pos))
+            || self.layers_widgets().is_some_and(|(panel, _)| panel.contains(screen_pos))
+            || self.stats_widgets(screen_size).is_some_and(|(panel, _)| panel.contains(screen_pos))
+    }
+
+    // Creates the template's nodes, and the connections between them, around where it was dropped.
+    // They end up selected together.
+    fn drop_template(&mut self, template_index: usize, pos: Vec2) {
+        let Some(template) = self.node_templates.get(template_index) else { return; };
+        let fragment = template.fragment(self.new_shape_kind, &self.default_shape_ports);
+        let name = template.name.clone();
+        let first_new_connection = self.diagram.connections.len();
+        let new_indices = self.diagram.append(&fragment, self.snap_position(pos));
+        for &index in &new_indices {
+            if self.diagram.shapes[index].text.is_none() { self.diagram.shapes[index].text = self.auto_shape_name(); }
+            self.diagram.shapes[index].layer = self.active_layer;
+        }
+        for connection in &mut self.diagram.connections[first_new_connection..] { connection.layer = self.active_layer; }
+        self.admit_new_connections(first_new_connection);
+        self.clear_selection();
+        self.selected_shape_index = new_indices.first().copied();
+        self.extra_selected_shapes = new_indices.iter().skip(1).copied().collect();
+        log::info!(target: logging::MODEL, "Added '{}' ({} node(s)).", name, new_indices.len());
+    }
+
+    // F fits every shape in the window, Shift+F only the selection. The toolbar and inspector are left uncovered.
+    fn zoom_to_fit(&mut self, ctx: &Context, selection_only: bool) {
+        let indices: Vec<usize> = if selection_only { self.selected_shape_indices() } else { (0..self.diagram.shapes.len()).collect() };
+        let Some((min, max)) = geometry::shapes_bounds(indices.iter().map(|&i| self.shape_bounds(&self.diagram.shapes[i]))) else {
+            log::debug!(target: logging::INPUT, "Nothing to fit{}.", if selection_only { ": no shape selected" } else { "" });
+            return;
+        };
+        let screen_size = self.logical_screen_size(ctx);
+        let viewport = Rect::new(0.0, tools::TOOLBAR_HEIGHT, self.canvas_right_edge(screen_size), screen_size.y - tools::TOOLBAR_HEIGHT);
+        let mut target = self.camera;
+        target.fit_to(min, max, viewport, ZOOM_TO_FIT_MARGIN);
+        self.animate_camera(target, screen_size, Tween::new(CAMERA_MOVE_DURATION, Easing::EaseOut));
+    }
+
+    fn toggle_inspector(&mut self) {
+        self.commit_inspector_edit();
+        self.inspector.visible = !self.inspector.visible;
+        log::info!(target: logging::RENDER, "Inspector {}.", if self.inspector.visible { "shown" } else { "hidden" });
+    }
+
+    // Writes a text field's contents back to its shape or connection
+    fn commit_inspector_edit(&mut self) {
+        let Some(InspectorEdit { field, item, text }) = self.inspector.editing.take() else { return; };
+        if let (InspectorField::ShapeMetadata, InspectedItem::Shape(index)) = (field, item) {
+            if let Some(shape) = self.diagram.shapes.get_mut(index) { shape.set_metadata_from_text(&text); }
+            return;
+        }
+        let value = if text.is_empty() { None } else { Some(text) };
+        match (field, item) {
+            (InspectorField::ShapeText, InspectedItem::Shape(index)) => {
+                if let Some(shape) = self.diagram.shapes.get_mut(index) { shape.text = value; }
+            }
+            (InspectorField::ShapeSubdiagram, InspectedItem::Shape(index)) => {
+                if let Some(shape) = self.diagram.shapes.get_mut(index) { shape.subdiagram = value.map(PathBuf::from); }
+            }
+            (InspectorField::ConnectionLabel, InspectedItem::Connection(index)) => {
+                if let Some(connection) = self.diagram.connections.get_mut(index) { connection.label = value; }
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_inspector_click(&mut self, field: InspectorField, hit: WidgetHit) {
+        self.commit_inspector_edit();
+        let Some(item) = self.inspected_item() else { return; };
+        let step = match hit { WidgetHit::Decrement => -1.0, _ => 1.0 };
+        let position_step = if self.grid_enabled { self.grid_spacing } else { INSPECTOR_POSITION_STEP };
+        let default_size = self.default_shape_size();
+        match item {
+            InspectedItem::Shape(index) => {
+                let Some(shape) = self.diagram.shapes.get_mut(index) else { return; };
+                let mut size = shape.size.map_or(default_size, Vec2::from);
+                match field {
+                    InspectorField::ShapeText => {
+                        // The in-canvas editor and the inspector field would fight over the text
+                        self.commit_shape_text_edit();
+                        let text = self.diagram.shapes[index].text.clone().unwrap_or_default();
+                        self.inspector.editing = Some(InspectorEdit { field, item, text });
+                    }
+                    InspectorField::ShapeMetadata => self.inspector.editing = Some(InspectorEdit { field, item, text: shape.metadata_text() }),
+                    InspectorField::ShapeSubdiagram => {
+                        let text = shape.subdiagram.as_ref().map_or(String::new(), |path| path.display().to_string());
+                        self.inspector.editing = Some(InspectorEdit { field, item, text });
+                    }
+                    InspectorField::ShapeX => shape.center_position.x += step * position_step,
+                    InspectorField::ShapeY => shape.center_position.y += step * position_step,
+                    InspectorField::ShapeWidth | InspectorField::ShapeHeight => {
+                        let dimension = if field == InspectorField::ShapeWidth { &mut size.x } else { &mut size.y };
+                        *dimension = (*dimension + step * INSPECTOR_SIZE_STEP).max(MIN_SHAPE_SIZE);
+                        shape.size = Some(size.to_array());
+                    }
+                    InspectorField::ShapeFill(slot) => shape.fill_rgb = slot.and_then(|i| self.shape_palette.get(i).copied()),
+                    InspectorField::ShapeOutline(slot) => shape.outline_rgb = slot.and_then(|i| self.shape_palette.get(i).copied()),
+                    InspectorField::ShapeTextColor(slot) => shape.text_rgb = slot.and_then(|i| self.shape_palette.get(i).copied()),
+                    InspectorField::ShapeFontSize => {
+                        let font_size = (shape.font_size.unwrap_or(self.shape_font_size) + step * FONT_SIZE_STEP).clamp(MIN_SHAPE_FONT_SIZE, MAX_SHAPE_FONT_SIZE);
+                        shape.font_size = (font_size != self.shape_font_size).then_some(font_size);
+                    }
+                    _ => {}
+                }
+            }
+            InspectedItem::Connection(index) => {
+                let Some(connection) = self.diagram.connections.get(index) else { return; };
+                // Steps through the ports of the shape at that end
+                let cycle_port = |shape_index: usize, port: PortId| {
+                    let port_count = self.diagram.shapes.get(shape_index).map_or(0, |s| s.ports.len());
+                    if port_count == 0 { return port; }
+                    if step < 0.0 { (port + port_count - 1) % port_count } else { (port + 1) % port_count }
+                };
+                let mut updated = connection.clone();
+                match field {
+                    InspectorField::ConnectionFromPort => updated.from_port = cycle_port(connection.from_shape_index, connection.from_port),
+                    InspectorField::ConnectionToPort => updated.to_port = cycle_port(connection.to_shape_index, connection.to_port),
+                    InspectorField::ConnectionLabel => {
+                        let text = connection.label.clone().unwrap_or_default();
+                        self.inspector.editing = Some(InspectorEdit { field, item, text });
+                    }
+                    InspectorField::ConnectionRouting => updated.smart_routed = !updated.smart_routed,
+                    InspectorField::ConnectionColor(slot) => updated.color_rgb = slot.and_then(|i| self.shape_palette.get(i).copied()),
+                    InspectorField::ConnectionWidth => {
+                        let width = connection.line_width.unwrap_or(CONNECTOR_LINE_WIDTH) + step * INSPECTOR_LINE_WIDTH_STEP;
+                        let width = width.clamp(MIN_CONNECTOR_LINE_WIDTH, MAX_CONNECTOR_LINE_WIDTH);
+                        updated.line_width = (width != CONNECTOR_LINE_WIDTH).then_some(width);
+                    }
+                    InspectorField::ConnectionDash => updated.dash = connection.dash.next(),
+                    _ => {}
+                }
+                let reattached = (updated.from_port, updated.to_port) != (connection.from_port, connection.to_port);
+                if reattached {
+                    if let Err(e) = self.connection_validator.check(&self.diagram, &updated, Some(index)) {
+                        log::warn!(target: logging::MODEL, "Port change refused: {}.", e);
+                        return;
+                    }
+                }
+                self.diagram.connections[index] = updated;
+            }
+        }
+    }
+
+    fn toggle_grid(&mut self) {
+        self.grid_enabled = !self.grid_enabled;
+        log::info!(target: logging::RENDER, "Grid {}.", if self.grid_enabled { "enabled" } else { "disabled" });
+    }
+
+    // Switches to the next of theme::available_themes, keeping the colors config.toml sets
+    fn cycle_theme(&mut self) {
+        let names = theme::available_themes();
+        let current = names.iter().position(|name| name.eq_ignore_ascii_case(&self.theme.name));
+        let next = &names[current.map_or(0, |index| (index + 1) % names.len())];
+        match Theme::load(next) {
+            Ok(theme) => {
+                self.theme = theme.with_all_overrides(&self.theme_overrides);
+                notify_io(format!("Theme: {}", self.theme.name));
+            }
+            Err(e) => log::error!(target: logging::IO, "{}", e),
+        }
+    }
+
+    // Grid lines covering the world-space rect currently on screen
+    fn draw_grid(&self, ctx: &mut Context, canvas: &mut graphics::Canvas, visible: Rect) -> GameResult {
+        if self.grid_spacing * self.camera.zoom < GRID_MIN_SCREEN_SPACING { return Ok(()); }
+        let line_width = 1.0 / self.camera.zoom;
+        let mut grid_builder = graphics::MeshBuilder::new();
+        let mut x = (visible.x / self.grid_spacing).floor() * self.grid_spacing;
+        while x <= visible.right() {
+            grid_builder.line(&[Vec2::new(x, visible.top()), Vec2::new(x, visible.bottom())], line_width, self.theme.grid)?;
+            x += self.grid_spacing;
+        }
+        let mut y = (visible.y / self.grid_spacing).floor() * self.grid_spacing;
+        while y <= visible.bottom() {
+            grid_builder.line(&[Vec2::new(visible.left(), y), Vec2::new(visible.right(), y)], line_width, self.theme.grid)?;
+            y += self.grid_spacing;
+        }
+        let grid_mesh = Mesh::from_data(ctx, grid_builder.build());
+        canvas.draw(&grid_mesh, graphics::DrawParam::default());
+        Ok(())
+    }
+
+    // --- Smart connector routing ---
+    fn shape_rect(&self, shape_data: &ShapeData) -> Rect {
+        shape_data.rect(self.default_shape_size())
+    }
+
+    // Box around the shape as drawn, rotation included
+    fn shape_bounds(&self, shape_data: &ShapeData) -> Rect {
+        shape_data.bounds(self.default_shape_size())
+    }
+
+    fn default_shape_size(&self) -> Vec2 {
+        Vec2::new(self.default_shape_width, self.default_shape_height)
+    }
+
+    // Changes whenever any shape moves, appears or disappears
+    fn obstacles_signature(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.diagram.shapes.len().hash(&mut hasher);
+        for shape in &self.diagram.shapes {
+            let rect = self.shape_rect(shape);
+            for value in [rect.x, rect.y, rect.w, rect.h, shape.rotation] { value.to_bits().hash(&mut hasher); }
+            (shape.group, shape.layer).hash(&mut hasher);
+        }
+        for group in &self.diagram.groups {
+            (group.parent, group.collapsed).hash(&mut hasher);
+        }
+        for layer in &self.diagram.layers {
+            layer.visible.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    // Recomputes routes only for smart-routed connections whose endpoints or obstacles changed
+    fn refresh_connector_routes(&mut self) {
+        self.parallel_offsets = diagram::parallel_offsets(&self.diagram.connections);
+        self.connection_bundles = self.diagram.bundle_sizes();
+        let signature = self.obstacles_signature();
+        // Collapsed groups stand in for the shapes hidden in them
+        let default_size = self.default_shape_size();
+        let obstacles: Vec<Rect> = self.diagram.shapes.iter().enumerate()
+            .filter(|&(index, _)| !self.shape_hidden(index))
+            .map(|(_, s)| self.shape_bounds(s))
+            .chain((0..self.diagram.groups.len())
+                .filter(|&g| self.diagram.groups[g].collapsed && !self.diagram.group_hidden(g))
+                .filter_map(|g| self.diagram.group_frame(g, default_size)))
+            .collect();
+        let moving = self.moving_shapes();
+        let mut routed_keys = HashSet::new();
+        let routed: Vec<usize> = (0..self.diagram.connections.len())
+            .filter(|&conn_idx| self.diagram.connections[conn_idx].smart_routed && !self.connector_hidden(conn_idx))
+            .collect();
+        for conn_idx in routed {
+            let key = route_key(&self.diagram.connections[conn_idx]);
+            let (Some(start), Some(end)) = (self.get_port_point(key.0, key.1), self.get_port_point(key.2, key.3)) else { continue; };
+            let (Some(start_side), Some(end_side)) = (self.get_port_side(key.0, key.1), self.get_port_side(key.2, key.3)) else { continue; };
+            routed_keys.insert(key);
+            if let Some(cached) = self.connector_routes.get(&key) {
+                if cached.start == start && cached.end == end && cached.obstacles_signature == signature { continue; }
+                // Mid-drag, routes the dragged shapes don't touch keep going around its old place until it is dropped
+                if cached.start == start && cached.end == end && moving.as_ref().is_some_and(|moving| {
+                    !moving.get(key.0).copied().unwrap_or(false) && !moving.get(key.2).copied().unwrap_or(false)
+                }) { continue; }
+            }
+            let points = routing::route_between_ports(start, start_side, end, end_side, &obstacles);
+            self.connector_routes.insert(key, CachedRoute { start, end, obstacles_signature: signature, points });
+        }
+        self.connector_routes.retain(|key, _| routed_keys.contains(key));
+    }
+
+    // Hidden by the diagram (layers, collapsed groups) or folded into another connector of its bundle
+    fn connector_hidden(&self, conn_idx: usize) -> bool {
+        self.diagram.connections.get(conn_idx).is_none_or(|connection| self.diagram.connection_hidden(connection))
+            || self.connection_bundles.get(conn_idx) == Some(&0)
+    }
+
+    // Area a connector's line can cover, widened by the largest pick radius
+    fn connector_bounds(&self, conn_idx: usize) -> Option<Rect> {
+        let connection = self.diagram.connections.get(conn_idx)?;
+        if self.connector_hidden(conn_idx) { return None; }
+        let points: Vec<Vec2> = match self.connector_route(connection) {
+            Some(route) => route.to_vec(),
+            None => self.connection_curve(conn_idx)?.to_vec(), // A bezier stays inside its control points
+        };
+        let min = points.iter().fold(Vec2::splat(f32::INFINITY), |acc, p| acc.min(*p)) - Vec2::splat(MAX_CONNECTOR_PICK_RADIUS);
+        let max = points.iter().fold(Vec2::splat(f32::NEG_INFINITY), |acc, p| acc.max(*p)) + Vec2::splat(MAX_CONNECTOR_PICK_RADIUS);
+        Some(Rect::new(min.x, min.y, max.x - min.x, max.y - min.y))
+    }
+
+    // Refiles shapes and connectors in the hit-testing grids if anything moved, appeared, disappeared or was rerouted
+    fn refresh_spatial_index(&mut self) {
+        let mut hasher = DefaultHasher::new();
+        self.obstacles_signature().hash(&mut hasher);
+        for connection in &self.diagram.connections {
+            (connection.from_shape_index, connection.from_port, connection.to_shape_index, connection.to_port).hash(&mut hasher);
+            self.connector_route(connection).map(|route| route.len()).hash(&mut hasher);
+        }
+        let signature = hasher.finish();
+        if self.spatial_signature == Some(signature) { return; }
+        self.spatial_signature = Some(signature);
+
+        self.shape_grid.clear();
+        for (index, shape) in self.diagram.shapes.iter().enumerate() {
+            if self.shape_hidden(index) { continue; }
+            // Ports sit on the outline, so the box is widened to take in hover distance around them
+            let bounds = self.shape_bounds(shape);
+            let margin = PORT_HOVER_DETECT_DISTANCE.max(PORT_CLICK_RADIUS);
+            self.shape_grid.insert(index, Rect::new(bounds.x - margin, bounds.y - margin, bounds.w + margin * 2.0, bounds.h + margin * 2.0));
+        }
+        self.connector_grid.clear();
+        for index in 0..self.diagram.connections.len() {
+            if let Some(bounds) = self.connector_bounds(index) { self.connector_grid.insert(index, bounds); }
+        }
+    }
+
+    fn connector_route(&self, connection: &Connection) -> Option<&[Vec2]> {
+        if !connection.smart_routed { return None; }
+        self.connector_routes.get(&route_key(connection))?.points.as_deref()
+    }
+
+    fn toggle_selected_connector_routing(&mut self) {
+        if let Some(connection) = self.selected_connector_index.and_then(|i| self.diagram.connections.get_mut(i)) {
+            connection.smart_routed = !connection.smart_routed;
+            log::info!(target: logging::MODEL, "Smart routing {} for selected connector.", if connection.smart_routed { "enabled" } else { "disabled" });
+        }
+    }
+
+    // --- Rendering of the diagram content (world space) ---
+    // `interactive` adds selection, hover and editing feedback; exports render without it.
+    fn connector_path(&self, conn_idx: usize) -> Option<ConnectorPath> {
+        let connection = self.diagram.connections.get(conn_idx)?;
+        if self.connector_hidden(conn_idx) { return None; }
+        match self.connector_route(connection) {
+            Some(route) => Some(ConnectorPath::Route(route.to_vec())),
+            None => self.connection_curve(conn_idx).map(ConnectorPath::Curve),
+        }
+    }
+
+    // The path a connector is drawn along: its connector_path, hopping over earlier connectors when bridges are on
+    fn drawn_connector_path(&self, conn_idx: usize) -> Option<ConnectorPath> {
+        let path = self.connector_path(conn_idx)?;
+        let crossings: Vec<f32> = self.connector_crossings.get(conn_idx)
+            .filter(|_| self.bridges_enabled)
+            .map_or(Vec::new(), |crossings| crossings.iter().map(|&(_, distance)| distance).collect());
+        if crossings.is_empty() { return Some(path); }
+        Some(ConnectorPath::Route(geometry::bridge_polyline(&path.points(), &crossings, BRIDGE_RADIUS, BRIDGE_ARC_SEGMENTS)))
+    }
+
+    // Finds where connectors cross. Only pairs involving a connector whose path changed are checked
+    // again, and while a shape is dragged only the connectors attached to it are looked at.
+    fn refresh_connector_crossings(&mut self) {
+        if !self.bridges_enabled { return; }
+        let count = self.diagram.connections.len();
+        let moving = self.moving_shapes();
+        let full = self.crossing_paths.len() != count;
+        if full {
+            self.crossing_paths = vec![None; count];
+            self.connector_crossings = vec![Vec::new(); count];
+        }
+        let mut changed = vec![full; count];
+        for (conn_idx, changed) in changed.iter_mut().enumerate() {
+            if !full && moving.as_ref().is_some_and(|moving| !self.connection_moving(conn_idx, moving)) { continue; }
+            let path = self.connector_path(conn_idx).map(|path| path.points());
+            if full || path != self.crossing_paths[conn_idx] {
+                *changed = true;
+                self.crossing_paths[conn_idx] = path;
+            }
+        }
+        if !changed.contains(&true) { return; }
+        // Each connector hops over the ones drawn before it
+        let paths = &self.crossing_paths;
+        for index in 0..count {
+            let crossings = &mut self.connector_crossings[index];
+            let Some(path) = &paths[index] else { crossings.clear(); continue; };
+            if changed[index] { crossings.clear(); } else { crossings.retain(|&(earlier, _)| !changed[earlier]); }
+            for earlier in (0..index).filter(|&earlier| changed[index] || changed[earlier]) {
+                let Some(earlier_path) = &paths[earlier] else { continue; };
+                crossings.extend(geometry::polyline_crossings(path, earlier_path).into_iter().map(|distance| (earlier, distance)));
+            }
+        }
+    }
+
+    // While a shape or group is being dragged, which shapes move with it; None otherwise.
+    // Connectors between shapes that stay put can't change during the drag, so refreshes skip them.
+    fn moving_shapes(&self) -> Option<Vec<bool>> {
+        if let Some(index) = self.dragged_shape_index {
+            return Some((0..self.diagram.shapes.len()).map(|i| i == index).collect());
+        }
+        let (group, _) = self.dragged_group?;
+        Some((0..self.diagram.shapes.len()).map(|i| self.diagram.shape_in_group(i, group)).collect())
+    }
+
+    fn connection_moving(&self, conn_idx: usize, moving: &[bool]) -> bool {
+        let Some(connection) = self.diagram.connections.get(conn_idx) else { return false; };
+        [connection.from_shape_index, connection.to_shape_index].iter().any(|&shape| moving.get(shape).copied().unwrap_or(false))
+    }
+
+    // Everything a connector's drawn line depends on apart from its stroke: where and which way its ends
+    // face, how far it bows, its route and where it hops over others. None while it is hidden.
+    fn connector_mesh_key(&self, conn_idx: usize) -> Option<u64> {
+        let connection = self.diagram.connections.get(conn_idx)?;
+        if self.connector_hidden(conn_idx) { return None; }
+        let mut hasher = DefaultHasher::new();
+        for (shape_index, port_id) in [(connection.from_shape_index, connection.from_port), (connection.to_shape_index, connection.to_port)] {
+            let (point, side) = self.diagram.displayed_port_anchor(shape_index, port_id, self.default_shape_size())?;
+            (point.x.to_bits(), point.y.to_bits(), side).hash(&mut hasher);
+        }
+        let bow = self.parallel_offsets.get(conn_idx).copied().unwrap_or(0.0) + connection.bend.unwrap_or(0.0);
+        bow.to_bits().hash(&mut hasher);
+        for point in self.connector_route(connection).unwrap_or_default() {
+            (point.x.to_bits(), point.y.to_bits()).hash(&mut hasher);
+        }
+        if self.bridges_enabled {
+            for &(_, distance) in self.connector_crossings.get(conn_idx).map_or(&[][..], Vec::as_slice) {
+                distance.to_bits().hash(&mut hasher);
+            }
+        }
+        Some(hasher.finish())
+    }
+
+    // The selection color wins over a connection's own color while editing
+    fn connector_stroke(&self, conn_idx: usize, interactive: bool) -> ConnectorStroke {
+        let connection = &self.diagram.connections[conn_idx];
+        let mut color = if interactive && self.selected_connector_index == Some(conn_idx) {
+            self.theme.selected_connector
+        } else {
+            connection.color_rgb.map_or(self.theme.connector, |[r, g, b]| Color::from_rgb(r, g, b))
+        };
+        let on_critical_path = self.show_critical_path && self.graph_analysis.as_ref().is_some_and(|a| a.critical_connections.contains(&conn_idx));
+        if interactive && on_critical_path { color = CRITICAL_PATH_COLOR; }
+        if interactive && self.cycle_connections.contains(&conn_idx) { color = self.theme.refused; }
+        if interactive && self.connection_dimmed(connection) { color.a *= DIMMED_ALPHA; }
+        ConnectorStroke { color, width: connection.line_width.unwrap_or(CONNECTOR_LINE_WIDTH), dash: connection.dash }
+    }
+
+    // Re-tessellates only the connectors whose key or stroke changed since the last frame, e.g. the
+    // ones attached to a shape being dragged
+    fn refresh_connector_meshes(&mut self, ctx: &Context) {
+        self.connector_meshes.resize(self.diagram.connections.len());
+        for conn_idx in 0..self.diagram.connections.len() {
+            let key = self.connector_mesh_key(conn_idx);
+            let stroke = self.connector_stroke(conn_idx, true);
+            if key.is_some_and(|key| self.connector_meshes.get(conn_idx, key, stroke).is_some()) {
+                self.frame_stats.connector_hits += 1;
+                continue;
+            }
+            match key.zip(self.drawn_connector_path(conn_idx)) {
+                Some((key, path)) => {
+                    // Timed in two parts for the debug overlay, so this doesn't go through tessellate_connector
+                    let started = Instant::now();
+                    let geometry = tessellation::stroke_geometry(&path, stroke);
+                    let tessellated = Instant::now();
+                    let mesh = geometry.map(|geometry| upload_geometry(ctx, &geometry));
+                    self.frame_stats.tessellation += tessellated - started;
+                    self.frame_stats.upload += tessellated.elapsed();
+                    self.frame_stats.connector_misses += 1;
+                    self.connector_meshes.insert(conn_idx, key, stroke, mesh);
+                }
+                None => self.connector_meshes.forget(conn_idx),
+            }
+        }
+    }
+
+    // --- Annotation layer: pen and highlighter strokes drawn over everything else ---
+    fn annotation_stroke(annotation: &Annotation) -> ConnectorStroke {
+        let [r, g, b] = annotation.color_rgb;
+        let alpha = if annotation.highlight { (HIGHLIGHT_ALPHA * 255.0) as u8 } else { 255 };
+        ConnectorStroke { color: Color::from_rgba(r, g, b, alpha), width: annotation.width, dash: LineDash::Solid }
+    }
+
+    fn annotation_mesh_key(annotation: &Annotation) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for point in &annotation.points { (point.x.to_bits(), point.y.to_bits()).hash(&mut hasher); }
+        hasher.finish()
+    }
+
+    fn refresh_annotation_meshes(&mut self, ctx: &Context) {
+        self.annotation_meshes.resize(self.diagram.annotations.len());
+        for (index, annotation) in self.diagram.annotations.iter().enumerate() {
+            let (key, stroke) = (Self::annotation_mesh_key(annotation), Self::annotation_stroke(annotation));
+            if self.annotation_meshes.get(index, key, stroke).is_some() { continue; }
+            let mesh = tessellation::freehand_geometry(&annotation.points, stroke.width, stroke.color).map(|geometry| upload_geometry(ctx, &geometry));
+            self.annotation_meshes.insert(index, key, stroke, mesh);
+        }
+    }
+
+    // Includes the stroke being drawn, which changes every frame and isn't cached
+    fn draw_annotations(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) {
+        if !self.annotations_visible { return; }
+        for (index, annotation) in self.diagram.annotations.iter().chain(self.pen_stroke.as_ref()).enumerate() {
+            let (key, stroke) = (Self::annotation_mesh_key(annotation), Self::annotation_stroke(annotation));
+            match self.annotation_meshes.get(index, key, stroke) {
+                Some(cached) => {
+                    if let Some(mesh) = cached { canvas.draw(mesh, graphics::DrawParam::default()); }
+                }
+                None => {
+                    if let Some(geometry) = tessellation::freehand_geometry(&annotation.points, stroke.width, stroke.color) {
+                        canvas.draw(&upload_geometry(ctx, &geometry), graphics::DrawParam::default());
+                    }
+                }
+            }
+        }
+    }
+
+    fn draw_connectors(&self, ctx: &mut Context, canvas: &mut graphics::Canvas, interactive: bool) {
+        for (conn_idx, connection) in self.diagram.connections.iter().enumerate() {
+            let Some(key) = self.connector_mesh_key(conn_idx) else { continue; };
+            let stroke = self.connector_stroke(conn_idx, interactive);
+            // Falls back to tessellating when the cache is stale, e.g. for PNG export which draws unselected colors
+            match self.connector_meshes.get(conn_idx, key, stroke) {
+                Some(cached) => {
+                    if let Some(line_mesh) = cached { canvas.draw(line_mesh, graphics::DrawParam::default()); }
+                }
+                None => {
+                    let Some(path) = self.drawn_connector_path(conn_idx) else { continue; };
+                    if let Some(line_mesh) = tessellate_connector(ctx, &path, stroke) { canvas.draw(&line_mesh, graphics::DrawParam::default()); }
+                }
+            }
+
+            // A bundle shows how many connections it stands for in place of a label
+            let bundle_size = self.connection_bundles.get(conn_idx).copied().unwrap_or(1);
+            if let Some(anchor) = self.connection_label_anchor(conn_idx).filter(|_| bundle_size > 1) {
+                if let Ok((circle, scale)) = self.mesh_cache.port_circle(ctx, BUNDLE_BADGE_RADIUS) {
+                    canvas.draw(&circle, graphics::DrawParam::default().dest(anchor).scale(Vec2::splat(scale)).color(stroke.color));
+                }
+                let mut count = Text::new(bundle_size.to_string());
+                count.set_layout(TextLayout::center());
+                count.set_scale(ORDER_BADGE_FONT_SIZE);
+                canvas.draw(&count, graphics::DrawParam::default().dest(anchor).color(self.theme.background));
+            } else if let (Some(label), Some(anchor)) = (connection.label.as_deref().filter(|l| !l.is_empty()), self.connection_label_anchor(conn_idx)) {
+                let mut label_text = self.label_text(label);
+                label_text.set_layout(TextLayout::center());
+                label_text.set_scale(CONNECTOR_LABEL_FONT_SIZE);
+                // Backdrop so the label stays readable on top of the line
+                if let Some(dimensions) = label_text.dimensions(ctx) {
+                    let backdrop = Rect::new(anchor.x - dimensions.w / 2.0 - 3.0, anchor.y - dimensions.h / 2.0 - 1.0, dimensions.w + 6.0, dimensions.h + 2.0);
+                    if let Ok(backdrop_mesh) = Mesh::new_rectangle(ctx, DrawMode::fill(), backdrop, self.theme.background) {
+                        canvas.draw(&backdrop_mesh, graphics::DrawParam::default());
+                    }
+                }
+                canvas.draw(&label_text, graphics::DrawParam::default().dest(anchor).color(stroke.color));
+            }
+        }
+    }
+
+    // Lanes beneath everything, with horizontal lanes' titles turned to run up their title strip
+    fn draw_lanes(&self, ctx: &mut Context, canvas: &mut graphics::Canvas, interactive: bool) -> GameResult {
+        for (index, lane) in self.diagram.lanes.iter().enumerate() {
+            let rect = lane.rect();
+            let title_rect = lane.title_rect();
+            let fill = Mesh::new_rectangle(ctx, DrawMode::fill(), rect, self.theme.frame_fill)?;
+            canvas.draw(&fill, graphics::DrawParam::default());
+            // Washed over the lane's own fill, so the title strip reads a shade stronger
+            let title_fill = Mesh::new_rectangle(ctx, DrawMode::fill(), title_rect, self.theme.frame_fill)?;
+            canvas.draw(&title_fill, graphics::DrawParam::default());
+            let outline_color = if interactive && self.selected_lane == Some(index) { self.theme.selection } else { self.theme.frame };
+            let outline = Mesh::new_rectangle(ctx, DrawMode::stroke(GROUP_FRAME_OUTLINE_WIDTH), rect, outline_color)?;
+            canvas.draw(&outline, graphics::DrawParam::default());
+            let mut title = self.label_text(&lane.title);
+            title.set_layout(TextLayout::center());
+            title.set_scale(LANE_TITLE_FONT_SIZE);
+            let rotation = if lane.orientation == LaneOrientation::Horizontal { -std::f32::consts::FRAC_PI_2 } else { 0.0 };
+            let center = Vec2::new(title_rect.x + title_rect.w / 2.0, title_rect.y + title_rect.h / 2.0);
+            canvas.draw(&title, graphics::DrawParam::default().dest(center).rotation(rotation).color(self.theme.frame_text));
+        }
+        Ok(())
+    }
+
+    // Group frames behind everything else, outermost first. A collapsed group is a solid box with its name.
+    fn draw_groups(&self, ctx: &mut Context, canvas: &mut graphics::Canvas, interactive: bool) -> GameResult {
+        let mut visible_groups: Vec<usize> = (0..self.diagram.groups.len()).filter(|&g| !self.diagram.group_hidden(g)).collect();
+        visible_groups.sort_by_key(|&g| self.diagram.group_depth(g));
+        for group_index in visible_groups {
+            let Some(frame) = self.diagram.group_frame(group_index, self.default_shape_size()) else { continue; };
+            let group = &self.diagram.groups[group_index];
+            let (fill_color, text_pos, layout) = if group.collapsed {
+                (self.theme.collapsed_group, Vec2::new(frame.x + frame.w / 2.0, frame.y + frame.h / 2.0), TextLayout::center())
+            } else {
+                (self.theme.frame_fill, Vec2::new(frame.x + 6.0, frame.y + GROUP_TITLE_HEIGHT / 2.0), TextLayout { h_align: graphics::TextAlign::Begin, v_align: graphics::TextAlign::Middle })
+            };
+            let outline_color = if interactive && self.selected_group == Some(group_index) { self.theme.selection } else { self.theme.frame };
+            let fill = Mesh::new_rounded_rectangle(ctx, DrawMode::fill(), frame, 6.0, fill_color)?;
+            canvas.draw(&fill, graphics::DrawParam::default());
+            let outline = Mesh::new_rounded_rectangle(ctx, DrawMode::stroke(GROUP_FRAME_OUTLINE_WIDTH), frame, 6.0, outline_color)?;
+            canvas.draw(&outline, graphics::DrawParam::default());
+            let mut title = self.label_text(&group.name);
+            title.set_layout(layout);
+            title.set_scale(GROUP_TITLE_FONT_SIZE);
+            canvas.draw(&title, graphics::DrawParam::default().dest(text_pos).color(self.theme.frame_text));
+        }
+        Ok(())
+    }
+
+    // Where a connection's label is drawn: halfway along its route or curve
+    fn connection_label_anchor(&self, conn_idx: usize) -> Option<Vec2> {
+        match self.connector_route(self.diagram.connections.get(conn_idx)?) {
+            Some(route) => Some(geometry::polyline_midpoint(route)),
+            None => Some(geometry::point_on_cubic_bezier(&self.connection_curve(conn_idx)?, 0.5)),
+        }
+    }
+
+    // Meshes a shape is drawn with, bottom to top: fill, own outline, selection outline.
+    // Each comes with the top-left corner to draw it at and its color.
+    fn shape_mesh_layers(&self, index: usize, shape_data: &ShapeData, interactive: bool) -> [Option<(ShapeMeshKey, Vec2, Color)>; 3] {
+        let rect = self.shape_rect(shape_data);
+        let size = Vec2::new(rect.w, rect.h);
+        let top_left = Vec2::new(rect.x, rect.y);
+        let corner_radius = self.shape_corner_radius(shape_data);
+        let kind = shape_data.kind;
+        let fill = (ShapeMeshKey::new(kind, None, size, corner_radius), top_left, self.shape_fill_color(shape_data));
+        let outline = shape_data.outline_rgb
+            .map(|rgb| (ShapeMeshKey::new(kind, Some(SHAPE_OUTLINE_WIDTH), size, corner_radius), top_left, Color::from_rgb(rgb[0], rgb[1], rgb[2])));
+        let selection = (interactive && self.is_shape_selected(index) && self.editing_shape_index != Some(index)).then(|| {
+            let outline_size = size * 1.05;
+            let outline_top_left = top_left + (size - outline_size) / 2.0;
+            (ShapeMeshKey::new(kind, Some(self.selection_outline_width), outline_size, corner_radius * 1.05), outline_top_left, self.theme.selection)
+        });
+        [Some(fill), outline, selection]
+    }
+
+    fn shape_mesh_keys(&self) -> Vec<ShapeMeshKey> {
+        self.diagram.shapes.iter().enumerate()
+            .filter(|&(index, _)| !self.shape_hidden(index))
+            .flat_map(|(index, shape_data)| self.shape_mesh_layers(index, shape_data, true))
+            .flatten()
+            .map(|(key, _, _)| key)
+            .collect()
+    }
+
+    // Makes sure every shape mesh this frame needs is cached, keeping those of parked tabs too
+    fn prepare_shape_meshes(&mut self, ctx: &mut Context) -> GameResult {
+        let parked_keys = self.tabs.iter().enumerate().filter(|&(index, _)| index != self.active_tab).flat_map(|(_, tab)| tab.shape_mesh_keys.iter().copied());
+        let keys: Vec<ShapeMeshKey> = self.shape_mesh_keys().into_iter().chain(parked_keys).collect();
+        let started = Instant::now();
+        let (hits, misses) = self.mesh_cache.prepare(ctx, keys)?;
+        self.frame_stats.upload += started.elapsed();
+        self.frame_stats.shape_mesh_hits += hits; self.frame_stats.shape_mesh_misses += misses;
+        Ok(())
+    }
+
+    fn draw_shapes(&self, ctx: &mut Context, canvas: &mut graphics::Canvas, interactive: bool) -> GameResult {
+        let hovered_port = if interactive { self.port_within(self.live_mouse_pos, PORT_HOVER_DETECT_DISTANCE, None) } else { None };
+        for (index, shape_data) in self.diagram.shapes.iter().enumerate() {
+            if self.shape_hidden(index) { continue; }
+            let rect = self.shape_rect(shape_data);
+            let alpha = if interactive && self.shape_dimmed(index) { DIMMED_ALPHA } else { 1.0 };
+            let faded = |mut color: Color| { color.a *= alpha; color };
+            for (key, top_left, color) in self.shape_mesh_layers(index, shape_data, interactive).into_iter().flatten() {
+                let mesh = self.mesh_cache.shape(ctx, key)?;
+                let param = graphics::DrawParam::default().dest(shape_data.to_world(top_left)).rotation(shape_data.rotation);
+                canvas.draw(&mesh, param.color(faded(color)));
+            }
+
+            // Determine port colors and radii
+            let selected_conn = if interactive { self.selected_connector_index.and_then(|i| self.diagram.connections.get(i)) } else { None };
+            for (port_id, port) in shape_data.ports.iter().enumerate() {
+                let Some(port_point) = self.get_port_point(index, port_id) else { continue; };
+                let mut port_color = if port.port_type.is_empty() { self.theme.port } else { port_type_color(&port.port_type) };
+                let mut port_radius = PORT_DRAW_RADIUS_DEFAULT;
+                // While a line is being drawn, ports it may end on are enlarged and the rest marked as refused
+                if let Some((start_shape_idx, start_port)) = self.new_line_start_info.filter(|&(start, _)| interactive && self.drawing_new_line && start != index) {
+                    let candidate = Connection::new(start_shape_idx, start_port, index, port_id);
+                    match self.connection_validator.check(&self.diagram, &candidate, None) {
+                        Ok(()) => { port_color = self.theme.active_port; port_radius = PORT_DRAW_RADIUS_HOVER; }
+                        Err(_) => port_color = self.theme.refused,
+                    }
+                }
+                if let Some(conn) = selected_conn {
+                    if (conn.from_shape_index == index && conn.from_port == port_id) || (conn.to_shape_index == index && conn.to_port == port_id) {
+                        port_color = self.theme.selected_port;
+                    }
+                }
+                if interactive && self.new_line_start_info == Some((index, port_id)) {
+                    port_color = self.theme.active_port;
+                }
+                // Check for hover on the port
+                if hovered_port == Some((index, port_id)) {
+                    port_radius = PORT_DRAW_RADIUS_HOVER;
+                }
+                let (port_mesh, scale) = self.mesh_cache.port_circle(ctx, port_radius)?;
+                canvas.draw(&port_mesh, graphics::DrawParam::default().dest(port_point).scale(Vec2::splat(scale)).color(faded(port_color)));
+            }
+
+            let is_editing = interactive && self.editing_shape_index == Some(index);
+            let text_to_display = if is_editing { self.text_edit.text.as_str() } else { shape_data.text.as_deref().unwrap_or("") };
+
+            if is_editing {
+                self.draw_text_selection(ctx, canvas, shape_data)?;
+            }
+            if !text_to_display.is_empty() {
+                let text_obj = self.shape_text(shape_data, text_to_display, rect);
+                let param = graphics::DrawParam::default().dest(shape_data.to_world(self.shape_text_anchor(shape_data))).rotation(shape_data.rotation);
+                canvas.draw(&text_obj, param.color(faded(self.shape_text_color(shape_data))));
+            }
+            if is_editing {
+                self.draw_ime_preedit(ctx, canvas, shape_data)?;
+            }
+
+            // Two stacked frames in the bottom-right corner mark a shape that opens a sub-diagram
+            if shape_data.subdiagram.is_some() {
+                let corner = Vec2::new(rect.right(), rect.bottom()) - Vec2::splat(SUBDIAGRAM_BADGE_INSET + SUBDIAGRAM_BADGE_SIZE);
+                let turned = graphics::DrawParam::default().dest(shape_data.to_world(corner)).rotation(shape_data.rotation).color(faded(self.shape_text_color(shape_data)));
+                let offset = SUBDIAGRAM_BADGE_SIZE / 3.0;
+                for frame in [Rect::new(offset, 0.0, SUBDIAGRAM_BADGE_SIZE - offset, SUBDIAGRAM_BADGE_SIZE - offset), Rect::new(0.0, offset, SUBDIAGRAM_BADGE_SIZE - offset, SUBDIAGRAM_BADGE_SIZE - offset)] {
+                    let frame_mesh = Mesh::new_rectangle(ctx, DrawMode::stroke(1.5), frame, Color::WHITE)?;
+                    canvas.draw(&frame_mesh, turned);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // --- Focus: shapes outside the focus filter are drawn faded ---
+    fn shape_dimmed(&self, index: usize) -> bool {
+        self.focused_shapes.as_ref().is_some_and(|focused| !focused.get(index).copied().unwrap_or(true))
+    }
+
+    // Faded unless both of its ends are in focus
+    fn connection_dimmed(&self, connection: &Connection) -> bool {
+        self.shape_dimmed(connection.from_shape_index) || self.shape_dimmed(connection.to_shape_index)
+    }
+
+    // --- DAG mode: cycles that got in anyway, e.g. from an opened or pasted diagram ---
+    fn refresh_cycle_connections(&mut self) {
+        if !self.dag_mode { return; }
+        let signature = self.graph_signature();
+        if self.cycle_signature == Some(signature) { return; }
+        self.cycle_signature = Some(signature);
+        let had_cycles = !self.cycle_connections.is_empty();
+        self.cycle_connections = self.diagram.cycle_connections();
+        if !had_cycles && !self.cycle_connections.is_empty() {
+            log::warn!(target: logging::MODEL, "The diagram has cycles although DAG mode is on ({} connections).", self.cycle_connections.len());
+        }
+    }
+
+    // "Cycle: A, B, C", naming the shapes on the flagged connections
+    fn cycle_alert(&self) -> Option<String> {
+        let mut shapes: Vec<usize> = self.cycle_connections.iter()
+            .filter_map(|&i| self.diagram.connections.get(i))
+            .flat_map(|c| [c.from_shape_index, c.to_shape_index])
+            .collect();
+        if shapes.is_empty() { return None; }
+        shapes.sort_unstable();
+        shapes.dedup();
+        let names: Vec<String> = shapes.iter().map(|&index| self.shape_name(index)).collect();
+        Some(format!("Cycle in DAG mode: {}", names.join(", ")))
+    }
+
+    // Changes whenever a shape or connection is added or removed, or a connection changes ends
+    fn graph_signature(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.diagram.shapes.len().hash(&mut hasher);
+        for connection in &self.diagram.connections { (connection.from_shape_index, connection.to_shape_index).hash(&mut hasher); }
+        hasher.finish()
+    }
+
+    // --- Graph statistics panel ---
+    // Computed from the connections when the panel is shown, and again only once the graph changes
+    fn refresh_graph_stats(&mut self) {
+        if !self.stats_panel_visible { self.graph_stats = None; return; }
+        let signature = self.graph_signature();
+        if self.graph_stats.as_ref().is_some_and(|(computed_for, _)| *computed_for == signature) { return; }
+        self.graph_stats = Some((signature, self.diagram.graph_stats()));
+    }
+
+    // Toolbar, palette, tabs, side panels and overlays; all hidden in read-only mode
+    fn draw_panels(&self, ctx: &mut Context, canvas: &mut graphics::Canvas, screen_size: Vec2) -> GameResult {
+        let (toolbar, tool_buttons) = tools::toolbar_widgets(self.tool);
+        ui::draw_panel(ctx, canvas, toolbar, &tool_buttons, &ui::UiStyle::default())?;
+        if let Some((strip, template_buttons)) = self.palette_widgets() {
+            ui::draw_panel(ctx, canvas, strip, &template_buttons, &ui::UiStyle::default())?;
+        }
+        let (tab_strip, tab_buttons) = self.tab_widgets();
+        ui::draw_panel(ctx, canvas, tab_strip, &tab_buttons, &ui::UiStyle::default())?;
+
+        if let Some((panel, widgets)) = self.inspector_widgets(screen_size) {
+            ui::draw_panel(ctx, canvas, panel, &widgets, &ui::UiStyle::default())?;
+        }
+        if let Some(panel) = self.code_panel_rect(screen_size) {
+            self.code_panel.draw(ctx, canvas, panel, &ui::UiStyle::default())?;
+        }
+        if let Some((panel, widgets)) = self.layers_widgets() {
+            ui::draw_panel(ctx, canvas, panel, &widgets, &ui::UiStyle::default())?;
+        }
+        if let Some((panel, widgets)) = self.stats_widgets(screen_size) {
+            ui::draw_panel(ctx, canvas, panel, &widgets, &ui::UiStyle::default())?;
+        }
+        if let Some((panel, widgets)) = self.debug_overlay_widgets(ctx, screen_size) {
+            ui::draw_panel(ctx, canvas, panel, &widgets, &ui::UiStyle::default())?;
+        }
+        if let Some(palette) = &self.command_palette {
+            let (panel, widgets) = palette.widgets(screen_size);
+            ui::draw_panel(ctx, canvas, panel, &widgets, &ui::UiStyle::default())?;
+        }
+        if let Some(search) = &self.shape_search {
+            let (panel, widgets) = search.widgets(screen_size, &self.diagram);
+            ui::draw_panel(ctx, canvas, panel, &widgets, &ui::UiStyle::default())?;
+        }
+        if let Some(menu) = &self.recent_menu {
+            let (panel, widgets) = menu.widgets(screen_size, &self.recent_files);
+            ui::draw_panel(ctx, canvas, panel, &widgets, &ui::UiStyle::default())?;
+        }
+        if let Some(menu) = &self.context_menu {
+            let (panel, widgets) = menu.widgets(screen_size);
+            ui::draw_panel(ctx, canvas, panel, &widgets, &ui::UiStyle::default())?;
+        }
+        if let Some((panel, widgets)) = self.simulation.as_ref().and_then(|simulation| simulation.branch_widgets(screen_size, &self.diagram)) {
+            ui::draw_panel(ctx, canvas, panel, &widgets, &ui::UiStyle::default())?;
+        }
+        Ok(())
+    }
+
+    fn debug_overlay_widgets(&self, ctx: &Context, screen_size: Vec2) -> Option<(Rect, Vec<ui::Widget<()>>)> {
+        let stats = self.debug_overlay.as_ref()?;
+        let counts = (self.diagram.shapes.len(), self.diagram.connections.len());
+        let top = self.tab_widgets().0.bottom();
+        Some(debug_overlay::overlay_widgets(stats, ctx.time.fps(), ctx.time.average_delta(), counts, self.canvas_right_edge(screen_size), top))
+    }
+
+    fn stats_widgets(&self, screen_size: Vec2) -> Option<(Rect, Vec<ui::Widget<()>>)> {
+        let (_, stats) = self.graph_stats.as_ref().filter(|_| self.stats_panel_visible)?;
+        let selected = self.selected_shape_index.map(|index| (self.shape_name(index), index));
+        Some(stats_panel::stats_widgets(stats, selected, self.toast_area(screen_size).bottom()))
+    }
+
+    // --- Analysis overlay: topological order badges and the critical path ---
+    // A shape's `duration` attribute (a number) weighs it on the critical path; others count as 1.
+    // Shape weights are read when the connections change, not on every attribute edit.
+    fn refresh_graph_analysis(&mut self) {
+        if !self.show_order_badges && !self.show_critical_path { self.graph_analysis = None; return; }
+        let signature = self.graph_signature();
+        if self.graph_analysis.as_ref().is_some_and(|analysis| analysis.signature == signature) { return; }
+        let mut order_badges = vec![None; self.diagram.shapes.len()];
+        for (position, shape) in self.diagram.topological_order().into_iter().enumerate() { order_badges[shape] = Some(position + 1); }
+        let duration = |shape: &ShapeData| shape.attributes.get("duration").and_then(|d| d.trim().parse::<f32>().ok()).unwrap_or(1.0);
+        let (critical_shapes, critical_connections) = self.diagram.critical_path(duration);
+        self.graph_analysis = Some(GraphAnalysis { signature, order_badges, critical_shapes, critical_connections });
+    }
+
+    fn draw_graph_analysis(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) -> GameResult {
+        let Some(analysis) = &self.graph_analysis else { return Ok(()); };
+        if self.show_critical_path {
+            for &index in &analysis.critical_shapes {
+                if self.shape_hidden(index) { continue; }
+                let rect = self.shape_bounds(&self.diagram.shapes[index]);
+                let halo = Rect::new(rect.x - SEARCH_MATCH_MARGIN, rect.y - SEARCH_MATCH_MARGIN, rect.w + SEARCH_MATCH_MARGIN * 2.0, rect.h + SEARCH_MATCH_MARGIN * 2.0);
+                let mesh = Mesh::new_rectangle(ctx, DrawMode::stroke(3.0 / self.camera.zoom), halo, CRITICAL_PATH_COLOR)?;
+                canvas.draw(&mesh, graphics::DrawParam::default());
+            }
+        }
+        if self.show_order_badges {
+            for (index, badge) in analysis.order_badges.iter().enumerate() {
+                let Some(position) = badge else { continue; };
+                if self.shape_hidden(index) { continue; }
+                let rect = self.shape_rect(&self.diagram.shapes[index]);
+                let center = Vec2::new(rect.x, rect.y);
+                let (circle, scale) = self.mesh_cache.port_circle(ctx, ORDER_BADGE_RADIUS)?;
+                canvas.draw(&circle, graphics::DrawParam::default().dest(center).scale(Vec2::splat(scale)).color(self.theme.frame));
+                let mut label = Text::new(position.to_string());
+                label.set_layout(TextLayout::center());
+                label.set_scale(ORDER_BADGE_FONT_SIZE);
+                canvas.draw(&label, graphics::DrawParam::default().dest(center).color(self.theme.frame_text));
+            }
+        }
+        Ok(())
+    }
+
+    // Steps the highlight around the selected shape through downstream, upstream, both and off.
+    // Selecting another shape starts over from downstream.
+    fn cycle_reach_highlight(&mut self) {
+        let Some(index) = self.selected_shape_index else {
+            self.reach_highlight = None;
+            return;
+        };
+        self.reach_highlight = match self.reach_highlight {
+            Some((highlighted, reach)) if highlighted == index => reach.next().map(|next| (index, next)),
+            _ => Some((index, Reach::Downstream)),
+        };
+        match self.reach_highlight {
+            Some((_, reach)) => log::info!(target: logging::RENDER, "Highlighting shapes {} of shape {}.", reach.name(), index),
+            None => log::info!(target: logging::RENDER, "Reach highlight off."),
+        }
+    }
+
+    // Shapes stay in focus when they match the focus filter and are reached by the highlight, if either is on
+    fn refresh_focused_shapes(&mut self) {
+        if self.reach_highlight.is_some_and(|(index, _)| index >= self.diagram.shapes.len()) { self.reach_highlight = None; }
+        let matches_filter = self.focus_filter.as_ref().map(|query| {
+            self.diagram.shapes.iter().map(|shape| find::match_score(query, shape).is_some()).collect::<Vec<bool>>()
+        });
+        let reached = self.reach_highlight.map(|(index, reach)| self.diagram.reachable(index, reach));
+        self.focused_shapes = match (matches_filter, reached) {
+            (Some(matches), Some(reached)) => Some(matches.iter().zip(&reached).map(|(&m, &r)| m && r).collect()),
+            (matches, reached) => matches.or(reached),
+        };
+    }
+
+    // Dots spaced along each connector, shifted towards its target by the flow phase
+    fn draw_flow_dots(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) -> GameResult {
+        let mut dots = graphics::MeshBuilder::new();
+        let mut any = false;
+        for conn_idx in 0..self.diagram.connections.len() {
+            let Some(path) = self.drawn_connector_path(conn_idx) else { continue; };
+            let points = path.points();
+            let stroke = self.connector_stroke(conn_idx, true);
+            let radius = FLOW_DOT_RADIUS.max(stroke.width * 1.5);
+            for dot in geometry::points_along_polyline(&points, self.flow_phase, FLOW_DOT_SPACING) {
+                dots.circle(DrawMode::fill(), dot, radius, 0.5, stroke.color)?;
+                any = true;
+            }
+        }
+        if any { canvas.draw(&Mesh::from_data(ctx, dots.build()), graphics::DrawParam::default()); }
+        Ok(())
+    }
+
+    // Soft halo around every shape matching the open search, thicker on the highlighted match
+    fn draw_search_highlights(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) -> GameResult {
+        let Some(search) = &self.shape_search else { return Ok(()); };
+        let chosen = search.chosen(&self.diagram);
+        for index in search.matches(&self.diagram) {
+            let rect = self.shape_bounds(&self.diagram.shapes[index]);
+            let halo = Rect::new(
+                rect.x - SEARCH_MATCH_MARGIN, rect.y - SEARCH_MATCH_MARGIN,
+                rect.w + SEARCH_MATCH_MARGIN * 2.0, rect.h + SEARCH_MATCH_MARGIN * 2.0,
+            );
+            let width = if chosen == Some(index) { 4.0 } else { 2.0 } / self.camera.zoom;
+            let mesh = Mesh::new_rectangle(ctx, DrawMode::stroke(width), halo, SEARCH_MATCH_COLOR)?;
+            canvas.draw(&mesh, graphics::DrawParam::default());
+        }
+        Ok(())
+    }
+
+    // --- Shape text layout and in-place editing ---
+    fn shape_font_size_of(&self, shape: &ShapeData) -> f32 {
+        shape.font_size.unwrap_or(self.shape_font_size)
+    }
+
+    fn shape_fill_color(&self, shape: &ShapeData) -> Color {
+        let default_color = if shape.kind == ShapeKind::Note { Color::from_rgb(NOTE_FILL_RGB[0], NOTE_FILL_RGB[1], NOTE_FILL_RGB[2]) } else { self.theme.shape };
+        shape.fill_rgb.map_or(default_color, |rgb| Color::from_rgb(rgb[0], rgb[1], rgb[2]))
+    }
+
+    fn shape_text_color(&self, shape: &ShapeData) -> Color {
+        let default_color = if shape.kind == ShapeKind::Note { Color::from_rgb(NOTE_TEXT_RGB[0], NOTE_TEXT_RGB[1], NOTE_TEXT_RGB[2]) } else { self.theme.text };
+        shape.text_rgb.map_or(default_color, |rgb| Color::from_rgb(rgb[0], rgb[1], rgb[2]))
+    }
+
+    // Where a shape's label is laid out from, before rotation: its center, or a note's padded top-left corner
+    fn shape_text_anchor(&self, shape: &ShapeData) -> Vec2 {
+        if shape.kind != ShapeKind::Note { return shape.center_position; }
+        let rect = self.shape_rect(shape);
+        Vec2::new(rect.x, rect.y) + Vec2::splat(NOTE_TEXT_PADDING)
+    }
+
+    // Font size of the shape being edited, which the caret is sized to
+    fn editing_font_size(&self) -> f32 {
+        self.editing_shape_index.and_then(|i| self.diagram.shapes.get(i)).map_or(self.shape_font_size, |shape| self.shape_font_size_of(shape))
+    }
+
+    // Text for a user-written label: one fragment per run of chars the same font in the fallback chain draws
+    fn label_text(&self, text: &str) -> Text {
+        let mut text_obj = Text::default();
+        for (font, run) in self.font_fallback.runs(text) {
+            let fragment = TextFragment::new(run);
+            text_obj.add(match font { Some(name) => fragment.font(name), None => fragment });
+        }
+        text_obj
+    }
+
+    // Text object for a shape's label, wrapped to the shape and centered on it (notes read from their top-left)
+    fn shape_text(&self, shape: &ShapeData, text: &str, shape_rect: Rect) -> Text {
+        let mut text_obj = self.label_text(text);
+        let padding = if shape.kind == ShapeKind::Note { NOTE_TEXT_PADDING } else { TEXT_PADDING };
+        text_obj.set_layout(if shape.kind == ShapeKind::Note { TextLayout::top_left() } else { TextLayout::center() });
+        text_obj.set_scale(self.shape_font_size_of(shape));
+        text_obj.set_bounds(Vec2::new(shape_rect.w - (padding * 2.0), f32::INFINITY));
+        text_obj
+    }
+
+    // Changes whenever a shape's text or font size (or the text being typed into one) changes
+    fn text_signature(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.diagram.shapes.len().hash(&mut hasher);
+        for shape in &self.diagram.shapes { shape.text.hash(&mut hasher); shape.font_size.map(f32::to_bits).hash(&mut hasher); }
+        self.editing_shape_index.hash(&mut hasher);
+        self.text_edit.text.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // Auto-size mode: widens shapes up to the max width, then grows their height, to fit the
+    // measured text plus padding. Shapes never shrink below the default size.
+    fn fit_shapes_to_text(&mut self, ctx: &Context) {
+        if !self.auto_size_shapes { return; }
+        let signature = self.text_signature();
+        if self.text_fit_signature == Some(signature) { return; }
+        self.text_fit_signature = Some(signature);
+
+        let default_size = self.default_shape_size();
+        let measure_rect = Rect::new(0.0, 0.0, self.auto_size_max_width, default_size.y);
+        for index in 0..self.diagram.shapes.len() {
+            if self.diagram.is_note(index) { continue; } // Notes keep their size and wrap inside it
+            let text = if self.editing_shape_index == Some(index) {
+                Some(self.text_edit.text.as_str())
+            } else {
+                self.diagram.shapes[index].text.as_deref()
+            };
+            let text_size = text.filter(|t| !t.is_empty())
+                .and_then(|t| self.shape_text(&self.diagram.shapes[index], t, measure_rect).dimensions(ctx))
+                .map_or(Vec2::ZERO, |bounds| Vec2::new(bounds.w.ceil(), bounds.h.ceil()) + Vec2::splat(TEXT_PADDING * 2.0));
+            let size = default_size.max(text_size).min(Vec2::new(self.auto_size_max_width, f32::INFINITY));
+            self.diagram.shapes[index].size = (size != default_size).then_some([size.x, size.y]);
+        }
+    }
+
+    // Lays out the edited text the same way it is drawn and records where each char ends up
+    fn layout_editing_text(&mut self, ctx: &Context) {
+        self.editing_glyph_rects.clear();
+        let Some(shape_data) = self.editing_shape_index.and_then(|i| self.diagram.shapes.get(i)) else { return; };
+        if self.text_edit.text.is_empty() { return; }
+        let text_obj = self.shape_text(shape_data, &self.text_edit.text, self.shape_rect(shape_data));
+        let anchor = self.shape_text_anchor(shape_data);
+        let line_top = self.caret_segment(0, shape_data).0.y;
+        let font_size = self.shape_font_size_of(shape_data);
+        // Glyph origins: where each glyph's advance starts, on its line's baseline
+        let (origins, block_height) = match (text_obj.glyph_positions(ctx), text_obj.measure(ctx)) {
+            (Ok(origins), Ok(size)) => (origins, size.y),
+            (Err(e), _) | (_, Err(e)) => { log::warn!(target: logging::RENDER, "Could not lay out text: {}", e); return; }
+        };
+        let Some(first) = origins.first() else { return; };
+        // The text block is centered on the anchor, except in a note where it hangs below it
+        let block_top = if shape_data.kind == ShapeKind::Note { anchor.y } else { anchor.y - block_height / 2.0 };
+        let ascent = first.y + anchor.y - block_top;
+        // A glyph reaches to the next one on its line; the last one on a line is as wide as it measures alone
+        let visible_chars: Vec<char> = self.text_edit.text.chars().filter(|c| !c.is_control()).collect();
+        let rects: Vec<Rect> = origins.iter().enumerate().map(|(i, origin)| {
+            let next_on_line = origins.get(i + 1).filter(|next| next.y == origin.y && next.x > origin.x);
+            let width = match (next_on_line, visible_chars.get(i)) {
+                (Some(next), _) => next.x - origin.x,
+                (None, Some(c)) => {
+                    let mut glyph = self.label_text(&c.to_string());
+                    glyph.set_scale(font_size);
+                    glyph.measure(ctx).map_or(0.0, |size| size.x)
+                }
+                (None, None) => 0.0,
+            };
+            Rect::new(origin.x + anchor.x, origin.y + anchor.y - ascent, width, font_size)
+        }).collect();
+        // Line breaks get no glyph; give them a zero-width box at the end of their line
+        let mut glyphs = rects.into_iter();
+        let mut previous: Option<Rect> = None;
+        for c in self.text_edit.text.chars() {
+            let rect = if c.is_control() {
+                previous.map_or(Rect::new(anchor.x, line_top, 0.0, font_size), |p| Rect::new(p.right(), p.y, 0.0, p.h))
+            } else {
+                match glyphs.next() { Some(rect) => rect, None => break }
+            };
+            self.editing_glyph_rects.push(rect);
+            previous = Some(rect);
+        }
+    }
+
+    // Top and bottom of the caret standing before char `index` of the edited text
+    fn caret_segment(&self, index: usize, shape: &ShapeData) -> (Vec2, Vec2) {
+        let font_size = self.editing_font_size();
+        let anchor = self.shape_text_anchor(shape);
+        // An empty line is centered on the anchor, except in a note where it hangs below it
+        let empty_line_top = if shape.kind == ShapeKind::Note { anchor } else { anchor - Vec2::new(0.0, font_size / 2.0) };
+        let (x, rect) = match (self.editing_glyph_rects.get(index), self.editing_glyph_rects.last()) {
+            (Some(rect), _) => (rect.x, *rect),
+            // After a trailing line break the caret starts the new, still empty, line
+            (None, Some(last)) if self.text_edit.text.ends_with('\n') => {
+                (anchor.x, Rect::new(anchor.x, last.y + last.h.max(font_size), 0.0, last.h))
+            }
+            (None, Some(last)) => (last.right(), *last), // After the last char
+            (None, None) => return (empty_line_top, empty_line_top + Vec2::new(0.0, font_size)),
+        };
+        (Vec2::new(x, rect.y), Vec2::new(x, rect.y + rect.h.max(font_size)))
+    }
+
+    // Turns the input method on while a shape's text is edited, with its candidate window kept just
+    // below the caret, and off again afterwards
+    fn place_ime_window(&mut self, ctx: &Context) {
+        if self.editing_shape_index.is_none() {
+            if self.ime_position.take().is_some() {
+                ctx.gfx.window().set_ime_allowed(false);
+                self.ime_preedit.clear();
+                self.ime_preedit_cursor = None;
+            }
+            return;
+        }
+        let Some(shape_data) = self.editing_shape_index.and_then(|i| self.diagram.shapes.get(i)) else { return; };
+        let caret_bottom = shape_data.to_world(self.caret_segment(self.text_edit.cursor(), shape_data).1);
+        let physical = (caret_bottom - self.camera.offset) * self.camera.zoom * self.ui_scale;
+        if self.ime_position == Some(physical) { return; }
+        if self.ime_position.is_none() { ctx.gfx.window().set_ime_allowed(true); }
+        self.ime_position = Some(physical);
+        ctx.gfx.window().set_ime_position(ggez::winit::dpi::PhysicalPosition::new(physical.x, physical.y));
+    }
+
+    // Caret position (char index) closest to a world point, for click-to-place
+    fn caret_index_at(&self, pos: Vec2) -> usize {
+        let Some(shape_data) = self.editing_shape_index.and_then(|i| self.diagram.shapes.get(i)) else { return 0; };
+        let pos = shape_data.to_local(pos);
+        (0..=self.editing_glyph_rects.len())
+            .min_by(|&a, &b| {
+                // Being on the right line matters more than horizontal distance
+                let distance = |index: usize| {
+                    let (top, bottom) = self.caret_segment(index, shape_data);
+                    let dy = if pos.y < top.y { top.y - pos.y } else if pos.y > bottom.y { pos.y - bottom.y } else { 0.0 };
+                    dy * 10.0 + (pos.x - top.x).abs()
+                };
+                distance(a).total_cmp(&distance(b))
+            })
+            .unwrap_or(0)
+    }
+
+    // Laid out as if the shape weren't rotated, then turned with it
+    fn draw_text_selection(&self, ctx: &mut Context, canvas: &mut graphics::Canvas, shape_data: &ShapeData) -> GameResult {
+        let turned = graphics::DrawParam::default().dest(shape_data.to_world(Vec2::ZERO)).rotation(shape_data.rotation);
+        if let Some((start, end)) = self.text_edit.selection() {
+            for rect in self.editing_glyph_rects.iter().take(end).skip(start) {
+                let highlight = Mesh::new_rectangle(ctx, DrawMode::fill(), *rect, Color::from_rgba(255, 255, 255, 140))?;
+                canvas.draw(&highlight, turned);
+            }
+        }
+        let (top, bottom) = self.caret_segment(self.text_edit.cursor(), shape_data);
+        let caret = Mesh::new_line(ctx, &[top, bottom], TEXT_CARET_WIDTH, self.shape_text_color(shape_data))?;
+        canvas.draw(&caret, turned);
+        Ok(())
+    }
+
+    // The input method's composition, underlined at the caret on a patch of the shape's fill that
+    // covers the text after it until it is committed
+    fn draw_ime_preedit(&self, ctx: &mut Context, canvas: &mut graphics::Canvas, shape_data: &ShapeData) -> GameResult {
+        if self.ime_preedit.is_empty() { return Ok(()); }
+        let turned = graphics::DrawParam::default().dest(shape_data.to_world(Vec2::ZERO)).rotation(shape_data.rotation);
+        let (top, bottom) = self.caret_segment(self.text_edit.cursor(), shape_data);
+        let font_size = self.editing_font_size();
+        let width_of = |text: &str| {
+            let mut text_obj = self.label_text(text);
+            text_obj.set_scale(font_size);
+            text_obj.measure(ctx).map_or(0.0, |size| size.x)
+        };
+        let width = width_of(&self.ime_preedit);
+        let color = self.shape_text_color(shape_data);
+        let patch = Mesh::new_rectangle(ctx, DrawMode::fill(), Rect::new(top.x, top.y, width, bottom.y - top.y), self.shape_fill_color(shape_data))?;
+        canvas.draw(&patch, turned);
+        let mut text_obj = self.label_text(&self.ime_preedit);
+        text_obj.set_scale(font_size);
+        canvas.draw(&text_obj, graphics::DrawParam::default().dest(shape_data.to_world(top)).rotation(shape_data.rotation).color(color));
+        let underline = Mesh::new_line(ctx, &[Vec2::new(top.x, bottom.y), Vec2::new(top.x + width, bottom.y)], 1.0, color)?;
+        canvas.draw(&underline, turned);
+        if let Some(cursor) = self.ime_preedit_cursor.filter(|&cursor| self.ime_preedit.is_char_boundary(cursor)) {
+            let x = top.x + width_of(&self.ime_preedit[..cursor]);
+            let caret = Mesh::new_line(ctx, &[Vec2::new(x, top.y), Vec2::new(x, bottom.y)], TEXT_CARET_WIDTH, color)?;
+            canvas.draw(&caret, turned);
+        }
+        Ok(())
+    }
+
+    fn svg_style(&self) -> export_svg::SvgStyle {
+        export_svg::SvgStyle {
+            shape_width: self.default_shape_width,
+            shape_height: self.default_shape_height,
+            corner_radius: self.default_shape_corner_radius,
+            background_color: self.theme.background,
+            shape_color: self.theme.shape,
+            text_color: self.theme.text,
+            connector_line_color: self.theme.connector,
+        }
+    }
+
+    // Exports leave out annotations while they are hidden
+    fn visible_annotations(&self) -> &[Annotation] {
+        if self.annotations_visible { &self.diagram.annotations } else { &[] }
+    }
+
+    fn export_svg(&self, path: &Path) {
+        // The SVG shows groups expanded, so routes bent to a collapsed group's box are left out
+        let in_collapsed_group = |index: usize| self.diagram.collapsed_group_of_shape(index).is_some();
+        let collapsed = |c: &Connection| in_collapsed_group(c.from_shape_index) || in_collapsed_group(c.to_shape_index);
+        let routes: Vec<Option<Vec<Vec2>>> = self.diagram.connections.iter()
+            .map(|c| self.connector_route(c).filter(|_| !collapsed(c)).map(|r| r.to_vec()))
+            .collect();
+        match export_svg::export_svg(path, &self.diagram.shapes, &self.diagram.connections, &self.diagram.lanes, self.visible_annotations(), &routes, &self.svg_style()) {
+            Ok(()) => notify_io(format!("Diagram exported to {}", path.display())),
+            Err(e) => log::error!(target: logging::IO, "{}", e),
+        }
+    }
+
+    fn export_dot(&self, path: &Path) {
+        let diagram = graph_export_diagram(&self.diagram, self.include_notes_in_exports);
+        match export_dot::export_dot(path, &diagram.shapes, &diagram.connections, &diagram.lanes) {
+            Ok(()) => notify_io(format!("Diagram exported to {}", path.display())),
+            Err(e) => log::error!(target: logging::IO, "{}", e),
+        }
+    }
+
+    // Connections as CSV rows, or shapes as a JSON adjacency list, next to the diagram file
+    fn export_graph(&self, format: cli::ExportFormat) {
+        let path = self.diagram_path.with_extension(format.extension());
+        let diagram = graph_export_diagram(&self.diagram, self.include_notes_in_exports);
+        let result = match format {
+            cli::ExportFormat::Csv => export_graph::export_csv(&path, &diagram.shapes, &diagram.connections),
+            _ => export_graph::export_adjacency_json(&path, &diagram.shapes, &diagram.connections),
+        };
+        match result {
+            Ok(()) => notify_io(format!("Graph exported to {}", path.display())),
+            Err(e) => log::error!(target: logging::IO, "{}", e),
+        }
+    }
+
+    // The diagram as a Rust state machine next to the diagram file. Notes are never states.
+    fn export_rust(&self) {
+        let path = self.diagram_path.with_extension(cli::ExportFormat::Rust.extension());
+        let diagram = self.diagram.without_notes();
+        match export_fsm::export_rust_fsm(&path, &diagram.shapes, &diagram.connections) {
+            Ok(warnings) => {
+                for warning in &warnings { log::warn!(target: logging::IO, "{}", warning); }
+                let skipped = if warnings.is_empty() { String::new() } else { format!(" ({} transition(s) left out, see the log)", warnings.len()) };
+                notify_io(format!("State machine exported to {}{}", path.display(), skipped));
+            }
+            Err(e) => log::error!(target: logging::IO, "{}", e),
+        }
+    }
+
+    // Merges the CSV edge list next to the diagram file (and its `.nodes.csv` node list, if any) into
+    // the diagram; shapes are matched by label, and new ones land on the active layer
+    fn import_csv(&mut self) {
+        let edges_path = self.diagram_path.with_extension("csv");
+        let first_new_connection = self.diagram.connections.len();
+        let nodes_path = import_csv::sibling_nodes_path(&edges_path);
+        let spacing = self.layout_spacing();
+        match import_csv::import_csv(&mut self.diagram, &edges_path, nodes_path.as_deref(), &spacing) {
+            Ok(summary) => {
+                for &index in &summary.added_shapes { self.diagram.shapes[index].layer = self.active_layer; }
+                for connection in &mut self.diagram.connections[first_new_connection..] { connection.layer = self.active_layer; }
+                self.admit_new_connections(first_new_connection);
+                let added_connections = self.diagram.connections.len() - first_new_connection;
+                notify_io(format!(
+                    "Imported {} new shape(s) and {} connection(s) from {}", summary.added_shapes.len(), added_connections, edges_path.display()
+                ));
+            }
+            Err(e) => log::error!(target: logging::IO, "{}", e),
+        }
+    }
+
+    // Renders the whole diagram (not just the visible window) off-screen and writes it as a PNG
+    fn render_png(&self, ctx: &mut Context, path: &Path, scale: u32) -> Result<(), String> {
+        let group_frames = (0..self.diagram.groups.len()).filter_map(|g| self.diagram.group_frame(g, self.default_shape_size()));
+        let lane_rects = self.diagram.lanes.iter().map(|lane| lane.rect());
+        let annotation_rects = self.visible_annotations().iter().filter_map(Annotation::bounds);
+        let (min, max) = geometry::shapes_bounds(self.diagram.shapes.iter().map(|s| self.shape_bounds(s)).chain(group_frames).chain(lane_rects).chain(annotation_rects))
+            .ok_or_else(|| "Nothing to export: the diagram is empty".to_string())?;
+        let (min, max) = (min - Vec2::splat(PNG_EXPORT_MARGIN), max + Vec2::splat(PNG_EXPORT_MARGIN));
+        let size = max - min;
+        let width_px = (size.x * scale as f32).ceil() as u32;
+        let height_px = (size.y * scale as f32).ceil() as u32;
+        if width_px > PNG_EXPORT_MAX_DIMENSION || height_px > PNG_EXPORT_MAX_DIMENSION {
+            return Err(format!("Diagram too large to export at {}x ({}x{} px, max {})", scale, width_px, height_px, PNG_EXPORT_MAX_DIMENSION));
+        }
+
+        let target_image = graphics::Image::new_canvas_image(ctx, graphics::ImageFormat::Rgba8UnormSrgb, width_px, height_px, 1);
+        let mut canvas = graphics::Canvas::from_image(ctx, target_image.clone(), self.theme.background);
+        canvas.set_screen_coordinates(Rect::new(min.x, min.y, size.x, size.y));
+        self.draw_lanes(ctx, &mut canvas, false).map_err(|e| e.to_string())?;
+        self.draw_groups(ctx, &mut canvas, false).map_err(|e| e.to_string())?;
+        self.draw_connectors(ctx, &mut canvas, false);
+        self.draw_shapes(ctx, &mut canvas, false).map_err(|e| e.to_string())?;
+        self.draw_annotations(ctx, &mut canvas);
+        canvas.finish(ctx).map_err(|e| e.to_string())?;
+
+        let pixels = target_image.to_pixels(ctx).map_err(|e| e.to_string())?;
+        image::save_buffer(path, &pixels, width_px, height_px, image::ColorType::Rgba8)
+            .map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+
+    fn export_png(&self, ctx: &mut Context, path: &Path) {
+        match self.render_png(ctx, path, self.png_export_scale) {
+            Ok(()) => notify_io(format!("Diagram exported to {} at {}x", path.display(), self.png_export_scale)),
+            Err(e) => log::error!(target: logging::IO, "{}", e),
+        }
+    }
+
+    // (shape, port) other than on `exclude_shape` with a port within PORT_CLICK_RADIUS of `pos`
+    fn find_port_target(&self, pos: Vec2, exclude_shape: Option<usize>) -> Option<(usize, PortId)> {
+        self.port_within(pos, PORT_CLICK_RADIUS, exclude_shape)
+    }
+
+    // Port the line being drawn ends on if clicked or released at `pos`: the nearest one within
+    // PORT_HOVER_DETECT_DISTANCE on any shape but the one it starts from. The preview snaps to it.
+    fn new_line_target(&self, pos: Vec2) -> Option<(usize, PortId)> {
+        let (start_shape_idx, _) = self.new_line_start_info?;
+        self.shape_grid.query_point(pos).iter().copied()
+            .filter(|&index| index != start_shape_idx && !self.diagram.shape_locked(index))
+            .flat_map(|index| (0..self.diagram.shapes.get(index).map_or(0, |s| s.ports.len())).map(move |port_id| (index, port_id)))
+            .filter_map(|(index, port_id)| Some(((index, port_id), self.get_port_point(index, port_id)?.distance(pos))))
+            .filter(|&(_, distance)| distance <= PORT_HOVER_DETECT_DISTANCE)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(target, _)| target)
+    }
+
+    fn port_within(&self, pos: Vec2, radius: f32, exclude_shape: Option<usize>) -> Option<(usize, PortId)> {
+        // Top-most shape first, like shape_at
+        self.shape_grid.query_point(pos).iter().rev().copied()
+            .filter(|&target_idx| Some(target_idx) != exclude_shape && !self.diagram.shape_locked(target_idx))
+            .find_map(|target_idx| {
+                let port_count = self.diagram.shapes.get(target_idx)?.ports.len();
+                (0..port_count)
+                    .find(|&port_id| {
+                        self.get_port_point(target_idx, port_id)
+                            .is_some_and(|port_pos| pos.distance(port_pos) <= radius)
+                    })
+                    .map(|port_id| (target_idx, port_id))
+            })
+    }
+
+    // Both endpoints of the selected connector, as (source port, target port)
+    fn selected_connector_endpoints(&self) -> Option<(Vec2, Vec2)> {
+        let connection = self.diagram.connections.get(self.selected_connector_index?)?;
+        Some((
+            self.get_port_point(connection.from_shape_index, connection.from_port)?,
+            self.get_port_point(connection.to_shape_index, connection.to_port)?,
+        ))
+    }
+
+    // Start and end of the preview line, if a new line or an endpoint drag is in progress
+    fn preview_line(&self) -> Option<(Vec2, Vec2)> {
+        if self.drawing_new_line {
+            let (start_shape_idx, start_port) = self.new_line_start_info?;
+            return Some((self.get_port_point(start_shape_idx, start_port)?, self.new_line_preview_end_pos?));
+        }
+        let (conn_idx, dragging_source_end) = self.dragged_connector_endpoint?;
+        let connection = self.diagram.connections.get(conn_idx)?;
+        // The preview runs from the end that stays attached to the mouse
+        let fixed_end = if dragging_source_end {
+            self.get_port_point(connection.to_shape_index, connection.to_port)?
+        } else {
+            self.get_port_point(connection.from_shape_index, connection.from_port)?
+        };
+        Some((fixed_end, self.live_mouse_pos))
+    }
+
+    // `connection` with one end moved to `target`
+    fn reattached(connection: &Connection, source_end: bool, target: (usize, PortId)) -> Connection {
+        let mut reattached = connection.clone();
+        if source_end {
+            reattached.from_shape_index = target.0; reattached.from_port = target.1;
+        } else {
+            reattached.to_shape_index = target.0; reattached.to_port = target.1;
+        }
+        reattached
+    }
+
+    // Whether the line being drawn or the connector end being dragged sits on a port the
+    // validation rules would refuse
+    fn preview_target_refused(&self) -> bool {
+        let candidate = if self.drawing_new_line {
+            let Some((start_shape_idx, start_port)) = self.new_line_start_info else { return false; };
+            let Some((target_idx, target_port)) = self.new_line_target(self.live_mouse_pos) else { return false; };
+            (Connection::new(start_shape_idx, start_port, target_idx, target_port), None)
+        } else {
+            let Some((conn_idx, dragging_source_end)) = self.dragged_connector_endpoint else { return false; };
+            let Some(connection) = self.diagram.connections.get(conn_idx) else { return false; };
+            let fixed_shape = if dragging_source_end { connection.to_shape_index } else { connection.from_shape_index };
+            let Some(target) = self.find_port_target(self.live_mouse_pos, Some(fixed_shape)) else { return false; };
+            (Self::reattached(connection, dragging_source_end, target), Some(conn_idx))
+        };
+        self.connection_validator.check(&self.diagram, &candidate.0, candidate.1).is_err()
+    }
+
+    // Re-attaches the dragged end of a connector to the shape whose port is under `drop_pos`
+    fn finish_connector_endpoint_drag(&mut self, drop_pos: Vec2) {
+        let Some((conn_idx, dragging_source_end)) = self.dragged_connector_endpoint.take() else { return; };
+        let Some(connection) = self.diagram.connections.get(conn_idx) else { return; };
+        let fixed_shape = if dragging_source_end { connection.to_shape_index } else { connection.from_shape_index };
+        let Some((target_idx, target_port)) = self.find_port_target(drop_pos, Some(fixed_shape)) else {
+            log::debug!(target: logging::INPUT, "Connector reconnection cancelled.");
+            return;
+        };
+        let reconnected = Self::reattached(connection, dragging_source_end, (target_idx, target_port));
+        if let Err(e) = self.connection_validator.check(&self.diagram, &reconnected, Some(conn_idx)) {
+            log::warn!(target: logging::MODEL, "Connector reconnection refused: {}.", e);
+            return;
+        }
+        match self.diagram.reconnect(conn_idx, reconnected) {
+            Ok(()) => log::info!(target: logging::MODEL, "Connector {} reconnected to shape {} (port {}).", conn_idx, target_idx, target_port),
+            Err(e) => log::warn!(target: logging::MODEL, "Connector reconnection skipped: {}.", e),
+        }
+    }
+
+    // Follows the monitor's scale factor, e.g. when the window moves to a HiDPI monitor
+    fn set_window_scale_factor(&mut self, scale_factor: f32) {
+        if scale_factor == self.window_scale_factor { return; }
+        log::info!(target: logging::RENDER, "Window scale factor changed from {} to {}", self.window_scale_factor, scale_factor);
+        // The pointer hasn't moved physically, so its logical position scales the other way
+        self.live_mouse_screen_pos *= self.window_scale_factor / scale_factor;
+        self.live_mouse_pos = self.camera.screen_to_world(self.live_mouse_screen_pos);
+        self.window_scale_factor = scale_factor;
+        self.ui_scale = scale_factor * self.ui_scale_multiplier;
+        self.ime_position = None;
+        self.needs_redraw = true;
+    }
+
+    // --- Read-only mode ---
+    // Finishes whatever edit is under way and closes the overlays, which would offer editing again
+    fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+        if read_only {
+            self.commit_shape_text_edit();
+            self.commit_inspector_edit();
+            self.drawing_new_line = false; self.new_line_start_info = None; self.new_line_preview_end_pos = None;
+            self.command_palette = None; self.shape_search = None; self.recent_menu = None; self.context_menu = None;
+            self.blur_code_panel();
+            self.palette_drag = None;
+        } else {
+            self.presentation_step = None; self.camera_animation = None;
+        }
+        log::info!(target: logging::INPUT, "Read-only mode {}.", if read_only { "on (F5 to edit again)" } else { "off" });
+    }
+
+    // A click highlights the shape or connector under it; a left drag anywhere else pans
+    fn read_only_press(&mut self, button: MouseButton, screen_pos: Vec2) {
+        self.camera_animation = None;
+        let pos = self.camera.screen_to_world(screen_pos);
+        if button == MouseButton::Left {
+            let shape = self.shape_at(pos);
+            let connector = if shape.is_none() { self.connector_at(pos) } else { None };
+            self.clear_selection();
+            self.selected_shape_index = shape; self.selected_connector_index = connector;
+            if shape.is_some() || connector.is_some() { return; }
+        }
+        if matches!(button, MouseButton::Left | MouseButton::Middle) { self.pan_last_screen_pos = Some(screen_pos); }
+    }
+
+    // --- Token simulation: walking a flowchart one connection per Space ---
+    // Starts on the selected shape when there is one; otherwise the next click picks the start
+    fn toggle_simulation(&mut self) {
+        if self.simulation.take().is_some() {
+            log::info!(target: logging::INPUT, "Simulation off.");
+            return;
+        }
+        self.commit_shape_text_edit();
+        self.commit_inspector_edit();
+        self.cancel_interactions();
+        self.command_palette = None; self.shape_search = None; self.recent_menu = None; self.context_menu = None;
+        self.blur_code_panel();
+        let mut simulation = Simulation::default();
+        if let Some(start) = self.selected_shape_index.filter(|&index| !self.diagram.is_note(index)) { simulation.start(start); }
+        self.simulation = Some(simulation);
+        log::info!(target: logging::INPUT, "Simulation on: Space steps the token, F6 or Esc stops.");
+    }
+
+    // A left click on a shape puts the token there; a left drag anywhere else pans
+    fn simulation_press(&mut self, button: MouseButton, screen_pos: Vec2) {
+        self.camera_animation = None;
+        let pos = self.camera.screen_to_world(screen_pos);
+        if button == MouseButton::Left {
+            if let Some(shape) = self.shape_at(pos).filter(|&index| !self.diagram.is_note(index)) {
+                if let Some(simulation) = self.simulation.as_mut() { simulation.start(shape); }
+                return;
+            }
+        }
+        if matches!(button, MouseButton::Left | MouseButton::Middle) { self.pan_last_screen_pos = Some(screen_pos); }
+    }
+
+    // Space: the token takes the only way on, or the highlighted branch; `branch` picks one instead
+    fn step_simulation(&mut self, screen_size: Vec2, branch: Option<usize>) {
+        let Some(simulation) = self.simulation.as_mut() else { return; };
+        let step = match branch {
+            Some(index) => simulation.choose(&self.diagram, Some(index)),
+            None => simulation.step(&self.diagram),
+        };
+        let token = simulation.token.unwrap_or_default();
+        match step {
+            simulation::Step::Moved(_) => {
+                // Keep the token in view
+                let center = self.diagram.shapes[token].center_position;
+                if !self.camera.visible_rect(screen_size.x, screen_size.y).contains(center) {
+                    let mut target = self.camera_target();
+                    target.center_on(center, screen_size);
+                    self.animate_camera(target, screen_size, Tween::new(CAMERA_MOVE_DURATION, Easing::EaseOut));
+                }
+            }
+            simulation::Step::End => log::info!(target: logging::INPUT, "Nothing leads on from {}.", self.shape_name(token)),
+            simulation::Step::NoToken => log::info!(target: logging::INPUT, "Click the shape to start the simulation from."),
+            simulation::Step::Branch => {}
+        }
+    }
+
+    fn step_token_animation(&mut self) {
+        let Some(simulation) = self.simulation.as_mut() else { return; };
+        if simulation.moving.is_some_and(|(_, tween)| tween.finished(Instant::now())) { simulation.moving = None; }
+    }
+
+    // The connections taken so far, the ones offered at a branch, and the token: on its shape, or
+    // partway along the connection it is taking
+    fn draw_simulation(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) -> GameResult {
+        let Some(simulation) = &self.simulation else { return Ok(()); };
+        let width = SIMULATION_TRAIL_WIDTH / self.camera.zoom;
+        let offered = simulation.branch.iter().flatten().map(|&conn_idx| (conn_idx, SIMULATION_BRANCH_COLOR));
+        for (conn_idx, color) in simulation.walked.iter().map(|&conn_idx| (conn_idx, SIMULATION_COLOR)).chain(offered) {
+            let Some(path) = self.drawn_connector_path(conn_idx) else { continue; };
+            let points = path.points();
+            if points.len() < 2 || geometry::polyline_length(&points) <= 0.0 { continue; }
+            canvas.draw(&Mesh::new_line(ctx, &points, width, color)?, graphics::DrawParam::default());
+        }
+        let Some(token) = simulation.token else { return Ok(()); };
+        let rect = self.shape_bounds(&self.diagram.shapes[token]);
+        let halo = Rect::new(
+            rect.x - STEP_HIGHLIGHT_MARGIN, rect.y - STEP_HIGHLIGHT_MARGIN,
+            rect.w + STEP_HIGHLIGHT_MARGIN * 2.0, rect.h + STEP_HIGHLIGHT_MARGIN * 2.0,
+        );
+        canvas.draw(&Mesh::new_rectangle(ctx, DrawMode::stroke(3.0 / self.camera.zoom), halo, SIMULATION_COLOR)?, graphics::DrawParam::default());
+        let moving_along = simulation.moving.and_then(|(conn_idx, tween)| Some((self.drawn_connector_path(conn_idx)?.points(), tween)));
+        let position = match moving_along {
+            Some((points, tween)) => geometry::point_along_polyline(&points, geometry::polyline_length(&points) * tween.progress(Instant::now())),
+            None => self.diagram.shapes[token].center_position,
+        };
+        let radius = SIMULATION_TOKEN_RADIUS / self.camera.zoom.sqrt();
+        canvas.draw(&Mesh::new_circle(ctx, DrawMode::fill(), position, radius, 0.5, SIMULATION_COLOR)?, graphics::DrawParam::default());
+        canvas.draw(&Mesh::new_circle(ctx, DrawMode::stroke(1.5 / self.camera.zoom), position, radius, 0.5, Color::WHITE)?, graphics::DrawParam::default());
+        Ok(())
+    }
+
+    // --- Live collaboration (--host or --join) ---
+    // Trades edits with the others and keeps our cursor and selection current for them. Only the
+    // shared diagram's tab takes part; what comes in while another diagram is showing waits until
+    // the shared one is back.
+    fn sync_collaboration(&mut self, now: Instant) {
+        let Some(mut collaboration) = self.collaboration.take() else { return; };
+        let showing = self.diagram_path == collaboration.document;
+        let mut connected = true;
+        for update in collaboration.poll() {
+            match update {
+                collab::Update::Joined(name) => notify_io(format!("{} joined.", name)),
+                collab::Update::Left(name) => notify_io(format!("{} left.", name)),
+                collab::Update::Presence => {}
+                collab::Update::Disconnected => {
+                    log::error!(target: logging::IO, "Lost the connection to the host; edits here are no longer shared.");
+                    connected = false;
+                }
+            }
+            self.needs_redraw = true;
+        }
+        if !connected { return; }
+        let mut selection = Vec::new();
+        if showing {
+            let ids = collaboration.identify(&mut self.diagram);
+            selection = self.selected_shape_indices().into_iter().filter_map(|index| ids.get(index).copied()).collect();
+            match collaboration.sync_diagram(Some(&mut self.diagram), now) {
+                collab::RemoteChange::Nothing => {}
+                collab::RemoteChange::Contents => self.needs_redraw = true,
+                collab::RemoteChange::Structure => self.apply_remote_structure(&selection),
+            }
+        } else {
+            collaboration.sync_diagram(None, now);
+        }
+        collaboration.share_presence(showing.then_some(self.live_mouse_pos), selection, now);
+        self.collaboration = Some(collaboration);
+    }
+
+    // Shapes or connections came or went under us: indices held by drags, edits or the simulation
+    // no longer mean what they did, so those stop, as after a script run. The selected shapes stay
+    // selected wherever they now are.
+    fn apply_remote_structure(&mut self, selection: &[u64]) {
+        self.cancel_interactions();
+        self.clear_selection();
+        self.simulation = None;
+        let mut selected = self.diagram.shapes.iter().enumerate().filter(|(_, shape)| selection.contains(&shape.id)).map(|(index, _)| index);
+        self.selected_shape_index = selected.next();
+        self.extra_selected_shapes = selected.collect();
+        let shape_count = self.diagram.shapes.len();
+        self.navigation_origin = self.navigation_origin.filter(|&(index, _)| index < shape_count);
+        self.needs_redraw = true;
+    }
+
+    // Each collaborator's selection as a halo, and their cursor with their name, in their color
+    fn draw_collaborators(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) -> GameResult {
+        let Some(collaboration) = self.collaboration.as_ref().filter(|c| c.document == self.diagram_path) else { return Ok(()); };
+        for (&peer, remote) in &collaboration.peers {
+            let Some(cursor) = remote.cursor else { continue; };
+            let color = peer_color(peer);
+            for shape in self.diagram.shapes.iter().filter(|shape| remote.selection.contains(&shape.id)) {
+                let rect = self.shape_bounds(shape);
+                let halo = Rect::new(
+                    rect.x - SEARCH_MATCH_MARGIN, rect.y - SEARCH_MATCH_MARGIN,
+                    rect.w + SEARCH_MATCH_MARGIN * 2.0, rect.h + SEARCH_MATCH_MARGIN * 2.0,
+                );
+                canvas.draw(&Mesh::new_rectangle(ctx, DrawMode::stroke(2.0 / self.camera.zoom), halo, color)?, graphics::DrawParam::default());
+            }
+            // The arrow and name keep their screen size at any zoom
+            let arrow: Vec<Vec2> = PEER_CURSOR_ARROW.iter().map(|&[x, y]| cursor + Vec2::new(x, y) / self.camera.zoom).collect();
+            canvas.draw(&Mesh::new_polygon(ctx, DrawMode::fill(), &arrow, color)?, graphics::DrawParam::default());
+            let mut name = self.label_text(&remote.name);
+            name.set_scale(PEER_NAME_FONT_SIZE);
+            let name_pos = cursor + Vec2::new(12.0, 14.0) / self.camera.zoom;
+            canvas.draw(&name, graphics::DrawParam::default().dest(name_pos).scale(Vec2::splat(1.0 / self.camera.zoom)).color(color));
+        }
+        Ok(())
+    }
+
+    // --- Camera transitions: the view glides to where zoom to fit, a search or a step puts it ---
+    fn animate_camera(&mut self, target: Camera, logical_size: Vec2, tween: Tween) {
+        self.camera_animation = Some(CameraAnimation { from: self.camera, to: target, logical_size, tween });
+    }
+
+    // The camera as a transition will leave it, so further moves start from there
+    fn camera_target(&self) -> Camera {
+        self.camera_animation.as_ref().map_or(self.camera, |animation| animation.to)
+    }
+
+    fn step_camera_animation(&mut self) {
+        let Some(animation) = &self.camera_animation else { return; };
+        let now = Instant::now();
+        self.camera = animation.from.interpolate(&animation.to, animation.tween.progress(now), animation.logical_size);
+        self.live_mouse_pos = self.camera.screen_to_world(self.live_mouse_screen_pos);
+        if animation.tween.finished(now) { self.camera_animation = None; }
+    }
+
+    // Jumps to the end of a transition, e.g. when the window first opens
+    fn finish_camera_animation(&mut self) {
+        if let Some(animation) = self.camera_animation.take() { self.camera = animation.to; }
+        self.live_mouse_pos = self.camera.screen_to_world(self.live_mouse_screen_pos);
+    }
+
+    // --- Presentation steps: stops walked through with Left/Right in read-only mode ---
+    // The selected group, or else the selected shapes
+    fn add_presentation_step(&mut self) {
+        let step = match self.selected_group {
+            Some(group) => PresentationStep::Group(group),
+            None => PresentationStep::Shapes(self.selected_shape_indices()),
+        };
+        if step == PresentationStep::Shapes(Vec::new()) {
+            log::warn!(target: logging::MODEL, "Select shapes or a group to add as a presentation step.");
+            return;
+        }
+        self.diagram.steps.push(step);
+        log::info!(target: logging::MODEL, "Added presentation step {}.", self.diagram.steps.len());
+    }
+
+    fn advance_presentation(&mut self, ctx: &Context, forward: bool) {
+        let count = self.diagram.steps.len();
+        if count == 0 {
+            log::info!(target: logging::INPUT, "No presentation steps; add some with \"{}\".", Command::AddPresentationStep.name());
+            return;
+        }
+        let step = match (self.presentation_step, forward) {
+            (None, true) => 0,
+            (None, false) => count - 1,
+            (Some(step), true) => (step + 1).min(count - 1),
+            (Some(step), false) => step.saturating_sub(1),
+        };
+        self.presentation_step = Some(step);
+        self.clear_selection();
+        log::info!(target: logging::INPUT, "Presentation step {} of {}.", step + 1, count);
+        let Some((min, max)) = self.step_bounds(step) else { return; };
+        let screen_size = self.logical_screen_size(ctx);
+        let mut target = self.camera;
+        target.fit_to(min, max, Rect::new(0.0, 0.0, screen_size.x, screen_size.y), STEP_FIT_MARGIN);
+        self.animate_camera(target, screen_size, Tween::new(PRESENTATION_STEP_DURATION, Easing::EaseInOut));
+    }
+
+    // A group step is framed as its group is drawn (which may be collapsed); shapes by their own outlines
+    fn step_rects(&self, step: usize) -> Vec<Rect> {
+        match self.diagram.steps.get(step) {
+            Some(&PresentationStep::Group(group)) => self.diagram.group_frame(group, self.default_shape_size()).into_iter().collect(),
+            _ => self.diagram.step_shapes(step).into_iter().map(|i| self.shape_bounds(&self.diagram.shapes[i])).collect(),
+        }
+    }
+
+    fn step_bounds(&self, step: usize) -> Option<(Vec2, Vec2)> {
+        geometry::shapes_bounds(self.step_rects(step))
+    }
+
+
+    fn draw_presentation_step(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) -> GameResult {
+        let Some(step) = self.presentation_step.filter(|_| self.read_only) else { return Ok(()); };
+        for rect in self.step_rects(step) {
+            let halo = Rect::new(
+                rect.x - STEP_HIGHLIGHT_MARGIN, rect.y - STEP_HIGHLIGHT_MARGIN,
+                rect.w + STEP_HIGHLIGHT_MARGIN * 2.0, rect.h + STEP_HIGHLIGHT_MARGIN * 2.0,
+            );
+            let mesh = Mesh::new_rectangle(ctx, DrawMode::stroke(3.0 / self.camera.zoom), halo, self.theme.selection)?;
+            canvas.draw(&mesh, graphics::DrawParam::default());
+        }
+        Ok(())
+    }
+
+    // --- Touch: fingers go through the mouse and gesture handlers, which record them for replay ---
+    fn touch_gesture(&mut self, ctx: &mut Context, gesture: TouchGesture) -> GameResult {
+        match gesture {
+            TouchGesture::Press(pos) => {
+                self.mouse_motion_event(ctx, pos.x, pos.y, 0.0, 0.0)?;
+                self.mouse_button_down_event(ctx, MouseButton::Left, pos.x, pos.y)
+            }
+            TouchGesture::Drag(pos) => self.mouse_motion_event(ctx, pos.x, pos.y, 0.0, 0.0),
+            TouchGesture::Release(pos) | TouchGesture::Cancel(pos) => self.mouse_button_up_event(ctx, MouseButton::Left, pos.x, pos.y),
+            // Selects what's under the finger (the press did) and offers the commands that act on it
+            TouchGesture::LongPress(pos) => {
+                self.mouse_button_up_event(ctx, MouseButton::Left, pos.x, pos.y)?;
+                if !self.read_only {
+                    let screen_pos = self.physical_to_screen(pos.x, pos.y);
+                    self.command_palette = None; self.shape_search = None; self.recent_menu = None;
+                    self.context_menu = Some(ContextMenu::new(screen_pos, self.selected_shape_index.is_some(), self.selected_connector_index.is_some()));
+                }
+                Ok(())
+            }
+            TouchGesture::TwoFinger { center, pan, zoom } => {
+                self.mouse_motion_event(ctx, center.x, center.y, 0.0, 0.0)?;
+                self.scroll_pan_event(ctx, pan.x, pan.y)?;
+                self.pinch_event(ctx, zoom - 1.0)
+            }
+        }
+    }
+
+    // Shows whether a drag will pan; panels keep the normal pointer unless a pan is under way
+    fn refresh_cursor(&mut self, ctx: &Context) {
+        let panning = self.pan_last_screen_pos.is_some();
+        let (_, space_pressed) = self.held_keys(ctx);
+        let over_ui = !panning && self.over_ui(self.live_mouse_screen_pos, self.logical_screen_size(ctx));
+        let icon = if over_ui { CursorIcon::Default } else { tools::canvas_cursor(self.tool, space_pressed && self.editing_shape_index.is_none(), panning) };
+        if icon == self.cursor_icon { return; }
+        ctx.gfx.window().set_cursor_icon(icon);
+        self.cursor_icon = icon;
+    }
+
+    // The inspector and code panel swallow wheel and gesture input over them
+    fn pointer_over_side_panel(&self, ctx: &Context) -> bool {
+        if self.read_only { return false; }
+        let screen_size = self.logical_screen_size(ctx);
+        self.inspector.panel_rect(screen_size.x, screen_size.y).or(self.code_panel_rect(screen_size))
+            .is_some_and(|panel| panel.contains(self.live_mouse_screen_pos))
+    }
+
+    // Wheel zoom, after [input]'s sensitivity and inversion
+    fn zoom_by_notches(&mut self, notches: f32) {
+        let direction = if self.invert_zoom { -1.0 } else { 1.0 };
+        self.zoom_at_pointer(camera::ZOOM_STEP.powf(notches * self.zoom_sensitivity * direction));
+    }
+
+    fn zoom_at_pointer(&mut self, factor: f32) {
+        self.camera_animation = None;
+        self.camera.zoom_at(self.live_mouse_screen_pos, factor);
+        self.live_mouse_pos = self.camera.screen_to_world(self.live_mouse_screen_pos);
+    }
+
+    // Converts physical window coordinates (as given by ggez events) to logical screen coordinates
+    fn physical_to_screen(&self, x: f32, y: f32) -> Vec2 {
+        Vec2::new(x / self.ui_scale, y / self.ui_scale)
+    }
+
+    // --- Canvas input: what a click, drag or release does once the panels and overlays have passed
+    // on it. Nothing here needs a Context, so the headless tests drive the editor through these. ---
+    fn press_canvas(&mut self, button: MouseButton, screen_pos: Vec2, mods: KeyMods, space_pressed: bool, time: Instant) {
+        self.refresh_spatial_index(); // Shapes may have changed since the last update
+        if self.simulation.is_some() { self.simulation_press(button, screen_pos); return; }
+        if self.read_only { self.read_only_press(button, screen_pos); return; }
+        let pos = self.camera.screen_to_world(screen_pos);
+
+        // --- Panning: middle-mouse drag, space + left drag, or left drag with the Pan tool ---
+        let space_held = space_pressed && self.editing_shape_index.is_none();
+        if button == MouseButton::Middle || (button == MouseButton::Left && (space_held || self.tool == Tool::Pan)) {
+            self.pan_last_screen_pos = Some(screen_pos);
+            return;
+        }
+
+        // Shift+click adds shapes to (or removes them from) the selection
+        if button == MouseButton::Left && self.tool == Tool::Select && mods.contains(KeyMods::SHIFT) && !self.drawing_new_line {
+            if let Some(index) = self.shape_at(pos) {
+                self.toggle_shape_in_selection(index);
+                return;
+            }
+        }
+
+        if button == MouseButton::Left {
+            match self.tool {
+                Tool::Select => self.select_tool_press(pos, time),
+                Tool::Connect => self.connect_tool_press(pos),
+                Tool::Create => self.create_tool_press(pos, time),
+                Tool::Delete => self.delete_tool_press(pos),
+                Tool::Pen => self.pen_tool_press(pos, mods.contains(KeyMods::SHIFT)),
+                Tool::Eraser => { self.erasing = true; self.erase_annotations_at(pos); }
+                Tool::Pan => {}
+            }
+        }
+    }
+
+    // `over_ui`: whether the pointer is over a panel, where a template dragged from the palette isn't dropped
+    fn release_canvas(&mut self, button: MouseButton, over_ui: bool) {
+        if button == MouseButton::Left && (self.pen_stroke.is_some() || self.erasing) {
+            self.finish_pen_stroke();
+            self.erasing = false;
+            return;
+        }
+        if button == MouseButton::Left {
+            if let Some(template_index) = self.palette_drag.take() {
+                if !over_ui { self.drop_template(template_index, self.live_mouse_pos); }
+                return;
+            }
+        }
+        if (button == MouseButton::Middle || button == MouseButton::Left) && self.pan_last_screen_pos.is_some() {
+            self.pan_last_screen_pos = None;
+            return;
+        }
+        if button == MouseButton::Left && self.dragged_connector_endpoint.is_some() {
+            self.finish_connector_endpoint_drag(self.live_mouse_pos);
+            return;
+        }
+        if button == MouseButton::Left {
+            if let Some((conn_idx, _)) = self.bending_connector.take() {
+                log::info!(target: logging::MODEL, "Connector {} curvature set.", conn_idx);
+                return;
+            }
+            if let Some((index, _)) = self.rotating_shape.take() {
+                let degrees = self.diagram.shapes.get(index).map_or(0.0, |shape_data| shape_data.rotation.to_degrees());
+                log::info!(target: logging::MODEL, "Shape {} rotated to {:.0}°.", index, degrees);
+                return;
+            }
+        }
+        // Drag-to-connect: releasing after dragging from the start port ends the line on the port under
+        // the mouse, or cancels it over empty space. Releasing in place keeps the line for a second click.
+        if button == MouseButton::Left {
+            if let Some(press_pos) = self.new_line_press_pos.take() {
+                if self.drawing_new_line && self.live_mouse_pos.distance(press_pos) * self.camera.zoom > NEW_LINE_DRAG_THRESHOLD {
+                    let mut target = self.new_line_target(self.live_mouse_pos);
+                    if self.tool == Tool::Connect {
+                        target = target.or_else(|| self.shape_body_target(self.live_mouse_pos, self.new_line_start_info.map(|(start, _)| start)));
+                    }
+                    self.finish_new_line(target);
+                    return;
+                }
+            }
+        }
+        if button == MouseButton::Left && self.dragged_shape_index.is_some() {
+            self.dragged_shape_index = None;
+            self.drag_offset = None;
+        }
+        if button == MouseButton::Left { self.dragged_group = None; self.lane_drag = None; }
+        if button == MouseButton::Left { self.selecting_text = false; }
+    }
+
+    fn move_pointer(&mut self, screen_pos: Vec2) {
+        if let Some(last_screen_pos) = self.pan_last_screen_pos {
+            self.camera_animation = None;
+            self.camera.pan_by_screen_delta(screen_pos - last_screen_pos);
+            self.pan_last_screen_pos = Some(screen_pos);
+        }
+        self.live_mouse_screen_pos = screen_pos;
+        self.live_mouse_pos = self.camera.screen_to_world(screen_pos);
+        if self.stress_timing.is_some() {
+            let started = Instant::now();
+            self.refresh_spatial_index();
+            std::hint::black_box((self.shape_at(self.live_mouse_pos), self.connector_at(self.live_mouse_pos)));
+            let elapsed = started.elapsed();
+            if let Some(timing) = self.stress_timing.as_mut() { timing.hit_tests += 1; timing.hit_test_time += elapsed; }
+        }
+        if self.selecting_text && self.editing_shape_index.is_some() {
+            self.text_edit.move_to(self.caret_index_at(self.live_mouse_pos), true);
+        }
+        if let Some(index) = self.dragged_shape_index {
+            if let Some(offset) = self.drag_offset {
+                self.diagram.move_shape(index, self.snap_position(self.live_mouse_pos + offset));
+            }
+        }
+        if self.lane_drag.is_some() { self.drag_lane(self.live_mouse_pos); }
+        if let Some((conn_idx, _)) = self.bending_connector { self.bend_connector(conn_idx, self.live_mouse_pos); }
+        if let Some((index, _)) = self.rotating_shape { self.rotate_shape_towards(index, self.live_mouse_pos); }
+        if self.pen_stroke.is_some() { self.extend_pen_stroke(self.live_mouse_pos); }
+        if self.erasing { self.erase_annotations_at(self.live_mouse_pos); }
+        if let Some((group, last_pos)) = self.dragged_group {
+            self.diagram.move_group(group, self.live_mouse_pos - last_pos);
+            self.dragged_group = Some((group, self.live_mouse_pos));
+        }
+    }
+}
+
+// --- EventHandler Implementation ---
+impl EventHandler<ggez::GameError> for AppState {
+    fn update(&mut self, ctx: &mut Context) -> GameResult {
+        self.last_frame_at = Instant::now();
+        if let Some(gesture) = self.touch.poll(Instant::now()) { self.touch_gesture(ctx, gesture)?; }
+        self.refresh_cursor(ctx);
+        self.feed_replay(ctx)?;
+        self.sync_collaboration(Instant::now());
+        self.report_stress_timing();
+        self.fit_shapes_to_text(ctx);
+        self.step_layout_animation();
+        self.step_camera_animation();
+        self.step_token_animation();
+        if self.flow_animation {
+            self.flow_phase = (self.flow_phase + ctx.time.delta().as_secs_f32() * FLOW_DOT_SPEED) % FLOW_DOT_SPACING;
+        }
+        self.refresh_connector_routes();
+        self.refresh_connector_crossings();
+        self.poll_config();
+        for notification in logging::take_notifications() {
+            self.toasts.push(notification);
+            self.needs_redraw = true;
+        }
+        if self.toasts.expire(Instant::now()) { self.needs_redraw = true; }
+        self.refresh_spatial_index();
+        if self.needs_redraw {
+            let fingerprint = self.refresh_dirty_flag(ctx);
+            if !self.interaction_in_progress() { self.history.record(fingerprint, &self.diagram); }
+            self.refresh_focused_shapes();
+            self.refresh_cycle_connections();
+            self.refresh_graph_analysis();
+            self.refresh_graph_stats();
+            self.refresh_code_panel();
+        }
+        if self.drawing_new_line {
+            let snapped = self.new_line_target(self.live_mouse_pos).and_then(|(index, port_id)| self.get_port_point(index, port_id));
+            self.new_line_preview_end_pos = Some(snapped.unwrap_or(self.live_mouse_pos));
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        let draw_started = Instant::now();
+        self.frame_stats = FrameStats::default();
+        let mut canvas = graphics::Canvas::from_frame(ctx, self.theme.background);
+
+        let screen_size = self.logical_screen_size(ctx);
+        let (logical_width, logical_height) = (screen_size.x, screen_size.y);
+        let visible_world_rect = self.camera.visible_rect(logical_width, logical_height);
+        canvas.set_screen_coordinates(visible_world_rect);
+
+        if self.grid_enabled {
+            self.draw_grid(ctx, &mut canvas, visible_world_rect)?;
+        }
+
+        self.layout_editing_text(ctx);
+        self.place_ime_window(ctx);
+        self.refresh_connector_meshes(ctx);
+        self.refresh_annotation_meshes(ctx);
+        self.prepare_shape_meshes(ctx)?;
+        self.draw_lanes(ctx, &mut canvas, true)?;
+        self.draw_groups(ctx, &mut canvas, true)?;
+        self.draw_connectors(ctx, &mut canvas, true);
+        if self.flow_animation { self.draw_flow_dots(ctx, &mut canvas)?; }
+
+        // --- Draw Preview Connector Line ---
+        if let Some((start_pos, preview_end_pos)) = self.preview_line() {
+            if start_pos != preview_end_pos {
+                let preview_color = if self.preview_target_refused() { self.theme.refused } else { self.theme.preview_connector };
+                let line_preview_mesh = Mesh::new_line(ctx, &[start_pos, preview_end_pos], CONNECTOR_LINE_WIDTH / 2.0, preview_color)?;
+                canvas.draw(&line_preview_mesh, graphics::DrawParam::default());
+            }
+        }
+
+        self.draw_shapes(ctx, &mut canvas, true)?;
+        self.draw_search_highlights(ctx, &mut canvas)?;
+        self.draw_presentation_step(ctx, &mut canvas)?;
+        self.draw_simulation(ctx, &mut canvas)?;
+        self.draw_collaborators(ctx, &mut canvas)?;
+        self.draw_graph_analysis(ctx, &mut canvas)?;
+        self.draw_annotations(ctx, &mut canvas);
+
+        // --- Draw Endpoint Handles of the Selected Connector ---
+        if let Some((source_pos, target_pos)) = self.selected_connector_endpoints() {
+            for handle_pos in [source_pos, target_pos] {
+                let handle_rect = Rect::new(
+                    handle_pos.x - CONNECTOR_HANDLE_SIZE / 2.0, handle_pos.y - CONNECTOR_HANDLE_SIZE / 2.0,
+                    CONNECTOR_HANDLE_SIZE, CONNECTOR_HANDLE_SIZE,
+                );
+                let handle_mesh = Mesh::new_rectangle(ctx, DrawMode::stroke(1.5), handle_rect, self.theme.selected_connector)?;
+                canvas.draw(&handle_mesh, graphics::DrawParam::default());
+            }
+        }
+        if let Some(bend_handle) = self.connector_bend_handle() {
+            let handle_mesh = Mesh::new_circle(ctx, DrawMode::stroke(1.5), bend_handle, CONNECTOR_HANDLE_SIZE / 2.0, 0.5, self.theme.selected_connector)?;
+            canvas.draw(&handle_mesh, graphics::DrawParam::default());
+        }
+        if let Some((index, handle)) = self.rotation_handle() {
+            let shape_data = &self.diagram.shapes[index];
+            let rect = self.shape_rect(shape_data);
+            let stem = [shape_data.to_world(Vec2::new(rect.center().x, rect.top())), handle];
+            let stem_mesh = Mesh::new_line(ctx, &stem, 1.5, self.theme.selection)?;
+            canvas.draw(&stem_mesh, graphics::DrawParam::default());
+            let handle_mesh = Mesh::new_circle(ctx, DrawMode::fill(), handle, CONNECTOR_HANDLE_SIZE / 2.0, 0.5, self.theme.selection)?;
+            canvas.draw(&handle_mesh, graphics::DrawParam::default());
+        }
+
+        // --- Outlines of the nodes a palette template would drop as ---
+        if let Some(template) = self.palette_drag.and_then(|i| self.node_templates.get(i)) {
+            let drop_point = self.snap_position(self.live_mouse_pos);
+            for part in template.parts() {
+                let size = part.size.map_or(self.default_shape_size(), Vec2::from);
+                let center = drop_point + Vec2::from(part.offset);
+                let ghost = Rect::new(center.x - size.x / 2.0, center.y - size.y / 2.0, size.x, size.y);
+                let ghost_mesh = Mesh::new_rectangle(ctx, DrawMode::stroke(1.5), ghost, self.theme.preview_connector)?;
+                canvas.draw(&ghost_mesh, graphics::DrawParam::default());
+            }
+        }
+
+        // --- Screen-space overlay ---
+        canvas.set_screen_coordinates(Rect::new(0.0, 0.0, logical_width, logical_height));
+
+        // Read-only shows the diagram alone, apart from notifications and the save prompt
+        if !self.read_only {
+            let status_area = self.status_area(Vec2::new(logical_width, logical_height));
+            status_bar::draw_status_bar(ctx, &mut canvas, status_area, &self.status_info(), &ui::UiStyle::default())?;
+        }
+        self.toasts.draw(ctx, &mut canvas, self.toast_area(Vec2::new(logical_width, logical_height)))?;
+        if !self.read_only { self.draw_panels(ctx, &mut canvas, screen_size)?; }
+        if self.save_prompt.is_some() {
+            let (panel, widgets) = self.save_prompt_widgets(screen_size);
+            ui::draw_panel(ctx, &mut canvas, panel, &widgets, &ui::UiStyle::default())?;
+        }
+        
+        canvas.finish(ctx)?;
+        self.needs_redraw = false;
+        self.frame_stats.total = draw_started.elapsed();
+        if self.debug_overlay.is_some() { self.debug_overlay = Some(self.frame_stats); }
+        if let Some(timing) = self.stress_timing.as_mut() { timing.frames += 1; timing.draw_time += self.frame_stats.total; }
+        Ok(())
+    }
+
+    fn mouse_button_down_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) -> GameResult {
+        let (mods, space_pressed) = self.held_keys(ctx);
+        if !self.observe(SessionEvent::MouseDown { button: RecordedButton::from_button(button), x, y, mods: mods.bits(), space_held: space_pressed }) {
+            return Ok(());
+        }
+        self.needs_redraw = true;
+
+        // --- An open command palette takes the click: a match runs, anywhere else closes it ---
+        let screen_pos = self.physical_to_screen(x, y);
+        if !self.toasts.is_empty() && self.toasts.dismiss_at(ctx, screen_pos, self.toast_area(self.logical_screen_size(ctx))) {
+            return Ok(());
+        }
+        if self.save_prompt.is_some() {
+            let (_, widgets) = self.save_prompt_widgets(self.logical_screen_size(ctx));
+            if let (Some((choice, _)), MouseButton::Left) = (ui::hit_test(&widgets, screen_pos), button) {
+                self.answer_save_prompt(ctx, choice);
+            }
+            return Ok(());
+        }
+        // While simulating, a branch button takes the token that way and anything else goes to the canvas
+        if let Some(simulation) = &self.simulation {
+            let screen_size = self.logical_screen_size(ctx);
+            let hit = simulation.branch_widgets(screen_size, &self.diagram).and_then(|(_, widgets)| ui::hit_test(&widgets, screen_pos));
+            match hit {
+                Some((index, _)) if button == MouseButton::Left => self.step_simulation(screen_size, Some(index)),
+                _ => self.press_canvas(button, screen_pos, mods, space_pressed, Instant::now()),
+            }
+            return Ok(());
+        }
+        if self.read_only {
+            self.press_canvas(button, screen_pos, mods, space_pressed, Instant::now());
+            return Ok(());
+        }
+        if let Some(palette) = &self.command_palette {
+            let (_, widgets) = palette.widgets(self.logical_screen_size(ctx));
+            match ui::hit_test(&widgets, screen_pos) {
+                Some((index, _)) if button == MouseButton::Left => self.run_palette_choice(ctx, Some(index)),
+                _ => self.command_palette = None,
+            }
+            return Ok(());
+        }
+        if let Some(search) = &self.shape_search {
+            let (_, widgets) = search.widgets(self.logical_screen_size(ctx), &self.diagram);
+            match ui::hit_test(&widgets, screen_pos) {
+                Some((index, _)) if button == MouseButton::Left => self.run_search_choice(self.logical_screen_size(ctx), Some(index)),
+                _ => self.shape_search = None,
+            }
+            return Ok(());
+        }
+        if let Some(menu) = &self.recent_menu {
+            let (_, widgets) = menu.widgets(self.logical_screen_size(ctx), &self.recent_files);
+            match ui::hit_test(&widgets, screen_pos) {
+                Some((index, _)) if button == MouseButton::Left => self.run_recent_choice(ctx, Some(index)),
+                _ => self.recent_menu = None,
+            }
+            return Ok(());
+        }
+        if let Some(menu) = &self.context_menu {
+            let (_, widgets) = menu.widgets(self.logical_screen_size(ctx));
+            match ui::hit_test(&widgets, screen_pos) {
+                Some((index, _)) if button == MouseButton::Left => self.run_context_menu_choice(ctx, Some(index)),
+                _ => self.context_menu = None,
+            }
+            return Ok(());
+        }
+
+        // --- The inspector panel is hit-tested on its own, before anything on the canvas ---
+        self.commit_inspector_edit();
+        if let Some((panel, widgets)) = self.inspector_widgets(self.logical_screen_size(ctx)) {
+            if panel.contains(screen_pos) {
+                if button == MouseButton::Left {
+                    if let Some((field, hit)) = ui::hit_test(&widgets, screen_pos) {
+                        self.handle_inspector_click(field, hit);
+                    }
+                }
+                return Ok(());
+            }
+        }
+        if let Some((panel, widgets)) = self.layers_widgets() {
+            if panel.contains(screen_pos) {
+                if button == MouseButton::Left {
+                    if let Some((action, _)) = ui::hit_test(&widgets, screen_pos) { self.handle_layer_click(action); }
+                }
+                return Ok(());
+            }
+        }
+        // A click in the code panel focuses its text; anywhere else hands the keys back to the canvas
+        match self.code_panel_rect(self.logical_screen_size(ctx)) {
+            Some(panel) if panel.contains(screen_pos) => {
+                if button == MouseButton::Left {
+                    let extend_selection = mods.contains(KeyMods::SHIFT);
+                    self.code_panel.click(ctx, panel, screen_pos, extend_selection);
+                }
+                return Ok(());
+            }
+            _ => self.blur_code_panel(),
+        }
+
+        // --- Toolbar ---
+        let (toolbar, tool_buttons) = tools::toolbar_widgets(self.tool);
+        if toolbar.contains(screen_pos) {
+            if button == MouseButton::Left {
+                if let Some((tool, _)) = ui::hit_test(&tool_buttons, screen_pos) { self.run_command(ctx, Command::UseTool(tool)); }
+            }
+            return Ok(());
+        }
+
+        // --- Node palette: pressing a template starts dragging it onto the canvas ---
+        if let Some((strip, template_buttons)) = self.palette_widgets() {
+            if strip.contains(screen_pos) {
+                if button == MouseButton::Left {
+                    self.palette_drag = ui::hit_test(&template_buttons, screen_pos).map(|(template_index, _)| template_index);
+                }
+                return Ok(());
+            }
+        }
+
+        // --- Tab strip ---
+        let (tab_strip, tab_buttons) = self.tab_widgets();
+        if tab_strip.contains(screen_pos) {
+            match ui::hit_test(&tab_buttons, screen_pos) {
+                Some((TabAction::Switch(index), _)) if button == MouseButton::Left => self.switch_tab(ctx, index),
+                Some((TabAction::Switch(index), _)) if button == MouseButton::Middle => {
+                    self.switch_tab(ctx, index);
+                    self.request_close_tab(ctx);
+                }
+                Some((TabAction::New, _)) if button == MouseButton::Left => self.run_command(ctx, Command::NewTab),
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        // --- Breadcrumb in the status bar: a click on a diagram above this one goes back up to it ---
+        let crumbs = status_bar::breadcrumb_rects(self.status_area(self.logical_screen_size(ctx)), self.status_font_size, self.breadcrumb_names().len());
+        if let Some(level) = crumbs.iter().position(|rect| rect.contains(screen_pos)) {
+            if button == MouseButton::Left && level < self.breadcrumb.len() { self.open_parent_diagram(ctx, level); }
+            return Ok(());
+        }
+
+        // --- Ctrl+double-click on a shape drills into its sub-diagram ---
+        if button == MouseButton::Left && mods.contains(KeyMods::CTRL) && self.tool == Tool::Select {
+            self.refresh_spatial_index();
+            let pos = self.camera.screen_to_world(screen_pos);
+            if let Some(index) = self.shape_at(pos).filter(|_| self.is_double_click(pos, Instant::now())) {
+                self.last_click_time = None; self.last_click_pos = None;
+                self.open_subdiagram(ctx, index);
+                return Ok(());
+            }
+        }
+
+        self.press_canvas(button, screen_pos, mods, space_pressed, Instant::now());
+        Ok(())
+    }
+
+    fn mouse_button_up_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) -> GameResult {
+        if !self.observe(SessionEvent::MouseUp { button: RecordedButton::from_button(button), x, y }) { return Ok(()); }
+        self.needs_redraw = true;
+        let over_ui = self.over_ui(self.live_mouse_screen_pos, self.logical_screen_size(ctx));
+        self.release_canvas(button, over_ui);
+        Ok(())
+    }
+
+    fn mouse_motion_event(&mut self, _ctx: &mut Context, x: f32, y: f32, dx: f32, dy: f32) -> GameResult {
+        if !self.observe(SessionEvent::MouseMotion { x, y, dx, dy }) { return Ok(()); }
+        self.needs_redraw = true;
+        self.move_pointer(self.physical_to_screen(x, y));
+        Ok(())
+    }
+
+    fn mouse_wheel_event(&mut self, ctx: &mut Context, x: f32, y: f32) -> GameResult {
+        let (mods, _) = self.held_keys(ctx);
+        if !self.observe(SessionEvent::Wheel { x, y, mods: mods.bits() }) { return Ok(()); }
+        self.needs_redraw = true;
+        if self.pointer_over_side_panel(ctx) { return Ok(()); }
+        if mods.contains(KeyMods::ALT) && self.rotate_selection_by_wheel(y) { return Ok(()); }
+        if self.wheel_action == WheelAction::Zoom || mods.contains(KeyMods::CTRL) {
+            if y != 0.0 { self.zoom_by_notches(y); }
+        } else {
+            let notches = if mods.contains(KeyMods::SHIFT) { Vec2::new(y, x) } else { Vec2::new(x, y) };
+            let direction = if self.invert_pan { -1.0 } else { 1.0 };
+            self.camera_animation = None;
+            self.camera.pan_by_screen_delta(notches * WHEEL_PAN_STEP * direction);
+            self.live_mouse_pos = self.camera.screen_to_world(self.live_mouse_screen_pos);
+        }
+        Ok(())
+    }
+
+    fn resize_event(&mut self, _ctx: &mut Context, width: f32, height: f32) -> GameResult {
+        // Not input: a replay resizes the window itself, so this is never ignored
+        if let Some(recorder) = self.recorder.as_mut() { recorder.record(SessionEvent::Resize { width, height }); }
+        self.needs_redraw = true;
+        Ok(())
+    }
+
+    // Closing the window with unsaved changes asks first, one tab at a time; returning true keeps the editor open
+    fn quit_event(&mut self, ctx: &mut Context) -> Result<bool, ggez::GameError> {
+        self.needs_redraw = true;
+        let keep_open = !self.quit_after_replay && self.ask_before_quit(ctx);
+        if !keep_open {
+            if let Some(recorder) = self.recorder.as_mut() {
+                match recorder.flush() {
+                    Ok(()) => log::info!(target: logging::IO, "Session recorded to {}", recorder.path().display()),
+                    Err(e) => log::error!(target: logging::IO, "{}", e),
+                }
+            }
+        }
+        Ok(keep_open)
     }
 
     fn text_input_event(&mut self, _ctx: &mut Context, character: char) -> GameResult {
+        if !self.observe(SessionEvent::Text { character }) { return Ok(()); }
+        self.needs_redraw = true;
+        if self.read_only || self.save_prompt.is_some() || self.recent_menu.is_some() { return Ok(()); }
+        if let Some(palette) = self.command_palette.as_mut() {
+            if !character.is_control() { palette.type_char(character); }
+            return Ok(());
+        }
+        if let Some(search) = self.shape_search.as_mut() {
+            if !character.is_control() { search.type_char(character); }
+            return Ok(());
+        }
+        if let Some(edit) = self.inspector.editing.as_mut() {
+            if !character.is_control() { edit.text.push(character); }
+            return Ok(());
+        }
+        if self.code_panel.focused {
+            if !character.is_control() { self.code_panel.edit.insert_char(character); self.code_panel.check_text(); }
+            return Ok(());
+        }
         if self.editing_shape_index.is_some() && !character.is_control() {
-            self.current_input_text.push(character);
+            self.text_edit.insert_char(character);
         }
         Ok(())
     }
 
-    fn key_down_event(&mut self, _ctx: &mut Context, input: KeyInput, repeated: bool) -> GameResult {
+    fn key_down_event(&mut self, ctx: &mut Context, input: KeyInput, repeated: bool) -> GameResult {
+        if let Some(keycode) = input.keycode {
+            if !self.observe(SessionEvent::key(keycode, input.mods, repeated)) { return Ok(()); }
+        }
+        self.needs_redraw = true;
         if let Some(keycode) = input.keycode {
+            // The save prompt takes all keys: Enter or S saves, D discards, Esc cancels
+            if self.save_prompt.is_some() {
+                match keycode {
+                    _ if repeated => {}
+                    KeyCode::Return | KeyCode::NumpadEnter | KeyCode::S => self.answer_save_prompt(ctx, SaveChoice::Save),
+                    KeyCode::D => self.answer_save_prompt(ctx, SaveChoice::Discard),
+                    KeyCode::Escape => self.answer_save_prompt(ctx, SaveChoice::Cancel),
+                    _ => {}
+                }
+                return Ok(());
+            }
+            // The simulation passes on only the view commands; Space steps, and at a branch the arrows
+            // and digits pick the way on
+            let screen_size = self.logical_screen_size(ctx);
+            if let Some(simulation) = self.simulation.as_mut() {
+                if repeated { return Ok(()); }
+                let digit = number_key_value(keycode).filter(|&n| n > 0).map(|n| n - 1);
+                match Command::from_key(keycode, input.mods) {
+                    Some(command @ (Command::Simulate | Command::ZoomToFit | Command::ZoomToSelection)) => self.run_command(ctx, command),
+                    _ if matches!(keycode, KeyCode::Escape) => self.toggle_simulation(),
+                    _ if matches!(keycode, KeyCode::Space | KeyCode::Return | KeyCode::NumpadEnter) => self.step_simulation(screen_size, None),
+                    _ if simulation.branch.is_some() && matches!(keycode, KeyCode::Up | KeyCode::Down) => simulation.move_selection(if keycode == KeyCode::Up { -1 } else { 1 }),
+                    _ if simulation.branch.is_some() && digit.is_some() => self.step_simulation(screen_size, digit),
+                    _ => {}
+                }
+                return Ok(());
+            }
+            // Read-only passes on only the view commands and its own toggle; arrows step through the
+            // presentation and Escape drops the highlight
+            if self.read_only {
+                if repeated { return Ok(()); }
+                match Command::from_key(keycode, input.mods) {
+                    Some(command @ (Command::ToggleReadOnly | Command::ZoomToFit | Command::ZoomToSelection)) => self.run_command(ctx, command),
+                    _ if matches!(keycode, KeyCode::Right | KeyCode::PageDown) => self.advance_presentation(ctx, true),
+                    _ if matches!(keycode, KeyCode::Left | KeyCode::PageUp) => self.advance_presentation(ctx, false),
+                    _ if keycode == KeyCode::Escape => self.clear_selection(),
+                    _ => {}
+                }
+                return Ok(());
+            }
+            // A focused inspector field takes all keys until it is committed or cancelled
+            if let Some(edit) = self.inspector.editing.as_mut() {
+                match keycode {
+                    KeyCode::Return | KeyCode::NumpadEnter if !repeated => self.commit_inspector_edit(),
+                    KeyCode::Escape if !repeated => self.inspector.editing = None,
+                    KeyCode::Back => { edit.text.pop(); }
+                    _ => {}
+                }
+                return Ok(());
+            }
+            // So does the command palette while it is open
+            if let Some(palette) = self.command_palette.as_mut() {
+                match keycode {
+                    KeyCode::Escape if !repeated => self.command_palette = None,
+                    KeyCode::Return | KeyCode::NumpadEnter if !repeated => self.run_palette_choice(ctx, None),
+                    KeyCode::Up => palette.move_selection(-1),
+                    KeyCode::Down => palette.move_selection(1),
+                    KeyCode::Back => palette.backspace(),
+                    _ => {}
+                }
+                return Ok(());
+            }
+            // ...the long-press menu
+            if let Some(menu) = self.context_menu.as_mut() {
+                match keycode {
+                    KeyCode::Escape if !repeated => self.context_menu = None,
+                    KeyCode::Return | KeyCode::NumpadEnter if !repeated => self.run_context_menu_choice(ctx, None),
+                    KeyCode::Up => menu.move_selection(-1),
+                    KeyCode::Down => menu.move_selection(1),
+                    _ => {}
+                }
+                return Ok(());
+            }
+            // ...the recent files overlay
+            if let Some(menu) = self.recent_menu.as_mut() {
+                match keycode {
+                    KeyCode::Escape if !repeated => self.recent_menu = None,
+                    KeyCode::Return | KeyCode::NumpadEnter if !repeated => self.run_recent_choice(ctx, None),
+                    KeyCode::Up => menu.move_selection(&self.recent_files, -1),
+                    KeyCode::Down => menu.move_selection(&self.recent_files, 1),
+                    _ => {}
+                }
+                return Ok(());
+            }
+            // ...and the search overlay
+            if let Some(search) = self.shape_search.as_mut() {
+                match keycode {
+                    KeyCode::Escape if !repeated => self.shape_search = None,
+                    KeyCode::Return | KeyCode::NumpadEnter if !repeated && input.mods.contains(KeyMods::CTRL) => {
+                        let query = search.query.trim().to_string();
+                        self.shape_search = None;
+                        if !query.is_empty() {
+                            log::info!(target: logging::RENDER, "Focusing on shapes matching '{}'.", query);
+                            self.focus_filter = Some(query);
+                        }
+                    }
+                    KeyCode::Return | KeyCode::NumpadEnter if !repeated => self.run_search_choice(self.logical_screen_size(ctx), None),
+                    KeyCode::Up => search.move_selection(&self.diagram, -1),
+                    KeyCode::Down => search.move_selection(&self.diagram, 1),
+                    KeyCode::Back => search.backspace(),
+                    _ => {}
+                }
+                return Ok(());
+            }
+
+            // ...and the code panel while focused, apart from Ctrl shortcuts like Ctrl+S (other than the editing ones)
+            if self.code_panel.focused {
+                let editing_shortcut = matches!(keycode, KeyCode::A | KeyCode::C | KeyCode::X | KeyCode::V | KeyCode::Back | KeyCode::Delete | KeyCode::Left | KeyCode::Right);
+                if input.mods.contains(KeyMods::CTRL) && !editing_shortcut && !repeated {
+                    if let Some(command) = Command::from_key(keycode, input.mods) {
+                        self.apply_code_panel_text(); // So that Ctrl+S and the like see what was typed
+                        self.run_command(ctx, command);
+                        return Ok(());
+                    }
+                }
+                if self.code_panel.key(keycode, input.mods, &mut self.system_clipboard) { self.code_panel.check_text(); }
+                if matches!(keycode, KeyCode::Return | KeyCode::NumpadEnter) || !self.code_panel.focused { self.apply_code_panel_text(); }
+                return Ok(());
+            }
+
             if self.drawing_new_line && keycode == KeyCode::Escape && !repeated {
                 self.drawing_new_line = false; self.new_line_start_info = None; self.new_line_preview_end_pos = None;
-                println!("New line drawing cancelled by Escape.");
+                log::debug!(target: logging::INPUT, "New line drawing cancelled by Escape.");
+                return Ok(());
+            }
+            if let (Some(index), KeyCode::Escape, false) = (self.dragged_shape_index, keycode, repeated) {
+                if let Some(start) = self.drag_start_position.take() { self.diagram.move_shape(index, start); }
+                self.dragged_shape_index = None; self.drag_offset = None;
+                log::debug!(target: logging::INPUT, "Shape drag cancelled by Escape.");
+                return Ok(());
+            }
+            if let (Some((conn_idx, previous_bend)), KeyCode::Escape, false) = (self.bending_connector, keycode, repeated) {
+                if let Some(connection) = self.diagram.connections.get_mut(conn_idx) { connection.bend = previous_bend; }
+                self.bending_connector = None;
+                log::debug!(target: logging::INPUT, "Connector bend cancelled by Escape.");
+                return Ok(());
+            }
+            if let (Some((index, previous_rotation)), KeyCode::Escape, false) = (self.rotating_shape, keycode, repeated) {
+                if let Some(shape_data) = self.diagram.shapes.get_mut(index) { shape_data.rotation = previous_rotation; }
+                self.rotating_shape = None;
+                log::debug!(target: logging::INPUT, "Shape rotation cancelled by Escape.");
+                return Ok(());
+            }
+            if self.pen_stroke.is_some() && keycode == KeyCode::Escape && !repeated {
+                self.pen_stroke = None;
+                log::debug!(target: logging::INPUT, "Pen stroke cancelled by Escape.");
+                return Ok(());
+            }
+            if self.dragged_connector_endpoint.is_some() && keycode == KeyCode::Escape && !repeated {
+                self.dragged_connector_endpoint = None;
+                log::debug!(target: logging::INPUT, "Connector reconnection cancelled by Escape.");
+                return Ok(());
+            }
+
+            // Shortcuts bound to commands; while a shape's text is being edited the keys go to the text instead
+            if self.editing_shape_index.is_none() && !repeated {
+                if let Some(command) = Command::from_key(keycode, input.mods) {
+                    self.run_command(ctx, command);
+                    return Ok(());
+                }
+            }
+
+            // Number keys recolor the selected shape from the palette (Shift: outline), [ / ] adjust its corner radius, + / - its font size
+            if self.editing_shape_index.is_none() && self.selected_shape_index.is_some() && !input.mods.contains(KeyMods::CTRL) && !repeated {
+                if let Some(slot) = number_key_value(keycode) {
+                    self.apply_palette_color(slot, input.mods.contains(KeyMods::SHIFT));
+                    return Ok(());
+                }
+                match keycode {
+                    KeyCode::LBracket => { self.adjust_selected_corner_radius(-CORNER_RADIUS_STEP); return Ok(()); }
+                    KeyCode::RBracket => { self.adjust_selected_corner_radius(CORNER_RADIUS_STEP); return Ok(()); }
+                    KeyCode::Equals | KeyCode::Plus | KeyCode::NumpadAdd => { self.adjust_selected_font_size(FONT_SIZE_STEP); return Ok(()); }
+                    KeyCode::Minus | KeyCode::NumpadSubtract => { self.adjust_selected_font_size(-FONT_SIZE_STEP); return Ok(()); }
+                    _ => {}
+                }
+            }
+
+            // Alt+arrows move the selection along connections
+            if self.editing_shape_index.is_none() && input.mods.contains(KeyMods::ALT) && arrow_key_direction(keycode).is_some() {
+                self.follow_connection(keycode, self.logical_screen_size(ctx));
+                return Ok(());
+            }
+            // Arrow keys nudge the selection; holding one keeps moving it at the key repeat rate
+            if self.editing_shape_index.is_none() && !input.mods.contains(KeyMods::CTRL) && self.dragged_shape_index.is_none() {
+                if let Some(direction) = arrow_key_direction(keycode) {
+                    self.nudge_selection(direction, input.mods.contains(KeyMods::SHIFT));
+                    return Ok(());
+                }
+            }
+
+            // Tab / Shift+Tab move the selection through the shapes, committing any text being edited
+            if keycode == KeyCode::Tab && !input.mods.contains(KeyMods::CTRL) && self.dragged_shape_index.is_none() {
+                self.cycle_shape_selection(input.mods.contains(KeyMods::SHIFT), self.logical_screen_size(ctx));
                 return Ok(());
             }
 
             if self.editing_shape_index.is_some() { 
+                let shift_held = input.mods.contains(KeyMods::SHIFT);
                 match keycode {
+                    KeyCode::Return | KeyCode::NumpadEnter if shift_held != self.enter_inserts_newline => self.text_edit.insert_char('\n'),
                     KeyCode::Return | KeyCode::NumpadEnter => {
                         if repeated { return Ok(()); }
-                        if let Some(index) = self.editing_shape_index.take() {
-                            self.clicked_shapes[index].text = if self.current_input_text.is_empty() { None } else { Some(self.current_input_text.clone()) };
-                            self.current_input_text.clear();
+                        if let Some(index) = self.editing_shape_index {
+                            self.commit_shape_text_edit();
                             self.selected_shape_index = Some(index); 
                         }
                     }
                     KeyCode::Escape => {
                         if repeated { return Ok(()); }
                         self.editing_shape_index = None; 
-                        self.current_input_text.clear();
+                        self.text_edit = TextEdit::default();
                     }
-                    KeyCode::Back => { self.current_input_text.pop(); }
-                    _ => { if repeated { return Ok(()); } } 
-                }
-            } else if let Some(index_to_delete) = self.selected_shape_index { 
-                if (keycode == KeyCode::Delete || keycode == KeyCode::Back) && !repeated { 
-                    let deleted_shape_idx = index_to_delete;
-                    self.clicked_shapes.remove(deleted_shape_idx);
-                    
-                    let mut new_connections = Vec::new();
-                    for conn in self.connections.iter() {
-                        if conn.from_shape_index == deleted_shape_idx || conn.to_shape_index == deleted_shape_idx {
-                            continue; 
+                    KeyCode::Back if input.mods.contains(KeyMods::CTRL) => self.text_edit.delete_word_backward(),
+                    KeyCode::Back => self.text_edit.backspace(),
+                    KeyCode::Delete if input.mods.contains(KeyMods::CTRL) => self.text_edit.delete_word_forward(),
+                    KeyCode::Delete => self.text_edit.delete_forward(),
+                    KeyCode::Left if input.mods.contains(KeyMods::CTRL) => self.text_edit.move_word_left(shift_held),
+                    KeyCode::Left => self.text_edit.move_left(shift_held),
+                    KeyCode::Right if input.mods.contains(KeyMods::CTRL) => self.text_edit.move_word_right(shift_held),
+                    KeyCode::Right => self.text_edit.move_right(shift_held),
+                    KeyCode::Home => self.text_edit.home(shift_held),
+                    KeyCode::End => self.text_edit.end(shift_held),
+                    KeyCode::A if input.mods.contains(KeyMods::CTRL) => self.text_edit.select_all(),
+                    KeyCode::C | KeyCode::X if input.mods.contains(KeyMods::CTRL) => {
+                        if let Some(selected) = self.text_edit.selected_text() {
+                            self.system_clipboard.set_text(selected);
+                            if keycode == KeyCode::X { self.text_edit.backspace(); }
                         }
-                        let mut new_conn = conn.clone();
-                        if conn.from_shape_index > deleted_shape_idx { new_conn.from_shape_index -= 1; }
-                        if conn.to_shape_index > deleted_shape_idx { new_conn.to_shape_index -= 1; }
-                        new_connections.push(new_conn);
                     }
-                    self.connections = new_connections;
-
-                    self.selected_shape_index = None;
-                    self.dragged_shape_index = None; 
-                    self.editing_shape_index = None; 
-                    self.selected_connector_index = None; 
-                    self.last_click_time = None; 
-                    self.last_click_pos = None;
-                    println!("Shape {} deleted, connections updated.", deleted_shape_idx);
-                }
-            } else if let Some(connector_idx_to_delete) = self.selected_connector_index { 
-                if (keycode == KeyCode::Delete || keycode == KeyCode::Back) && !repeated { 
-                    if connector_idx_to_delete < self.connections.len() {
-                        self.connections.remove(connector_idx_to_delete);
-                        println!("Connector {} deleted.", connector_idx_to_delete);
+                    KeyCode::V if input.mods.contains(KeyMods::CTRL) => {
+                        if let Some(pasted) = self.system_clipboard.text() { self.text_edit.insert_str(&pasted); }
                     }
-                    self.selected_connector_index = None;
+                    _ => { if repeated { return Ok(()); } } 
                 }
             }
         }
@@ -672,71 +5277,328 @@ impl EventHandler<ggez::GameError> for AppState {
     }
 }
 
-fn load_config() -> AppConfig {
-    let default_config = AppConfig {
+// --- Touchpad gestures, which ggez's own loop doesn't pass on ---
+// Both zoom around the pointer, as the platforms give no other gesture center.
+impl GestureHandler for AppState {
+    fn pinch_event(&mut self, ctx: &mut Context, delta: f32) -> GameResult {
+        if !self.observe(SessionEvent::Pinch { delta }) { return Ok(()); }
+        self.needs_redraw = true;
+        if !self.pointer_over_side_panel(ctx) { self.zoom_at_pointer((1.0 + delta * self.zoom_sensitivity).max(0.01)); }
+        Ok(())
+    }
+
+    // Two fingers pan the canvas; with Ctrl held they zoom, like the wheel
+    fn scroll_pan_event(&mut self, ctx: &mut Context, dx: f32, dy: f32) -> GameResult {
+        let (mods, _) = self.held_keys(ctx);
+        if !self.observe(SessionEvent::ScrollPan { dx, dy, mods: mods.bits() }) { return Ok(()); }
+        self.needs_redraw = true;
+        if self.pointer_over_side_panel(ctx) { return Ok(()); }
+        if mods.contains(KeyMods::CTRL) {
+            self.zoom_by_notches(dy / TOUCHPAD_PIXELS_PER_WHEEL_NOTCH);
+        } else {
+            self.camera_animation = None;
+            self.camera.pan_by_screen_delta(Vec2::new(dx, dy) / self.ui_scale);
+            self.live_mouse_pos = self.camera.screen_to_world(self.live_mouse_screen_pos);
+        }
+        Ok(())
+    }
+
+    fn finger_event(&mut self, ctx: &mut Context, id: u64, phase: TouchPhase, x: f32, y: f32) -> GameResult {
+        match self.touch.finger(id, phase, Vec2::new(x, y), Instant::now()) {
+            Some(gesture) => self.touch_gesture(ctx, gesture),
+            None => Ok(()),
+        }
+    }
+
+    // Only reaches the editor while a shape's text is edited, the one time the input method is on
+    fn preedit_event(&mut self, _ctx: &mut Context, text: String, cursor: Option<(usize, usize)>) -> GameResult {
+        if self.replay.is_some() { return Ok(()); }
+        self.needs_redraw = true;
+        self.ime_preedit = text;
+        self.ime_preedit_cursor = cursor.map(|(start, _)| start);
+        Ok(())
+    }
+
+    fn file_drop_event(&mut self, ctx: &mut Context, path: PathBuf) -> GameResult {
+        if self.replay.is_some() || self.save_prompt.is_some() { return Ok(()); }
+        self.needs_redraw = true;
+        log::debug!(target: logging::INPUT, "{} dropped onto the window.", path.display());
+        self.open_diagram_file(ctx, path);
+        Ok(())
+    }
+
+    fn scale_factor_event(&mut self, _ctx: &mut Context, scale_factor: f32) -> GameResult {
+        self.set_window_scale_factor(scale_factor);
+        Ok(())
+    }
+
+    fn next_frame_at(&mut self) -> Instant {
+        self.pace_frame()
+    }
+}
+
+// Strokes a connector's path with lyon into a mesh; None if there is nothing to draw.
+// Dashed lines are flattened and cut into one sub-path per dash.
+fn tessellate_connector(ctx: &Context, path: &ConnectorPath, stroke: ConnectorStroke) -> Option<Mesh> {
+    Some(upload_geometry(ctx, &tessellation::stroke_geometry(path, stroke)?))
+}
+
+fn upload_geometry(ctx: &Context, geometry: &VertexBuffers<Vertex, u32>) -> Mesh {
+    Mesh::from_data(ctx, MeshData { vertices: &geometry.vertices, indices: &geometry.indices })
+}
+
+// Logs a file operation that went through and tells the user too
+fn notify_io(message: String) {
+    log::info!(target: logging::IO, "{}", message);
+    logging::notify(logging::Severity::Info, message);
+}
+
+fn file_modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Fingerprint of a diagram as it would be saved, to tell whether it changed since the last save or load
+fn diagram_fingerprint(diagram: &Diagram) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    persistence::diagram_to_json(diagram).unwrap_or_default().hash(&mut hasher);
+    hasher.finish()
+}
+
+// Where an index into a list points once the item at `removed` is taken out; None for that item
+fn index_after_removal(index: usize, removed: usize) -> Option<usize> {
+    match index.cmp(&removed) {
+        std::cmp::Ordering::Less => Some(index),
+        std::cmp::Ordering::Equal => None,
+        std::cmp::Ordering::Greater => Some(index - 1),
+    }
+}
+
+// Registers the font file under `name` and hands back its bytes. Under the default font's name,
+// every Text drawn after this uses it.
+fn load_font(ctx: &mut Context, name: &str, path: &Path) -> Result<Vec<u8>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Could not read font {}: {}", path.display(), e))?;
+    let font = graphics::FontData::from_vec(bytes.clone()).map_err(|e| format!("Could not load font {}: {}", path.display(), e))?;
+    ctx.gfx.add_font(name, font);
+    Ok(bytes)
+}
+
+// Color standing for a port type: the same type always gets the same hue
+fn port_type_color(port_type: &str) -> Color {
+    let mut hasher = DefaultHasher::new();
+    port_type.hash(&mut hasher);
+    hue_color((hasher.finish() % 360) as f32)
+}
+
+// Color marking a collaborator's cursor and selection; hues a golden angle apart stay distinct
+fn peer_color(peer: u32) -> Color {
+    hue_color((peer as f32 * 137.5) % 360.0)
+}
+
+// Fully saturated hue (in degrees), lightened a little so it reads on the dark background
+fn hue_color(degrees: f32) -> Color {
+    let hue = degrees / 60.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    let lighten = |c: f32| 0.35 + c * 0.65;
+    Color::new(lighten(r), lighten(g), lighten(b), 1.0)
+}
+
+fn number_key_value(keycode: KeyCode) -> Option<usize> {
+    match keycode {
+        KeyCode::Key0 | KeyCode::Numpad0 => Some(0),
+        KeyCode::Key1 | KeyCode::Numpad1 => Some(1),
+        KeyCode::Key2 | KeyCode::Numpad2 => Some(2),
+        KeyCode::Key3 | KeyCode::Numpad3 => Some(3),
+        KeyCode::Key4 | KeyCode::Numpad4 => Some(4),
+        KeyCode::Key5 | KeyCode::Numpad5 => Some(5),
+        KeyCode::Key6 | KeyCode::Numpad6 => Some(6),
+        KeyCode::Key7 | KeyCode::Numpad7 => Some(7),
+        KeyCode::Key8 | KeyCode::Numpad8 => Some(8),
+        KeyCode::Key9 | KeyCode::Numpad9 => Some(9),
+        _ => None,
+    }
+}
+
+fn arrow_key_direction(keycode: KeyCode) -> Option<Vec2> {
+    match keycode {
+        KeyCode::Left => Some(Vec2::new(-1.0, 0.0)),
+        KeyCode::Right => Some(Vec2::new(1.0, 0.0)),
+        KeyCode::Up => Some(Vec2::new(0.0, -1.0)),
+        KeyCode::Down => Some(Vec2::new(0.0, 1.0)),
+        _ => None,
+    }
+}
+
+fn default_app_config() -> AppConfig {
+    AppConfig {
         window: WindowConfig {
             width: 800.0,
             height: 600.0,
             title: "Rust: Shapes - Configurable Colors (Default)".to_string(), 
             msaa_level: None, 
             ui_scale_factor: None, 
+            max_fps: None,
+            vsync: None,
+            render_on_demand: None,
         },
         shape: ShapeConfig {
             width: 120.0,
             height: 70.0,
             corner_radius: 10.0,
-            base_color_rgb: [100, 200, 255], // Default shape base color
-            selection_outline_color_rgb: None, // Will default to Yellow in AppState
+            base_color_rgb: None, // The theme's shape color
+            selection_outline_color_rgb: None, // The theme's selection color
             selection_outline_width: None,
+            ports: None,
+            auto_size: None,
+            auto_size_max_width: None,
+            auto_name: None,
         },
         colors: None, 
-    };
+        theme: None,
+        grid: None,
+        export: None,
+        text: None,
+        connections: None,
+        input: None,
+        node_templates: None,
+    }
+}
 
-    let config_path = "config.toml";
-    match fs::read_to_string(config_path) {
-        Ok(contents) => match toml::from_str(&contents) {
-            Ok(config) => {
-                println!("Successfully loaded configuration from {}", config_path);
-                config
-            }
-            Err(e) => {
-                eprintln!("Failed to parse {}: {}. Using default.", config_path, e);
-                default_config
-            }
-        },
-        Err(_) => {
-            println!("{} not found. Using default & creating new one.", config_path);
-            match toml::to_string_pretty(&default_config) {
-                Ok(toml_string) => {
-                    if let Err(e) = fs::write(config_path, toml_string) {
-                        eprintln!("Could not write default {}: {}", config_path, e);
-                    } else {
-                        println!("Default {} created.", config_path);
-                    }
-                }
-                Err(e) => eprintln!("Could not serialize default config: {}", e),
-            }
+// Reads the config layers (see config::layer_paths). With none of them present, the defaults are
+// written to the --config file, else the per-user one.
+fn load_config(config_paths: &[PathBuf], explicit_path: Option<&Path>) -> AppConfig {
+    let default_config = default_app_config();
+    if !config_paths.iter().any(|path| path.exists()) {
+        let target = explicit_path.map(Path::to_path_buf).or_else(config::user_config_path).unwrap_or_else(|| PathBuf::from(config::CONFIG_FILE_NAME));
+        log::info!(target: logging::IO, "No config file found. Using default & creating {}.", target.display());
+        match config::write_config(&target, &default_config) {
+            Ok(()) => log::info!(target: logging::IO, "Default {} created.", target.display()),
+            Err(e) => log::warn!(target: logging::IO, "{}", e),
+        }
+        return default_config;
+    }
+    match config::load_layers(&default_config, config_paths) {
+        Ok((config, read)) => {
+            log::info!(target: logging::IO, "Successfully loaded configuration from {}", display_paths(&read));
+            config
+        }
+        Err(e) => {
+            log::warn!(target: logging::IO, "{}. Using default.", e);
             default_config
         }
     }
 }
 
+fn display_paths(paths: &[PathBuf]) -> String {
+    paths.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")
+}
+
+fn layout_spacing(default_shape_size: Vec2) -> layout::LayoutSpacing {
+    layout::LayoutSpacing {
+        horizontal: default_shape_size.x + LAYOUT_HORIZONTAL_GAP,
+        vertical: default_shape_size.y + LAYOUT_VERTICAL_GAP,
+    }
+}
+
+// The diagram graph exports are written from: without its sticky notes unless they were asked for
+fn graph_export_diagram(diagram: &Diagram, include_notes: bool) -> std::borrow::Cow<'_, Diagram> {
+    if include_notes { std::borrow::Cow::Borrowed(diagram) } else { std::borrow::Cow::Owned(diagram.without_notes()) }
+}
+
+// SVG, DOT, CSV, adjacency and Rust exports straight from the file, without creating a window or graphics context
+fn export_without_window(app_config: &AppConfig, diagram_path: &Path, format: cli::ExportFormat, out_path: &Path) -> Result<(), String> {
+    let default_size = Vec2::new(app_config.shape.width, app_config.shape.height);
+    let (diagram, _) = persistence::open_diagram(diagram_path, &layout_spacing(default_size))?;
+    match format {
+        cli::ExportFormat::Svg => {
+            let (appearance, warnings) = Appearance::from_config(app_config);
+            for warning in warnings { log::warn!(target: logging::IO, "{}", warning); }
+            let theme = appearance.theme;
+            let style = export_svg::SvgStyle {
+                shape_width: app_config.shape.width,
+                shape_height: app_config.shape.height,
+                corner_radius: app_config.shape.corner_radius,
+                background_color: theme.background,
+                shape_color: theme.shape,
+                text_color: theme.text,
+                connector_line_color: theme.connector,
+            };
+            let routes = routing::diagram_routes(&diagram, default_size);
+            export_svg::export_svg(out_path, &diagram.shapes, &diagram.connections, &diagram.lanes, &diagram.annotations, &routes, &style)
+        }
+        cli::ExportFormat::Dot | cli::ExportFormat::Csv | cli::ExportFormat::Adjacency => {
+            let include_notes = app_config.export.as_ref().and_then(|e| e.include_notes).unwrap_or(false);
+            let diagram = graph_export_diagram(&diagram, include_notes);
+            match format {
+                cli::ExportFormat::Dot => export_dot::export_dot(out_path, &diagram.shapes, &diagram.connections, &diagram.lanes),
+                cli::ExportFormat::Csv => export_graph::export_csv(out_path, &diagram.shapes, &diagram.connections),
+                _ => export_graph::export_adjacency_json(out_path, &diagram.shapes, &diagram.connections),
+            }
+        }
+        cli::ExportFormat::Rust => {
+            let diagram = diagram.without_notes();
+            let warnings = export_fsm::export_rust_fsm(out_path, &diagram.shapes, &diagram.connections)?;
+            for warning in warnings { log::warn!(target: logging::IO, "{}", warning); }
+            Ok(())
+        }
+        cli::ExportFormat::Png => Err(String::from("PNG export needs a graphics context")),
+    }
+}
+
 pub fn main() -> GameResult {
-    let app_config = load_config(); 
+    logging::init();
+    let cli_args = match cli::CliArgs::parse(std::env::args().skip(1)) {
+        Ok(cli_args) => cli_args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    let config_paths = config::layer_paths(cli_args.config_path.as_deref());
+    let app_config = load_config(&config_paths, cli_args.config_path.as_deref());
+
+    // A replay starts from the diagram its recording started from, unless given another
+    let replay_session = cli_args.replay_path.as_deref().map(|path| session::Session::load(path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }));
+    let opened_path = cli_args.diagram_path.clone().or_else(|| replay_session.as_ref().and_then(|session| session.diagram.clone()));
+    let diagram_path = opened_path.clone().unwrap_or_else(|| PathBuf::from(persistence::DEFAULT_DIAGRAM_PATH));
+    let export_out_path = cli_args.export.map(|format| cli_args.out_path.clone().unwrap_or_else(|| diagram_path.with_extension(format.extension())));
+    if let (Some(format @ (cli::ExportFormat::Svg | cli::ExportFormat::Dot | cli::ExportFormat::Csv | cli::ExportFormat::Adjacency | cli::ExportFormat::Rust)), Some(out_path)) = (cli_args.export, &export_out_path) {
+        match export_without_window(&app_config, &diagram_path, format, out_path) {
+            Ok(()) => println!("Diagram exported to {}", out_path.display()),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
 
     let msaa = match app_config.window.msaa_level {
         Some(1) => NumSamples::One, 
         Some(4) => NumSamples::Four,
         Some(other) => {
-            println!(
-                "Warning: Invalid msaa_level '{}' in config.toml. Valid options are 1 or 4. Defaulting to 4.",
+            log::warn!(
+                target: logging::RENDER,
+                "Invalid msaa_level '{}' in config.toml. Valid options are 1 or 4. Defaulting to 4.",
                 other
             );
             NumSamples::Four
         }
         None => NumSamples::Four, 
     };
-    println!("Using MSAA level: {:?}", msaa);
+    log::info!(target: logging::RENDER, "Using MSAA level: {:?}", msaa);
+    // Only PNG export gets here; it renders through ggez, so it needs a (hidden) window
+    let headless_export = export_out_path.is_some();
 
 
     let (mut ctx, event_loop) = ContextBuilder::new("shapes_app_configurable_colors", "YourName")
@@ -744,16 +5606,489 @@ pub fn main() -> GameResult {
             WindowSetup::default()
                 .title(&app_config.window.title)
                 .samples(msaa) 
+                .vsync(app_config.window.vsync.unwrap_or(true))
         )
         .window_mode(
             WindowMode::default()
                 .dimensions(app_config.window.width, app_config.window.height) 
                 .resizable(true)
+                .visible(!headless_export && !cli_args.headless)
         )
         .build()?;
     
-    let app_state = AppState::new(&mut ctx, &app_config)?;
+    let mut app_state = AppState::new(&mut ctx, &app_config)?;
+    app_state.watch_config(config_paths);
+    if opened_path.is_some() || headless_export {
+        app_state.diagram_path = diagram_path;
+        app_state.load_diagram();
+    } else if !app_state.recent_files.paths.is_empty() {
+        // Started without a file: offer the recent ones
+        app_state.recent_menu = Some(RecentFilesMenu::default());
+    }
+    if let Some(png_path) = export_out_path {
+        if let Some(scale) = cli_args.png_scale { app_state.png_export_scale = scale; }
+        app_state.refresh_connector_routes();
+        app_state.refresh_connector_crossings();
+        app_state.export_png(&mut ctx, &png_path);
+        return Ok(());
+    }
+    if let Some(session) = replay_session {
+        // Recorded positions are window coordinates, so the window needs the recorded size
+        let [width, height] = session.window_size;
+        ctx.gfx.set_drawable_size(width, height)?;
+        app_state.replay = Some(Replayer::new(session, cli_args.headless));
+    }
+    if let Some(shape_count) = cli_args.stress {
+        app_state.diagram = stress::stress_diagram(shape_count);
+        app_state.diagram_path = PathBuf::from(format!("stress-{}.json", shape_count)); // Never over the default diagram
+        app_state.recent_menu = None;
+        app_state.stress_timing = Some(StressTiming::new());
+        app_state.zoom_to_fit(&ctx, false);
+        app_state.finish_camera_animation(); // Open already fitted
+        log::info!(target: logging::RENDER, "Stress test: {} shapes, {} connections.", shape_count, app_state.diagram.connections.len());
+    }
+    if cli_args.read_only { app_state.set_read_only(true); }
+    let collaborator_name = std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_else(|_| String::from("Someone"));
+    let document = app_state.diagram_path.clone();
+    let collaboration = match (cli_args.host.as_deref(), cli_args.join.as_deref()) {
+        (Some(address), _) => Some(Collaboration::host(address, collaborator_name, document)),
+        (_, Some(address)) => Some(Collaboration::join(address, collaborator_name, document)),
+        _ => None,
+    };
+    match collaboration {
+        Some(Ok(collaboration)) => {
+            log::info!(target: logging::IO, "Sharing {} live.", app_state.diagram_path.display());
+            // A joined editor shows the host's diagram, not one picked from the recent files
+            app_state.recent_menu = None;
+            app_state.collaboration = Some(collaboration);
+        }
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        None => {}
+    }
+    if let Some(record_path) = cli_args.record_path {
+        let (width, height) = ctx.gfx.drawable_size();
+        app_state.recorder = Some(Recorder::new(record_path, [width, height], cli_args.diagram_path.clone()));
+    }
     
-    event::run(ctx, event_loop, app_state)
+    event_loop::run(ctx, event_loop, app_state)
 }
 
+// --- Headless tests: the editor built without a window, driven through its canvas input ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor() -> AppState {
+        AppState::from_config(&default_app_config())
+    }
+
+    // Shapes spaced well apart on a row, with the camera left at the origin so world and screen agree
+    fn editor_with_shapes(count: usize) -> AppState {
+        let mut app = editor();
+        for i in 0..count {
+            app.diagram.add_shape(ShapeData::new(Vec2::new(i as f32 * 300.0, 0.0)));
+        }
+        app
+    }
+
+    fn press(app: &mut AppState, pos: Vec2) {
+        let screen_pos = app.camera.world_to_screen(pos);
+        app.move_pointer(screen_pos);
+        app.press_canvas(MouseButton::Left, screen_pos, KeyMods::empty(), false, Instant::now());
+    }
+
+    fn drag(app: &mut AppState, from: Vec2, to: Vec2) {
+        press(app, from);
+        app.move_pointer(app.camera.world_to_screen(to));
+        app.release_canvas(MouseButton::Left, false);
+    }
+
+    fn click(app: &mut AppState, pos: Vec2) {
+        press(app, pos);
+        app.release_canvas(MouseButton::Left, false);
+    }
+
+    fn center(app: &AppState, index: usize) -> Vec2 {
+        app.diagram.shapes[index].center_position
+    }
+
+    fn connected_pairs(app: &AppState) -> Vec<(usize, usize)> {
+        app.diagram.connections.iter().map(|c| (c.from_shape_index, c.to_shape_index)).collect()
+    }
+
+    #[test]
+    fn create_tool_adds_shapes_and_edits_the_newest() {
+        let mut app = editor();
+        app.set_tool(Tool::Create);
+        click(&mut app, Vec2::new(100.0, 100.0));
+        assert_eq!(app.diagram.shapes.len(), 1);
+        assert_eq!((app.selected_shape_index, app.editing_shape_index), (Some(0), Some(0)));
+
+        app.text_edit.insert_str("First");
+        click(&mut app, Vec2::new(500.0, 100.0));
+        assert_eq!(app.diagram.shapes.len(), 2);
+        assert_eq!(app.diagram.shapes[0].text.as_deref(), Some("First"), "leaving a shape commits its text");
+        assert_eq!(app.editing_shape_index, Some(1));
+    }
+
+    #[test]
+    fn dragging_between_shapes_connects_them() {
+        let mut app = editor_with_shapes(2);
+        app.set_tool(Tool::Connect);
+        let (from, to) = (center(&app, 0), center(&app, 1));
+        drag(&mut app, from, to);
+        assert_eq!(connected_pairs(&app), [(0, 1)]);
+        assert!(!app.drawing_new_line);
+
+        // A drag that ends over empty space draws nothing
+        let (from, to) = (center(&app, 1), Vec2::new(150.0, 400.0));
+        drag(&mut app, from, to);
+        assert_eq!(connected_pairs(&app), [(0, 1)]);
+        assert!(!app.drawing_new_line);
+    }
+
+    #[test]
+    fn deleting_a_shape_drops_its_connections_and_reindexes_the_rest() {
+        let mut app = editor_with_shapes(4);
+        app.set_tool(Tool::Connect);
+        for (from, to) in [(0, 1), (1, 2), (2, 3), (0, 3)] {
+            let (from, to) = (center(&app, from), center(&app, to));
+            drag(&mut app, from, to);
+        }
+        assert_eq!(connected_pairs(&app), [(0, 1), (1, 2), (2, 3), (0, 3)]);
+
+        app.set_tool(Tool::Select);
+        let target = center(&app, 1);
+        click(&mut app, target);
+        assert_eq!(app.selected_shape_index, Some(1));
+        app.delete_selection(false);
+        assert_eq!(app.diagram.shapes.len(), 3);
+        assert_eq!(connected_pairs(&app), [(1, 2), (0, 2)]);
+        assert_eq!(app.selected_shape_index, None);
+
+        app.set_tool(Tool::Delete);
+        let target = center(&app, 2);
+        click(&mut app, target);
+        assert_eq!(app.diagram.shapes.len(), 2);
+        assert_eq!(connected_pairs(&app), []);
+        // What's left still responds where it is drawn
+        app.set_tool(Tool::Select);
+        let target = center(&app, 1);
+        click(&mut app, target);
+        assert_eq!(app.selected_shape_index, Some(1));
+    }
+
+    #[test]
+    fn splicing_out_a_shape_keeps_its_chain_connected() {
+        let mut app = editor_with_shapes(3);
+        app.set_tool(Tool::Connect);
+        let (from, to) = (center(&app, 0), center(&app, 1));
+        drag(&mut app, from, to);
+        let (from, to) = (center(&app, 1), center(&app, 2));
+        drag(&mut app, from, to);
+        app.set_tool(Tool::Select);
+        let target = center(&app, 1);
+        click(&mut app, target);
+        app.delete_selection(true);
+        assert_eq!(connected_pairs(&app), [(0, 1)]);
+    }
+
+    #[test]
+    fn simulation_walks_the_token_and_asks_at_branches() {
+        let mut app = editor_with_shapes(3);
+        app.set_tool(Tool::Connect);
+        let (from, to) = (center(&app, 0), center(&app, 1));
+        drag(&mut app, from, to);
+        let (from, to) = (center(&app, 0), center(&app, 2));
+        drag(&mut app, from, to);
+        app.set_tool(Tool::Select);
+        app.clear_selection();
+        app.toggle_simulation();
+        let screen_size = Vec2::new(1280.0, 720.0);
+        app.step_simulation(screen_size, None);
+        assert_eq!(app.simulation.as_ref().unwrap().token, None);
+
+        let target = center(&app, 0);
+
+        click(&mut app, target); // Picks the start instead of selecting or dragging
+        app.step_simulation(screen_size, None);
+        assert_eq!(app.simulation.as_ref().unwrap().branch, Some(vec![0, 1]));
+        app.step_simulation(screen_size, Some(1));
+        let simulation = app.simulation.as_ref().unwrap();
+        assert_eq!((simulation.token, simulation.walked.as_slice(), simulation.branch.is_none()), (Some(2), &[1][..], true));
+        app.step_simulation(screen_size, None); // Nothing leads on from here
+        assert_eq!(app.simulation.as_ref().unwrap().token, Some(2));
+        assert_eq!(app.selected_shape_index, None);
+        assert_eq!(connected_pairs(&app), [(0, 1), (0, 2)]);
+
+        // Choosing a branch that isn't offered keeps the question open
+        app.simulation.as_mut().unwrap().start(0);
+        app.step_simulation(screen_size, None);
+        app.step_simulation(screen_size, Some(5));
+        assert_eq!(app.simulation.as_ref().unwrap().branch, Some(vec![0, 1]));
+
+        // A connection on a hidden layer isn't a way on
+        let layer = app.diagram.add_layer();
+        app.diagram.connections[1].layer = layer;
+        app.diagram.layers[layer].visible = false;
+        app.simulation.as_mut().unwrap().start(0);
+        app.step_simulation(screen_size, None);
+        assert_eq!(app.simulation.as_ref().unwrap().token, Some(1));
+    }
+
+    #[test]
+    fn a_note_cycled_into_a_node_gets_ports() {
+        let mut app = editor();
+        app.add_note_at(Vec2::ZERO);
+        app.commit_shape_text_edit();
+        app.selected_shape_index = Some(0);
+        app.cycle_shape_kind();
+        let shape = &app.diagram.shapes[0];
+        assert_eq!((shape.kind, shape.ports.len()), (ShapeKind::Rectangle, app.default_shape_ports.len()));
+    }
+
+    #[test]
+    fn reversing_a_connector_can_be_undone_and_redone() {
+        let mut app = editor_with_shapes(2);
+        app.diagram.connect(0, ports::default_outgoing_port(), 1, ports::default_incoming_port());
+        app.history.record(diagram_fingerprint(&app.diagram), &app.diagram);
+        app.reverse_connection(0);
+        assert_eq!(app.diagram.connections[0].from_shape_index, 1);
+        app.step_history(false);
+        assert_eq!(app.diagram.connections[0].from_shape_index, 0);
+        app.step_history(true);
+        assert_eq!(app.diagram.connections[0].from_shape_index, 1);
+        app.step_history(true); // Nothing left to redo
+        assert_eq!(app.diagram.connections[0].from_shape_index, 1);
+    }
+
+    #[test]
+    fn inspector_port_cycling_keeps_typed_ports_compatible() {
+        let mut app = editor_with_shapes(2);
+        app.diagram.connect(0, ports::default_outgoing_port(), 1, ports::default_incoming_port());
+        app.diagram.shapes[0].ports[ports::default_outgoing_port()].port_type = String::from("audio");
+        for port in &mut app.diagram.shapes[1].ports { port.port_type = String::from("number"); }
+        app.diagram.shapes[1].ports[ports::default_incoming_port()].port_type = String::from("audio");
+        app.selected_connector_index = Some(0);
+        app.handle_inspector_click(InspectorField::ConnectionToPort, WidgetHit::Increment);
+        assert_eq!(app.diagram.connections[0].to_port, ports::default_incoming_port());
+        let next = (ports::default_incoming_port() + 1) % ports::default_ports().len();
+        app.diagram.shapes[1].ports[next].port_type = String::new(); // Untyped ports join any type
+        app.handle_inspector_click(InspectorField::ConnectionToPort, WidgetHit::Increment);
+        assert_eq!(app.diagram.connections[0].to_port, next);
+    }
+
+    #[test]
+    fn collaborators_merge_edits_shape_by_shape() {
+        use std::sync::mpsc;
+        let (host_out, host_lines) = mpsc::channel();
+        let (guest_out, guest_lines) = mpsc::channel();
+        let (to_host, host_in) = mpsc::channel();
+        let (to_guest, guest_in) = mpsc::channel();
+        let mut host = editor_with_shapes(2);
+        let mut guest = editor();
+        host.collaboration = Some(Collaboration::over(Some(0), String::from("host"), host.diagram_path.clone(), host_out, host_in));
+        guest.collaboration = Some(Collaboration::over(Some(1), String::from("guest"), guest.diagram_path.clone(), guest_out, guest_in));
+        let relay = |lines: &mpsc::Receiver<String>, to: &mpsc::Sender<collab::NetEvent>| {
+            for line in lines.try_iter() { to.send(collab::NetEvent::Line(line)).unwrap(); }
+        };
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        to_host.send(collab::NetEvent::Joined(1)).unwrap();
+        host.sync_collaboration(at(0));
+        relay(&host_lines, &to_guest);
+        guest.sync_collaboration(at(0));
+        assert_eq!(guest.diagram.shapes.len(), 2); // The host's diagram replaces the guest's
+        assert!(guest.collaboration.as_ref().unwrap().peers.contains_key(&0));
+
+        // Edits to different shapes made at once both stay
+        host.diagram.shapes[0].center_position = Vec2::new(50.0, 50.0);
+        guest.diagram.shapes[1].center_position = Vec2::new(900.0, 0.0);
+        guest.diagram.add_shape(ShapeData::new(Vec2::new(300.0, 300.0)));
+        host.sync_collaboration(at(200));
+        guest.sync_collaboration(at(200));
+        relay(&host_lines, &to_guest);
+        relay(&guest_lines, &to_host);
+        host.sync_collaboration(at(250));
+        guest.sync_collaboration(at(250));
+        for app in [&host, &guest] {
+            assert_eq!(app.diagram.shapes.len(), 3);
+            assert_eq!(center(app, 0), Vec2::new(50.0, 50.0));
+            assert_eq!(center(app, 1), Vec2::new(900.0, 0.0));
+        }
+
+        // Edits to the same shape made at once: both end up with the one from the higher peer id
+        host.diagram.shapes[2].center_position = Vec2::new(0.0, 400.0);
+        guest.diagram.shapes[2].center_position = Vec2::new(400.0, 0.0);
+        host.sync_collaboration(at(400));
+        guest.sync_collaboration(at(400));
+        relay(&host_lines, &to_guest);
+        relay(&guest_lines, &to_host);
+        host.sync_collaboration(at(450));
+        guest.sync_collaboration(at(450));
+        assert_eq!(center(&host, 2), Vec2::new(400.0, 0.0));
+        assert_eq!(center(&guest, 2), Vec2::new(400.0, 0.0));
+
+        // An edit held back until the next send isn't overwritten by one that comes in meanwhile
+        guest.diagram.shapes[0].center_position = Vec2::new(-100.0, 0.0);
+        guest.sync_collaboration(at(460));
+        host.diagram.shapes[1].center_position = Vec2::new(0.0, -100.0);
+        host.sync_collaboration(at(600));
+        relay(&host_lines, &to_guest);
+        guest.sync_collaboration(at(470));
+        relay(&guest_lines, &to_host);
+        host.sync_collaboration(at(650));
+        for app in [&host, &guest] {
+            assert_eq!(center(app, 0), Vec2::new(-100.0, 0.0));
+            assert_eq!(center(app, 1), Vec2::new(0.0, -100.0));
+        }
+    }
+
+    #[test]
+    fn state_held_across_a_deletion_follows_its_shape() {
+        let mut app = editor_with_shapes(3);
+        app.set_tool(Tool::Connect);
+        let target = center(&app, 2);
+        click(&mut app, target); // Releasing in place keeps the line for a second click
+        assert_eq!(app.new_line_start_info.map(|(index, _)| index), Some(2));
+        app.reach_highlight = Some((2, Reach::Downstream));
+        app.navigation_origin = Some((1, true));
+
+        app.delete_shape(0);
+        assert!(app.drawing_new_line);
+        assert_eq!(app.new_line_start_info.map(|(index, _)| index), Some(1));
+        assert_eq!(app.reach_highlight, Some((1, Reach::Downstream)));
+        assert_eq!(app.navigation_origin, Some((0, true)));
+
+        // Losing the shape the line starts from ends the line
+        app.delete_shape(1);
+        assert!(!app.drawing_new_line);
+        assert_eq!(app.new_line_start_info, None);
+        assert_eq!(app.reach_highlight, None);
+        assert_eq!(app.navigation_origin, Some((0, true)));
+    }
+
+    #[test]
+    fn cut_removes_every_selected_shape() {
+        let mut app = editor_with_shapes(4);
+        app.set_tool(Tool::Select);
+        let target = center(&app, 0);
+        click(&mut app, target);
+        app.press_canvas(MouseButton::Left, center(&app, 2), KeyMods::SHIFT, false, Instant::now());
+        app.release_canvas(MouseButton::Left, false);
+        app.press_canvas(MouseButton::Left, center(&app, 3), KeyMods::SHIFT, false, Instant::now());
+        app.release_canvas(MouseButton::Left, false);
+        app.cut_selection();
+        assert_eq!(app.diagram.shapes.len(), 1);
+        assert_eq!(center(&app, 0), Vec2::new(300.0, 0.0));
+        assert_eq!(app.clipboard.as_ref().map(|fragment| fragment.shapes.len()), Some(3));
+    }
+
+    #[test]
+    fn multi_node_templates_drop_connected_and_selected() {
+        let mut app = editor_with_shapes(1);
+        let templates: String = [
+            "[[templates]]", "name = \"Request/response\"",
+            "nodes = [{ text = \"Request\" }, { text = \"Response\", offset = [200.0, 0.0] }]",
+            "edges = [{ from = 0, to = 1, label = \"reply\" }, { from = 1, to = 5 }]",
+        ].join("\n");
+        let path = std::env::temp_dir().join(format!("templates_test_{}.toml", std::process::id()));
+        fs::write(&path, templates).unwrap();
+        let (templates, problems) = palette::load_templates(std::slice::from_ref(&path));
+        let _ = fs::remove_file(&path);
+        assert_eq!((templates.len(), templates[0].edges.len(), problems.len()), (1, 1, 1));
+
+        app.node_templates = templates;
+        app.drop_template(0, Vec2::new(0.0, 400.0));
+        assert_eq!(app.diagram.shapes.len(), 3);
+        assert_eq!((center(&app, 1), center(&app, 2)), (Vec2::new(0.0, 400.0), Vec2::new(200.0, 400.0)));
+        let connection = &app.diagram.connections[0];
+        assert_eq!((connection.from_shape_index, connection.to_shape_index, connection.label.as_deref()), (1, 2, Some("reply")));
+        assert_eq!((app.selected_shape_index, app.extra_selected_shapes.clone()), (Some(1), vec![2]));
+
+        // Template edges answer to the connection rules like drawn ones
+        app.connection_validator.add_rule(validation::Acyclic);
+        let mut round_trip = app.node_templates[0].clone();
+        round_trip.edges.push(palette::TemplateEdge { from: 1, to: 0, ..round_trip.edges[0].clone() });
+        app.node_templates.push(round_trip);
+        app.drop_template(1, Vec2::new(0.0, 800.0));
+        assert_eq!(connected_pairs(&app), [(1, 2), (3, 4)]);
+    }
+
+    #[test]
+    fn new_sub_diagrams_are_named_after_their_shape() {
+        let mut app = editor_with_shapes(2);
+        app.diagram_path = std::env::temp_dir().join("nested_names_test").join("system.json");
+        app.diagram.shapes[0].text = Some(String::from("Auth Service!"));
+        assert_eq!(app.new_subdiagram_path(0), PathBuf::from("system-auth-service.json"));
+        assert_eq!(app.new_subdiagram_path(1), PathBuf::from("system-1.json"));
+        app.diagram.shapes[1].subdiagram = Some(PathBuf::from("system-auth-service.json"));
+        assert_eq!(app.new_subdiagram_path(0), PathBuf::from("system-auth-service-2.json"));
+        app.breadcrumb = vec![PathBuf::from("top.json")];
+        assert_eq!(app.breadcrumb_names(), vec![String::from("top"), String::from("system")]);
+    }
+
+    #[test]
+    fn read_only_clicks_select_and_drags_pan_without_moving_shapes() {
+        let mut app = editor_with_shapes(2);
+        app.set_read_only(true);
+        let before = center(&app, 0);
+        drag(&mut app, before, before + Vec2::new(40.0, 0.0));
+        assert_eq!(center(&app, 0), before);
+        assert_eq!(app.selected_shape_index, Some(0));
+
+        // Empty space pans instead of starting a selection or a shape
+        let offset = app.camera.offset;
+        drag(&mut app, Vec2::new(-300.0, -300.0), Vec2::new(-250.0, -300.0));
+        assert_ne!(app.camera.offset, offset);
+        assert_eq!(app.selected_shape_index, None);
+        assert_eq!(app.diagram.shapes.len(), 2);
+    }
+
+    #[test]
+    fn reopening_the_open_file_keeps_unsaved_changes() {
+        let mut app = editor_with_shapes(2);
+        let path = std::env::temp_dir().join(format!("reopen_test_{}.json", std::process::id()));
+        fs::write(&path, persistence::diagram_to_json(&Diagram::new()).unwrap()).unwrap();
+        app.diagram_path = path.clone();
+        app.dirty = true;
+        app.open_diagram_document(path.clone());
+        let _ = fs::remove_file(&path);
+        assert_eq!((app.diagram.shapes.len(), app.tabs.len()), (2, 1));
+        assert!(app.dirty);
+    }
+
+    #[test]
+    fn each_tab_undoes_its_own_changes() {
+        let mut app = editor_with_shapes(2);
+        app.history.record(diagram_fingerprint(&app.diagram), &app.diagram);
+        app.diagram.add_shape(ShapeData::new(Vec2::new(600.0, 0.0)));
+        app.tabs.push(Document::new(app.untitled_path()));
+        app.bring_in_tab(1);
+        app.history.record(diagram_fingerprint(&app.diagram), &app.diagram);
+        app.diagram.add_shape(ShapeData::new(Vec2::ZERO));
+        app.bring_in_tab(0);
+        app.step_history(false);
+        assert_eq!(app.diagram.shapes.len(), 2);
+        app.bring_in_tab(1);
+        app.step_history(false);
+        assert!(app.diagram.shapes.is_empty());
+        app.step_history(false); // Tab 0's steps stay with tab 0
+        assert!(app.diagram.shapes.is_empty());
+    }
+
+    #[test]
+    fn closing_the_last_tab_leaves_an_empty_diagram() {
+        let mut app = editor_with_shapes(2);
+        let path = app.diagram_path.clone();
+        app.close_active_document();
+        assert_eq!((app.tabs.len(), app.active_tab), (1, 0));
+        assert!(app.diagram.shapes.is_empty());
+        assert_ne!(app.diagram_path, path);
+        assert!(!app.dirty);
+    }
+}
@@ -0,0 +1,91 @@
+// mesh_cache.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::{Color, DrawMode, Mesh, Rect};
+use ggez::{Context, GameResult};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+
+use crate::shape_kind::ShapeKind;
+
+const PORT_MESH_RADIUS: f32 = 8.0; // Port circles are this mesh scaled down
+const PORT_MESH_TOLERANCE: f32 = 0.1;
+
+// --- Everything that determines a shape mesh's geometry ---
+// Meshes are built white at the origin and placed/tinted with DrawParam, so position and
+// color don't need a new mesh. Floats are stored as bits to be hashable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ShapeMeshKey {
+    kind: ShapeKind,
+    stroke_width: Option<u32>, // None for a fill
+    size: (u32, u32),
+    corner_radius: u32,
+}
+
+impl ShapeMeshKey {
+    pub fn new(kind: ShapeKind, stroke_width: Option<f32>, size: Vec2, corner_radius: f32) -> ShapeMeshKey {
+        ShapeMeshKey {
+            kind,
+            stroke_width: stroke_width.map(f32::to_bits),
+            size: (size.x.to_bits(), size.y.to_bits()),
+            corner_radius: corner_radius.to_bits(),
+        }
+    }
+
+    fn build(&self, ctx: &mut Context) -> GameResult<Mesh> {
+        let mode = self.stroke_width.map_or(DrawMode::fill(), |bits| DrawMode::stroke(f32::from_bits(bits)));
+        let rect = Rect::new(0.0, 0.0, f32::from_bits(self.size.0), f32::from_bits(self.size.1));
+        self.kind.mesh(ctx, mode, rect, f32::from_bits(self.corner_radius), Color::WHITE)
+    }
+}
+
+fn build_port_circle(ctx: &mut Context) -> GameResult<Mesh> {
+    Mesh::new_circle(ctx, DrawMode::fill(), Vec2::ZERO, PORT_MESH_RADIUS, PORT_MESH_TOLERANCE, Color::WHITE)
+}
+
+// --- GPU meshes shared across shapes and frames ---
+// Empty until the first frame is drawn, so the editor can be set up without a graphics context.
+#[derive(Default)]
+pub struct MeshCache {
+    port_circle: Option<Mesh>,
+    shapes: HashMap<ShapeMeshKey, Mesh>,
+}
+
+impl MeshCache {
+    // Builds the meshes in `keys` that don't exist yet and drops those no longer used; returns how
+    // many of `keys` were already there and how many had to be built
+    pub fn prepare(&mut self, ctx: &mut Context, keys: impl IntoIterator<Item = ShapeMeshKey>) -> GameResult<(u32, u32)> {
+        if self.port_circle.is_none() { self.port_circle = Some(build_port_circle(ctx)?); }
+        let keys: HashSet<ShapeMeshKey> = keys.into_iter().collect();
+        self.shapes.retain(|key, _| keys.contains(key));
+        let (mut hits, mut misses) = (0, 0);
+        for key in keys {
+            match self.shapes.entry(key) {
+                Entry::Occupied(_) => hits += 1,
+                Entry::Vacant(entry) => {
+                    let mesh = entry.key().build(ctx)?;
+                    entry.insert(mesh);
+                    misses += 1;
+                }
+            }
+        }
+        Ok((hits, misses))
+    }
+
+    // The cached mesh, or a new one if `prepare` didn't ask for it (e.g. during PNG export)
+    pub fn shape(&self, ctx: &mut Context, key: ShapeMeshKey) -> GameResult<Mesh> {
+        match self.shapes.get(&key) {
+            Some(mesh) => Ok(mesh.clone()),
+            None => key.build(ctx),
+        }
+    }
+
+    // Unit port circle and the scale that gives it `radius`
+    pub fn port_circle(&self, ctx: &mut Context, radius: f32) -> GameResult<(Mesh, f32)> {
+        let mesh = match &self.port_circle {
+            Some(mesh) => mesh.clone(),
+            None => build_port_circle(ctx)?,
+        };
+        Ok((mesh, radius / PORT_MESH_RADIUS))
+    }
+}
@@ -0,0 +1,138 @@
+// palette.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::Rect;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::ports::{self, Port, PortId};
+use crate::shape_kind::ShapeKind;
+use crate::tools::TOOLBAR_HEIGHT;
+use crate::ui::{self, Widget};
+use crate::{Diagram, ShapeData};
+
+const PALETTE_BUTTON_WIDTH: f32 = 110.0;
+pub const TEMPLATES_FILE_NAME: &str = "templates.toml"; // [[templates]], possibly several connected nodes each
+
+// --- A kind of node configured under [[node_templates]] in config.toml or [[templates]] in templates.toml ---
+// Anything left out falls back to the [shape] defaults. With `nodes`, the template drops those
+// instead, joined by `edges`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NodeTemplate {
+    pub name: String,
+    pub text: Option<String>, // Label new nodes start with
+    pub kind: Option<ShapeKind>,
+    pub size: Option<[f32; 2]>, // [width, height]
+    pub color_rgb: Option<[u8; 3]>,
+    pub outline_rgb: Option<[u8; 3]>,
+    pub ports: Option<Vec<Port>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nodes: Vec<TemplateNode>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub edges: Vec<TemplateEdge>,
+}
+
+// One node of a multi-node template, placed `offset` from the drop point
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct TemplateNode {
+    #[serde(default)]
+    pub offset: [f32; 2],
+    pub text: Option<String>,
+    pub kind: Option<ShapeKind>,
+    pub size: Option<[f32; 2]>,
+    pub color_rgb: Option<[u8; 3]>,
+    pub outline_rgb: Option<[u8; 3]>,
+    pub ports: Option<Vec<Port>>,
+}
+
+// A connection made between two of a template's nodes (indices into `nodes`)
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TemplateEdge {
+    pub from: usize,
+    pub to: usize,
+    pub label: Option<String>,
+    pub from_port: Option<PortId>, // Defaults to the usual outgoing port
+    pub to_port: Option<PortId>, // Defaults to the usual incoming port
+}
+
+impl TemplateNode {
+    pub fn instantiate(&self, center_position: Vec2, default_kind: ShapeKind, default_ports: &[Port]) -> ShapeData {
+        ShapeData {
+            text: self.text.clone(),
+            kind: self.kind.unwrap_or(default_kind),
+            ports: self.ports.clone().filter(|ports| !ports.is_empty()).unwrap_or_else(|| default_ports.to_vec()),
+            fill_rgb: self.color_rgb,
+            outline_rgb: self.outline_rgb,
+            size: self.size,
+            ..ShapeData::new(center_position + Vec2::from(self.offset))
+        }
+    }
+}
+
+impl NodeTemplate {
+    // The nodes the template drops: its `nodes`, or itself as the only one
+    pub fn parts(&self) -> Vec<TemplateNode> {
+        if !self.nodes.is_empty() { return self.nodes.clone(); }
+        vec![TemplateNode {
+            offset: [0.0, 0.0],
+            text: self.text.clone(),
+            kind: self.kind,
+            size: self.size,
+            color_rgb: self.color_rgb,
+            outline_rgb: self.outline_rgb,
+            ports: self.ports.clone(),
+        }]
+    }
+
+    // The template's nodes around the origin with its edges already made; appending it to a diagram
+    // shifts the edges' node indices along with the nodes. Edges whose ports don't exist are left out.
+    pub fn fragment(&self, default_kind: ShapeKind, default_ports: &[Port]) -> Diagram {
+        let mut fragment = Diagram::new();
+        for part in self.parts() { fragment.add_shape(part.instantiate(Vec2::ZERO, default_kind, default_ports)); }
+        for edge in &self.edges {
+            let (from_port, to_port) = (edge.from_port.unwrap_or(ports::default_outgoing_port()), edge.to_port.unwrap_or(ports::default_incoming_port()));
+            match fragment.connect(edge.from, from_port, edge.to, to_port) {
+                Some(index) => fragment.connections[index].label = edge.label.clone(),
+                None => log::warn!(target: crate::logging::MODEL, "Template '{}' has an edge from node {} to {} whose ports don't exist. Leaving it out.", self.name, edge.from, edge.to),
+            }
+        }
+        fragment
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct TemplatesFile {
+    #[serde(default)]
+    templates: Vec<NodeTemplate>,
+}
+
+// Templates from each of `paths` that exists, in order, plus a message for every file that
+// couldn't be parsed and every edge between nodes the template doesn't have
+pub fn load_templates(paths: &[PathBuf]) -> (Vec<NodeTemplate>, Vec<String>) {
+    let (mut templates, mut problems) = (Vec::new(), Vec::new());
+    for path in paths {
+        let Ok(contents) = fs::read_to_string(path) else { continue; };
+        let file: TemplatesFile = match toml::from_str(&contents) {
+            Ok(file) => file,
+            Err(e) => { problems.push(format!("Failed to parse {}: {}", path.display(), e.message())); continue; }
+        };
+        for mut template in file.templates {
+            let node_count = template.parts().len();
+            let before = template.edges.len();
+            template.edges.retain(|edge| edge.from < node_count && edge.to < node_count);
+            if template.edges.len() != before {
+                problems.push(format!("Template '{}' in {} has edges to nodes it doesn't have. Leaving them out.", template.name, path.display()));
+            }
+            templates.push(template);
+        }
+    }
+    (templates, problems)
+}
+
+// Strip of template buttons next to the toolbar, starting at `left`; None without templates
+pub fn palette_widgets(left: f32, templates: &[NodeTemplate]) -> Option<(Rect, Vec<Widget<usize>>)> {
+    if templates.is_empty() { return None; }
+    let buttons = templates.iter().enumerate().map(|(index, template)| (index, template.name.clone(), false));
+    Some(ui::button_bar(Vec2::new(left, 0.0), PALETTE_BUTTON_WIDTH, TOOLBAR_HEIGHT, buttons))
+}
@@ -0,0 +1,160 @@
+// persistence.rs
+
+use ggez::glam::Vec2;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+
+use crate::layout::{self, LayoutSpacing};
+use crate::{import_csv, import_dot, ports, Diagram};
+
+pub const DEFAULT_DIAGRAM_PATH: &str = "diagram.json";
+
+// --- File format versions ---
+// Bump FORMAT_VERSION whenever a change to the saved schema needs more than a serde default to read
+// older files, and append the step that upgrades the previous version to MIGRATIONS.
+// Files saved before versioning have no `version` field and count as version 1.
+pub const FORMAT_VERSION: u64 = 2;
+
+// MIGRATIONS[i] upgrades a version i + 1 document to version i + 2
+const MIGRATIONS: [fn(&mut Map<String, Value>); (FORMAT_VERSION - 1) as usize] = [pin_legacy_ports];
+
+// Version 1 files could leave out ports and connector ends, relying on the defaults at the time;
+// write those defaults in so later changes to them don't move the connectors of old diagrams
+fn pin_legacy_ports(document: &mut Map<String, Value>) {
+    let default_ports = serde_json::to_value(ports::default_ports()).unwrap_or(Value::Null);
+    if let Some(Value::Array(shapes)) = document.get_mut("shapes") {
+        for shape in shapes.iter_mut().filter_map(Value::as_object_mut) {
+            shape.entry("ports").or_insert_with(|| default_ports.clone());
+        }
+    }
+    if let Some(Value::Array(connections)) = document.get_mut("connections") {
+        for connection in connections.iter_mut().filter_map(Value::as_object_mut) {
+            connection.entry("from_port").or_insert_with(|| ports::default_outgoing_port().into());
+            connection.entry("to_port").or_insert_with(|| ports::default_incoming_port().into());
+        }
+    }
+}
+
+// What gets written: the diagram with its format version first
+#[derive(Serialize)]
+struct VersionedDiagram<'a> {
+    version: u64,
+    #[serde(flatten)]
+    diagram: &'a Diagram,
+}
+
+pub fn diagram_to_json(diagram: &Diagram) -> Result<String, String> {
+    serde_json::to_string_pretty(&VersionedDiagram { version: FORMAT_VERSION, diagram })
+        .map_err(|e| format!("Could not serialize diagram: {}", e))
+}
+
+// Reads a saved diagram of any known version, upgrading it to the current schema
+pub fn diagram_from_json(json: &str) -> Result<Diagram, String> {
+    let document: Value = serde_json::from_str(json)
+        .map_err(|e| format!("the file is damaged or not a diagram (line {}, column {})", e.line(), e.column()))?;
+    let Value::Object(mut document) = document else {
+        return Err(String::from("the file is not a diagram"));
+    };
+    let version = match document.remove("version") {
+        None => 1,
+        Some(value) => value.as_u64().filter(|&v| v >= 1).ok_or_else(|| format!("unknown format version {}", value))?,
+    };
+    if version > FORMAT_VERSION {
+        return Err(format!(
+            "it was saved in format version {} by a newer release; this one reads up to version {}", version, FORMAT_VERSION
+        ));
+    }
+    for migrate in &MIGRATIONS[(version - 1) as usize..] {
+        migrate(&mut document);
+    }
+    let diagram: Diagram = serde_json::from_value(Value::Object(document))
+        .map_err(|e| format!("the file is damaged or not a diagram ({})", e))?;
+    Ok(diagram.validated())
+}
+
+pub fn save_diagram(path: &Path, diagram: &Diagram) -> Result<(), String> {
+    let json = diagram_to_json(diagram)?;
+    fs::write(path, json).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
+
+pub fn load_diagram(path: &Path) -> Result<Diagram, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    diagram_from_json(&contents).map_err(|e| format!("Could not open {}: {}", path.display(), e))
+}
+
+// Loads `path`; `.dot`, `.gv` and `.txt` files are imported as Graphviz / edge lists and laid out
+// with `spacing` unless every node came with coordinates, and `.csv` files as CSV edge lists (with
+// their `.nodes.csv` node list, if any). Also returns whether it was an import.
+pub fn open_diagram(path: &Path, spacing: &LayoutSpacing) -> Result<(Diagram, bool), String> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    if extension == "csv" {
+        let mut diagram = Diagram::new();
+        import_csv::import_csv(&mut diagram, path, import_csv::sibling_nodes_path(path).as_deref(), spacing)?;
+        return Ok((diagram, true));
+    }
+    if !matches!(extension.as_str(), "dot" | "gv" | "txt") {
+        return Ok((load_diagram(path)?, false));
+    }
+    let mut imported = import_dot::import_dot(path, Vec2::new(spacing.horizontal, spacing.vertical))?;
+    if !imported.fully_positioned && !imported.diagram.connections.is_empty() {
+        let targets = layout::diagram_layout(&imported.diagram, spacing);
+        for (shape, target) in imported.diagram.shapes.iter_mut().zip(targets) {
+            shape.center_position = target;
+        }
+    }
+    Ok((imported.diagram, true))
+}
+
+// glam's Vec2 isn't serializable without its serde feature, so positions are stored as [x, y]
+pub mod vec2_as_array {
+    use ggez::glam::Vec2;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &Vec2, serializer: S) -> Result<S::Ok, S::Error> {
+        [v.x, v.y].serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec2, D::Error> {
+        let [x, y] = <[f32; 2]>::deserialize(deserializer)?;
+        Ok(Vec2::new(x, y))
+    }
+}
+
+// The same for lists of points
+pub mod vec2s_as_arrays {
+    use ggez::glam::Vec2;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(points: &[Vec2], serializer: S) -> Result<S::Ok, S::Error> {
+        points.iter().map(|p| [p.x, p.y]).collect::<Vec<[f32; 2]>>().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Vec2>, D::Error> {
+        Ok(<Vec<[f32; 2]>>::deserialize(deserializer)?.into_iter().map(Vec2::from).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unversioned_files_are_migrated_and_newer_ones_refused() {
+        let legacy = r#"{ "shapes": [{ "center_position": [0, 0], "text": null }, { "center_position": [0, 100], "text": "B" }],
+                          "connections": [{ "from_shape_index": 0, "to_shape_index": 1 }] }"#;
+        let diagram = diagram_from_json(legacy).unwrap();
+        assert_eq!(diagram.shapes[0].ports.len(), ports::default_ports().len());
+        assert_eq!(diagram.connections[0].from_port, ports::default_outgoing_port());
+
+        let saved = diagram_to_json(&diagram).unwrap();
+        assert!(saved.contains(&format!("\"version\": {}", FORMAT_VERSION)));
+        assert_eq!(diagram_from_json(&saved).unwrap().connections.len(), 1);
+
+        let future = saved.replace(&format!("\"version\": {}", FORMAT_VERSION), "\"version\": 999");
+        assert!(diagram_from_json(&future).unwrap_err().contains("newer release"));
+        assert!(diagram_from_json("{ \"shapes\": [").is_err());
+        assert!(diagram_from_json("[1, 2]").is_err());
+    }
+}
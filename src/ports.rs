@@ -0,0 +1,109 @@
+// ports.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::Rect;
+use serde::{Deserialize, Serialize};
+
+use crate::shape_kind::ShapeKind;
+
+// Index of a port within its shape's `ports` list
+pub type PortId = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PortSide {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+impl PortSide {
+    // Unit vector pointing away from the shape on this side
+    pub fn outward_normal(self) -> Vec2 {
+        match self {
+            PortSide::Top => Vec2::new(0.0, -1.0),
+            PortSide::Bottom => Vec2::new(0.0, 1.0),
+            PortSide::Left => Vec2::new(-1.0, 0.0),
+            PortSide::Right => Vec2::new(1.0, 0.0),
+        }
+    }
+
+    // The side facing closest to where this one faces once its shape is turned by `rotation` radians
+    pub fn rotated(self, rotation: f32) -> PortSide {
+        if rotation == 0.0 { return self; }
+        let facing = Vec2::from_angle(rotation).rotate(self.outward_normal());
+        [PortSide::Top, PortSide::Bottom, PortSide::Left, PortSide::Right].into_iter()
+            .max_by(|a, b| a.outward_normal().dot(facing).total_cmp(&b.outward_normal().dot(facing)))
+            .unwrap_or(self)
+    }
+}
+
+// Which way connections may run through a port
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PortDirection {
+    In, // Connections may only arrive here
+    Out, // Connections may only leave from here
+    #[default]
+    Any,
+}
+
+impl PortDirection {
+    fn is_any(&self) -> bool {
+        *self == PortDirection::Any
+    }
+}
+
+// --- A connection point on a shape's outline ---
+// `offset` is the fraction along the side: left to right for top/bottom, top to bottom for left/right.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Port {
+    pub side: PortSide,
+    #[serde(default = "default_port_offset")]
+    pub offset: f32,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub port_type: String, // e.g. "audio" or "number"; empty for an untyped port
+    #[serde(default, skip_serializing_if = "PortDirection::is_any")]
+    pub direction: PortDirection,
+}
+
+fn default_port_offset() -> f32 {
+    0.5
+}
+
+impl Port {
+    pub fn new(side: PortSide, offset: f32) -> Port {
+        Port { side, offset: offset.clamp(0.0, 1.0), port_type: String::new(), direction: PortDirection::Any }
+    }
+
+    // Ports of the same type connect, and untyped ports connect to anything
+    pub fn compatible_with(&self, other: &Port) -> bool {
+        self.port_type.is_empty() || other.port_type.is_empty() || self.port_type == other.port_type
+    }
+
+    // Where the port sits on the outline of a `kind` shape occupying `rect`
+    pub fn position(&self, kind: ShapeKind, rect: Rect) -> Vec2 {
+        kind.outline_point(rect, self.side, self.offset)
+    }
+}
+
+// One port centered on each side, in the order top, bottom, left, right
+pub fn default_ports() -> Vec<Port> {
+    vec![
+        Port::new(PortSide::Top, 0.5),
+        Port::new(PortSide::Bottom, 0.5),
+        Port::new(PortSide::Left, 0.5),
+        Port::new(PortSide::Right, 0.5),
+    ]
+}
+
+// Ports in `default_ports()` that connections without explicit ports attach to
+// (e.g. diagrams saved before ports were configurable, or imported edges)
+pub fn default_outgoing_port() -> PortId {
+    1 // Bottom
+}
+
+pub fn default_incoming_port() -> PortId {
+    0 // Top
+}
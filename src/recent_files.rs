@@ -0,0 +1,102 @@
+// recent_files.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::Rect;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ui::{self, ListOverlay, Widget};
+
+const MAX_RECENT_FILES: usize = 10;
+const STATE_DIR_NAME: &str = "rust_visual_mouse_app";
+const RECENT_FILES_NAME: &str = "recent_files.json";
+const RECENT_PANEL_WIDTH: f32 = 480.0;
+const RECENT_PANEL_TOP: f32 = 60.0;
+
+// --- Most recently opened or saved diagrams, newest first, kept in the per-user state file ---
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct RecentFiles {
+    pub paths: Vec<PathBuf>,
+}
+
+impl RecentFiles {
+    // An empty list when there is no state file yet or it can't be read
+    pub fn load() -> RecentFiles {
+        let Some(path) = state_file_path() else { return RecentFiles::default(); };
+        fs::read_to_string(&path).ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = state_file_path().ok_or("No per-user state directory (HOME is not set)")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(|e| format!("Could not serialize recent files: {}", e))?;
+        fs::write(&path, json).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+
+    // Moves `path` to the front, dropping the oldest entry past the limit
+    pub fn remember(&mut self, path: &Path) {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.paths.retain(|known| *known != path);
+        self.paths.insert(0, path);
+        self.paths.truncate(MAX_RECENT_FILES);
+    }
+
+    pub fn forget(&mut self, path: &Path) {
+        self.paths.retain(|known| known != path);
+    }
+}
+
+// $XDG_STATE_HOME, else ~/.local/state; %LOCALAPPDATA% on Windows
+fn state_file_path() -> Option<PathBuf> {
+    let xdg_state = env::var_os("XDG_STATE_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from);
+    let base = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        xdg_state.or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("state")))
+    }?;
+    Some(base.join(STATE_DIR_NAME).join(RECENT_FILES_NAME))
+}
+
+// --- Recent files overlay, shown on startup and by Ctrl+Shift+O: Up/Down to choose, Enter to open ---
+#[derive(Default)]
+pub struct RecentFilesMenu {
+    list: ListOverlay, // Over RecentFiles::paths
+}
+
+impl RecentFilesMenu {
+    pub fn chosen<'a>(&self, recent: &'a RecentFiles) -> Option<&'a PathBuf> {
+        recent.paths.get(self.list.selected())
+    }
+
+    // Moves the highlight up (negative) or down, wrapping around the list
+    pub fn move_selection(&mut self, recent: &RecentFiles, delta: isize) {
+        self.list.move_selection(recent.paths.len(), delta);
+    }
+
+    // Makes the clicked file the chosen one
+    pub fn select(&mut self, index: usize) {
+        self.list.select(index);
+    }
+
+    // Panel centered near the top of the screen: a title, then one button per file showing its
+    // name and folder. Button ids are indices into RecentFiles::paths.
+    pub fn widgets(&self, screen_size: Vec2, recent: &RecentFiles) -> (Rect, Vec<Widget<usize>>) {
+        let mut headers = vec![String::from("Recent diagrams")];
+        if recent.paths.is_empty() { headers.push(String::from("Nothing opened yet")); }
+        let items = recent.paths.iter().map(|path| {
+            let name = path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned());
+            match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                Some(dir) => format!("{}   ({})", name, dir.display()),
+                None => name,
+            }
+        }).collect();
+        let width = RECENT_PANEL_WIDTH.min(screen_size.x);
+        self.list.widgets(ui::centered_origin(screen_size, width, RECENT_PANEL_TOP), width, MAX_RECENT_FILES, headers, items)
+    }
+}
@@ -0,0 +1,185 @@
+// routing.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::Rect;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::ports::PortSide;
+use crate::Diagram;
+
+pub const ROUTING_CELL_SIZE: f32 = 10.0;
+pub const ROUTING_CLEARANCE: f32 = 8.0; // Gap kept between routed lines and shape outlines
+const ROUTING_REGION_MARGIN_CELLS: i32 = 4;
+const ROUTING_MAX_CELLS: i32 = 250_000; // Grid is coarsened beyond this many cells
+const STEP_COST: u32 = 10;
+const TURN_COST: u32 = 25; // Discourages staircase routes in favour of few long segments
+
+const DIRECTIONS: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const NO_DIRECTION: usize = 4;
+
+// --- Coarse occupancy grid over the area a route may use ---
+struct RoutingGrid {
+    origin: Vec2, // World position of the corner of cell (0, 0)
+    cell_size: f32,
+    columns: i32,
+    rows: i32,
+    blocked: Vec<bool>,
+}
+
+impl RoutingGrid {
+    fn new(region: Rect, obstacles: &[Rect]) -> RoutingGrid {
+        let mut cell_size = ROUTING_CELL_SIZE;
+        while ((region.w / cell_size).ceil() * (region.h / cell_size).ceil()) as i32 > ROUTING_MAX_CELLS {
+            cell_size *= 2.0;
+        }
+        let columns = (region.w / cell_size).ceil() as i32 + 1;
+        let rows = (region.h / cell_size).ceil() as i32 + 1;
+        let mut grid = RoutingGrid {
+            origin: Vec2::new(region.x, region.y),
+            cell_size,
+            columns,
+            rows,
+            blocked: vec![false; (columns * rows) as usize],
+        };
+        for obstacle in obstacles {
+            let (min_col, min_row) = grid.cell_of(Vec2::new(obstacle.left() - ROUTING_CLEARANCE, obstacle.top() - ROUTING_CLEARANCE));
+            let (max_col, max_row) = grid.cell_of(Vec2::new(obstacle.right() + ROUTING_CLEARANCE, obstacle.bottom() + ROUTING_CLEARANCE));
+            for row in min_row.max(0)..=max_row.min(rows - 1) {
+                for col in min_col.max(0)..=max_col.min(columns - 1) {
+                    grid.blocked[(row * columns + col) as usize] = true;
+                }
+            }
+        }
+        grid
+    }
+
+    fn cell_of(&self, position: Vec2) -> (i32, i32) {
+        let local = (position - self.origin) / self.cell_size;
+        (local.x.floor() as i32, local.y.floor() as i32)
+    }
+
+    fn cell_center(&self, cell: (i32, i32)) -> Vec2 {
+        self.origin + (Vec2::new(cell.0 as f32, cell.1 as f32) + Vec2::splat(0.5)) * self.cell_size
+    }
+
+    fn index(&self, cell: (i32, i32)) -> Option<usize> {
+        let (col, row) = cell;
+        if col < 0 || row < 0 || col >= self.columns || row >= self.rows { return None; }
+        Some((row * self.columns + col) as usize)
+    }
+}
+
+// Route of every connection, in order: Some for smart-routed connections that found one.
+// Nothing is cached, so this suits one-off exports rather than redrawing.
+pub fn diagram_routes(diagram: &Diagram, default_size: Vec2) -> Vec<Option<Vec<Vec2>>> {
+    let obstacles: Vec<Rect> = diagram.shapes.iter().map(|s| s.rect(default_size)).collect();
+    diagram.connections.iter().map(|connection| {
+        if !connection.smart_routed { return None; }
+        let (start, start_side) = diagram.port_anchor(connection.from_shape_index, connection.from_port, default_size)?;
+        let (end, end_side) = diagram.port_anchor(connection.to_shape_index, connection.to_port, default_size)?;
+        route_between_ports(start, start_side, end, end_side, &obstacles)
+    }).collect()
+}
+
+// Routes between two ports, leaving and entering perpendicular to each port's side just clear of its shape
+pub fn route_between_ports(start: Vec2, start_side: PortSide, end: Vec2, end_side: PortSide, obstacles: &[Rect]) -> Option<Vec<Vec2>> {
+    let lead = ROUTING_CLEARANCE + ROUTING_CELL_SIZE;
+    route_around(start, start + start_side.outward_normal() * lead, end + end_side.outward_normal() * lead, end, obstacles)
+}
+
+// Finds an orthogonal polyline from `start` to `end` that stays clear of `obstacles`.
+// `start_exit` / `end_entry` are points just outside the source and target shapes that the
+// route leaves from and arrives at. Returns None when no route exists.
+pub fn route_around(start: Vec2, start_exit: Vec2, end_entry: Vec2, end: Vec2, obstacles: &[Rect]) -> Option<Vec<Vec2>> {
+    let margin = ROUTING_REGION_MARGIN_CELLS as f32 * ROUTING_CELL_SIZE + ROUTING_CLEARANCE;
+    let mut region_min = start_exit.min(end_entry);
+    let mut region_max = start_exit.max(end_entry);
+    for obstacle in obstacles {
+        region_min = region_min.min(Vec2::new(obstacle.left(), obstacle.top()));
+        region_max = region_max.max(Vec2::new(obstacle.right(), obstacle.bottom()));
+    }
+    region_min -= Vec2::splat(margin);
+    region_max += Vec2::splat(margin);
+    let region = Rect::new(region_min.x, region_min.y, region_max.x - region_min.x, region_max.y - region_min.y);
+
+    let mut grid = RoutingGrid::new(region, obstacles);
+    let start_cell = grid.cell_of(start_exit);
+    let goal_cell = grid.cell_of(end_entry);
+    // The exit/entry points hug their own shapes, so they must stay usable
+    for cell in [start_cell, goal_cell] {
+        let index = grid.index(cell)?;
+        grid.blocked[index] = false;
+    }
+
+    let cells = find_cell_path(&grid, start_cell, goal_cell)?;
+
+    let mut points = vec![start, start_exit];
+    points.extend(cells.iter().skip(1).take(cells.len().saturating_sub(2)).map(|&cell| grid.cell_center(cell)));
+    points.push(end_entry);
+    points.push(end);
+    Some(simplify_polyline(&points))
+}
+
+// A* over (cell, arrival direction) states so turns can be penalised
+fn find_cell_path(grid: &RoutingGrid, start: (i32, i32), goal: (i32, i32)) -> Option<Vec<(i32, i32)>> {
+    let state_count = grid.blocked.len() * (NO_DIRECTION + 1);
+    let state_of = |cell: (i32, i32), direction: usize| grid.index(cell).map(|i| i * (NO_DIRECTION + 1) + direction);
+    let heuristic = |cell: (i32, i32)| ((cell.0 - goal.0).unsigned_abs() + (cell.1 - goal.1).unsigned_abs()) * STEP_COST;
+
+    let mut best_cost = vec![u32::MAX; state_count];
+    let mut came_from: Vec<Option<usize>> = vec![None; state_count];
+    let mut open = BinaryHeap::new();
+
+    let start_state = state_of(start, NO_DIRECTION)?;
+    best_cost[start_state] = 0;
+    open.push(Reverse((heuristic(start), 0u32, start, NO_DIRECTION)));
+
+    while let Some(Reverse((_, cost, cell, direction))) = open.pop() {
+        let state = state_of(cell, direction)?;
+        if cost > best_cost[state] { continue; }
+        if cell == goal {
+            // Walk back through the recorded states
+            let mut path = vec![cell];
+            let mut current = state;
+            while let Some(previous) = came_from[current] {
+                let cell_index = previous / (NO_DIRECTION + 1);
+                path.push(((cell_index as i32) % grid.columns, (cell_index as i32) / grid.columns));
+                current = previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for (next_direction, &(dx, dy)) in DIRECTIONS.iter().enumerate() {
+            let next_cell = (cell.0 + dx, cell.1 + dy);
+            let Some(next_index) = grid.index(next_cell) else { continue; };
+            if grid.blocked[next_index] { continue; }
+            let turn = if direction != NO_DIRECTION && direction != next_direction { TURN_COST } else { 0 };
+            let next_cost = cost + STEP_COST + turn;
+            let next_state = next_index * (NO_DIRECTION + 1) + next_direction;
+            if next_cost < best_cost[next_state] {
+                best_cost[next_state] = next_cost;
+                came_from[next_state] = Some(state);
+                open.push(Reverse((next_cost + heuristic(next_cell), next_cost, next_cell, next_direction)));
+            }
+        }
+    }
+    None
+}
+
+// Drops points that lie on the straight line between their neighbours
+fn simplify_polyline(points: &[Vec2]) -> Vec<Vec2> {
+    let mut simplified: Vec<Vec2> = Vec::with_capacity(points.len());
+    for &point in points {
+        if simplified.last() == Some(&point) { continue; }
+        if simplified.len() >= 2 {
+            let a = simplified[simplified.len() - 2];
+            let b = simplified[simplified.len() - 1];
+            if (b - a).perp_dot(point - b).abs() < 1e-3 && (b - a).dot(point - b) >= 0.0 {
+                simplified.pop();
+            }
+        }
+        simplified.push(point);
+    }
+    simplified
+}
@@ -0,0 +1,251 @@
+// scripting.rs
+// Rhai scripts that edit the diagram: generators, layout tweaks, exporters. A script runs against a
+// copy of the diagram, which replaces the original only if the script finishes without error.
+//
+//     fn register() { register_command("Chain of five", "chain"); }    // optional palette entries
+//     fn chain() {
+//         let previous = add_shape(0, 0, "Step 1");
+//         for i in 2..=5 { let next = add_shape(0, i * 120, `Step ${i}`); connect(previous, next); previous = next; }
+//     }
+//
+// Shapes and connections are referred to by index. Connections a script adds are checked against
+// the connection rules once it has finished. Without a `register` function the whole file
+// is one command, named after it.
+
+use ggez::glam::Vec2;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope, AST, FLOAT, INT};
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+use crate::{ports, Diagram, ShapeData};
+
+pub const SCRIPT_EXTENSION: &str = "rhai";
+const MAX_OPERATIONS: u64 = 10_000_000; // Stops runaway loops before they freeze the editor
+
+// --- One palette entry backed by a script ---
+#[derive(Clone, Debug)]
+pub struct ScriptCommand {
+    pub title: String,
+    pub path: PathBuf,
+    pub function: Option<String>, // Function to call; None runs the file's top level
+}
+
+// --- What a finished script did ---
+pub struct ScriptRun {
+    pub changed: bool, // Whether the diagram was edited
+    pub messages: Vec<String>, // print() output
+}
+
+// Everything the registered functions work on
+#[derive(Default)]
+struct ScriptState {
+    diagram: Diagram,
+    changed: bool,
+    commands: Vec<(String, String)>, // (title, function) from register_command
+    messages: Vec<String>,
+    output_dir: PathBuf, // Where write_file puts files
+}
+
+type Shared = Rc<RefCell<ScriptState>>;
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+// Scripts accept ints wherever a number is expected
+fn number(value: &Dynamic) -> ScriptResult<f32> {
+    value.as_float().map(|f| f as f32)
+        .or_else(|_| value.as_int().map(|i| i as f32))
+        .map_err(|_| format!("expected a number, got {}", value.type_name()).into())
+}
+
+fn index(value: INT, count: usize, what: &str) -> ScriptResult<usize> {
+    usize::try_from(value).ok().filter(|&i| i < count).ok_or_else(|| format!("no {} {}", what, value).into())
+}
+
+fn engine(state: &Shared) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    let s = state.clone();
+    engine.on_print(move |text| s.borrow_mut().messages.push(text.to_string()));
+
+    // --- Reading the graph ---
+    let s = state.clone();
+    engine.register_fn("shape_count", move || s.borrow().diagram.shapes.len() as INT);
+    let s = state.clone();
+    engine.register_fn("connection_count", move || s.borrow().diagram.connections.len() as INT);
+    let s = state.clone();
+    engine.register_fn("shapes", move || -> Array {
+        s.borrow().diagram.shapes.iter().enumerate().map(|(i, shape)| {
+            let mut map = Map::new();
+            map.insert("index".into(), (i as INT).into());
+            map.insert("text".into(), shape.text.clone().unwrap_or_default().into());
+            map.insert("x".into(), (shape.center_position.x as FLOAT).into());
+            map.insert("y".into(), (shape.center_position.y as FLOAT).into());
+            map.insert("tags".into(), shape.tags.iter().cloned().map(Dynamic::from).collect::<Array>().into());
+            map.insert("attributes".into(), shape.attributes.iter().map(|(k, v)| (k.as_str().into(), v.clone().into())).collect::<Map>().into());
+            Dynamic::from_map(map)
+        }).collect()
+    });
+    let s = state.clone();
+    engine.register_fn("connections", move || -> Array {
+        s.borrow().diagram.connections.iter().enumerate().map(|(i, connection)| {
+            let mut map = Map::new();
+            map.insert("index".into(), (i as INT).into());
+            map.insert("from".into(), (connection.from_shape_index as INT).into());
+            map.insert("to".into(), (connection.to_shape_index as INT).into());
+            map.insert("label".into(), connection.label.clone().unwrap_or_default().into());
+            Dynamic::from_map(map)
+        }).collect()
+    });
+    for (name, outgoing) in [("outgoing", true), ("incoming", false)] {
+        let s = state.clone();
+        engine.register_fn(name, move |shape: INT| -> ScriptResult<Array> {
+            let state = s.borrow();
+            let shape = index(shape, state.diagram.shapes.len(), "shape")?;
+            Ok(state.diagram.neighbors(shape, outgoing).into_iter().map(|n| Dynamic::from(n as INT)).collect())
+        });
+    }
+    let s = state.clone();
+    engine.register_fn("text", move |shape: INT| -> ScriptResult<String> {
+        let state = s.borrow();
+        Ok(state.diagram.shapes[index(shape, state.diagram.shapes.len(), "shape")?].text.clone().unwrap_or_default())
+    });
+    let s = state.clone();
+    engine.register_fn("attribute", move |shape: INT, key: &str| -> ScriptResult<Dynamic> {
+        let state = s.borrow();
+        let shape = &state.diagram.shapes[index(shape, state.diagram.shapes.len(), "shape")?];
+        Ok(shape.attributes.get(key).map_or(Dynamic::UNIT, |value| value.clone().into()))
+    });
+
+    // --- Editing it ---
+    let s = state.clone();
+    let add_shape = move |x: Dynamic, y: Dynamic, text: Option<String>| -> ScriptResult<INT> {
+        let mut state = s.borrow_mut();
+        let shape = ShapeData { text, ..ShapeData::new(Vec2::new(number(&x)?, number(&y)?)) };
+        state.changed = true;
+        Ok(state.diagram.add_shape(shape) as INT)
+    };
+    let add = add_shape.clone();
+    engine.register_fn("add_shape", move |x: Dynamic, y: Dynamic| add(x, y, None));
+    engine.register_fn("add_shape", move |x: Dynamic, y: Dynamic, text: &str| add_shape(x, y, Some(text.to_string())));
+    let s = state.clone();
+    let connect = move |from: INT, to: INT, label: Option<String>| -> ScriptResult<INT> {
+        let mut state = s.borrow_mut();
+        let count = state.diagram.shapes.len();
+        let (from, to) = (index(from, count, "shape")?, index(to, count, "shape")?);
+        let connection = state.diagram.connect(from, ports::default_outgoing_port(), to, ports::default_incoming_port())
+            .ok_or_else(|| format!("shape {} or {} has no default port", from, to))?;
+        state.diagram.connections[connection].label = label;
+        state.changed = true;
+        Ok(connection as INT)
+    };
+    let unlabelled = connect.clone();
+    engine.register_fn("connect", move |from: INT, to: INT| unlabelled(from, to, None));
+    engine.register_fn("connect", move |from: INT, to: INT, label: &str| connect(from, to, Some(label.to_string())));
+    let s = state.clone();
+    engine.register_fn("set_text", move |shape: INT, text: &str| -> ScriptResult<()> {
+        let mut state = s.borrow_mut();
+        let shape = index(shape, state.diagram.shapes.len(), "shape")?;
+        state.diagram.shapes[shape].text = Some(text.to_string()).filter(|t| !t.is_empty());
+        state.changed = true;
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("set_label", move |connection: INT, label: &str| -> ScriptResult<()> {
+        let mut state = s.borrow_mut();
+        let connection = index(connection, state.diagram.connections.len(), "connection")?;
+        state.diagram.connections[connection].label = Some(label.to_string()).filter(|l| !l.is_empty());
+        state.changed = true;
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("move_shape", move |shape: INT, x: Dynamic, y: Dynamic| -> ScriptResult<()> {
+        let mut state = s.borrow_mut();
+        let shape = index(shape, state.diagram.shapes.len(), "shape")?;
+        state.diagram.move_shape(shape, Vec2::new(number(&x)?, number(&y)?));
+        state.changed = true;
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("set_attribute", move |shape: INT, key: &str, value: &str| -> ScriptResult<()> {
+        let mut state = s.borrow_mut();
+        let shape = index(shape, state.diagram.shapes.len(), "shape")?;
+        state.diagram.shapes[shape].attributes.insert(key.to_string(), value.to_string());
+        state.changed = true;
+        Ok(())
+    });
+
+    // --- Output and registration ---
+    // Only plain relative names are accepted, so a script can't write outside the diagram's folder
+    let s = state.clone();
+    engine.register_fn("write_file", move |name: &str, contents: &str| -> ScriptResult<()> {
+        let relative = Path::new(name);
+        if name.is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(format!("write_file needs a relative file name, not '{}'", name).into());
+        }
+        let path = s.borrow().output_dir.join(relative);
+        fs::write(&path, contents).map_err(|e| format!("Could not write {}: {}", path.display(), e).into())
+    });
+    let s = state.clone();
+    engine.register_fn("register_command", move |title: &str, function: &str| {
+        s.borrow_mut().commands.push((title.to_string(), function.to_string()));
+    });
+    engine
+}
+
+fn compile(path: &Path, engine: &Engine) -> Result<AST, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    engine.compile(&source).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// Commands offered by the `.rhai` files in `dirs`, in file name order, plus the scripts that failed
+// to load and why. A file's `register()` is run (against an empty diagram) to collect its commands.
+pub fn discover(dirs: &[PathBuf]) -> (Vec<ScriptCommand>, Vec<String>) {
+    let (mut commands, mut problems) = (Vec::new(), Vec::new());
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(dir) else { continue; };
+        let mut paths: Vec<PathBuf> = entries.filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|e| e == SCRIPT_EXTENSION))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let state: Shared = Rc::default();
+            let engine = engine(&state);
+            let ast = match compile(&path, &engine) {
+                Ok(ast) => ast,
+                Err(e) => { problems.push(e); continue; }
+            };
+            if ast.iter_functions().any(|f| f.name == "register" && f.params.is_empty()) {
+                if let Err(e) = engine.call_fn::<Dynamic>(&mut Scope::new(), &ast, "register", ()) {
+                    problems.push(format!("{}: register() failed: {}", path.display(), e));
+                    continue;
+                }
+            }
+            let registered = std::mem::take(&mut state.borrow_mut().commands);
+            if registered.is_empty() {
+                let title = path.file_stem().map_or_else(String::new, |s| s.to_string_lossy().into_owned());
+                commands.push(ScriptCommand { title, path: path.clone(), function: None });
+            }
+            for (title, function) in registered {
+                commands.push(ScriptCommand { title, path: path.clone(), function: Some(function) });
+            }
+        }
+    }
+    (commands, problems)
+}
+
+// Runs a script command on `diagram`; files it writes go to `output_dir`
+pub fn run(command: &ScriptCommand, diagram: &mut Diagram, output_dir: &Path) -> Result<ScriptRun, String> {
+    let state: Shared = Rc::new(RefCell::new(ScriptState { diagram: diagram.clone(), output_dir: output_dir.to_path_buf(), ..ScriptState::default() }));
+    let engine = engine(&state);
+    let ast = compile(&command.path, &engine)?;
+    let mut scope = Scope::new();
+    let result = match &command.function {
+        None => engine.run_ast_with_scope(&mut scope, &ast),
+        Some(function) => engine.call_fn::<Dynamic>(&mut scope, &ast, function, ()).map(|_| ()),
+    };
+    result.map_err(|e| format!("Script '{}' failed: {}", command.title, e))?;
+    let mut state = state.borrow_mut();
+    if state.changed { *diagram = std::mem::take(&mut state.diagram).validated(); }
+    Ok(ScriptRun { changed: state.changed, messages: std::mem::take(&mut state.messages) })
+}
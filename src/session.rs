@@ -0,0 +1,193 @@
+// session.rs
+// Recordings of the input the editor received, for reproducing interaction bugs: `--record file`
+// writes one while you work, `--replay file` feeds it back through the EventHandler at the recorded
+// times (with `--headless`, in a hidden window that closes when the recording ends).
+
+use ggez::event::MouseButton;
+use ggez::input::keyboard::{KeyCode, KeyMods};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const FLUSH_EVERY_EVENTS: usize = 100; // Written out this often too, in case the editor doesn't close cleanly
+
+// Keys a replay can press: every key the editor reacts to. Others are recorded but skipped on replay.
+const REPLAYABLE_KEYS: [KeyCode; 73] = [
+    KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G, KeyCode::H, KeyCode::I,
+    KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N, KeyCode::O, KeyCode::P, KeyCode::Q, KeyCode::R,
+    KeyCode::S, KeyCode::T, KeyCode::U, KeyCode::V, KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z,
+    KeyCode::Key0, KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4,
+    KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+    KeyCode::Numpad0, KeyCode::Numpad1, KeyCode::Numpad2, KeyCode::Numpad3, KeyCode::Numpad4,
+    KeyCode::Numpad5, KeyCode::Numpad6, KeyCode::Numpad7, KeyCode::Numpad8, KeyCode::Numpad9,
+    KeyCode::NumpadAdd, KeyCode::NumpadSubtract, KeyCode::NumpadEnter,
+    KeyCode::Escape, KeyCode::Return, KeyCode::Back, KeyCode::Delete, KeyCode::Insert, KeyCode::Tab, KeyCode::Space,
+    KeyCode::Left, KeyCode::Right, KeyCode::Up, KeyCode::Down, KeyCode::Home, KeyCode::End, KeyCode::PageUp, KeyCode::PageDown,
+    KeyCode::LBracket, KeyCode::RBracket, KeyCode::Equals, KeyCode::Minus, KeyCode::Plus, KeyCode::F2, KeyCode::F3, KeyCode::F5, KeyCode::F6,
+];
+
+fn key_name(keycode: KeyCode) -> String {
+    format!("{:?}", keycode)
+}
+
+pub fn key_from_name(name: &str) -> Option<KeyCode> {
+    REPLAYABLE_KEYS.into_iter().find(|&key| key_name(key) == name)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecordedButton {
+    Left,
+    Right,
+    Middle,
+    Other(u16),
+}
+
+impl RecordedButton {
+    pub fn from_button(button: MouseButton) -> RecordedButton {
+        match button {
+            MouseButton::Left => RecordedButton::Left,
+            MouseButton::Right => RecordedButton::Right,
+            MouseButton::Middle => RecordedButton::Middle,
+            MouseButton::Other(other) => RecordedButton::Other(other),
+        }
+    }
+
+    pub fn button(self) -> MouseButton {
+        match self {
+            RecordedButton::Left => MouseButton::Left,
+            RecordedButton::Right => MouseButton::Right,
+            RecordedButton::Middle => MouseButton::Middle,
+            RecordedButton::Other(other) => MouseButton::Other(other),
+        }
+    }
+}
+
+// --- One input event, with the keyboard state the editor reads while handling it ---
+// Positions are physical window coordinates, as the EventHandler receives them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SessionEvent {
+    MouseDown { button: RecordedButton, x: f32, y: f32, mods: u8, space_held: bool },
+    MouseUp { button: RecordedButton, x: f32, y: f32 },
+    MouseMotion { x: f32, y: f32, dx: f32, dy: f32 },
+    Wheel { x: f32, y: f32, #[serde(default)] mods: u8 }, // Mouse wheel notches
+    Pinch { delta: f32 },
+    ScrollPan { dx: f32, dy: f32, mods: u8 }, // Two-finger touchpad scroll, in physical pixels
+    Key { key: String, mods: u8, repeated: bool },
+    Text { character: char },
+    Resize { width: f32, height: f32 },
+}
+
+impl SessionEvent {
+    pub fn key(keycode: KeyCode, mods: KeyMods, repeated: bool) -> SessionEvent {
+        SessionEvent::Key { key: key_name(keycode), mods: mods.bits(), repeated }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TimedEvent {
+    pub at_ms: u64, // Since the recording started
+    #[serde(flatten)]
+    pub event: SessionEvent,
+}
+
+// --- The file: the window it was recorded in, the diagram it started from, and the events ---
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub window_size: [f32; 2], // Drawable size in physical pixels
+    pub diagram: Option<PathBuf>,
+    pub events: Vec<TimedEvent>,
+}
+
+impl Session {
+    pub fn load(path: &Path) -> Result<Session, String> {
+        let json = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        serde_json::from_str(&json).map_err(|e| format!("{} is not a session recording: {}", path.display(), e))
+    }
+}
+
+pub struct Recorder {
+    path: PathBuf,
+    started: Instant,
+    session: Session,
+    unflushed: usize,
+}
+
+impl Recorder {
+    pub fn new(path: PathBuf, window_size: [f32; 2], diagram: Option<PathBuf>) -> Recorder {
+        Recorder { path, started: Instant::now(), session: Session { window_size, diagram, events: Vec::new() }, unflushed: 0 }
+    }
+
+    pub fn record(&mut self, event: SessionEvent) {
+        let at_ms = self.started.elapsed().as_millis() as u64;
+        self.session.events.push(TimedEvent { at_ms, event });
+        self.unflushed += 1;
+        if self.unflushed >= FLUSH_EVERY_EVENTS {
+            if let Err(e) = self.flush() { log::warn!(target: crate::logging::IO, "{}", e); }
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.unflushed = 0;
+        let json = serde_json::to_string_pretty(&self.session).map_err(|e| format!("Could not serialize the recording: {}", e))?;
+        fs::write(&self.path, json).map_err(|e| format!("Could not write {}: {}", self.path.display(), e))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+// --- Feeds a recording back at its recorded pace ---
+pub struct Replayer {
+    events: Vec<TimedEvent>,
+    next: usize,
+    started: Option<Instant>, // When the first frame asked for events, so loading doesn't eat into the timing
+    pub headless: bool, // Close the editor once everything has been replayed
+}
+
+impl Replayer {
+    pub fn new(session: Session, headless: bool) -> Replayer {
+        Replayer { events: session.events, next: 0, started: None, headless }
+    }
+
+    // Events whose time has come, in order
+    pub fn due(&mut self) -> Vec<SessionEvent> {
+        let elapsed = self.started.get_or_insert_with(Instant::now).elapsed();
+        let count = self.events[self.next..].iter().take_while(|e| Duration::from_millis(e.at_ms) <= elapsed).count();
+        self.next += count;
+        self.events[self.next - count..self.next].iter().map(|e| e.event.clone()).collect()
+    }
+
+    pub fn finished(&self) -> bool {
+        self.next >= self.events.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_sessions_round_trip_and_replay_in_order() {
+        let session = Session {
+            window_size: [800.0, 600.0],
+            diagram: None,
+            events: vec![
+                TimedEvent { at_ms: 0, event: SessionEvent::MouseMotion { x: 1.0, y: 2.0, dx: 1.0, dy: 2.0 } },
+                TimedEvent { at_ms: 0, event: SessionEvent::key(KeyCode::Delete, KeyMods::SHIFT, false) },
+                TimedEvent { at_ms: 60_000, event: SessionEvent::Text { character: 'x' } },
+            ],
+        };
+        let json = serde_json::to_string(&session).unwrap();
+        let parsed: Session = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.events.len(), 3);
+        assert_eq!(parsed.events[1].event, SessionEvent::key(KeyCode::Delete, KeyMods::SHIFT, false));
+        assert_eq!(key_from_name("Delete"), Some(KeyCode::Delete));
+
+        let mut replay = Replayer::new(parsed, true);
+        assert_eq!(replay.due().len(), 2);
+        assert!(replay.due().is_empty() && !replay.finished());
+    }
+}
@@ -0,0 +1,130 @@
+// shape_kind.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::{Color, DrawMode, Mesh, Rect};
+use ggez::{Context, GameResult};
+use serde::{Deserialize, Serialize};
+
+use crate::ports::PortSide;
+
+const PARALLELOGRAM_SKEW: f32 = 0.2; // Horizontal slant as a fraction of the shape width
+const ELLIPSE_TOLERANCE: f32 = 0.1;
+
+// --- Outline a shape is drawn with, hit-tested against and has its ports placed on ---
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShapeKind {
+    #[default]
+    Rectangle, // Rounded by the shape's corner radius
+    Ellipse,
+    Diamond,
+    Parallelogram,
+    Note, // Sticky note: a comment beside the graph, with no ports and its text from the top left
+}
+
+impl ShapeKind {
+    // Order used when cycling with Tab. Notes are left out, since a node turned into one would lose its connections.
+    pub fn next(self) -> ShapeKind {
+        match self {
+            ShapeKind::Rectangle => ShapeKind::Ellipse,
+            ShapeKind::Ellipse => ShapeKind::Diamond,
+            ShapeKind::Diamond => ShapeKind::Parallelogram,
+            ShapeKind::Parallelogram | ShapeKind::Note => ShapeKind::Rectangle,
+        }
+    }
+
+    // Notes can't be connected, whatever ports they were given
+    pub fn has_ports(self) -> bool {
+        self != ShapeKind::Note
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ShapeKind::Rectangle => "Rectangle",
+            ShapeKind::Ellipse => "Ellipse",
+            ShapeKind::Diamond => "Diamond",
+            ShapeKind::Parallelogram => "Parallelogram",
+            ShapeKind::Note => "Note",
+        }
+    }
+
+    // Corners of the polygonal kinds, clockwise from the top; None for curved/rounded kinds
+    pub fn polygon(self, rect: Rect) -> Option<[Vec2; 4]> {
+        let center = rect.center();
+        match self {
+            ShapeKind::Diamond => Some([
+                Vec2::new(center.x, rect.top()),
+                Vec2::new(rect.right(), center.y),
+                Vec2::new(center.x, rect.bottom()),
+                Vec2::new(rect.left(), center.y),
+            ]),
+            ShapeKind::Parallelogram => {
+                let skew = rect.w * PARALLELOGRAM_SKEW;
+                Some([
+                    Vec2::new(rect.left() + skew, rect.top()),
+                    Vec2::new(rect.right(), rect.top()),
+                    Vec2::new(rect.right() - skew, rect.bottom()),
+                    Vec2::new(rect.left(), rect.bottom()),
+                ])
+            }
+            ShapeKind::Rectangle | ShapeKind::Ellipse | ShapeKind::Note => None,
+        }
+    }
+
+    pub fn contains(self, rect: Rect, point: Vec2) -> bool {
+        if !rect.contains(point) { return false; }
+        let half_size = Vec2::new(rect.w / 2.0, rect.h / 2.0);
+        let local = (point - Vec2::from(rect.center())) / half_size;
+        match self {
+            ShapeKind::Rectangle | ShapeKind::Note => true,
+            ShapeKind::Ellipse => local.length_squared() <= 1.0,
+            ShapeKind::Diamond => local.x.abs() + local.y.abs() <= 1.0,
+            ShapeKind::Parallelogram => {
+                // Slanted edges move inwards by up to `skew` at the top (left edge) and bottom (right edge)
+                let skew = rect.w * PARALLELOGRAM_SKEW;
+                let t = (point.y - rect.top()) / rect.h;
+                point.x >= rect.left() + skew * (1.0 - t) && point.x <= rect.right() - skew * t
+            }
+        }
+    }
+
+    // Point on the outline for a port on `side`, `offset` of the way along it
+    pub fn outline_point(self, rect: Rect, side: PortSide, offset: f32) -> Vec2 {
+        let center = Vec2::from(rect.center());
+        let half_size = Vec2::new(rect.w / 2.0, rect.h / 2.0);
+        // The slanted sides run between the parallelogram's own corners
+        if let (ShapeKind::Parallelogram, Some([top_left, top_right, bottom_right, bottom_left])) = (self, self.polygon(rect)) {
+            return match side {
+                PortSide::Top => top_left.lerp(top_right, offset),
+                PortSide::Bottom => bottom_left.lerp(bottom_right, offset),
+                PortSide::Left => top_left.lerp(bottom_left, offset),
+                PortSide::Right => top_right.lerp(bottom_right, offset),
+            };
+        }
+        // Position along the side, from -1 to 1 relative to the center
+        let along = offset * 2.0 - 1.0;
+        // How far the outline reaches from the center at that position, as a fraction of the half size
+        let reach = match self {
+            ShapeKind::Rectangle | ShapeKind::Parallelogram | ShapeKind::Note => 1.0,
+            ShapeKind::Ellipse => (1.0 - along * along).max(0.0).sqrt(),
+            ShapeKind::Diamond => 1.0 - along.abs(),
+        };
+        match side {
+            PortSide::Top => Vec2::new(center.x + along * half_size.x, center.y - reach * half_size.y),
+            PortSide::Bottom => Vec2::new(center.x + along * half_size.x, center.y + reach * half_size.y),
+            PortSide::Left => Vec2::new(center.x - reach * half_size.x, center.y + along * half_size.y),
+            PortSide::Right => Vec2::new(center.x + reach * half_size.x, center.y + along * half_size.y),
+        }
+    }
+
+    pub fn mesh(self, ctx: &mut Context, mode: DrawMode, rect: Rect, corner_radius: f32, color: Color) -> GameResult<Mesh> {
+        if let Some(points) = self.polygon(rect) {
+            return Mesh::new_polygon(ctx, mode, &points, color);
+        }
+        match self {
+            ShapeKind::Ellipse => Mesh::new_ellipse(ctx, mode, rect.center(), rect.w / 2.0, rect.h / 2.0, ELLIPSE_TOLERANCE, color),
+            ShapeKind::Note => Mesh::new_rectangle(ctx, mode, rect, color),
+            _ => Mesh::new_rounded_rectangle(ctx, mode, rect, corner_radius, color),
+        }
+    }
+}
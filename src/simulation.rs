@@ -0,0 +1,106 @@
+// simulation.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::Rect;
+use std::time::Duration;
+
+use crate::tween::{Easing, Tween};
+use crate::ui::{self, ListOverlay, Widget};
+use crate::Diagram;
+
+const BRANCH_PANEL_WIDTH: f32 = 360.0;
+const BRANCH_PANEL_TOP: f32 = 60.0;
+const TOKEN_MOVE_DURATION: Duration = Duration::from_millis(450);
+
+// What a step did with the token
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    Moved(usize), // Along this connection
+    Branch, // More than one way on; the branch panel is open
+    End, // Nothing leads on from here
+    NoToken, // No start shape clicked yet
+}
+
+// --- Token simulation: a token walked along the connections, one step per Space ---
+// Click the shape to start from; where several connections lead on, the branch panel asks which.
+#[derive(Default)]
+pub struct Simulation {
+    pub token: Option<usize>, // Shape the token is on; None until a start shape is clicked
+    pub walked: Vec<usize>, // Connections taken so far, oldest first
+    pub branch: Option<Vec<usize>>, // Outgoing connections offered at a branch
+    list: ListOverlay, // Highlighted branch
+    pub moving: Option<(usize, Tween)>, // Connection the token is still travelling along
+}
+
+impl Simulation {
+    // Puts the token on `shape`, forgetting the walk so far
+    pub fn start(&mut self, shape: usize) {
+        *self = Simulation { token: Some(shape), ..Simulation::default() };
+    }
+
+    // Takes the only way on, or opens the branch panel when there are several. With the panel
+    // open, takes the highlighted branch.
+    pub fn step(&mut self, diagram: &Diagram) -> Step {
+        if self.branch.is_some() { return self.choose(diagram, None); }
+        let Some(token) = self.token else { return Step::NoToken; };
+        let outgoing = outgoing(diagram, token);
+        match outgoing.as_slice() {
+            [] => Step::End,
+            [only] => { self.take(diagram, *only); Step::Moved(*only) }
+            _ => {
+                self.branch = Some(outgoing);
+                self.list = ListOverlay::default();
+                Step::Branch
+            }
+        }
+    }
+
+    // Takes the clicked branch, or the highlighted one
+    pub fn choose(&mut self, diagram: &Diagram, index: Option<usize>) -> Step {
+        let Some(options) = &self.branch else { return Step::NoToken; };
+        let Some(&connection) = options.get(index.unwrap_or(self.list.selected())) else { return Step::Branch; };
+        self.branch = None;
+        self.take(diagram, connection);
+        Step::Moved(connection)
+    }
+
+    // Moves the highlight between the offered branches
+    pub fn move_selection(&mut self, delta: isize) {
+        let Some(options) = &self.branch else { return; };
+        self.list.move_selection(options.len(), delta);
+    }
+
+    fn take(&mut self, diagram: &Diagram, connection: usize) {
+        self.token = Some(diagram.connections[connection].to_shape_index);
+        self.walked.push(connection);
+        self.moving = Some((connection, Tween::new(TOKEN_MOVE_DURATION, Easing::EaseInOut)));
+    }
+
+    // Panel near the top of the screen, one button per branch: its label and where it leads.
+    // Button ids are indices into the offered branches.
+    pub fn branch_widgets(&self, screen_size: Vec2, diagram: &Diagram) -> Option<(Rect, Vec<Widget<usize>>)> {
+        let options = self.branch.as_ref()?;
+        let width = BRANCH_PANEL_WIDTH.min(screen_size.x);
+        let items = options.iter().enumerate().map(|(index, &connection)| {
+            let connection = &diagram.connections[connection];
+            let target = diagram.shapes[connection.to_shape_index].text.as_deref().and_then(|t| t.lines().next()).filter(|t| !t.trim().is_empty())
+                .map_or_else(|| format!("Shape {}", connection.to_shape_index), str::to_string);
+            match connection.label.as_deref().filter(|label| !label.trim().is_empty()) {
+                Some(label) => format!("{}. {} → {}", index + 1, label, target),
+                None => format!("{}. → {}", index + 1, target),
+            }
+        }).collect();
+        let origin = ui::centered_origin(screen_size, width, BRANCH_PANEL_TOP);
+        Some(self.list.widgets(origin, width, options.len(), vec![String::from("Which way?")], items))
+    }
+}
+
+// Shown connections leading out of `shape` to a shown shape, in drawing order. Layers and collapsed
+// groups hide the rest, so the token doesn't vanish down them.
+pub fn outgoing(diagram: &Diagram, shape: usize) -> Vec<usize> {
+    diagram.connections.iter().enumerate()
+        .filter(|(_, connection)| connection.from_shape_index == shape && connection.to_shape_index < diagram.shapes.len())
+        .filter(|(_, connection)| !diagram.connection_hidden(connection) && diagram.collapsed_group_of_shape(connection.to_shape_index).is_none())
+        .map(|(index, _)| index)
+        .collect()
+}
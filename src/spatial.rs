@@ -0,0 +1,43 @@
+// spatial.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::Rect;
+use std::collections::HashMap;
+
+// --- Uniform grid of buckets over the world, for finding items near a point ---
+// Items are filed by bounding box under every cell the box overlaps, so a point query only
+// looks at one bucket. Results are candidates that still need an exact hit test.
+pub struct SpatialHash {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> SpatialHash {
+        SpatialHash { cell_size, cells: HashMap::new() }
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+    }
+
+    fn cell_of(&self, point: Vec2) -> (i32, i32) {
+        ((point.x / self.cell_size).floor() as i32, (point.y / self.cell_size).floor() as i32)
+    }
+
+    // Items should be inserted in ascending order; queries return them in insertion order
+    pub fn insert(&mut self, item: usize, bounds: Rect) {
+        let (min_col, min_row) = self.cell_of(Vec2::new(bounds.left(), bounds.top()));
+        let (max_col, max_row) = self.cell_of(Vec2::new(bounds.right(), bounds.bottom()));
+        for row in min_row..=max_row {
+            for col in min_col..=max_col {
+                self.cells.entry((col, row)).or_default().push(item);
+            }
+        }
+    }
+
+    // Items whose bounds may contain `point`
+    pub fn query_point(&self, point: Vec2) -> &[usize] {
+        self.cells.get(&self.cell_of(point)).map_or(&[], |items| items.as_slice())
+    }
+}
@@ -0,0 +1,29 @@
+// stats_panel.rs
+
+use ggez::graphics::Rect;
+
+use crate::diagram::GraphStats;
+use crate::ui::{self, PanelLayout, Widget};
+
+const STATS_PANEL_WIDTH: f32 = 240.0;
+
+// Read-only panel docked bottom-left, just above `bottom` (the status bar's top edge).
+// The selected shape's degrees are listed when there is one: (its name, its index).
+pub fn stats_widgets(stats: &GraphStats, selected: Option<(String, usize)>, bottom: f32) -> (Rect, Vec<Widget<()>>) {
+    let mut lines = vec![
+        String::from("Graph statistics"),
+        format!("Nodes: {}", stats.node_count),
+        format!("Edges: {}", stats.edge_count),
+        format!("Components: {}", stats.component_count),
+        format!("Acyclic: {}", if stats.acyclic { "yes" } else { "no" }),
+    ];
+    if let Some((name, index)) = selected {
+        let degree = |degrees: &[usize]| degrees.get(index).copied().unwrap_or(0);
+        lines.push(format!("{}: in {} / out {}", name, degree(&stats.in_degree), degree(&stats.out_degree)));
+    }
+    let height = ui::panel_height(lines.len());
+    let panel = Rect::new(0.0, bottom - height, STATS_PANEL_WIDTH, height);
+    let mut layout = PanelLayout::new(panel);
+    for line in lines { layout.label(line); }
+    (panel, layout.widgets)
+}
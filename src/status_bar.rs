@@ -0,0 +1,88 @@
+// status_bar.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::{self, Canvas, Color, DrawMode, Drawable, Mesh, Rect, Text, TextAlign, TextLayout};
+use ggez::{Context, GameResult};
+
+use crate::ui::UiStyle;
+
+const STATUS_BAR_HEIGHT: f32 = 24.0; // At least; grows with a larger font
+const STATUS_BAR_VERTICAL_PADDING: f32 = 9.0;
+pub const DEFAULT_STATUS_FONT_SIZE: f32 = 15.0;
+const STATUS_PADDING: f32 = 8.0;
+const UNSAVED_COLOR: Color = Color { r: 1.0, g: 0.75, b: 0.3, a: 1.0 };
+const ALERT_COLOR: Color = Color { r: 1.0, g: 0.45, b: 0.4, a: 1.0 };
+const BREADCRUMB_WIDTH: f32 = 130.0; // Per diagram in the breadcrumb; longer names are cut short
+const BREADCRUMB_SEPARATOR: &str = ">";
+
+// --- What the status bar shows; AppState fills it in every frame ---
+pub struct StatusInfo {
+    pub alert: Option<String>, // Shown in place of the hint, e.g. a config.toml that failed to reload
+    pub hint: String, // What the keys and mouse do right now, e.g. "Esc to cancel line"
+    pub selection: String, // Empty when nothing is selected
+    pub zoom: f32,
+    pub shape_count: usize,
+    pub connection_count: usize,
+    pub unsaved: bool,
+    pub font_size: f32,
+    pub breadcrumb: Vec<String>, // Diagrams drilled down through, root first, then the open one; empty at the top
+}
+
+pub fn bar_height(font_size: f32) -> f32 {
+    STATUS_BAR_HEIGHT.max(font_size + STATUS_BAR_VERTICAL_PADDING)
+}
+
+// Where each of `count` breadcrumb entries sits, from the left end of the bar; a click on one goes back up to it
+pub fn breadcrumb_rects(area: Rect, font_size: f32, count: usize) -> Vec<Rect> {
+    let height = bar_height(font_size);
+    (0..count).map(|i| Rect::new(area.x + STATUS_PADDING + i as f32 * (BREADCRUMB_WIDTH + STATUS_PADDING), area.bottom() - height, BREADCRUMB_WIDTH, height)).collect()
+}
+
+// Strip along the bottom of `area`: hint (or alert) on the left, selection in the middle, zoom, counts and
+// the unsaved marker on the right
+pub fn draw_status_bar(ctx: &mut Context, canvas: &mut Canvas, area: Rect, info: &StatusInfo, style: &UiStyle) -> GameResult {
+    let height = bar_height(info.font_size);
+    let bar = Rect::new(area.x, area.bottom() - height, area.w, height);
+    let background = Mesh::new_rectangle(ctx, DrawMode::fill(), bar, style.panel_color)?;
+    canvas.draw(&background, graphics::DrawParam::default());
+
+    let middle_y = bar.y + bar.h / 2.0;
+    let plural = |count: usize| if count == 1 { "" } else { "s" };
+    let counts = format!(
+        "{:.0}%  |  {} shape{}, {} connection{}",
+        info.zoom * 100.0, info.shape_count, plural(info.shape_count), info.connection_count, plural(info.connection_count),
+    );
+    // Right section laid out from the right edge inwards
+    let mut right_x = bar.right() - STATUS_PADDING;
+    if info.unsaved {
+        right_x -= draw_aligned(ctx, canvas, "Unsaved", info.font_size, Vec2::new(right_x, middle_y), TextAlign::End, UNSAVED_COLOR) + STATUS_PADDING * 2.0;
+    }
+    draw_aligned(ctx, canvas, &counts, info.font_size, Vec2::new(right_x, middle_y), TextAlign::End, style.text_color);
+    let (left_text, left_color) = match &info.alert {
+        Some(alert) => (alert.as_str(), ALERT_COLOR),
+        None => (info.hint.as_str(), style.text_color),
+    };
+    // The breadcrumb, if any, comes first, with the open diagram highlighted
+    let crumbs = breadcrumb_rects(area, info.font_size, info.breadcrumb.len());
+    for (index, (name, rect)) in info.breadcrumb.iter().zip(&crumbs).enumerate() {
+        let current = index + 1 == info.breadcrumb.len();
+        let max_chars = (BREADCRUMB_WIDTH / (info.font_size * 0.6)) as usize;
+        let shown = if name.chars().count() > max_chars { format!("{}…", name.chars().take(max_chars.saturating_sub(1)).collect::<String>()) } else { name.clone() };
+        draw_aligned(ctx, canvas, &shown, info.font_size, Vec2::new(rect.x, middle_y), TextAlign::Begin, if current { style.focus_color } else { style.text_color });
+        if !current { draw_aligned(ctx, canvas, BREADCRUMB_SEPARATOR, info.font_size, Vec2::new(rect.right() + STATUS_PADDING / 2.0, middle_y), TextAlign::Middle, style.text_color); }
+    }
+    let left_x = crumbs.last().map_or(bar.x, |rect| rect.right() + STATUS_PADDING);
+    draw_aligned(ctx, canvas, left_text, info.font_size, Vec2::new(left_x + STATUS_PADDING, middle_y), TextAlign::Begin, left_color);
+    draw_aligned(ctx, canvas, &info.selection, info.font_size, Vec2::new(bar.x + bar.w / 2.0, middle_y), TextAlign::Middle, style.text_color);
+    Ok(())
+}
+
+// Draws one line of text anchored at its left edge, center or right edge; returns its width
+fn draw_aligned(ctx: &Context, canvas: &mut Canvas, text: &str, font_size: f32, anchor: Vec2, h_align: TextAlign, color: Color) -> f32 {
+    if text.is_empty() { return 0.0; }
+    let mut text_obj = Text::new(text);
+    text_obj.set_scale(font_size);
+    text_obj.set_layout(TextLayout { h_align, v_align: TextAlign::Middle });
+    canvas.draw(&text_obj, graphics::DrawParam::default().dest(anchor).color(color));
+    text_obj.dimensions(ctx).map_or(0.0, |dimensions| dimensions.w)
+}
@@ -0,0 +1,45 @@
+// stress.rs
+// Large generated diagrams for measuring performance: `--stress N` opens one in the editor, and the
+// benchmarks in benches/ run against them. The same count always gives the same diagram.
+
+use ggez::glam::Vec2;
+
+use crate::{ports, Diagram, ShapeData};
+
+pub const STRESS_SPACING: Vec2 = Vec2::new(180.0, 130.0); // Between neighbouring shape centers
+const LONG_CONNECTION_REACH: usize = 3; // Rows a long connection may skip ahead
+
+// Small fixed-seed xorshift, enough to scatter connections without a rand dependency
+struct Xorshift(u64);
+
+impl Xorshift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+// Shapes on a square-ish grid; each connects to its right neighbour and to one shape a few rows
+// further on, giving about two connections per shape, many of them crossing
+pub fn stress_diagram(shape_count: usize) -> Diagram {
+    let mut diagram = Diagram::new();
+    let columns = ((shape_count as f32).sqrt().ceil() as usize).max(1);
+    for i in 0..shape_count {
+        let position = Vec2::new((i % columns) as f32, (i / columns) as f32) * STRESS_SPACING;
+        diagram.add_shape(ShapeData { text: Some(format!("Node {}", i + 1)), ..ShapeData::new(position) });
+    }
+    let mut random = Xorshift(0x9e37_79b9_7f4a_7c15);
+    for i in 0..shape_count {
+        if (i + 1) % columns != 0 && i + 1 < shape_count {
+            diagram.connect(i, ports::default_outgoing_port(), i + 1, ports::default_incoming_port());
+        }
+        let later = shape_count - i - 1;
+        if later > 0 {
+            let target = i + 1 + (random.next() as usize) % later.min(columns * LONG_CONNECTION_REACH);
+            diagram.connect(i, ports::default_outgoing_port(), target, ports::default_incoming_port());
+        }
+    }
+    diagram
+}
@@ -0,0 +1,27 @@
+// tabs.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::Rect;
+
+use crate::tools::TOOLBAR_HEIGHT;
+use crate::ui::{self, Widget};
+
+const TAB_WIDTH: f32 = 150.0;
+const NEW_TAB_BUTTON_WIDTH: f32 = 36.0;
+
+// --- What a click on the tab strip does ---
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TabAction {
+    Switch(usize),
+    New,
+}
+
+// One button per open diagram along the top, starting at `left`, with the active one highlighted,
+// then a "+" that opens a new tab. `titles` holds each tab's label, e.g. its file name.
+pub fn tab_widgets(left: f32, titles: &[String], active: usize) -> (Rect, Vec<Widget<TabAction>>) {
+    let tabs = titles.iter().enumerate().map(|(index, title)| (TabAction::Switch(index), title.clone(), index == active));
+    let (strip, mut widgets) = ui::button_bar(Vec2::new(left, 0.0), TAB_WIDTH, TOOLBAR_HEIGHT, tabs);
+    let (new_button, new_widgets) = ui::button_bar(Vec2::new(strip.right(), 0.0), NEW_TAB_BUTTON_WIDTH, TOOLBAR_HEIGHT, [(TabAction::New, String::from("+"), false)]);
+    widgets.extend(new_widgets);
+    (Rect::new(strip.x, strip.y, strip.w + new_button.w, strip.h), widgets)
+}
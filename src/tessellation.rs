@@ -0,0 +1,137 @@
+// tessellation.rs
+// Connector lines as triangles. The lyon stroke tessellation behind the editor's connector meshes
+// lives here, apart from the graphics context, so it can be benchmarked; the editor uploads the result.
+
+use ggez::glam::Vec2;
+use ggez::graphics::{Color, Vertex};
+use lyon_path::math::Point as LyonPoint;
+use lyon_path::path::Builder as LyonPathBuilder;
+use lyon_tessellation::{BuffersBuilder, LineCap, LineJoin, StrokeOptions, StrokeTessellator, StrokeVertex, VertexBuffers};
+
+use crate::{geometry, logging, LineDash};
+
+pub const CURVE_FLATTEN_TOLERANCE: f32 = 0.25; // How far a flattened curve may stray from the true one
+
+// --- What a connector's line follows: its route's points, or its curve's control points ---
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectorPath {
+    Route(Vec<Vec2>),
+    Curve([Vec2; 4]),
+}
+
+impl ConnectorPath {
+    // The line as a polyline, curves flattened finely enough to draw
+    pub fn points(&self) -> Vec<Vec2> {
+        match self {
+            ConnectorPath::Route(route) => route.clone(),
+            ConnectorPath::Curve(curve) => geometry::flatten_cubic_bezier(curve, CURVE_FLATTEN_TOLERANCE),
+        }
+    }
+}
+
+// --- How a connector's line is stroked: the configured defaults unless the connection overrides them ---
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConnectorStroke {
+    pub color: Color,
+    pub width: f32,
+    pub dash: LineDash,
+}
+
+// Triangles covering the stroked line and the arrowhead at its `to` end, or None when there are none
+// (e.g. a zero-length route). The line stops at the arrowhead's base so a wide line can't poke past its tip.
+pub fn stroke_geometry(path: &ConnectorPath, stroke: ConnectorStroke) -> Option<VertexBuffers<Vertex, u32>> {
+    let points = path.points();
+    let arrowhead = geometry::arrowhead(&points, stroke.width);
+    let line = match arrowhead {
+        Some(_) => geometry::trim_polyline_end(&points, geometry::arrowhead_length(stroke.width)).unwrap_or_default(),
+        None => points,
+    };
+    let pieces = match stroke.dash.pattern() {
+        Some([dash, gap]) => geometry::dash_polyline(&line, dash * stroke.width, gap * stroke.width),
+        None => vec![line],
+    };
+    let mut path_builder = LyonPathBuilder::new();
+    for piece in pieces.iter().filter(|piece| piece.len() >= 2) {
+        path_builder.begin(LyonPoint::new(piece[0].x, piece[0].y));
+        for point in &piece[1..] {
+            path_builder.line_to(LyonPoint::new(point.x, point.y));
+        }
+        path_builder.end(false);
+    }
+    let mut geometry = tessellate(&path_builder.build(), &StrokeOptions::default().with_line_width(stroke.width), stroke.color)
+        .unwrap_or_else(VertexBuffers::new);
+    if let Some(corners) = arrowhead {
+        let first = geometry.vertices.len() as u32;
+        let color = [stroke.color.r, stroke.color.g, stroke.color.b, stroke.color.a];
+        geometry.vertices.extend(corners.map(|p| Vertex { position: [p.x, p.y], uv: [0.0, 0.0], color }));
+        geometry.indices.extend([first, first + 1, first + 2]);
+    }
+    (!geometry.vertices.is_empty() && !geometry.indices.is_empty()).then_some(geometry)
+}
+
+// Triangles covering a freehand annotation stroke. Round caps and joins keep jittery input looking
+// like ink; a stroke that never moved becomes a dot.
+pub fn freehand_geometry(points: &[Vec2], width: f32, color: Color) -> Option<VertexBuffers<Vertex, u32>> {
+    let (first, rest) = points.split_first()?;
+    let mut path_builder = LyonPathBuilder::new();
+    path_builder.begin(LyonPoint::new(first.x, first.y));
+    for point in rest {
+        path_builder.line_to(LyonPoint::new(point.x, point.y));
+    }
+    path_builder.end(false);
+    let options = StrokeOptions::default().with_line_width(width).with_line_cap(LineCap::Round).with_line_join(LineJoin::Round);
+    tessellate(&path_builder.build(), &options, color)
+}
+
+fn tessellate(lyon_path: &lyon_path::Path, stroke_options: &StrokeOptions, color: Color) -> Option<VertexBuffers<Vertex, u32>> {
+    let mut geometry: VertexBuffers<Vertex, u32> = VertexBuffers::new();
+    let color = [color.r, color.g, color.b, color.a];
+    let result = StrokeTessellator::new().tessellate_path(lyon_path, stroke_options,
+        &mut BuffersBuilder::new(&mut geometry, |vertex: StrokeVertex| {
+            Vertex { position: [vertex.position().x, vertex.position().y], uv: [0.0, 0.0], color }
+        }),
+    );
+    if let Err(e) = result { log::warn!(target: logging::RENDER, "Lyon tessellation error: {:?}", e); }
+
+    (!geometry.vertices.is_empty() && !geometry.indices.is_empty()).then_some(geometry)
+}
+
+// --- Tessellated lines by index (connections, or annotation strokes), each reused while its geometry key and stroke stay the same ---
+// `T` is whatever the tessellation was turned into: a GPU mesh in the editor.
+pub struct StrokeCache<T> {
+    entries: Vec<Option<CachedStroke<T>>>,
+}
+
+struct CachedStroke<T> {
+    key: u64, // Hash of everything that shapes the line
+    stroke: ConnectorStroke,
+    mesh: Option<T>, // None when tessellation produced no triangles
+}
+
+impl<T> Default for StrokeCache<T> {
+    fn default() -> Self {
+        StrokeCache { entries: Vec::new() }
+    }
+}
+
+impl<T> StrokeCache<T> {
+    pub fn resize(&mut self, count: usize) {
+        self.entries.resize_with(count, || None);
+    }
+
+    // The entry for this connection if it was built for `key` and `stroke`: Some(None) when that
+    // produced nothing to draw, None when the line needs tessellating again
+    pub fn get(&self, index: usize, key: u64, stroke: ConnectorStroke) -> Option<Option<&T>> {
+        let cached = self.entries.get(index)?.as_ref()?;
+        (cached.key == key && cached.stroke == stroke).then_some(cached.mesh.as_ref())
+    }
+
+    pub fn insert(&mut self, index: usize, key: u64, stroke: ConnectorStroke, mesh: Option<T>) {
+        if index >= self.entries.len() { self.resize(index + 1); }
+        self.entries[index] = Some(CachedStroke { key, stroke, mesh });
+    }
+
+    pub fn forget(&mut self, index: usize) {
+        if let Some(entry) = self.entries.get_mut(index) { *entry = None; }
+    }
+}
@@ -0,0 +1,201 @@
+// text_edit.rs
+
+// --- Text being edited in place: its contents, caret and selection ---
+// Positions are char indices, so they stay valid for non-ASCII text.
+#[derive(Clone, Debug, Default)]
+pub struct TextEdit {
+    pub text: String,
+    cursor: usize,
+    anchor: Option<usize>, // Other end of the selection while one exists
+}
+
+impl TextEdit {
+    // Starts editing `text` with the caret at its end
+    pub fn new(text: String) -> TextEdit {
+        let cursor = text.chars().count();
+        TextEdit { text, cursor, anchor: None }
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    fn char_count(&self) -> usize {
+        self.text.chars().count()
+    }
+
+    fn byte_index(&self, char_index: usize) -> usize {
+        self.text.char_indices().nth(char_index).map_or(self.text.len(), |(i, _)| i)
+    }
+
+    // Selected char range as (start, end), or None when nothing is selected
+    pub fn selection(&self) -> Option<(usize, usize)> {
+        let anchor = self.anchor?;
+        (anchor != self.cursor).then(|| (anchor.min(self.cursor), anchor.max(self.cursor)))
+    }
+
+    fn delete_selection(&mut self) -> bool {
+        let Some((start, end)) = self.selection() else {
+            self.anchor = None;
+            return false;
+        };
+        let (start_byte, end_byte) = (self.byte_index(start), self.byte_index(end));
+        self.text.replace_range(start_byte..end_byte, "");
+        self.cursor = start;
+        self.anchor = None;
+        true
+    }
+
+    // Types a character, replacing the selection if there is one
+    pub fn insert_char(&mut self, c: char) {
+        self.delete_selection();
+        let byte = self.byte_index(self.cursor);
+        self.text.insert(byte, c);
+        self.cursor += 1;
+    }
+
+    // Pastes text, replacing the selection. Line breaks are normalized to '\n'; other control chars are dropped.
+    pub fn insert_str(&mut self, text: &str) {
+        for c in text.replace("\r\n", "\n").chars().filter(|&c| c == '\n' || !c.is_control()) {
+            self.insert_char(c);
+        }
+    }
+
+    pub fn selected_text(&self) -> Option<&str> {
+        let (start, end) = self.selection()?;
+        Some(&self.text[self.byte_index(start)..self.byte_index(end)])
+    }
+
+    pub fn backspace(&mut self) {
+        if self.delete_selection() || self.cursor == 0 { return; }
+        let byte = self.byte_index(self.cursor - 1);
+        self.text.remove(byte);
+        self.cursor -= 1;
+    }
+
+    pub fn delete_forward(&mut self) {
+        if self.delete_selection() || self.cursor >= self.char_count() { return; }
+        let byte = self.byte_index(self.cursor);
+        self.text.remove(byte);
+    }
+
+    // Deletes back to the start of the word before the caret (Ctrl+Backspace)
+    pub fn delete_word_backward(&mut self) {
+        if self.selection().is_some() { self.delete_selection(); return; }
+        self.anchor = Some(self.previous_word_start());
+        self.delete_selection();
+    }
+
+    // Deletes up to the end of the word after the caret (Ctrl+Delete)
+    pub fn delete_word_forward(&mut self) {
+        if self.selection().is_some() { self.delete_selection(); return; }
+        self.anchor = Some(self.next_word_end());
+        self.delete_selection();
+    }
+
+    // Start of the word the caret is in or after: skips spaces and punctuation, then the word's chars
+    fn previous_word_start(&self) -> usize {
+        let before: Vec<char> = self.text.chars().take(self.cursor).collect();
+        let mut index = before.len();
+        while index > 0 && !is_word_char(before[index - 1]) { index -= 1; }
+        while index > 0 && is_word_char(before[index - 1]) { index -= 1; }
+        index
+    }
+
+    // End of the word the caret is in or before
+    fn next_word_end(&self) -> usize {
+        let mut after = self.text.chars().skip(self.cursor).peekable();
+        let mut index = self.cursor;
+        while after.next_if(|&c| !is_word_char(c)).is_some() { index += 1; }
+        while after.next_if(|&c| is_word_char(c)).is_some() { index += 1; }
+        index
+    }
+
+    // Moves the caret, extending the selection from where it was when `extend_selection` is set
+    pub fn move_to(&mut self, index: usize, extend_selection: bool) {
+        if extend_selection {
+            self.anchor.get_or_insert(self.cursor);
+        } else {
+            self.anchor = None;
+        }
+        self.cursor = index.min(self.char_count());
+    }
+
+    pub fn move_left(&mut self, extend_selection: bool) {
+        // Without Shift, Left collapses a selection to its start
+        match self.selection() {
+            Some((start, _)) if !extend_selection => self.move_to(start, false),
+            _ => self.move_to(self.cursor.saturating_sub(1), extend_selection),
+        }
+    }
+
+    pub fn move_right(&mut self, extend_selection: bool) {
+        match self.selection() {
+            Some((_, end)) if !extend_selection => self.move_to(end, false),
+            _ => self.move_to(self.cursor + 1, extend_selection),
+        }
+    }
+
+    // Ctrl+Left/Right jump a word at a time
+    pub fn move_word_left(&mut self, extend_selection: bool) {
+        self.move_to(self.previous_word_start(), extend_selection);
+    }
+
+    pub fn move_word_right(&mut self, extend_selection: bool) {
+        self.move_to(self.next_word_end(), extend_selection);
+    }
+
+    // Home/End go to the start/end of the caret's line
+    pub fn home(&mut self, extend_selection: bool) {
+        let line_start = self.text.chars().take(self.cursor).collect::<Vec<_>>()
+            .iter().rposition(|&c| c == '\n').map_or(0, |i| i + 1);
+        self.move_to(line_start, extend_selection);
+    }
+
+    pub fn end(&mut self, extend_selection: bool) {
+        let line_end = self.text.chars().skip(self.cursor).position(|c| c == '\n')
+            .map_or(self.char_count(), |i| self.cursor + i);
+        self.move_to(line_end, extend_selection);
+    }
+
+    // Up/Down go to the same column of the previous/next line, or that line's end if it is shorter
+    pub fn move_line_up(&mut self, extend_selection: bool) {
+        let (line, column) = self.line_and_column();
+        if line == 0 { return self.move_to(0, extend_selection); }
+        self.move_to(self.index_at(line - 1, column), extend_selection);
+    }
+
+    pub fn move_line_down(&mut self, extend_selection: bool) {
+        let (line, column) = self.line_and_column();
+        if line + 1 >= self.text.split('\n').count() { return self.move_to(self.char_count(), extend_selection); }
+        self.move_to(self.index_at(line + 1, column), extend_selection);
+    }
+
+    // Line and column (both in chars, from 0) of the caret
+    pub fn line_and_column(&self) -> (usize, usize) {
+        let before: Vec<char> = self.text.chars().take(self.cursor).collect();
+        let line = before.iter().filter(|&&c| c == '\n').count();
+        let column = before.iter().rev().take_while(|&&c| c != '\n').count();
+        (line, column)
+    }
+
+    // Char index of a line and column, the column clamped to the line's length
+    pub fn index_at(&self, line: usize, column: usize) -> usize {
+        let mut index = 0;
+        for (number, text) in self.text.split('\n').enumerate() {
+            let length = text.chars().count();
+            if number == line { return index + column.min(length); }
+            index += length + 1;
+        }
+        self.char_count()
+    }
+
+    pub fn select_all(&mut self) {
+        self.anchor = Some(0);
+        self.cursor = self.char_count();
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
@@ -0,0 +1,202 @@
+// theme.rs
+
+use ggez::graphics::Color;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+
+pub const PRESETS: [&str; 2] = ["dark", "light"];
+pub const PALETTES: [&str; 4] = ["default", "deuteranopia", "protanopia", "tritanopia"];
+const PREVIEW_LINE_ALPHA: u8 = 178; // The line previewed while connecting is drawn at ~0.7 opacity
+const REFUSED_ALPHA: u8 = 204;
+
+// --- Every color the canvas is drawn with ---
+#[derive(Clone, Debug)]
+pub struct Theme {
+    pub name: String,
+    pub background: Color,
+    pub shape: Color, // Fill of shapes without their own color
+    pub text: Color,
+    pub port: Color,
+    pub selected_port: Color, // Ends of the selected connector
+    pub active_port: Color, // Port a new line starts from, and valid targets under the cursor
+    pub refused: Color, // Preview line and port over a target the connection rules refuse
+    pub connector: Color,
+    pub selected_connector: Color,
+    pub preview_connector: Color,
+    pub selection: Color, // Outline around selected shapes, groups and lanes
+    pub grid: Color,
+    pub frame: Color, // Outlines of lanes and groups
+    pub frame_fill: Color, // Translucent wash over lanes and expanded groups
+    pub frame_text: Color, // Lane and group titles
+    pub collapsed_group: Color,
+}
+
+impl Theme {
+    pub fn dark() -> Theme {
+        Theme {
+            name: String::from("dark"),
+            background: Color::from_rgb(30, 30, 40),
+            shape: Color::from_rgb(100, 200, 255),
+            text: Color::BLACK,
+            port: Color::WHITE,
+            selected_port: Color::CYAN,
+            active_port: Color::from_rgb(50, 205, 50),
+            refused: Color::from_rgba(230, 64, 64, REFUSED_ALPHA),
+            connector: Color::WHITE,
+            selected_connector: Color::CYAN,
+            preview_connector: Color::from_rgba(204, 204, 204, PREVIEW_LINE_ALPHA),
+            selection: Color::from_rgb(255, 255, 0),
+            grid: Color::from_rgb(50, 50, 62),
+            frame: Color::from_rgb(130, 130, 150),
+            frame_fill: Color::from_rgba(255, 255, 255, 18),
+            frame_text: Color::from_rgb(220, 220, 230),
+            collapsed_group: Color::from_rgb(70, 70, 90),
+        }
+    }
+
+    pub fn light() -> Theme {
+        Theme {
+            name: String::from("light"),
+            background: Color::from_rgb(245, 245, 240),
+            shape: Color::from_rgb(170, 215, 245),
+            text: Color::from_rgb(20, 20, 30),
+            port: Color::from_rgb(90, 90, 100),
+            selected_port: Color::from_rgb(0, 130, 200),
+            active_port: Color::from_rgb(20, 150, 40),
+            refused: Color::from_rgba(200, 30, 30, REFUSED_ALPHA),
+            connector: Color::from_rgb(60, 60, 70),
+            selected_connector: Color::from_rgb(0, 130, 200),
+            preview_connector: Color::from_rgba(120, 120, 130, PREVIEW_LINE_ALPHA),
+            selection: Color::from_rgb(230, 120, 0),
+            grid: Color::from_rgb(220, 220, 215),
+            frame: Color::from_rgb(150, 150, 160),
+            frame_fill: Color::from_rgba(0, 0, 0, 12),
+            frame_text: Color::from_rgb(50, 50, 60),
+            collapsed_group: Color::from_rgb(210, 210, 220),
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Theme> {
+        match name.to_ascii_lowercase().as_str() {
+            "dark" => Some(Theme::dark()),
+            "light" => Some(Theme::light()),
+            _ => None,
+        }
+    }
+
+    // A preset by name, else a theme file: a path, or the name of a .toml in the user themes folder
+    pub fn load(name: &str) -> Result<Theme, String> {
+        if let Some(theme) = Theme::preset(name) { return Ok(theme); }
+        let path = theme_file_path(name).ok_or_else(|| format!("Unknown theme '{}'", name))?;
+        let contents = fs::read_to_string(&path).map_err(|e| format!("Could not read theme {}: {}", path.display(), e))?;
+        let colors: ThemeColors = toml::from_str(&contents).map_err(|e| format!("Failed to parse theme {}: {}", path.display(), e.message()))?;
+        let base_name = colors.base.as_deref().unwrap_or("dark");
+        let base = Theme::preset(base_name).ok_or_else(|| format!("Theme {} is based on unknown preset '{}'", path.display(), base_name))?;
+        let stem = path.file_stem().map_or_else(|| name.to_string(), |stem| stem.to_string_lossy().into_owned());
+        Ok(Theme { name: stem, ..base.with_overrides(&colors) })
+    }
+
+    // This theme with each of `layers` applied in turn, later ones winning
+    pub fn with_all_overrides(self, layers: &[ThemeColors]) -> Theme {
+        layers.iter().fold(self, |theme, colors| theme.with_overrides(colors))
+    }
+
+    // This theme with any colors `colors` sets replacing its own
+    pub fn with_overrides(mut self, colors: &ThemeColors) -> Theme {
+        let rgb = |value: Option<[u8; 3]>, color: &mut Color| {
+            if let Some([r, g, b]) = value { *color = Color::from_rgb(r, g, b); }
+        };
+        rgb(colors.background_rgb, &mut self.background);
+        rgb(colors.shape_rgb, &mut self.shape);
+        rgb(colors.text_rgb, &mut self.text);
+        rgb(colors.port_rgb, &mut self.port);
+        rgb(colors.selected_port_rgb, &mut self.selected_port);
+        rgb(colors.active_port_rgb, &mut self.active_port);
+        rgb(colors.connector_rgb, &mut self.connector);
+        rgb(colors.selected_connector_rgb, &mut self.selected_connector);
+        rgb(colors.selection_rgb, &mut self.selection);
+        rgb(colors.grid_rgb, &mut self.grid);
+        rgb(colors.frame_rgb, &mut self.frame);
+        rgb(colors.frame_text_rgb, &mut self.frame_text);
+        rgb(colors.collapsed_group_rgb, &mut self.collapsed_group);
+        if let Some([r, g, b]) = colors.refused_rgb { self.refused = Color::from_rgba(r, g, b, REFUSED_ALPHA); }
+        if let Some([r, g, b]) = colors.preview_connector_rgb { self.preview_connector = Color::from_rgba(r, g, b, PREVIEW_LINE_ALPHA); }
+        if let Some([r, g, b, a]) = colors.frame_fill_rgba { self.frame_fill = Color::from_rgba(r, g, b, a); }
+        self
+    }
+}
+
+// --- A theme file, or the colors config.toml sets on top of the theme ---
+// Anything left out keeps the color of the `base` preset (dark unless given).
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ThemeColors {
+    pub base: Option<String>,
+    pub background_rgb: Option<[u8; 3]>,
+    pub shape_rgb: Option<[u8; 3]>,
+    pub text_rgb: Option<[u8; 3]>,
+    pub port_rgb: Option<[u8; 3]>,
+    pub selected_port_rgb: Option<[u8; 3]>,
+    pub active_port_rgb: Option<[u8; 3]>,
+    pub refused_rgb: Option<[u8; 3]>,
+    pub connector_rgb: Option<[u8; 3]>,
+    pub selected_connector_rgb: Option<[u8; 3]>,
+    pub preview_connector_rgb: Option<[u8; 3]>,
+    pub selection_rgb: Option<[u8; 3]>,
+    pub grid_rgb: Option<[u8; 3]>,
+    pub frame_rgb: Option<[u8; 3]>,
+    pub frame_fill_rgba: Option<[u8; 4]>,
+    pub frame_text_rgb: Option<[u8; 3]>,
+    pub collapsed_group_rgb: Option<[u8; 3]>,
+}
+
+// --- Highlight colors that stay apart under color vision deficiencies ---
+// Selection, the selected connector and its ports, valid targets and refused ones, taken from the
+// Okabe-Ito palette so no two of them rely on telling red from green (or blue from yellow for
+// tritanopia). Applied over any theme; "default" leaves the theme's own colors.
+pub fn palette(name: &str) -> Result<ThemeColors, String> {
+    let highlights = |selection, selected, active, refused| ThemeColors {
+        selection_rgb: Some(selection),
+        selected_connector_rgb: Some(selected),
+        selected_port_rgb: Some(selected),
+        active_port_rgb: Some(active),
+        refused_rgb: Some(refused),
+        ..ThemeColors::default()
+    };
+    match name.to_ascii_lowercase().as_str() {
+        "default" => Ok(ThemeColors::default()),
+        "deuteranopia" => Ok(highlights([230, 159, 0], [86, 180, 233], [240, 228, 66], [0, 114, 178])),
+        "protanopia" => Ok(highlights([240, 228, 66], [86, 180, 233], [204, 121, 167], [0, 114, 178])),
+        "tritanopia" => Ok(highlights([213, 94, 0], [0, 158, 115], [204, 121, 167], [230, 230, 230])),
+        _ => Err(format!("Unknown palette '{}' (expected one of {})", name, PALETTES.join(", "))),
+    }
+}
+
+// <user config dir>/themes, holding one .toml per theme
+pub fn user_themes_dir() -> Option<PathBuf> {
+    Some(config::user_config_path()?.parent()?.join("themes"))
+}
+
+fn theme_file_path(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if path.is_file() { return Some(path.to_path_buf()); }
+    let in_themes_dir = user_themes_dir()?.join(path).with_extension("toml");
+    in_themes_dir.is_file().then_some(in_themes_dir)
+}
+
+// The presets, then the user themes in alphabetical order: what the theme shortcut cycles through
+pub fn available_themes() -> Vec<String> {
+    let mut user_themes: Vec<String> = user_themes_dir()
+        .and_then(|dir| fs::read_dir(dir).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "toml"))
+        .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+        .filter(|name| Theme::preset(name).is_none())
+        .collect();
+    user_themes.sort();
+    PRESETS.iter().map(|preset| preset.to_string()).chain(user_themes).collect()
+}
@@ -0,0 +1,90 @@
+// toasts.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::{self, Canvas, Color, DrawMode, Drawable, Mesh, Rect, Text};
+use ggez::{Context, GameResult};
+use std::time::{Duration, Instant};
+
+use rust_visual_mouse_app::logging::{Notification, Severity};
+
+const TOAST_WIDTH: f32 = 360.0;
+const TOAST_PADDING: f32 = 8.0;
+const TOAST_SPACING: f32 = 6.0;
+const TOAST_FONT_SIZE: f32 = 15.0;
+const TOAST_CORNER_RADIUS: f32 = 4.0;
+const MAX_TOASTS: usize = 5; // Older ones are dropped early when more arrive
+const TEXT_COLOR: Color = Color { r: 0.95, g: 0.95, b: 0.95, a: 1.0 };
+
+// How long a toast stays up, and its background
+fn severity_style(severity: Severity) -> (Duration, Color) {
+    match severity {
+        Severity::Info => (Duration::from_secs(3), Color { r: 0.2, g: 0.3, b: 0.45, a: 0.92 }),
+        Severity::Warning => (Duration::from_secs(6), Color { r: 0.55, g: 0.4, b: 0.1, a: 0.92 }),
+        Severity::Error => (Duration::from_secs(10), Color { r: 0.6, g: 0.15, b: 0.15, a: 0.92 }),
+    }
+}
+
+struct Toast {
+    notification: Notification,
+    shown_at: Instant,
+}
+
+// --- Notifications stacked in the bottom-right corner, newest at the bottom ---
+// Each goes away after a time that depends on its severity; clicking one dismisses it early.
+#[derive(Default)]
+pub struct Toasts {
+    toasts: Vec<Toast>,
+}
+
+impl Toasts {
+    pub fn push(&mut self, notification: Notification) {
+        self.toasts.push(Toast { notification, shown_at: Instant::now() });
+        let excess = self.toasts.len().saturating_sub(MAX_TOASTS);
+        self.toasts.drain(..excess);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.toasts.is_empty()
+    }
+
+    // Drops expired toasts; returns whether any went
+    pub fn expire(&mut self, now: Instant) -> bool {
+        let before = self.toasts.len();
+        self.toasts.retain(|toast| now.duration_since(toast.shown_at) < severity_style(toast.notification.severity).0);
+        self.toasts.len() != before
+    }
+
+    // Dismisses the toast under `pos`, if any; returns whether there was one
+    pub fn dismiss_at(&mut self, ctx: &Context, pos: Vec2, area: Rect) -> bool {
+        let Some(index) = self.layout(ctx, area).into_iter().position(|(rect, _)| rect.contains(pos)) else { return false; };
+        self.toasts.remove(index);
+        true
+    }
+
+    // Stacked upwards from the bottom-right corner of `area`, in the order of `toasts`
+    fn layout(&self, ctx: &Context, area: Rect) -> Vec<(Rect, Text)> {
+        let mut bottom = area.bottom() - TOAST_SPACING;
+        let mut placed: Vec<(Rect, Text)> = self.toasts.iter().rev().map(|toast| {
+            let mut text = Text::new(toast.notification.message.as_str());
+            text.set_scale(TOAST_FONT_SIZE);
+            text.set_bounds(Vec2::new(TOAST_WIDTH - TOAST_PADDING * 2.0, f32::INFINITY));
+            let text_height = text.dimensions(ctx).map_or(TOAST_FONT_SIZE, |dimensions| dimensions.h);
+            let height = text_height + TOAST_PADDING * 2.0;
+            let rect = Rect::new(area.right() - TOAST_WIDTH - TOAST_SPACING, bottom - height, TOAST_WIDTH, height);
+            bottom = rect.y - TOAST_SPACING;
+            (rect, text)
+        }).collect();
+        placed.reverse();
+        placed
+    }
+
+    pub fn draw(&self, ctx: &mut Context, canvas: &mut Canvas, area: Rect) -> GameResult {
+        for ((rect, text), toast) in self.layout(ctx, area).into_iter().zip(&self.toasts) {
+            let background = severity_style(toast.notification.severity).1;
+            let mesh = Mesh::new_rounded_rectangle(ctx, DrawMode::fill(), rect, TOAST_CORNER_RADIUS, background)?;
+            canvas.draw(&mesh, graphics::DrawParam::default());
+            canvas.draw(&text, graphics::DrawParam::default().dest(Vec2::new(rect.x + TOAST_PADDING, rect.y + TOAST_PADDING)).color(TEXT_COLOR));
+        }
+        Ok(())
+    }
+}
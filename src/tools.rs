@@ -0,0 +1,85 @@
+// tools.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::Rect;
+use ggez::input::keyboard::KeyCode;
+use ggez::winit::window::CursorIcon;
+
+use crate::ui::{self, Widget};
+
+const TOOLBAR_BUTTON_WIDTH: f32 = 96.0;
+pub const TOOLBAR_HEIGHT: f32 = 36.0;
+
+// --- What a left click on the canvas does ---
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tool {
+    #[default]
+    Select, // Select, drag and edit; also draws connectors from ports and creates shapes on double-click
+    Pan,
+    Connect, // Click a source shape or port, then a target
+    Create, // Single click on empty space creates a shape
+    Delete, // Click a shape or connector to delete it
+    Pen, // Drag to draw on the annotation layer; Shift+drag highlights
+    Eraser, // Drag over annotation strokes to remove them
+}
+
+impl Tool {
+    pub const ALL: [Tool; 7] = [Tool::Select, Tool::Pan, Tool::Connect, Tool::Create, Tool::Delete, Tool::Pen, Tool::Eraser];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Tool::Select => "Select",
+            Tool::Pan => "Pan",
+            Tool::Connect => "Connect",
+            Tool::Create => "Create",
+            Tool::Delete => "Delete",
+            Tool::Pen => "Pen",
+            Tool::Eraser => "Eraser",
+        }
+    }
+
+    pub fn shortcut(self) -> KeyCode {
+        match self {
+            Tool::Select => KeyCode::V,
+            Tool::Pan => KeyCode::H,
+            Tool::Connect => KeyCode::C,
+            Tool::Create => KeyCode::N,
+            Tool::Delete => KeyCode::E,
+            Tool::Pen => KeyCode::P,
+            Tool::Eraser => KeyCode::X,
+        }
+    }
+
+    // Status bar hint while nothing more specific is going on
+    pub fn hint(self) -> &'static str {
+        match self {
+            Tool::Select => "Drag from a port to connect, double-click to add a shape, Ctrl+Shift+P for commands",
+            Tool::Pan => "Drag to pan, scroll to zoom",
+            Tool::Connect => "Click a source shape, then a target",
+            Tool::Create => "Click empty space to add a shape",
+            Tool::Delete => "Click a shape or connector to delete it",
+            Tool::Pen => "Drag to draw, Shift+drag to highlight, Shift+P shows or hides annotations",
+            Tool::Eraser => "Drag over annotations to erase them",
+        }
+    }
+}
+
+// Pointer over the canvas: an open hand while a left drag would pan (Space held or the Pan tool),
+// a closed one while a drag is panning, and crosshairs for drawing and erasing annotations
+pub fn canvas_cursor(tool: Tool, space_held: bool, panning: bool) -> CursorIcon {
+    if panning {
+        CursorIcon::Grabbing
+    } else if space_held || tool == Tool::Pan {
+        CursorIcon::Grab
+    } else if matches!(tool, Tool::Pen | Tool::Eraser) {
+        CursorIcon::Crosshair
+    } else {
+        CursorIcon::Default
+    }
+}
+
+// Toolbar area along the top-left of the window and one button per tool
+pub fn toolbar_widgets(active: Tool) -> (Rect, Vec<Widget<Tool>>) {
+    let buttons = Tool::ALL.map(|tool| (tool, format!("{:?} {}", tool.shortcut(), tool.name()), tool == active));
+    ui::button_bar(Vec2::ZERO, TOOLBAR_BUTTON_WIDTH, TOOLBAR_HEIGHT, buttons)
+}
@@ -0,0 +1,125 @@
+// touch.rs
+// Touchscreen fingers turned into the editor's pointer input: one finger acts as the left mouse
+// button (a tap clicks, two taps double-click, a drag drags), holding it still opens a menu of
+// what can be done with what's under it, and two fingers pan and zoom. Positions are physical window coordinates.
+
+use ggez::glam::Vec2;
+use ggez::winit::event::TouchPhase;
+use std::time::{Duration, Instant};
+
+const LONG_PRESS: Duration = Duration::from_millis(600);
+const TOUCH_SLOP: f32 = 12.0; // A finger that stays within this many pixels is holding still
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TouchGesture {
+    Press(Vec2), // A lone finger landed
+    Drag(Vec2),
+    Release(Vec2),
+    LongPress(Vec2), // The press is over; lifting the finger does nothing more
+    Cancel(Vec2), // A second finger landed, so the press is let go of where it is
+    TwoFinger { center: Vec2, pan: Vec2, zoom: f32 }, // Since the last one: centroid movement and spread ratio
+}
+
+#[derive(Clone, Copy)]
+struct Finger {
+    id: u64,
+    position: Vec2,
+}
+
+#[derive(Clone, Copy)]
+struct Press {
+    id: u64,
+    started: Instant,
+    start: Vec2,
+    held_still: bool, // Hasn't left TOUCH_SLOP, so it may still become a long press
+}
+
+#[derive(Default)]
+pub struct TouchTracker {
+    fingers: Vec<Finger>,
+    press: Option<Press>, // The finger acting as the mouse button
+}
+
+impl TouchTracker {
+    pub fn finger(&mut self, id: u64, phase: TouchPhase, position: Vec2, time: Instant) -> Option<TouchGesture> {
+        match phase {
+            TouchPhase::Started => {
+                self.fingers.retain(|finger| finger.id != id);
+                self.fingers.push(Finger { id, position });
+                match self.fingers.len() {
+                    1 => {
+                        self.press = Some(Press { id, started: time, start: position, held_still: true });
+                        Some(TouchGesture::Press(position))
+                    }
+                    _ => self.press.take().and_then(|press| self.position(press.id)).map(TouchGesture::Cancel),
+                }
+            }
+            TouchPhase::Moved => {
+                let before = self.two_finger_span();
+                let finger = self.fingers.iter_mut().find(|finger| finger.id == id)?;
+                finger.position = position;
+                if let Some(press) = self.press.as_mut().filter(|press| press.id == id) {
+                    if press.start.distance(position) > TOUCH_SLOP { press.held_still = false; }
+                    return Some(TouchGesture::Drag(position));
+                }
+                let ((old_center, old_spread), (center, spread)) = before.zip(self.two_finger_span())?;
+                let zoom = if old_spread > 0.0 { spread / old_spread } else { 1.0 };
+                Some(TouchGesture::TwoFinger { center, pan: center - old_center, zoom })
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.fingers.retain(|finger| finger.id != id);
+                if self.press.is_none_or(|press| press.id != id) { return None; }
+                self.press = None;
+                Some(TouchGesture::Release(position))
+            }
+        }
+    }
+
+    // A press held still long enough; checked every frame since no event marks it
+    pub fn poll(&mut self, time: Instant) -> Option<TouchGesture> {
+        let press = self.press.filter(|press| press.held_still && time.duration_since(press.started) >= LONG_PRESS)?;
+        self.press = None;
+        self.position(press.id).map(TouchGesture::LongPress)
+    }
+
+    fn position(&self, id: u64) -> Option<Vec2> {
+        self.fingers.iter().find(|finger| finger.id == id).map(|finger| finger.position)
+    }
+
+    // Centroid of and distance between the first two fingers down
+    fn two_finger_span(&self) -> Option<(Vec2, f32)> {
+        let [a, b] = [self.fingers.first()?, self.fingers.get(1)?];
+        Some(((a.position + b.position) / 2.0, a.position.distance(b.position)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn touch_press_turns_into_a_pinch_when_a_second_finger_lands() {
+        let mut touch = TouchTracker::default();
+        let now = Instant::now();
+        assert_eq!(touch.finger(1, TouchPhase::Started, Vec2::new(100.0, 100.0), now), Some(TouchGesture::Press(Vec2::new(100.0, 100.0))));
+        assert_eq!(touch.finger(2, TouchPhase::Started, Vec2::new(200.0, 100.0), now), Some(TouchGesture::Cancel(Vec2::new(100.0, 100.0))));
+        let Some(TouchGesture::TwoFinger { center, pan, zoom }) = touch.finger(2, TouchPhase::Moved, Vec2::new(300.0, 100.0), now) else {
+            panic!("expected a two-finger gesture");
+        };
+        assert_eq!((center, pan, zoom), (Vec2::new(200.0, 100.0), Vec2::new(50.0, 0.0), 2.0));
+        // Neither finger acts as the mouse any more
+        assert_eq!(touch.finger(1, TouchPhase::Ended, Vec2::new(100.0, 100.0), now), None);
+        assert_eq!(touch.poll(now + Duration::from_secs(5)), None);
+    }
+
+    #[test]
+    fn holding_a_finger_still_is_a_long_press() {
+        let mut touch = TouchTracker::default();
+        let now = Instant::now();
+        touch.finger(1, TouchPhase::Started, Vec2::new(50.0, 50.0), now);
+        touch.finger(1, TouchPhase::Moved, Vec2::new(53.0, 50.0), now);
+        assert_eq!(touch.poll(now + Duration::from_millis(100)), None);
+        assert_eq!(touch.poll(now + Duration::from_secs(1)), Some(TouchGesture::LongPress(Vec2::new(53.0, 50.0))));
+        assert_eq!(touch.finger(1, TouchPhase::Ended, Vec2::new(53.0, 50.0), now), None);
+    }
+}
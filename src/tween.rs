@@ -0,0 +1,48 @@
+// tween.rs
+// Timed transitions stepped from the editor's update(): a start time, a duration and an easing
+// curve, turned into how far along a move is. What moves (shape positions, the camera) stays with
+// whoever owns the animation.
+
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Easing {
+    Linear,
+    #[default]
+    EaseInOut, // Smoothstep: gentle start and stop
+    EaseOut, // Cubic: fast start, gentle stop; for moves answering a key press
+}
+
+impl Easing {
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Tween {
+    started_at: Instant,
+    duration: Duration,
+    easing: Easing,
+}
+
+impl Tween {
+    pub fn new(duration: Duration, easing: Easing) -> Tween {
+        Tween { started_at: Instant::now(), duration, easing }
+    }
+
+    // Eased progress at `now`, from 0 to 1
+    pub fn progress(&self, now: Instant) -> f32 {
+        if self.duration.is_zero() { return 1.0; }
+        self.easing.apply(now.duration_since(self.started_at).as_secs_f32() / self.duration.as_secs_f32())
+    }
+
+    pub fn finished(&self, now: Instant) -> bool {
+        now.duration_since(self.started_at) >= self.duration
+    }
+}
@@ -0,0 +1,286 @@
+// ui.rs
+
+use ggez::glam::Vec2;
+use ggez::graphics::{self, Canvas, Color, DrawMode, Mesh, Rect, Text};
+use ggez::{Context, GameResult};
+
+const ROW_HEIGHT: f32 = 24.0;
+const ROW_SPACING: f32 = 6.0;
+const PANEL_PADDING: f32 = 10.0;
+const FONT_SIZE: f32 = 16.0;
+const LABEL_WIDTH: f32 = 70.0; // Caption column to the left of field/stepper values
+const STEPPER_BUTTON_WIDTH: f32 = 24.0;
+const SWATCH_SIZE: f32 = 20.0;
+
+// --- Colors the widgets are drawn with ---
+pub struct UiStyle {
+    pub panel_color: Color,
+    pub field_color: Color,
+    pub text_color: Color,
+    pub focus_color: Color,
+}
+
+impl Default for UiStyle {
+    fn default() -> Self {
+        UiStyle {
+            panel_color: Color::from_rgb(45, 45, 58),
+            field_color: Color::from_rgb(28, 28, 36),
+            text_color: Color::from_rgb(230, 230, 230),
+            focus_color: Color::from_rgb(255, 255, 0),
+        }
+    }
+}
+
+pub enum WidgetKind {
+    Label(String),
+    TextField { caption: String, text: String, focused: bool },
+    Stepper { caption: String, value: String }, // Shows "- value +"
+    Swatch { color: Option<Color>, selected: bool }, // None draws a "reset" swatch
+    Button { text: String, active: bool },
+}
+
+// A widget placed in screen space; `id` is what a click on it reports (labels have none)
+pub struct Widget<Id> {
+    pub id: Option<Id>,
+    pub rect: Rect,
+    pub kind: WidgetKind,
+}
+
+// Which part of a widget was clicked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WidgetHit {
+    Activate,
+    Decrement,
+    Increment,
+}
+
+// --- Top-to-bottom row layout inside a panel ---
+pub struct PanelLayout<Id> {
+    left: f32,
+    width: f32,
+    cursor_y: f32,
+    pub widgets: Vec<Widget<Id>>,
+}
+
+impl<Id> PanelLayout<Id> {
+    pub fn new(panel: Rect) -> PanelLayout<Id> {
+        PanelLayout {
+            left: panel.x + PANEL_PADDING,
+            width: panel.w - PANEL_PADDING * 2.0,
+            cursor_y: panel.y + PANEL_PADDING,
+            widgets: Vec::new(),
+        }
+    }
+
+    fn next_row(&mut self) -> Rect {
+        let row = Rect::new(self.left, self.cursor_y, self.width, ROW_HEIGHT);
+        self.cursor_y += ROW_HEIGHT + ROW_SPACING;
+        row
+    }
+
+    pub fn label(&mut self, text: impl Into<String>) {
+        let rect = self.next_row();
+        self.widgets.push(Widget { id: None, rect, kind: WidgetKind::Label(text.into()) });
+    }
+
+    pub fn text_field(&mut self, id: Id, caption: &str, text: &str, focused: bool) {
+        let rect = self.next_row();
+        self.widgets.push(Widget { id: Some(id), rect, kind: WidgetKind::TextField { caption: caption.to_string(), text: text.to_string(), focused } });
+    }
+
+    pub fn stepper(&mut self, id: Id, caption: &str, value: String) {
+        let rect = self.next_row();
+        self.widgets.push(Widget { id: Some(id), rect, kind: WidgetKind::Stepper { caption: caption.to_string(), value } });
+    }
+
+    pub fn button(&mut self, id: Id, text: impl Into<String>) {
+        self.toggle_button(id, text, false);
+    }
+
+    // A button drawn highlighted while `active`
+    pub fn toggle_button(&mut self, id: Id, text: impl Into<String>, active: bool) {
+        let rect = self.next_row();
+        self.widgets.push(Widget { id: Some(id), rect, kind: WidgetKind::Button { text: text.into(), active } });
+    }
+
+    // One row of buttons sharing its width in proportion to their weights
+    pub fn button_row(&mut self, buttons: impl IntoIterator<Item = (Id, String, bool, f32)>) {
+        let row = self.next_row();
+        let buttons: Vec<(Id, String, bool, f32)> = buttons.into_iter().collect();
+        let total_weight: f32 = buttons.iter().map(|b| b.3).sum();
+        let available = row.w - ROW_SPACING * buttons.len().saturating_sub(1) as f32;
+        let mut x = row.x;
+        for (id, text, active, weight) in buttons {
+            let width = available * weight / total_weight.max(f32::EPSILON);
+            self.widgets.push(Widget { id: Some(id), rect: Rect::new(x, row.y, width, row.h), kind: WidgetKind::Button { text, active } });
+            x += width + ROW_SPACING;
+        }
+    }
+
+    // One row of swatches; wraps onto further rows when they don't fit
+    pub fn swatch_row(&mut self, swatches: impl IntoIterator<Item = (Id, Option<Color>, bool)>) {
+        let per_row = ((self.width + ROW_SPACING) / (SWATCH_SIZE + ROW_SPACING)).floor().max(1.0) as usize;
+        let mut row = self.next_row();
+        for (i, (id, color, selected)) in swatches.into_iter().enumerate() {
+            if i > 0 && i % per_row == 0 { row = self.next_row(); }
+            let x = row.x + (i % per_row) as f32 * (SWATCH_SIZE + ROW_SPACING);
+            let rect = Rect::new(x, row.y + (ROW_HEIGHT - SWATCH_SIZE) / 2.0, SWATCH_SIZE, SWATCH_SIZE);
+            self.widgets.push(Widget { id: Some(id), rect, kind: WidgetKind::Swatch { color, selected } });
+        }
+    }
+}
+
+// Height of a panel holding `rows` rows laid out by PanelLayout
+pub fn panel_height(rows: usize) -> f32 {
+    PANEL_PADDING * 2.0 + rows as f32 * (ROW_HEIGHT + ROW_SPACING) - ROW_SPACING
+}
+
+// A horizontal strip of buttons starting at `origin`; returns the strip's area and its buttons
+pub fn button_bar<Id>(origin: Vec2, button_width: f32, height: f32, buttons: impl IntoIterator<Item = (Id, String, bool)>) -> (Rect, Vec<Widget<Id>>) {
+    let button_height = height - ROW_SPACING * 2.0;
+    let mut x = origin.x + ROW_SPACING;
+    let mut widgets = Vec::new();
+    for (id, text, active) in buttons {
+        let rect = Rect::new(x, origin.y + ROW_SPACING, button_width, button_height);
+        widgets.push(Widget { id: Some(id), rect, kind: WidgetKind::Button { text, active } });
+        x += button_width + ROW_SPACING;
+    }
+    (Rect::new(origin.x, origin.y, x - origin.x, height), widgets)
+}
+
+// --- Overlay to pick one item from a list: header rows, then a button per item with the highlighted
+// one drawn active. A long list scrolls to keep the highlight shown. Button ids are item indices. ---
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ListOverlay {
+    selected: usize,
+}
+
+impl ListOverlay {
+    pub fn selected(self) -> usize {
+        self.selected
+    }
+
+    // Makes the clicked item the highlighted one
+    pub fn select(&mut self, index: usize) {
+        self.selected = index;
+    }
+
+    // Moves the highlight up (negative) or down, wrapping around the `count` items
+    pub fn move_selection(&mut self, count: usize, delta: isize) {
+        if count == 0 { return; }
+        self.selected = (self.selected as isize + delta).rem_euclid(count as isize) as usize;
+    }
+
+    // Panel with its top-left corner at `origin`, showing at most `max_rows` items at once
+    pub fn widgets(self, origin: Vec2, width: f32, max_rows: usize, headers: Vec<String>, items: Vec<String>) -> (Rect, Vec<Widget<usize>>) {
+        let first = self.selected.saturating_sub(max_rows.saturating_sub(1));
+        let shown = items.len().saturating_sub(first).min(max_rows);
+        let panel = Rect::new(origin.x, origin.y, width, panel_height(headers.len() + shown));
+        let mut layout = PanelLayout::new(panel);
+        for header in headers { layout.label(header); }
+        for (index, text) in items.into_iter().enumerate().skip(first).take(shown) {
+            layout.toggle_button(index, text, index == self.selected);
+        }
+        (panel, layout.widgets)
+    }
+}
+
+// Top-left corner of an overlay `width` wide, centered across the screen `top` below its edge
+pub fn centered_origin(screen_size: Vec2, width: f32, top: f32) -> Vec2 {
+    Vec2::new((screen_size.x - width) / 2.0, top)
+}
+
+fn value_rect(row: Rect) -> Rect {
+    Rect::new(row.x + LABEL_WIDTH, row.y, row.w - LABEL_WIDTH, row.h)
+}
+
+pub fn hit_test<Id: Copy>(widgets: &[Widget<Id>], pos: Vec2) -> Option<(Id, WidgetHit)> {
+    widgets.iter().find(|w| w.rect.contains(pos)).and_then(|widget| {
+        let id = widget.id?;
+        let hit = match widget.kind {
+            WidgetKind::Stepper { .. } => {
+                let value = value_rect(widget.rect);
+                if pos.x < value.x + STEPPER_BUTTON_WIDTH {
+                    WidgetHit::Decrement
+                } else if pos.x > value.right() - STEPPER_BUTTON_WIDTH {
+                    WidgetHit::Increment
+                } else {
+                    return None;
+                }
+            }
+            _ => WidgetHit::Activate,
+        };
+        Some((id, hit))
+    })
+}
+
+fn draw_text(canvas: &mut Canvas, text: &str, area: Rect, centered: bool, color: Color) {
+    let mut text_obj = Text::new(text);
+    text_obj.set_scale(FONT_SIZE);
+    text_obj.set_bounds(Vec2::new(area.w, area.h));
+    let dest = if centered {
+        text_obj.set_layout(graphics::TextLayout::center());
+        Vec2::new(area.x + area.w / 2.0, area.y + area.h / 2.0)
+    } else {
+        text_obj.set_layout(graphics::TextLayout { h_align: graphics::TextAlign::Begin, v_align: graphics::TextAlign::Middle });
+        Vec2::new(area.x + 4.0, area.y + area.h / 2.0)
+    };
+    canvas.draw(&text_obj, graphics::DrawParam::default().dest(dest).color(color));
+}
+
+pub fn draw_panel<Id>(ctx: &mut Context, canvas: &mut Canvas, panel: Rect, widgets: &[Widget<Id>], style: &UiStyle) -> GameResult {
+    let background = Mesh::new_rectangle(ctx, DrawMode::fill(), panel, style.panel_color)?;
+    canvas.draw(&background, graphics::DrawParam::default());
+
+    for widget in widgets {
+        let row = widget.rect;
+        match &widget.kind {
+            WidgetKind::Label(text) => draw_text(canvas, text, row, false, style.text_color),
+            WidgetKind::TextField { caption, text, focused } => {
+                draw_text(canvas, caption, row, false, style.text_color);
+                let field = value_rect(row);
+                let field_mesh = Mesh::new_rectangle(ctx, DrawMode::fill(), field, style.field_color)?;
+                canvas.draw(&field_mesh, graphics::DrawParam::default());
+                if *focused {
+                    let focus_mesh = Mesh::new_rectangle(ctx, DrawMode::stroke(1.5), field, style.focus_color)?;
+                    canvas.draw(&focus_mesh, graphics::DrawParam::default());
+                }
+                let shown = if *focused { format!("{}|", text) } else { text.clone() };
+                draw_text(canvas, &shown, field, false, style.text_color);
+            }
+            WidgetKind::Stepper { caption, value } => {
+                draw_text(canvas, caption, row, false, style.text_color);
+                let field = value_rect(row);
+                let field_mesh = Mesh::new_rectangle(ctx, DrawMode::fill(), field, style.field_color)?;
+                canvas.draw(&field_mesh, graphics::DrawParam::default());
+                let minus = Rect::new(field.x, field.y, STEPPER_BUTTON_WIDTH, field.h);
+                let plus = Rect::new(field.right() - STEPPER_BUTTON_WIDTH, field.y, STEPPER_BUTTON_WIDTH, field.h);
+                draw_text(canvas, "-", minus, true, style.text_color);
+                draw_text(canvas, "+", plus, true, style.text_color);
+                draw_text(canvas, value, field, true, style.text_color);
+            }
+            WidgetKind::Swatch { color, selected } => {
+                let fill = color.unwrap_or(style.field_color);
+                let swatch_mesh = Mesh::new_rectangle(ctx, DrawMode::fill(), row, fill)?;
+                canvas.draw(&swatch_mesh, graphics::DrawParam::default());
+                if color.is_none() {
+                    let cross = Mesh::new_line(ctx, &[Vec2::new(row.x, row.bottom()), Vec2::new(row.right(), row.y)], 1.5, style.text_color)?;
+                    canvas.draw(&cross, graphics::DrawParam::default());
+                }
+                let border_color = if *selected { style.focus_color } else { style.text_color };
+                let border_mesh = Mesh::new_rectangle(ctx, DrawMode::stroke(if *selected { 2.0 } else { 1.0 }), row, border_color)?;
+                canvas.draw(&border_mesh, graphics::DrawParam::default());
+            }
+            WidgetKind::Button { text, active } => {
+                let button_mesh = Mesh::new_rectangle(ctx, DrawMode::fill(), row, style.field_color)?;
+                canvas.draw(&button_mesh, graphics::DrawParam::default());
+                if *active {
+                    let active_mesh = Mesh::new_rectangle(ctx, DrawMode::stroke(2.0), row, style.focus_color)?;
+                    canvas.draw(&active_mesh, graphics::DrawParam::default());
+                }
+                draw_text(canvas, text, row, true, if *active { style.focus_color } else { style.text_color });
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,185 @@
+// validation.rs
+
+use std::collections::HashSet;
+
+use crate::ports::PortDirection;
+use crate::{Connection, Diagram};
+
+// --- A rule new and re-attached connections must pass ---
+// `replacing` is the index of the connection being changed (e.g. an endpoint dragged elsewhere),
+// which rules should treat as already gone.
+pub trait ConnectionRule {
+    fn check(&self, diagram: &Diagram, candidate: &Connection, replacing: Option<usize>) -> Result<(), String>;
+}
+
+// Forbids connecting a shape to itself
+pub struct NoSelfLoops;
+
+impl ConnectionRule for NoSelfLoops {
+    fn check(&self, _diagram: &Diagram, candidate: &Connection, _replacing: Option<usize>) -> Result<(), String> {
+        if candidate.from_shape_index == candidate.to_shape_index {
+            return Err(String::from("a shape can't connect to itself"));
+        }
+        Ok(())
+    }
+}
+
+// Forbids A→B when B→A already exists
+pub struct NoReverseDuplicates;
+
+impl ConnectionRule for NoReverseDuplicates {
+    fn check(&self, diagram: &Diagram, candidate: &Connection, replacing: Option<usize>) -> Result<(), String> {
+        let reversed_exists = diagram.connections.iter().enumerate().any(|(i, c)| {
+            Some(i) != replacing && c.from_shape_index == candidate.to_shape_index && c.to_shape_index == candidate.from_shape_index
+        });
+        if reversed_exists { return Err(String::from("the opposite connection already exists")); }
+        Ok(())
+    }
+}
+
+// Forbids joining ports of different types
+pub struct PortTypesMatch;
+
+impl ConnectionRule for PortTypesMatch {
+    fn check(&self, diagram: &Diagram, candidate: &Connection, _replacing: Option<usize>) -> Result<(), String> {
+        let port = |shape_index: usize, port_id: usize| diagram.shapes.get(shape_index).and_then(|s| s.ports.get(port_id));
+        let (Some(from), Some(to)) = (port(candidate.from_shape_index, candidate.from_port), port(candidate.to_shape_index, candidate.to_port)) else {
+            return Ok(()); // Missing ports are refused when connecting
+        };
+        if !from.compatible_with(to) {
+            return Err(format!("a {} port can't connect to a {} port", from.port_type, to.port_type));
+        }
+        Ok(())
+    }
+}
+
+// Forbids leaving through an "in" port or arriving at an "out" one
+pub struct PortDirectionsMatch;
+
+impl ConnectionRule for PortDirectionsMatch {
+    fn check(&self, diagram: &Diagram, candidate: &Connection, _replacing: Option<usize>) -> Result<(), String> {
+        let port = |shape_index: usize, port_id: usize| diagram.shapes.get(shape_index).and_then(|s| s.ports.get(port_id));
+        if port(candidate.from_shape_index, candidate.from_port).is_some_and(|p| p.direction == PortDirection::In) {
+            return Err(String::from("connections can't leave from an in port"));
+        }
+        if port(candidate.to_shape_index, candidate.to_port).is_some_and(|p| p.direction == PortDirection::Out) {
+            return Err(String::from("connections can't arrive at an out port"));
+        }
+        Ok(())
+    }
+}
+
+// DAG mode: forbids connections that would close a cycle
+pub struct Acyclic;
+
+impl ConnectionRule for Acyclic {
+    fn check(&self, diagram: &Diagram, candidate: &Connection, replacing: Option<usize>) -> Result<(), String> {
+        // The candidate closes a cycle if its target already leads back to its source
+        let mut visited = HashSet::new();
+        let mut pending = vec![candidate.to_shape_index];
+        while let Some(shape) = pending.pop() {
+            if shape == candidate.from_shape_index { return Err(String::from("it would create a cycle")); }
+            if !visited.insert(shape) { continue; }
+            pending.extend(diagram.connections.iter().enumerate()
+                .filter(|&(i, c)| Some(i) != replacing && c.from_shape_index == shape)
+                .map(|(_, c)| c.to_shape_index));
+        }
+        Ok(())
+    }
+}
+
+// --- The rules in force, checked in order; the first failure is reported ---
+#[derive(Default)]
+pub struct ConnectionValidator {
+    rules: Vec<Box<dyn ConnectionRule>>,
+}
+
+impl ConnectionValidator {
+    pub fn new() -> ConnectionValidator {
+        ConnectionValidator::default()
+    }
+
+    pub fn add_rule(&mut self, rule: impl ConnectionRule + 'static) {
+        self.rules.push(Box::new(rule));
+    }
+
+    pub fn check(&self, diagram: &Diagram, candidate: &Connection, replacing: Option<usize>) -> Result<(), String> {
+        self.rules.iter().try_for_each(|rule| rule.check(diagram, candidate, replacing))
+    }
+
+    // Checks the connections from index `first` on (e.g. just pasted or imported) one at a time,
+    // each against the diagram as it stands with the ones before it admitted. Refused connections
+    // are dropped; returns why, one line per refusal.
+    pub fn admit_from(&self, diagram: &mut Diagram, first: usize) -> Vec<String> {
+        let candidates = diagram.connections.split_off(first.min(diagram.connections.len()));
+        let mut refusals = Vec::new();
+        for candidate in candidates {
+            match self.check(diagram, &candidate, None) {
+                Ok(()) => diagram.connections.push(candidate),
+                Err(e) => refusals.push(format!("shape {} → shape {}: {}", candidate.from_shape_index, candidate.to_shape_index, e)),
+            }
+        }
+        refusals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagram::chain;
+
+    fn strict() -> ConnectionValidator {
+        let mut validator = ConnectionValidator::new();
+        validator.add_rule(NoSelfLoops);
+        validator.add_rule(NoReverseDuplicates);
+        validator.add_rule(Acyclic);
+        validator.add_rule(PortTypesMatch);
+        validator.add_rule(PortDirectionsMatch);
+        validator
+    }
+
+    #[test]
+    fn validator_rules_refuse_loops_reversals_and_cycles() {
+        let mut diagram = chain();
+        diagram.shapes[0].ports[3].port_type = String::from("audio");
+        diagram.shapes[2].ports[2].port_type = String::from("number");
+        let validator = strict();
+        assert!(validator.check(&diagram, &Connection::new(0, 1, 2, 0), None).is_ok());
+        assert!(validator.check(&diagram, &Connection::new(1, 1, 1, 0), None).is_err());
+        assert!(validator.check(&diagram, &Connection::new(1, 1, 0, 0), None).is_err());
+        assert!(validator.check(&diagram, &Connection::new(2, 1, 0, 0), None).is_err());
+        // Re-attaching 1→2 as 2→0 breaks the path that would have closed the cycle
+        assert!(validator.check(&diagram, &Connection::new(2, 1, 0, 0), Some(1)).is_ok());
+        assert!(validator.check(&diagram, &Connection::new(0, 3, 2, 2), None).is_err());
+        assert!(validator.check(&diagram, &Connection::new(0, 3, 2, 0), None).is_ok());
+    }
+
+    #[test]
+    fn port_directions_keep_connections_running_out_to_in() {
+        let mut diagram = chain();
+        diagram.shapes[0].ports[3].direction = PortDirection::In;
+        diagram.shapes[2].ports[2].direction = PortDirection::Out;
+        diagram.shapes[2].ports[0].direction = PortDirection::In;
+        let validator = strict();
+        assert!(validator.check(&diagram, &Connection::new(0, 3, 2, 0), None).is_err());
+        assert!(validator.check(&diagram, &Connection::new(0, 1, 2, 2), None).is_err());
+        assert!(validator.check(&diagram, &Connection::new(0, 1, 2, 0), None).is_ok());
+        // Re-attaching 1→2 at a port that only lets connections leave is refused too
+        assert!(validator.check(&diagram, &Connection::new(1, 1, 2, 2), Some(1)).is_err());
+    }
+
+    #[test]
+    fn admitting_added_connections_drops_the_refused_ones_in_order() {
+        let mut diagram = chain();
+        diagram.connections.push(Connection::new(0, 1, 2, 0)); // Fine
+        diagram.connections.push(Connection::new(2, 1, 0, 0)); // Closes a cycle
+        diagram.connections.push(Connection::new(1, 1, 1, 0)); // Loops
+        let refusals = strict().admit_from(&mut diagram, 2);
+        let endpoints: Vec<(usize, usize)> = diagram.connections.iter().map(|c| (c.from_shape_index, c.to_shape_index)).collect();
+        assert_eq!(endpoints, [(0, 1), (1, 2), (0, 2)]);
+        assert_eq!(refusals.len(), 2);
+        // Connections before `first` aren't looked at again
+        assert!(strict().admit_from(&mut diagram, 3).is_empty());
+        assert_eq!(diagram.connections.len(), 3);
+    }
+}